@@ -0,0 +1,59 @@
+use indexmap::IndexMap;
+
+use crate::key::{normalize_key, KeyStyle};
+use crate::pattern::glob_match;
+use crate::types::{SysctlValue, ValidationError};
+
+// 1行に1つ、許可するキーかグロブを書いたリストを読み込む。空行と # で始まる行は無視する
+// 設定ファイルのキーと比べられるよう、. 区切りに揃える
+pub fn parse_allowlist(input: &str) -> Vec<String> {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| normalize_key(line, KeyStyle::Dots).into_owned())
+        .collect()
+}
+
+// 許可リストのどれにもマッチしないキーをUnknownKeyとして返す(キーの順)
+pub fn check_allowlist(
+    value: &IndexMap<String, SysctlValue>,
+    allowlist: &[String],
+) -> Vec<ValidationError> {
+    let mut keys: Vec<&String> = value
+        .keys()
+        .filter(|key| !allowlist.iter().any(|pattern| glob_match(pattern, key)))
+        .collect();
+    keys.sort();
+    keys.into_iter()
+        .map(|key| ValidationError::UnknownKey(key.to_owned()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_sysctl;
+
+    #[test]
+    fn test_check_allowlist() {
+        let allowlist =
+            parse_allowlist("# allowed keys\nvm.swappiness\n\n  net.ipv4.conf.*.rp_filter\n");
+        assert_eq!(
+            allowlist,
+            vec!["vm.swappiness", "net.ipv4.conf.*.rp_filter"]
+        );
+        let check = |input: &str| check_allowlist(&parse_sysctl(input).unwrap(), &allowlist);
+
+        assert!(check("vm.swappiness = 10").is_empty());
+        // グロブにマッチする
+        assert!(check("net.ipv4.conf.eth0.rp_filter = 1").is_empty());
+        assert_eq!(
+            check("vm.swappiness = 10\nkernel.sysrq = 1\nnet.ipv4.conf.eth0.forwarding = 1"),
+            vec![
+                ValidationError::UnknownKey("kernel.sysrq".to_owned()),
+                ValidationError::UnknownKey("net.ipv4.conf.eth0.forwarding".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::format::parse_bool;
+use crate::tr;
+use crate::types::{Schema, SysctlValue};
+
+// 先頭に - が付いていて、反映に失敗しても無視されるキーの一覧(ソート済み)
+pub fn ignore_error_keys(value: &IndexMap<String, SysctlValue>) -> Vec<&String> {
+    let mut keys: Vec<&String> = value
+        .iter()
+        .filter(|(_, sysctl_value)| sysctl_value.ignore_error)
+        .map(|(key, _)| key)
+        .collect();
+    keys.sort();
+    keys
+}
+
+// カーネルの既定値から大きく離れた数値
+#[derive(Debug, PartialEq)]
+pub struct Outlier {
+    pub key: String,
+    pub value: f64,
+    pub default: f64,
+    // 値と既定値の比(大きい方 / 小さい方)
+    pub ratio: f64,
+}
+
+impl Outlier {
+    pub const CODE: &'static str = "SL0202";
+}
+
+impl Display for Outlier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = tr!(
+            "'{}'の値{}は既定値{}から{:.1}倍離れています。桁を間違えていないか確認してください。",
+            "the value {1} of '{0}' is {3:.1} times away from the default {2}. Check the number of digits.",
+            self.key,
+            self.value,
+            self.default,
+            self.ratio
+        );
+        write!(f, "{}", message)
+    }
+}
+
+// 値と既定値が何倍離れているか。符号が異なる場合や、片方だけが0の場合は無限大
+fn deviation_ratio(value: f64, default: f64) -> f64 {
+    if value == default {
+        return 1.0;
+    }
+    if value == 0.0 || default == 0.0 || value.signum() != default.signum() {
+        return f64::INFINITY;
+    }
+    let (value, default) = (value.abs(), default.abs());
+    value.max(default) / value.min(default)
+}
+
+// 既定値の表にあるキーのうち、値が既定値からthreshold倍以上離れているものを列挙する。
+// 数値として解釈できない値は対象外
+pub fn find_outliers(
+    value: &IndexMap<String, SysctlValue>,
+    defaults: &HashMap<String, f64>,
+    threshold: f64,
+) -> Vec<Outlier> {
+    let mut outliers: Vec<Outlier> = value
+        .iter()
+        .filter_map(|(key, sysctl_value)| {
+            let default = *defaults.get(key)?;
+            let number = sysctl_value.value.parse::<f64>().ok()?;
+            let ratio = deviation_ratio(number, default);
+            (ratio >= threshold).then(|| Outlier {
+                key: key.to_owned(),
+                value: number,
+                default,
+                ratio,
+            })
+        })
+        .collect();
+    outliers.sort_by(|a, b| a.key.cmp(&b.key));
+    outliers
+}
+
+// スキーマのキーのうち、設定ファイルで明示的に設定されているものの割合
+#[derive(Debug, PartialEq)]
+pub struct Coverage {
+    pub covered: usize,
+    pub total: usize,
+}
+
+impl Coverage {
+    // スキーマにキーが無い場合は100%とする
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            return 100.0;
+        }
+        self.covered as f64 * 100.0 / self.total as f64
+    }
+}
+
+impl Display for Coverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = tr!(
+            "スキーマのキー{}件のうち{}件({:.1}%)が設定されています。",
+            "{1} of {0} schema keys ({2:.1}%) are set.",
+            self.total,
+            self.covered,
+            self.percent()
+        );
+        write!(f, "{}", message)
+    }
+}
+
+// readonlyのキーは設定しないのが正しいため、対象から除く。省略できるキーと、数が決まらないグロブのエントリも除く
+pub fn schema_coverage(value: &IndexMap<String, SysctlValue>, schema: &Schema) -> Coverage {
+    let keys: Vec<&String> = schema
+        .entries
+        .iter()
+        .filter(|entry| !entry.readonly && !entry.optional && !entry.is_glob())
+        .map(|entry| &entry.name)
+        .collect();
+    Coverage {
+        covered: keys.iter().filter(|key| value.contains_key(**key)).count(),
+        total: keys.len(),
+    }
+}
+
+// %dependsで依存しているキーが、明示的に無効(0やfalseなど)にされている
+#[derive(Debug, PartialEq)]
+pub struct DependencyDisabled {
+    pub key: String,
+    pub dependency: String,
+}
+
+impl DependencyDisabled {
+    pub const CODE: &'static str = "SL0203";
+}
+
+impl Display for DependencyDisabled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = tr!(
+            "'{}'が設定されていますが、依存先の'{}'が無効になっているため効果がありません。",
+            "'{}' is set, but has no effect because its dependency '{}' is disabled.",
+            self.key,
+            self.dependency
+        );
+        write!(f, "{}", message)
+    }
+}
+
+// 設定されているキーのうち、依存先が偽の値に設定されているものを列挙する。
+// 依存先が設定されていない場合は、検証でMissingDependencyとして扱うため対象外
+// 偽の表記はスキーマの%boolを優先し、読めなければ既定の表記として読む
+pub fn find_disabled_dependencies(
+    value: &IndexMap<String, SysctlValue>,
+    schema: &Schema,
+) -> Vec<DependencyDisabled> {
+    schema
+        .dependencies
+        .iter()
+        .filter(|(key, dependency)| {
+            value.contains_key(key)
+                && value.get(dependency).and_then(|sysctl_value| {
+                    schema
+                        .parse_bool(&sysctl_value.value)
+                        .or_else(|| parse_bool(&sysctl_value.value))
+                }) == Some(false)
+        })
+        .map(|(key, dependency)| DependencyDisabled {
+            key: key.to_owned(),
+            dependency: dependency.to_owned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_schema, parse_sysctl};
+
+    #[test]
+    fn test_ignore_error_keys() {
+        let value = parse_sysctl("-b.key = 1\na.key = 2\n-a.other = 3\nc.key = 4\n").unwrap();
+        assert_eq!(ignore_error_keys(&value), vec!["a.other", "b.key"]);
+        assert!(ignore_error_keys(&parse_sysctl("a = 1").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_find_outliers() {
+        let defaults: HashMap<String, f64> = [
+            ("vm.swappiness".to_owned(), 60.0),
+            ("net.core.somaxconn".to_owned(), 4096.0),
+            ("vm.overcommit_memory".to_owned(), 0.0),
+        ]
+        .into_iter()
+        .collect();
+        let outliers = |input: &str| {
+            find_outliers(&parse_sysctl(input).unwrap(), &defaults, 10.0)
+                .into_iter()
+                .map(|outlier| outlier.key)
+                .collect::<Vec<_>>()
+        };
+
+        // 10倍離れている
+        assert_eq!(outliers("vm.swappiness = 600"), vec!["vm.swappiness"]);
+        assert_eq!(
+            outliers("net.core.somaxconn = 40"),
+            vec!["net.core.somaxconn"]
+        );
+        // 多少の変更は問題ない
+        assert!(outliers("vm.swappiness = 10\nnet.core.somaxconn = 8192").is_empty());
+        // 既定値が0のキーを変更した場合
+        assert_eq!(
+            outliers("vm.overcommit_memory = 1"),
+            vec!["vm.overcommit_memory"]
+        );
+        // 表に無いキーや、数値でない値は対象外
+        assert!(outliers("kernel.pid_max = 1\nvm.swappiness = many").is_empty());
+    }
+
+    #[test]
+    fn test_schema_coverage() {
+        let schema =
+            parse_schema("a: bool\nb: bool\nc: number\nd: string\ne: string readonly\n").unwrap();
+        let coverage = |input: &str| schema_coverage(&parse_sysctl(input).unwrap(), &schema);
+
+        assert_eq!(
+            coverage("a = 1\nb = 0\nx = 1"),
+            Coverage {
+                covered: 2,
+                total: 4
+            }
+        );
+        assert_eq!(coverage("a = 1\nb = 0").percent(), 50.0);
+        assert_eq!(coverage("a = 1\nb = 0\nc = 1\nd = x").percent(), 100.0);
+        assert_eq!(coverage("").percent(), 0.0);
+        assert_eq!(
+            schema_coverage(&IndexMap::new(), &Schema::default()).percent(),
+            100.0
+        );
+
+        // 省略できるキーは数えない
+        let schema = parse_schema("a: bool\nb: bool\nc?: bool\n").unwrap();
+        assert_eq!(
+            schema_coverage(&parse_sysctl("a = 1").unwrap(), &schema),
+            Coverage {
+                covered: 1,
+                total: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_find_disabled_dependencies() {
+        let schema = parse_schema("a.key: bool\nb.key: number\n%depends b.key a.key\n").unwrap();
+        let disabled =
+            |input: &str| find_disabled_dependencies(&parse_sysctl(input).unwrap(), &schema);
+
+        assert_eq!(
+            disabled("a.key = 0\nb.key = 5"),
+            vec![DependencyDisabled {
+                key: "b.key".to_owned(),
+                dependency: "a.key".to_owned(),
+            }]
+        );
+        assert_eq!(disabled("a.key = false\nb.key = 5").len(), 1);
+        assert!(disabled("a.key = 1\nb.key = 5").is_empty());
+        // 依存元が設定されていなければ問題ない
+        assert!(disabled("a.key = 0").is_empty());
+        // 依存先が無い場合は検証側で報告する
+        assert!(disabled("b.key = 5").is_empty());
+
+        // %boolの偽の表記も無効として扱う
+        let schema = parse_schema(
+            "%bool disabled/enabled\na.key: bool\nb.key: number\n%depends b.key a.key\n",
+        )
+        .unwrap();
+        let disabled =
+            |input: &str| find_disabled_dependencies(&parse_sysctl(input).unwrap(), &schema);
+        assert_eq!(disabled("a.key = disabled\nb.key = 5").len(), 1);
+        assert!(disabled("a.key = enabled\nb.key = 5").is_empty());
+    }
+}
@@ -0,0 +1,344 @@
+use indexmap::IndexMap;
+use std::fmt::Display;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::key::{normalize_key, KeyStyle};
+use crate::tr;
+use crate::types::SysctlValue;
+
+// キーに対応する /proc/sys 以下のファイルパス
+// 名前の中の . はそのまま残すため、/ 区切りに書き直してからパスにする
+// 例) net.ipv4.ip_forward -> <root>/proc/sys/net/ipv4/ip_forward
+//     net.ipv4.conf.eth0/100.forwarding -> <root>/proc/sys/net/ipv4/conf/eth0.100/forwarding
+pub fn key_to_path(root: &Path, key: &str) -> PathBuf {
+    root.join("proc/sys")
+        .join(normalize_key(key, KeyStyle::Slashes).as_ref())
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ApplyOutcome {
+    // 書き込みに成功したキー
+    pub applied: Vec<String>,
+    // 失敗したが、-が付いていたため無視したキー
+    pub ignored_failures: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct ApplyError {
+    pub key: String,
+    pub source: io::Error,
+    // 失敗前に書き込み、元の値に戻したキー
+    pub rolled_back: Vec<String>,
+    // 元の値に戻せなかったキー
+    pub rollback_failures: Vec<String>,
+}
+
+impl Display for ApplyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = tr!(
+            "'{}'の書き込みに失敗しました",
+            "failed to write '{}'",
+            self.key
+        );
+        write!(f, "{}: {}", message, self.source)
+    }
+}
+
+// 反映すると危険な設定
+struct DangerousKey {
+    key: &'static str,
+    // 値が危険かどうか
+    matches: fn(&str) -> bool,
+    // 現在の言語での理由
+    reason: fn() -> String,
+}
+
+const DANGEROUS_KEYS: &[DangerousKey] = &[
+    DangerousKey {
+        key: "kernel.randomize_va_space",
+        matches: |value| value == "0",
+        reason: || {
+            tr!(
+                "ASLRが無効になり、メモリ破壊の脆弱性が悪用されやすくなります",
+                "ASLR is disabled, making memory corruption bugs easier to exploit"
+            )
+        },
+    },
+    DangerousKey {
+        key: "kernel.core_pattern",
+        matches: |value| value.starts_with('|'),
+        reason: || {
+            tr!(
+                "コアダンプがroot権限で任意のプログラムに渡されます",
+                "core dumps are piped to an arbitrary program as root"
+            )
+        },
+    },
+    DangerousKey {
+        key: "kernel.modules_disabled",
+        matches: |value| value == "1",
+        reason: || {
+            tr!(
+                "再起動するまでカーネルモジュールを読み込めなくなります",
+                "kernel modules cannot be loaded until the next reboot"
+            )
+        },
+    },
+    DangerousKey {
+        key: "kernel.sysrq",
+        matches: |value| value == "1",
+        reason: || {
+            tr!(
+                "SysRqの全機能が有効になり、コンソールから再起動などが可能になります",
+                "all SysRq functions are enabled, allowing a reboot and more from the console"
+            )
+        },
+    },
+];
+
+#[derive(Debug, PartialEq)]
+pub struct DangerousSetting {
+    pub key: String,
+    pub value: String,
+    pub reason: String,
+}
+
+impl Display for DangerousSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = tr!(
+            "'{} = {}'は危険です",
+            "'{} = {}' is dangerous",
+            self.key,
+            self.value
+        );
+        write!(f, "{}: {}", message, self.reason)
+    }
+}
+
+// 危険な設定を探す
+pub fn find_dangerous(value: &IndexMap<String, SysctlValue>) -> Vec<DangerousSetting> {
+    let mut settings: Vec<DangerousSetting> = DANGEROUS_KEYS
+        .iter()
+        .filter_map(|dangerous| {
+            let sysctl_value = value.get(dangerous.key)?;
+            (dangerous.matches)(&sysctl_value.value).then(|| DangerousSetting {
+                key: dangerous.key.to_owned(),
+                value: sysctl_value.value.clone(),
+                reason: (dangerous.reason)(),
+            })
+        })
+        .collect();
+    settings.sort_by(|a, b| a.key.cmp(&b.key));
+    settings
+}
+
+// 危険な設定が含まれている場合、許可されていなければ反映を拒否する
+pub fn check_dangerous(
+    value: &IndexMap<String, SysctlValue>,
+    allow_dangerous: bool,
+) -> Result<(), Vec<DangerousSetting>> {
+    let settings = find_dangerous(value);
+    if settings.is_empty() || allow_dangerous {
+        Ok(())
+    } else {
+        Err(settings)
+    }
+}
+
+// 危険な設定の確認も含めた反映の失敗
+#[derive(Debug)]
+pub enum ApplyFailure {
+    // 許可されていない危険な設定があり、何も書き込まなかった
+    Dangerous(Vec<DangerousSetting>),
+    Write(ApplyError),
+}
+
+// 危険な設定を確かめてから反映する。許可されていない危険な設定があれば、1つも書き込まない
+pub fn apply_checked(
+    value: &IndexMap<String, SysctlValue>,
+    root: &Path,
+    allow_dangerous: bool,
+) -> Result<ApplyOutcome, ApplyFailure> {
+    check_dangerous(value, allow_dangerous).map_err(ApplyFailure::Dangerous)?;
+    apply(value, root).map_err(ApplyFailure::Write)
+}
+
+fn write_value(path: &Path, value: &str) -> io::Result<()> {
+    // /proc/sys に存在しないキーを作ってしまわないよう、createはしない
+    let mut file = OpenOptions::new().write(true).truncate(true).open(path)?;
+    file.write_all(value.as_bytes())
+}
+
+// 設定をカーネルに反映する。
+// -の付いていないキーの書き込みに失敗した場合は、それまでに書き込んだキーを
+// 書き込み前の値に戻してからエラーを返す
+pub fn apply(
+    value: &IndexMap<String, SysctlValue>,
+    root: &Path,
+) -> Result<ApplyOutcome, ApplyError> {
+    let mut keys: Vec<&String> = value.keys().collect();
+    keys.sort();
+
+    let mut outcome = ApplyOutcome::default();
+    // 書き込んだキーと、書き込み前の値
+    let mut snapshots: Vec<(&String, PathBuf, String)> = Vec::new();
+    for key in keys {
+        let sysctl_value = &value[key];
+        let path = key_to_path(root, key);
+        let result = fs::read_to_string(&path).and_then(|previous| {
+            write_value(&path, &sysctl_value.value)?;
+            Ok(previous)
+        });
+        match result {
+            Ok(previous) => {
+                snapshots.push((key, path, previous));
+                outcome.applied.push(key.to_owned());
+            }
+            Err(_) if sysctl_value.ignore_error => {
+                outcome.ignored_failures.push(key.to_owned());
+            }
+            Err(source) => {
+                let mut rolled_back = Vec::new();
+                let mut rollback_failures = Vec::new();
+                for (key, path, previous) in snapshots.into_iter().rev() {
+                    match write_value(&path, &previous) {
+                        Ok(()) => rolled_back.push(key.to_owned()),
+                        Err(_) => rollback_failures.push(key.to_owned()),
+                    }
+                }
+                return Err(ApplyError {
+                    key: key.to_owned(),
+                    source,
+                    rolled_back,
+                    rollback_failures,
+                });
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_sysctl;
+
+    fn setup_root(keys: &[(&str, &str)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (key, value) in keys {
+            let path = key_to_path(root.path(), key);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        }
+        root
+    }
+
+    fn read(root: &Path, key: &str) -> String {
+        fs::read_to_string(key_to_path(root, key)).unwrap()
+    }
+
+    #[test]
+    fn test_key_to_path() {
+        assert_eq!(
+            key_to_path(Path::new("/"), "net.ipv4.ip_forward"),
+            PathBuf::from("/proc/sys/net/ipv4/ip_forward")
+        );
+        // VLANのインターフェース名のような、名前の中の . は区切りにしない
+        assert_eq!(
+            key_to_path(Path::new("/"), "net.ipv4.conf.eth0/100.forwarding"),
+            PathBuf::from("/proc/sys/net/ipv4/conf/eth0.100/forwarding")
+        );
+    }
+
+    #[test]
+    fn test_find_dangerous() {
+        let value = parse_sysctl(
+            "kernel.core_pattern = |/usr/bin/handler %p\nkernel.randomize_va_space = 0\nkernel.sysrq = 0\n",
+        )
+        .unwrap();
+        let keys: Vec<String> = find_dangerous(&value).into_iter().map(|s| s.key).collect();
+        assert_eq!(
+            keys,
+            vec!["kernel.core_pattern", "kernel.randomize_va_space"]
+        );
+
+        // 危険でない値は対象外
+        let value =
+            parse_sysctl("kernel.core_pattern = core\nkernel.randomize_va_space = 2\n").unwrap();
+        assert!(find_dangerous(&value).is_empty());
+    }
+
+    #[test]
+    fn test_dangerous_key_blocked_without_flag() {
+        let root = setup_root(&[("kernel.randomize_va_space", "2"), ("a.key", "0")]);
+        let value = parse_sysctl("kernel.randomize_va_space = 0\na.key = 1\n").unwrap();
+        let settings = match apply_checked(&value, root.path(), false) {
+            Err(ApplyFailure::Dangerous(settings)) => settings,
+            result => panic!("{:?}", result),
+        };
+        assert_eq!(settings.len(), 1);
+        assert_eq!(
+            settings[0].to_string(),
+            "'kernel.randomize_va_space = 0'は危険です: ASLRが無効になり、メモリ破壊の脆弱性が悪用されやすくなります"
+        );
+        // 拒否されたので、危険ではないキーも含めて書き込まれていない
+        assert_eq!(read(root.path(), "kernel.randomize_va_space"), "2");
+        assert_eq!(read(root.path(), "a.key"), "0");
+    }
+
+    #[test]
+    fn test_dangerous_key_allowed_with_flag() {
+        let root = setup_root(&[("kernel.randomize_va_space", "2")]);
+        let value = parse_sysctl("kernel.randomize_va_space = 0\n").unwrap();
+        apply_checked(&value, root.path(), true).unwrap();
+        assert_eq!(read(root.path(), "kernel.randomize_va_space"), "0");
+    }
+
+    #[test]
+    fn test_apply_success() {
+        let root = setup_root(&[("a.key", "0"), ("b.key", "0")]);
+        let value = parse_sysctl("a.key = 1\nb.key = 2\n").unwrap();
+        assert_eq!(
+            apply(&value, root.path()).unwrap(),
+            ApplyOutcome {
+                applied: vec!["a.key".to_owned(), "b.key".to_owned()],
+                ignored_failures: vec![],
+            }
+        );
+        assert_eq!(read(root.path(), "a.key"), "1");
+        assert_eq!(read(root.path(), "b.key"), "2");
+    }
+
+    #[test]
+    fn test_apply_rolls_back_on_failure() {
+        // c.key は存在しないため書き込みに失敗する
+        let root = setup_root(&[("a.key", "0"), ("b.key", "0"), ("d.key", "0")]);
+        let value = parse_sysctl("a.key = 1\nb.key = 2\nc.key = 3\nd.key = 4\n").unwrap();
+        let error = apply(&value, root.path()).unwrap_err();
+        assert_eq!(error.key, "c.key");
+        assert_eq!(error.rolled_back, vec!["b.key", "a.key"]);
+        assert!(error.rollback_failures.is_empty());
+        assert_eq!(read(root.path(), "a.key"), "0");
+        assert_eq!(read(root.path(), "b.key"), "0");
+        // 失敗より後のキーは書き込まれない
+        assert_eq!(read(root.path(), "d.key"), "0");
+    }
+
+    #[test]
+    fn test_apply_ignore_error_does_not_roll_back() {
+        let root = setup_root(&[("a.key", "0"), ("d.key", "0")]);
+        let value = parse_sysctl("a.key = 1\n-c.key = 3\nd.key = 4\n").unwrap();
+        assert_eq!(
+            apply(&value, root.path()).unwrap(),
+            ApplyOutcome {
+                applied: vec!["a.key".to_owned(), "d.key".to_owned()],
+                ignored_failures: vec!["c.key".to_owned()],
+            }
+        );
+        assert_eq!(read(root.path(), "a.key"), "1");
+        assert_eq!(read(root.path(), "d.key"), "4");
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+// 既存の違反を記録したベースライン。記録済みの違反ではエラーにしない
+// 古い設定にも、すべてを直す前から検証を導入できるようにする
+// 違反はファイル、エラーコード、キーの組で見分ける。行番号は編集でずれ、メッセージは言語で変わるため使わない
+#[derive(Debug, Default, PartialEq)]
+pub struct Baseline {
+    entries: BTreeSet<BaselineEntry>,
+    // ファイルがまだ無く、今回の違反をすべて記録する
+    recording: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    pub file: String,
+    pub code: String,
+    pub key: Option<String>,
+}
+
+impl Baseline {
+    // 今回の違反を記録するための、空のベースライン
+    pub fn recording() -> Self {
+        Baseline {
+            entries: BTreeSet::new(),
+            recording: true,
+        }
+    }
+
+    pub fn parse(input: &str) -> serde_json::Result<Self> {
+        Ok(Baseline {
+            entries: serde_json::from_str(input)?,
+            recording: false,
+        })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.entries)
+            .expect("ベースラインのシリアライズに失敗しました。")
+    }
+
+    // ベースラインにある違反を取り除き、新しい違反だけを残す
+    // 記録中なら、すべての違反を記録してから取り除く
+    // idは違反の(エラーコード, キー)を返す
+    pub fn suppress<T>(
+        &mut self,
+        file: &str,
+        violations: &mut Vec<T>,
+        id: impl Fn(&T) -> (&'static str, Option<String>),
+    ) {
+        let entry = |violation: &T| {
+            let (code, key) = id(violation);
+            BaselineEntry {
+                file: file.to_owned(),
+                code: code.to_owned(),
+                key,
+            }
+        };
+        if self.recording {
+            self.entries.extend(violations.iter().map(entry));
+            violations.clear();
+        } else {
+            violations.retain(|violation| !self.entries.contains(&entry(violation)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ValidationError;
+
+    fn id(error: &ValidationError) -> (&'static str, Option<String>) {
+        (error.code(), Some(error.key_name().to_owned()))
+    }
+
+    #[test]
+    fn test_suppress() {
+        let mut baseline = Baseline::recording();
+        let mut errors = vec![ValidationError::MissingKey("a".to_owned())];
+        baseline.suppress("a.conf", &mut errors, id);
+        assert!(errors.is_empty());
+        assert_eq!(baseline.len(), 1);
+
+        // 記録したものを読み込むと、記録済みの違反だけを取り除く
+        let mut baseline = Baseline::parse(&baseline.to_json()).unwrap();
+        assert!(!baseline.is_recording());
+        let mut errors = vec![
+            ValidationError::MissingKey("a".to_owned()),
+            ValidationError::MissingKey("b".to_owned()),
+        ];
+        baseline.suppress("a.conf", &mut errors, id);
+        assert_eq!(errors, vec![ValidationError::MissingKey("b".to_owned())]);
+
+        // 別のファイルの同じ違反は取り除かない
+        let mut errors = vec![ValidationError::MissingKey("a".to_owned())];
+        baseline.suppress("b.conf", &mut errors, id);
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -0,0 +1,69 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::types::{Schema, SysctlValue};
+
+// 設定とそのスキーマを1つにまとめたもの。
+// オフラインの環境でも、このファイル1つで検証できる
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Bundle {
+    pub schema: Schema,
+    // 出力が安定するよう、キーの順に並べる
+    pub config: BTreeMap<String, SysctlValue>,
+}
+
+pub fn bundle(config: &IndexMap<String, SysctlValue>, schema: &Schema) -> String {
+    let bundle = Bundle {
+        schema: schema.clone(),
+        config: config
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    };
+    serde_json::to_string_pretty(&bundle).expect("バンドルのシリアライズに失敗しました。")
+}
+
+pub fn load_bundle(input: &str) -> serde_json::Result<(IndexMap<String, SysctlValue>, Schema)> {
+    let bundle: Bundle = serde_json::from_str(input)?;
+    Ok((bundle.config.into_iter().collect(), bundle.schema))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_schema, parse_sysctl};
+    use crate::validation::{validate_by_schema, ValidationOptions};
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let config =
+            parse_sysctl("port = 3910\n-debug = true\nlog.file = /var/log/a.log\n").unwrap();
+        let schema =
+            parse_schema("port: number\ndebug: bool\nlog.file: path\n%depends port debug\n")
+                .unwrap();
+
+        let json = bundle(&config, &schema);
+        let (loaded_config, loaded_schema) = load_bundle(&json).unwrap();
+        assert_eq!(loaded_config, config);
+        assert_eq!(loaded_schema, schema);
+
+        assert!(validate_by_schema(
+            &loaded_config,
+            &loaded_schema,
+            &ValidationOptions::default()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_bundle_format() {
+        let config = parse_sysctl("port = 3910").unwrap();
+        let schema = parse_schema("port: number").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&bundle(&config, &schema)).unwrap();
+        assert_eq!(json["config"]["port"]["value"], "3910");
+        assert_eq!(json["schema"]["entries"][0]["name"], "port");
+        assert_eq!(json["schema"]["entries"][0]["schema_type"], "number");
+        assert!(load_bundle("{}").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use indexmap::IndexMap;
+
+use crate::report::{Diagnostic, Report};
+
+// 検証結果をCheckstyleのXMLで出力する。多くのコード品質のダッシュボードで取り込める
+// ファイルごとに<file>を、診断ごとに<error>を出力する。sourceにはエラーコードを入れる
+// 取り込んだファイルで設定された値の診断は、そのファイルの<file>にまとめる
+pub fn to_checkstyle(reports: &[Report]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<checkstyle version=\"4.3\">\n");
+    for report in reports {
+        let mut files: IndexMap<&str, Vec<&Diagnostic>> = IndexMap::new();
+        files.insert(&report.file, vec![]);
+        for diagnostic in &report.diagnostics {
+            files
+                .entry(diagnostic.file_or(&report.file))
+                .or_default()
+                .push(diagnostic);
+        }
+        for (file, diagnostics) in files {
+            write_file(&mut xml, file, &diagnostics);
+        }
+    }
+    xml.push_str("</checkstyle>\n");
+    xml
+}
+
+fn write_file(xml: &mut String, file: &str, diagnostics: &[&Diagnostic]) {
+    xml.push_str(&format!("  <file name=\"{}\">\n", escape(file)));
+    for diagnostic in diagnostics {
+        // 足りないキーなど、行に結びつかない診断ではline属性を付けない
+        let line = diagnostic
+            .line
+            .map(|line| format!(" line=\"{}\"", line))
+            .unwrap_or_default();
+        xml.push_str(&format!(
+            "    <error{} severity=\"{}\" message=\"{}\" source=\"{}\"/>\n",
+            line,
+            diagnostic.severity,
+            escape(&diagnostic.message),
+            diagnostic.code
+        ));
+    }
+    xml.push_str("  </file>\n");
+}
+
+// 属性値として書けるよう、XMLの特殊文字をエスケープする
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+        .replace('\n', "&#10;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_checkstyle() {
+        let reports = vec![
+            Report::new(
+                "a&b.conf",
+                vec![
+                    Diagnostic {
+                        severity: "error",
+                        code: "SL0003",
+                        kind: "wrong_type",
+                        key: Some("port".to_owned()),
+                        file: None,
+                        line: Some(2),
+                        expected: None,
+                        actual: None,
+                        comment: None,
+                        message: "'port' <number>".to_owned(),
+                    },
+                    Diagnostic {
+                        severity: "error",
+                        code: "SL0001",
+                        kind: "missing_key",
+                        key: Some("debug".to_owned()),
+                        file: None,
+                        line: None,
+                        expected: None,
+                        actual: None,
+                        comment: None,
+                        message: "missing".to_owned(),
+                    },
+                ],
+            ),
+            Report::new("ok.conf", vec![]),
+        ];
+        assert_eq!(
+            to_checkstyle(&reports),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <checkstyle version=\"4.3\">\n  \
+             <file name=\"a&amp;b.conf\">\n    \
+             <error line=\"2\" severity=\"error\" message=\"&apos;port&apos; &lt;number&gt;\" source=\"SL0003\"/>\n    \
+             <error severity=\"error\" message=\"missing\" source=\"SL0001\"/>\n  \
+             </file>\n  \
+             <file name=\"ok.conf\">\n  \
+             </file>\n\
+             </checkstyle>\n"
+        );
+    }
+}
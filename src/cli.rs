@@ -0,0 +1,518 @@
+use clap::{Args, Parser, Subcommand};
+use load_sysctl::conditional::KernelVersion;
+use load_sysctl::dialect::Dialect;
+use load_sysctl::format::BoolStyle;
+use load_sysctl::i18n::Lang;
+use load_sysctl::key::KeyStyle;
+use load_sysctl::merge::CommentPolicy;
+use load_sysctl::parser::{Compat, ParseOptions, DEFAULT_MAX_LINE_LENGTH};
+use load_sysctl::policy::Severity;
+use load_sysctl::report::FailOn;
+use load_sysctl::style::ColorChoice;
+use load_sysctl::tr;
+use load_sysctl::validation::ValidationOptions;
+
+// コマンドライン引数
+// サブコマンドを付けずに <入力ファイル> [オプション] とする従来の使い方もそのまま使える
+// 例) load_sysctl validate a.conf --pretty は load_sysctl a.conf --validate --pretty と同じ
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "sysctl.conf形式のファイルをパースし、スキーマで検証する",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// 読み込むファイル。ディレクトリを指定すると、その中の *.conf をすべて読み込む。- で標準入力を読み込む
+    pub files: Vec<String>,
+    #[command(flatten)]
+    pub options: Options,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 設定ファイルをパースして、読み込んだデータを表示する
+    Parse(FileArgs),
+    /// スキーマで設定ファイルを検証する
+    Validate(FileArgs),
+    /// 設定ファイルをsysctl.conf形式に整形して表示する
+    Fmt(FileArgs),
+    /// 2つのスキーマファイルの差分を表示する
+    Diff {
+        /// 古いスキーマファイル
+        old: String,
+        /// 新しいスキーマファイル
+        new: String,
+        #[command(flatten)]
+        options: Options,
+    },
+    /// 設定を /proc/sys に書き込んで反映する
+    Apply(FileArgs),
+    /// スキーマの内容を一覧で表示する
+    Explain(FileArgs),
+    /// 設定ファイルに別のファイルを順に重ねて、マージした結果を表示する
+    Merge {
+        /// 元の設定ファイル
+        file: String,
+        /// 重ねるファイル。後のファイルの値が優先される
+        #[arg(required = true)]
+        layers: Vec<String>,
+        #[command(flatten)]
+        options: Options,
+    },
+    /// 入力した行をその場でパースし、スキーマファイルで検証する
+    Repl(FileArgs),
+    /// 標準入力から追記されていく設定を、スキーマファイルで1行ずつ検証する
+    Stream(FileArgs),
+    /// 設定内容のフィンガープリント(SHA-256)を表示する
+    Fingerprint(FileArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct FileArgs {
+    /// 読み込むファイル。ディレクトリを指定すると、その中の *.conf をすべて読み込む。- で標準入力を読み込む
+    #[arg(required_unless_present = "staged")]
+    pub files: Vec<String>,
+    #[command(flatten)]
+    pub options: Options,
+}
+
+// 従来のフラグ。サブコマンドでも同じものを使える
+#[derive(Debug, Default, Args)]
+pub struct Options {
+    /// メッセージの言語 (ja, en)。指定が無ければ環境変数のロケールに従う
+    #[arg(long, value_parser = parse_lang)]
+    pub lang: Option<Lang>,
+    /// 色付けするか (auto, always, never)。auto では端末に出力し、NO_COLOR が無い場合だけ色を付ける
+    #[arg(long, value_parser = parse_color_choice, default_value = "auto")]
+    pub color: ColorChoice,
+    /// 開いたファイルやパース・検証の件数と時間をログに出す
+    #[arg(long)]
+    pub verbose: bool,
+    /// キーごとの処理もログに出す
+    #[arg(long)]
+    pub trace: bool,
+    /// スキーマで検証する。-v は以前からの --validate の短縮形で、ログの詳しさは --verbose と --trace で選ぶ
+    #[arg(long, short = 'v')]
+    pub validate: bool,
+    /// 検証を有効にし、結果をJSONレポートとしてのみ出力する
+    #[arg(long)]
+    pub ci: bool,
+    /// <入力ファイル>.schema の代わりに使うスキーマファイル。複数回指定すると、後のファイルの定義を重ねる
+    #[arg(long = "schema", value_name = "PATH", conflicts_with = "annotations")]
+    pub schemas: Vec<String>,
+    /// バージョンごとのスキーマを version=path の形式で指定する
+    #[arg(long = "target-schema", value_name = "VERSION=PATH")]
+    pub target_schemas: Vec<String>,
+    /// どのバージョンのスキーマで検証するか
+    #[arg(long)]
+    pub target: Option<String>,
+    /// キーの直前の # @type 注釈からスキーマを作って検証する
+    #[arg(long)]
+    pub annotations: bool,
+    /// パースと検証にかかった時間を表示する
+    #[arg(long)]
+    pub profile: bool,
+    /// 設定内容のフィンガープリントだけを出力する
+    #[arg(long)]
+    pub fingerprint: bool,
+    /// 反映の失敗が無視されるキーの一覧を出力する
+    #[arg(long = "list-ignored")]
+    pub list_ignored: bool,
+    /// 各キーのファイル中のバイト範囲を出力する
+    #[arg(long = "key-spans")]
+    pub key_spans: bool,
+    /// エラーを該当する行とヒントを添えて表示する
+    #[arg(long)]
+    pub pretty: bool,
+    /// 検証に成功したら、設定とスキーマをまとめたJSONを出力する
+    #[arg(long)]
+    pub bundle: bool,
+    /// 設定を /proc/sys に書き込んで反映する
+    #[arg(long)]
+    pub apply: bool,
+    /// 危険な設定が含まれていても反映する
+    #[arg(long = "allow-dangerous")]
+    pub allow_dangerous: bool,
+    /// エラーになったキーとその問題だけを出力する。検証も有効にする
+    #[arg(long = "failed-only")]
+    pub failed_only: bool,
+    /// procpsのsysctlと同じ規則でパースする。--compat procps と同じ
+    #[arg(long = "sysctl-compat", conflicts_with = "compat")]
+    pub sysctl_compat: bool,
+    /// procps か busybox のsysctl -p と同じ規則でパースする。読み込めない行は警告を出して読み飛ばす
+    #[arg(long, value_parser = parse_compat)]
+    pub compat: Option<Compat>,
+    /// sysctl(8)と同じく、文法に誤りのある行を警告にして読み飛ばし、残りの行を読み込んで検証する
+    #[arg(long)]
+    pub lenient: bool,
+    /// スキーマのキーのうち設定されているものの割合の下限(%)
+    #[arg(long = "min-coverage", value_name = "PERCENT")]
+    pub min_coverage: Option<f64>,
+    /// スキーマを探さずにパースだけを行う
+    #[arg(long = "no-schema")]
+    pub no_schema: bool,
+    /// path(exists)の存在確認を省く
+    #[arg(long)]
+    pub offline: bool,
+    /// (exists)の無いものも含めて、すべてのpathのファイルが存在するか確認する
+    #[arg(long = "check-paths", conflicts_with = "offline")]
+    pub check_paths: bool,
+    /// enumの値を前後の空白も含めて完全一致で比べる
+    #[arg(long = "exact-enum")]
+    pub exact_enum: bool,
+    /// sysctl.conf形式に整形して出力する
+    #[arg(long)]
+    pub fmt: bool,
+    /// スキーマに既定値のあるキーが設定ファイルに無ければ、既定値の行をファイルの末尾に追記する
+    #[arg(long, conflicts_with = "staged")]
+    pub fix: bool,
+    /// 読み込んだデータを標準出力の代わりにファイルに書き込む
+    #[arg(long, value_name = "FILE", conflicts_with = "no_print")]
+    pub output: Option<String>,
+    /// 読み込んだデータを出力しない。終了コードは検証の結果に従う
+    #[arg(long = "no-print")]
+    pub no_print: bool,
+    /// 成功した場合は何も出力しない。エラーは表示する
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// 記録済みの違反をエラーにしない。ファイルが無ければ、今回の違反を記録する
+    #[arg(long, value_name = "FILE")]
+    pub baseline: Option<String>,
+    /// どの重大度の診断で失敗にするか (error, warning, never)
+    #[arg(long = "fail-on", value_parser = parse_fail_on, default_value = "error")]
+    pub fail_on: FailOn,
+    /// gitでステージされた *.conf を、インデックスにある内容で検証する。ファイルを指定すればその中だけにする
+    #[arg(long)]
+    pub staged: bool,
+    /// 値の後ろの、空白に続く # や ; からをコメントとして値から除く
+    #[arg(long = "inline-comments")]
+    pub inline_comments: bool,
+    /// include <パス> の行で指定されたファイルも、その位置で取り込む。パスには * と ? を使える
+    #[arg(long = "follow-includes")]
+    pub follow_includes: bool,
+    /// 値の中の ${NAME} を環境変数の値に置き換える。定義されていない変数はエラーになる
+    #[arg(long = "expand-env")]
+    pub expand_env: bool,
+    /// 値の中の {{ name }} を、YAMLの変数ファイルの値に置き換えてからパースする
+    #[arg(long, value_name = "FILE", conflicts_with = "follow_includes")]
+    pub vars: Option<String>,
+    /// #@if kernel >= 5.10 のブロックを、このバージョンのカーネルとして読み込むか決める。省略すると実行中のカーネル
+    #[arg(long = "kernel-version", value_name = "VERSION", value_parser = parse_kernel_version)]
+    pub kernel_version: Option<KernelVersion>,
+    /// linux か freebsd のsysctl.confの文法で読み込む。検証では、そのOSに無い名前空間のキーもエラーにする
+    #[arg(long, value_parser = parse_dialect)]
+    pub dialect: Option<Dialect>,
+    /// [net.ipv4] のような見出しを受け付け、続くキーの前に見出しの名前を付ける
+    #[arg(long)]
+    pub sections: bool,
+    /// 行の長さの上限(バイト数)。改行を除いてこの長さ以上の行は文法の誤りにする。0 なら制限しない
+    #[arg(long = "max-line-length", value_name = "BYTES", default_value_t = DEFAULT_MAX_LINE_LENGTH)]
+    pub max_line_length: usize,
+    /// 同じキーが複数回設定されている場合に、警告とエラーのどちらにするか (warning, error)
+    #[arg(long = "duplicate-keys", value_parser = parse_severity, default_value = "warning")]
+    pub duplicate_keys: Severity,
+    /// 表示するスキーマエラーと文法エラーの上限。超えた分は件数だけを表示する
+    #[arg(long = "max-errors", value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_errors: Option<u64>,
+    /// 整形時のboolの書き方 (kernel, words)。bool型のキーを知るため、スキーマを読み込む
+    #[arg(long = "bool-style", value_parser = parse_bool_style)]
+    pub bool_style: Option<BoolStyle>,
+    /// 出力するキーの区切り (dots, slashes)。/ 区切りで書かれたキーも、読み込むときは . 区切りに揃える
+    #[arg(long, value_parser = parse_key_style, default_value = "dots")]
+    pub normalize: KeyStyle,
+    /// 入力ファイルを古いスキーマとして、指定したスキーマとの差分を表示する
+    #[arg(long = "diff-schema", value_name = "NEW_SCHEMA")]
+    pub diff_schema: Option<String>,
+    /// 入力ファイルをスキーマとして、その内容を説明する
+    #[arg(long)]
+    pub explain: bool,
+    /// 出力形式。--explain では text か markdown、読み込んだデータでは text、json、yaml、toml のいずれか
+    /// sarif、github、json-report、checkstyle、tap では --ci と同じく検証し、結果をそれぞれの形式のレポートで出力する
+    #[arg(
+        long,
+        value_parser = [
+            "text", "markdown", "json", "yaml", "toml", "sarif", "github", "json-report",
+            "checkstyle", "tap",
+        ],
+        default_value = "text"
+    )]
+    pub format: String,
+    /// 入力ファイルをスキーマとして、標準入力の行をその場でパース・検証する
+    #[arg(long)]
+    pub repl: bool,
+    /// 入力ファイルをスキーマとして、標準入力から追記される設定を1行ずつ検証する
+    #[arg(long)]
+    pub stream: bool,
+    /// 入力ファイルを --bundle で出力したJSONとして検証する
+    #[arg(long = "from-bundle")]
+    pub from_bundle: bool,
+    /// 入力ファイルに重ねるファイル。複数回指定できる
+    #[arg(long = "merge", value_name = "FILE")]
+    pub merge_paths: Vec<String>,
+    /// マージ時のコメントの扱い (winner, concat)
+    #[arg(long = "merge-comments", value_parser = parse_comment_policy)]
+    pub merge_comments: Option<CommentPolicy>,
+    /// マージした結果の各キーの前に、値を設定したファイルと行、上書きした設定元をコメントで書く
+    #[arg(long)]
+    pub provenance: bool,
+    /// 許可するキーを1行に1つ書いたファイル
+    #[arg(long)]
+    pub allowlist: Option<String>,
+    /// ポリシーファイル
+    #[arg(long)]
+    pub policy: Option<String>,
+    /// 既定値をsysctl.confと同じ形式で書いたファイル
+    #[arg(long)]
+    pub defaults: Option<String>,
+    /// 既定値から何倍離れたら警告するか
+    #[arg(long = "outlier-ratio", default_value_t = 10.0)]
+    pub outlier_ratio: f64,
+}
+
+impl Cli {
+    // サブコマンドを、従来の使い方での入力ファイルとフラグに読み替える
+    // サブコマンドもファイルも無ければNone
+    pub fn into_invocation(self) -> Option<(Vec<String>, Options)> {
+        let Some(command) = self.command else {
+            return (!self.files.is_empty() || self.options.staged)
+                .then(|| with_staged_default(self.files, self.options));
+        };
+        let (files, options) = match command {
+            Command::Parse(args) => (args.files, args.options),
+            Command::Validate(mut args) => {
+                args.options.validate = true;
+                (args.files, args.options)
+            }
+            Command::Fmt(mut args) => {
+                args.options.fmt = true;
+                (args.files, args.options)
+            }
+            Command::Diff {
+                old,
+                new,
+                mut options,
+            } => {
+                options.diff_schema = Some(new);
+                (vec![old], options)
+            }
+            Command::Apply(mut args) => {
+                args.options.apply = true;
+                (args.files, args.options)
+            }
+            Command::Explain(mut args) => {
+                args.options.explain = true;
+                (args.files, args.options)
+            }
+            Command::Merge {
+                file,
+                layers,
+                mut options,
+            } => {
+                options.merge_paths.extend(layers);
+                (vec![file], options)
+            }
+            Command::Repl(mut args) => {
+                args.options.repl = true;
+                (args.files, args.options)
+            }
+            Command::Stream(mut args) => {
+                args.options.stream = true;
+                (args.files, args.options)
+            }
+            Command::Fingerprint(mut args) => {
+                args.options.fingerprint = true;
+                (args.files, args.options)
+            }
+        };
+        Some(with_staged_default(files, options))
+    }
+}
+
+// --staged でファイルを指定しなければ、現在のディレクトリ以下のステージされたファイルを検証する
+fn with_staged_default(mut files: Vec<String>, options: Options) -> (Vec<String>, Options) {
+    if options.staged && files.is_empty() {
+        files.push(".".to_owned());
+    }
+    (files, options)
+}
+
+impl Options {
+    // 検証の結果を、ファイルごとのテキストではなくレポートにまとめて出力するか
+    // --ci と、レポートの形式を指定した --format では検証を有効にする
+    pub fn use_report(&self) -> bool {
+        self.ci
+            || matches!(
+                self.format.as_str(),
+                "sarif" | "github" | "json-report" | "checkstyle" | "tap"
+            )
+    }
+
+    // 対象のマシン以外で検証する場合に、--offline でpath(exists)の存在確認を省く
+    pub fn validation_options(&self) -> ValidationOptions {
+        ValidationOptions {
+            check_path_exists: !self.offline,
+            exact_enum: self.exact_enum,
+            check_all_paths: self.check_paths,
+        }
+    }
+
+    pub fn compat(&self) -> Option<Compat> {
+        self.compat.or(self.sysctl_compat.then_some(Compat::Procps))
+    }
+
+    pub fn parse_options(&self) -> ParseOptions {
+        let base = self.dialect.unwrap_or_default().parse_options();
+        ParseOptions {
+            inline_comments: self.inline_comments || base.inline_comments,
+            max_line_length: (self.max_line_length > 0).then_some(self.max_line_length),
+            sections: self.sections,
+            ..base
+        }
+    }
+}
+
+fn parse_lang(name: &str) -> Result<Lang, String> {
+    Lang::from_name(name).ok_or_else(|| tr!("ja か en を指定してください", "must be ja or en"))
+}
+
+fn parse_compat(name: &str) -> Result<Compat, String> {
+    Compat::from_name(name).ok_or_else(|| {
+        tr!(
+            "procps か busybox を指定してください",
+            "must be procps or busybox"
+        )
+    })
+}
+
+fn parse_dialect(name: &str) -> Result<Dialect, String> {
+    Dialect::from_name(name).ok_or_else(|| {
+        tr!(
+            "linux か freebsd を指定してください",
+            "must be linux or freebsd"
+        )
+    })
+}
+
+fn parse_kernel_version(input: &str) -> Result<KernelVersion, String> {
+    KernelVersion::parse(input).ok_or_else(|| {
+        tr!(
+            "5.10 のような . 区切りの数字を指定してください",
+            "must be numbers separated by dots, like 5.10"
+        )
+    })
+}
+
+fn parse_key_style(name: &str) -> Result<KeyStyle, String> {
+    KeyStyle::from_name(name).ok_or_else(|| {
+        tr!(
+            "dots か slashes を指定してください",
+            "must be dots or slashes"
+        )
+    })
+}
+
+fn parse_bool_style(name: &str) -> Result<BoolStyle, String> {
+    BoolStyle::from_name(name).ok_or_else(|| {
+        tr!(
+            "kernel か words を指定してください",
+            "must be kernel or words"
+        )
+    })
+}
+
+fn parse_color_choice(name: &str) -> Result<ColorChoice, String> {
+    ColorChoice::from_name(name).ok_or_else(|| {
+        tr!(
+            "auto か always か never を指定してください",
+            "must be auto, always or never"
+        )
+    })
+}
+
+fn parse_fail_on(name: &str) -> Result<FailOn, String> {
+    FailOn::from_name(name).ok_or_else(|| {
+        tr!(
+            "error か warning か never を指定してください",
+            "must be error, warning or never"
+        )
+    })
+}
+
+fn parse_severity(name: &str) -> Result<Severity, String> {
+    Severity::from_name(name).ok_or_else(|| {
+        tr!(
+            "warning か error を指定してください",
+            "must be warning or error"
+        )
+    })
+}
+
+fn parse_comment_policy(name: &str) -> Result<CommentPolicy, String> {
+    CommentPolicy::from_name(name).ok_or_else(|| {
+        tr!(
+            "winner か concat を指定してください",
+            "must be winner or concat"
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation(args: &[&str]) -> (Vec<String>, Options) {
+        Cli::try_parse_from(std::iter::once("load_sysctl").chain(args.iter().copied()))
+            .unwrap()
+            .into_invocation()
+            .unwrap()
+    }
+
+    #[test]
+    fn legacy_usage() {
+        let (files, options) =
+            invocation(&["a.conf", "--validate", "--merge", "b", "--merge", "c"]);
+        assert_eq!(files, vec!["a.conf"]);
+        assert!(options.validate);
+        assert_eq!(options.merge_paths, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn subcommands_set_flags() {
+        let (files, options) = invocation(&["validate", "a.conf", "sysctl.d", "--pretty"]);
+        assert_eq!(files, vec!["a.conf", "sysctl.d"]);
+        assert!(options.validate && options.pretty);
+
+        let (files, options) = invocation(&["diff", "old.schema", "new.schema"]);
+        assert_eq!(files, vec!["old.schema"]);
+        assert_eq!(options.diff_schema.as_deref(), Some("new.schema"));
+
+        let (_, options) = invocation(&["merge", "a.conf", "b.conf", "c.conf"]);
+        assert_eq!(options.merge_paths, vec!["b.conf", "c.conf"]);
+    }
+
+    #[test]
+    fn short_validate() {
+        // -v はログの詳しさではなく、--validate の短縮形
+        let (_, options) = invocation(&["a.conf", "-v"]);
+        assert!(options.validate && !options.verbose && !options.trace);
+    }
+
+    #[test]
+    fn staged_defaults_to_current_directory() {
+        let (files, options) = invocation(&["--staged"]);
+        assert_eq!(files, vec!["."]);
+        assert!(options.staged);
+        let (files, _) = invocation(&["validate", "--staged", "sysctl.d"]);
+        assert_eq!(files, vec!["sysctl.d"]);
+    }
+
+    #[test]
+    fn no_file() {
+        assert!(Cli::try_parse_from(["load_sysctl"])
+            .unwrap()
+            .into_invocation()
+            .is_none());
+    }
+}
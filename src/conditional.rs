@@ -0,0 +1,237 @@
+use crate::parser::{parse_error, skip_bom};
+use crate::types::{ParseError, ParseErrorKind};
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+
+// #@if kernel >= 5.10 から #@endif までを、カーネルのバージョンによって読み込むか読み飛ばす
+// 1つの設定ファイルで、カーネルのバージョンが混在するサーバーを扱えるようにする
+// #@else も書ける。ブロックは入れ子にできる
+// 読み飛ばす行と指示の行は空行にする。行番号は元のファイルのまま変わらない
+
+// 5.10 や 6.1.0 のような、. 区切りの数字のバージョン
+// 足りない桁は0として比べるため、5.10 と 5.10.0 は等しい
+#[derive(Debug, Clone, Eq)]
+pub struct KernelVersion(Vec<u64>);
+
+impl KernelVersion {
+    // 6.1.0-18-amd64 のように、数字の後ろに続く文字は無視する
+    pub fn parse(input: &str) -> Option<Self> {
+        let numeric = input
+            .trim()
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()?;
+        let parts = numeric
+            .split('.')
+            .take_while(|part| !part.is_empty())
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        (!parts.is_empty()).then_some(KernelVersion(parts))
+    }
+
+    // 実行中のカーネルのバージョン。/proc が無い環境ではNone
+    pub fn running() -> Option<Self> {
+        let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+        KernelVersion::parse(&release)
+    }
+}
+
+impl PartialEq for KernelVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl PartialOrd for KernelVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KernelVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        let part = |version: &KernelVersion, i: usize| version.0.get(i).copied().unwrap_or(0);
+        (0..len)
+            .map(|i| part(self, i).cmp(&part(other, i)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u64::to_string).collect();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+// #@if, #@else, #@endif で始まる指示の行があるか
+pub fn has_directives(input: &str) -> bool {
+    skip_bom(input)
+        .lines()
+        .any(|line| directive(line.trim_start()).is_some())
+}
+
+// 指示の行なら #@ の後ろを返す。#@type のような他の #@ のコメントは指示ではない
+fn directive(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("#@")?;
+    matches!(
+        rest.split_whitespace().next(),
+        Some("if" | "else" | "endif")
+    )
+    .then_some(rest)
+}
+
+// 開いているブロックの状態
+struct Block<'a> {
+    // #@if の行。閉じていなければ、誤りとして報告する
+    start: &'a str,
+    // このブロックの条件が成り立つか
+    matched: bool,
+    // #@else の後か
+    in_else: bool,
+}
+
+// 条件に合わないブロックを空行にした入力を返す。指示の誤りがあれば、そのすべての位置を返す
+pub fn evaluate_conditionals(
+    input: &str,
+    version: &KernelVersion,
+) -> Result<String, Vec<ParseError>> {
+    let body = skip_bom(input);
+    let mut evaluated = String::with_capacity(input.len());
+    evaluated.push_str(&input[..input.len() - body.len()]);
+    let mut errors = Vec::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    for line in body.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[text.len()..];
+        let trimmed = text.trim_start();
+        let active = blocks.iter().all(|block| block.matched != block.in_else);
+        let Some(directive) = directive(trimmed) else {
+            evaluated.push_str(if active { line } else { ending });
+            continue;
+        };
+        let invalid = || parse_error(input, trimmed, ParseErrorKind::InvalidDirective);
+        let mut words = directive.split_whitespace();
+        match (words.next(), words.next(), words.next(), words.next()) {
+            (Some("if"), Some("kernel"), Some(operator), Some(operand))
+                if words.next().is_none() =>
+            {
+                match compare(version, operator, operand) {
+                    Some(matched) => blocks.push(Block {
+                        start: trimmed,
+                        matched,
+                        in_else: false,
+                    }),
+                    None => errors.push(invalid()),
+                }
+            }
+            (Some("else"), None, None, None) => match blocks.last_mut() {
+                Some(block) if !block.in_else => block.in_else = true,
+                _ => errors.push(invalid()),
+            },
+            (Some("endif"), None, None, None) => {
+                if blocks.pop().is_none() {
+                    errors.push(invalid());
+                }
+            }
+            _ => errors.push(invalid()),
+        }
+        evaluated.push_str(ending);
+    }
+    errors.extend(
+        blocks
+            .iter()
+            .map(|block| parse_error(input, block.start, ParseErrorKind::InvalidDirective)),
+    );
+    if errors.is_empty() {
+        Ok(evaluated)
+    } else {
+        errors.sort_by_key(|error| error.line);
+        Err(errors)
+    }
+}
+
+// 比較の演算子かバージョンが不正ならNone
+fn compare(version: &KernelVersion, operator: &str, operand: &str) -> Option<bool> {
+    let operand = KernelVersion::parse(operand)?;
+    let ordering = version.cmp(&operand);
+    match operator {
+        ">=" => Some(ordering.is_ge()),
+        ">" => Some(ordering.is_gt()),
+        "<=" => Some(ordering.is_le()),
+        "<" => Some(ordering.is_lt()),
+        "==" => Some(ordering.is_eq()),
+        "!=" => Some(ordering.is_ne()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(input: &str) -> KernelVersion {
+        KernelVersion::parse(input).unwrap()
+    }
+
+    #[test]
+    fn test_kernel_version() {
+        assert_eq!(version("6.1.0-18-amd64"), version("6.1"));
+        assert!(version("5.10") > version("5.4.200"));
+        assert!(version("5.10") < version("5.10.1"));
+        assert_eq!(version("6.18.44-fc-v139").to_string(), "6.18.44");
+        assert_eq!(KernelVersion::parse("linux"), None);
+    }
+
+    #[test]
+    fn test_evaluate_conditionals() {
+        let input = "a.key = 1\n#@if kernel >= 5.10\nb.key = 2\n#@if kernel < 6\nc.key = 3\n#@else\nd.key = 4\n#@endif\n#@else\ne.key = 5\n#@endif\n";
+        assert_eq!(
+            evaluate_conditionals(input, &version("5.15")).unwrap(),
+            "a.key = 1\n\nb.key = 2\n\nc.key = 3\n\n\n\n\n\n\n"
+        );
+        assert_eq!(
+            evaluate_conditionals(input, &version("6.1")).unwrap(),
+            "a.key = 1\n\nb.key = 2\n\n\n\nd.key = 4\n\n\n\n\n"
+        );
+        assert_eq!(
+            evaluate_conditionals(input, &version("4.19")).unwrap(),
+            "a.key = 1\n\n\n\n\n\n\n\n\ne.key = 5\n\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_directives() {
+        let kernel = version("6.1");
+        for input in [
+            "#@if kernel ~ 5\n#@endif\n",
+            "#@if arch == x86\n#@endif\n",
+            "#@endif\n",
+            "#@if kernel >= 5\n",
+            "#@if kernel >= 5\n#@else\n#@else\n#@endif\n",
+            "#@else if kernel >= 5\n",
+        ] {
+            let errors = evaluate_conditionals(input, &kernel).unwrap_err();
+            assert_eq!(
+                errors[0].kind,
+                ParseErrorKind::InvalidDirective,
+                "{}",
+                input
+            );
+        }
+        let errors = evaluate_conditionals("a = 1\n  #@if kernel >= 5\n", &kernel).unwrap_err();
+        assert_eq!((errors[0].line, errors[0].column), (2, 3));
+    }
+
+    #[test]
+    fn test_other_annotations_are_comments() {
+        // @type の注釈などの #@ で始まるコメントは、指示として扱わずにそのまま残す
+        let input = "#@type number\nvm.swappiness = 60\n#@unless kernel >= 5\n";
+        assert!(!has_directives(input));
+        assert_eq!(
+            evaluate_conditionals(input, &version("6.1")).unwrap(),
+            input
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::style::{paint, Style};
+use crate::tr;
+use crate::types::{ParseError, ParseErrorKind, SchemaType, Span, ValidationError};
+
+// 人が読むための診断の表示。問題のある行を示し、^で箇所に印を付けて、直し方のヒントを添える
+// 例)
+// エラー[SL0003]: 'port'の型が間違っています。numberが必要ですが、stringの形式になっています。
+//  --> a.conf:1:8
+//   |
+// 1 | port = :3910
+//   |        ^^^^^
+//   = ヘルプ: numberの値を指定してください
+struct Snippet<'a> {
+    file: &'a str,
+    line_number: usize,
+    // 改行を除いた行そのもの
+    line: &'a str,
+    // 印を付ける箇所の、行頭からのバイト範囲
+    start: usize,
+    end: usize,
+}
+
+fn render(code: &str, message: &str, snippet: &Snippet, help: &str) -> String {
+    let prefix = &snippet.line[..snippet.start];
+    // タブはそのまま残して、印の位置がずれないようにする
+    let indent: String = prefix
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let carets = "^".repeat(
+        snippet.line[snippet.start..snippet.end]
+            .chars()
+            .count()
+            .max(1),
+    );
+    let gutter = " ".repeat(snippet.line_number.to_string().len());
+    format!(
+        "{}: {message}\n\
+         {gutter}--> {}:{}:{}\n\
+         {gutter} |\n\
+         {} | {}\n\
+         {gutter} | {indent}{}\n\
+         {gutter} = {}: {help}\n",
+        paint(format!("{}[{code}]", tr!("エラー", "error")), Style::Error),
+        snippet.file,
+        snippet.line_number,
+        prefix.chars().count() + 1,
+        snippet.line_number,
+        snippet.line,
+        paint(carets, Style::Error),
+        paint(tr!("ヘルプ", "help"), Style::Help),
+    )
+}
+
+// パースの誤りを、失敗した位置の語に印を付けて表示する
+pub fn render_parse_error(file: &str, error: &ParseError) -> String {
+    let start = error
+        .snippet
+        .char_indices()
+        .nth(error.column - 1)
+        .map_or(error.snippet.len(), |(i, _)| i);
+    let end = error.snippet[start..]
+        .find(char::is_whitespace)
+        .map_or(error.snippet.len(), |i| start + i);
+    let snippet = Snippet {
+        file,
+        line_number: error.line,
+        line: &error.snippet,
+        start,
+        end,
+    };
+    render(
+        error.kind.code(),
+        &tr!("文法に誤りがあります。{}", "syntax error: {}", error.kind),
+        &snippet,
+        &parse_help(error.kind),
+    )
+}
+
+// 検証エラーを、textのspanの位置に印を付けて表示する
+pub fn render_validation_error(
+    file: &str,
+    text: &str,
+    error: &ValidationError,
+    span: &Span,
+) -> String {
+    let line_start = text[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[span.start..]
+        .find(['\r', '\n'])
+        .map_or(text.len(), |i| span.start + i);
+    let snippet = Snippet {
+        file,
+        line_number: text[..span.start].matches('\n').count() + 1,
+        line: &text[line_start..line_end],
+        start: span.start - line_start,
+        end: span.end.min(line_end) - line_start,
+    };
+    render(
+        error.code(),
+        &format!("{:#}", error),
+        &snippet,
+        &validation_help(error),
+    )
+}
+
+fn parse_help(kind: ParseErrorKind) -> String {
+    match kind {
+        ParseErrorKind::InvalidLine => tr!(
+            "key = value の形式で書くか、先頭に#を付けてコメントにしてください",
+            "write it as key = value, or prefix it with # to make it a comment"
+        ),
+        ParseErrorKind::InvalidSchema => {
+            tr!("key: type の形式で書いてください", "write it as key: type")
+        }
+        ParseErrorKind::InvalidTypeName => tr!(
+            "%typeで型を定義するか、組み込みの型を指定してください",
+            "define the type with %type, or use a built-in type"
+        ),
+        ParseErrorKind::LineTooLong { .. } => tr!(
+            "値を短くするか、--max-line-length で上限を変えてください",
+            "shorten the value, or change the limit with --max-line-length"
+        ),
+        ParseErrorKind::InvalidKey => tr!(
+            "キーには英数字と _ - . / : @ + だけを使い、区切りを続けないでください",
+            "use only letters, digits and _ - . / : @ + in keys, and do not repeat separators"
+        ),
+        ParseErrorKind::UndefinedVariable => tr!(
+            "{{{{ name }}}} は --vars の変数ファイルに、${{NAME}} は環境変数に定義してください",
+            "define {{{{ name }}}} in the --vars file, and ${{NAME}} in the environment"
+        ),
+        ParseErrorKind::InvalidDirective => tr!(
+            "#@if kernel >= 5.10 のように書き、#@endif で閉じてください",
+            "write it like #@if kernel >= 5.10, and close it with #@endif"
+        ),
+        ParseErrorKind::InvalidDefault => tr!(
+            "既定値は --fix で設定ファイルに書き込まれるため、そのキーの検証に通る値にしてください",
+            "the default is written to the config file by --fix, so it must pass the checks for that key"
+        ),
+        ParseErrorKind::InvalidPattern => tr!(
+            "括弧やエスケープの対応を確認してください。/ は \\/ と書きます",
+            "check the brackets and escapes; write / as \\/"
+        ),
+    }
+}
+
+fn validation_help(error: &ValidationError) -> String {
+    match error {
+        ValidationError::MissingKey(_) => tr!(
+            "設定ファイルにキーを追加してください",
+            "add the key to the configuration file"
+        ),
+        ValidationError::MissingKeyWithDefault { .. } => tr!(
+            "--fix で既定値を設定ファイルに追記できます",
+            "run with --fix to append the default to the configuration file"
+        ),
+        ValidationError::UnknownKey(_) => tr!(
+            "キーの綴りを確認するか、スキーマに定義を追加してください",
+            "check the spelling of the key, or add it to the schema"
+        ),
+        ValidationError::WrongType {
+            trailing: Some(trailing),
+            ..
+        } => tr!(
+            "数値の後ろの'{}'を取り除いてください",
+            "remove '{}' after the number",
+            trailing
+        ),
+        ValidationError::WrongType {
+            expect: SchemaType::Path { .. },
+            ..
+        } => tr!(
+            "/ で始まる絶対パスを指定してください",
+            "use an absolute path starting with /"
+        ),
+        ValidationError::WrongType { expect, .. } => {
+            tr!("{}の値を指定してください", "use a {} value", expect)
+        }
+        ValidationError::ValueTooLong { .. } => {
+            tr!("値を短くしてください", "shorten the value")
+        }
+        ValidationError::PathNotFound { .. } => tr!(
+            "パスの綴りと、対象のマシンに存在するかを確認してください",
+            "check the spelling of the path and that it exists on the target machine"
+        ),
+        ValidationError::MutuallyExclusive { .. } => {
+            tr!("どれか1つだけを設定してください", "set only one of them")
+        }
+        ValidationError::MissingDependency { dependency, .. } => {
+            tr!("'{}'も設定してください", "set '{}' as well", dependency)
+        }
+        ValidationError::DuplicateKey { .. } => {
+            tr!("どちらか一方を削除してください", "remove one of them")
+        }
+        ValidationError::IncompleteGroup { .. } => tr!(
+            "足りないキーを設定するか、グループのキーをすべて削除してください",
+            "set the missing keys, or remove all keys of the group"
+        ),
+        ValidationError::OutOfRange { range, .. } => tr!(
+            "{}の範囲の値を指定してください",
+            "use a value in the range {}",
+            range
+        ),
+        ValidationError::WrongArity { expected, .. } => {
+            tr!("要素の数を{}にしてください", "use {} values", expected)
+        }
+        ValidationError::InvalidChoice { choices, .. } => tr!(
+            "{}のいずれかを指定してください",
+            "use one of {}",
+            choices.join(", ")
+        ),
+        ValidationError::PatternMismatch { pattern, .. } => tr!(
+            "{}に一致する値にしてください",
+            "use a value that matches {}",
+            pattern
+        ),
+        ValidationError::ReadOnlyKey(_) => tr!(
+            "設定ファイルから削除してください",
+            "remove it from the configuration file"
+        ),
+        ValidationError::InvalidElement { error, .. } => validation_help(error),
+        ValidationError::ForeignNamespace { dialect, .. } => tr!(
+            "{}で始まるキーにするか、--dialect を見直してください",
+            "start the key with one of {}, or check --dialect",
+            dialect.namespaces().join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_sysctl, parse_sysctl_spanned};
+    use crate::types::SchemaType;
+    use crate::validation::attach_spans;
+
+    #[test]
+    fn test_render_parse_error() {
+        let error = parse_sysctl("a.key = 1\n  bad line\n").unwrap_err();
+        assert_eq!(
+            render_parse_error("a.conf", &error),
+            "エラー[SL0101]: 文法に誤りがあります。key = value の形式になっていません\n \
+             --> a.conf:2:3\n  \
+             |\n\
+             2 |   bad line\n  \
+             |   ^^^\n  \
+             = ヘルプ: key = value の形式で書くか、先頭に#を付けてコメントにしてください\n"
+        );
+    }
+
+    #[test]
+    fn test_render_validation_error() {
+        let text = "a.key = 1\n\tb.key = abc\n";
+        let error = ValidationError::WrongType {
+            key_name: "b.key".to_owned(),
+            expect: SchemaType::Number,
+            actual: SchemaType::String,
+            trailing: None,
+        };
+        let spanned = attach_spans(
+            std::slice::from_ref(&error),
+            &parse_sysctl_spanned(text).unwrap(),
+        );
+        assert_eq!(
+            render_validation_error("a.conf", text, &error, &spanned[0].span.unwrap()),
+            format!(
+                "エラー[SL0003]: {}\n \
+                 --> a.conf:2:10\n  \
+                 |\n\
+                 2 | \tb.key = abc\n  \
+                 | \t        ^^^\n  \
+                 = ヘルプ: numberの値を指定してください\n",
+                error
+            )
+        );
+    }
+}
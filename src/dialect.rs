@@ -0,0 +1,117 @@
+use crate::parser::ParseOptions;
+use crate::types::{SysctlValue, ValidationError};
+use indexmap::IndexMap;
+use std::fmt::{self, Display};
+
+// 設定ファイルを読み込むOSの、sysctl.confの書き方の違い (--dialect)
+// 文法の違いはParseOptionsで表し、キーの先頭の名前空間はOSごとに決まっているものだけを受け付ける
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+    #[default]
+    Linux,
+    Freebsd,
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linux" => Some(Dialect::Linux),
+            "freebsd" => Some(Dialect::Freebsd),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dialect::Linux => "linux",
+            Dialect::Freebsd => "freebsd",
+        }
+    }
+
+    // FreeBSDのsysctl.confは # だけがコメントで、値の後ろにも書ける。行頭の - の印は無い
+    pub fn parse_options(self) -> ParseOptions {
+        match self {
+            Dialect::Linux => ParseOptions::default(),
+            Dialect::Freebsd => ParseOptions {
+                comment_chars: vec!['#'],
+                ignore_error_prefix: false,
+                inline_comments: true,
+                ..Default::default()
+            },
+        }
+    }
+
+    // キーの最初の名前として使えるもの。カーネルが持つ最上位のMIB
+    pub fn namespaces(self) -> &'static [&'static str] {
+        match self {
+            Dialect::Linux => &[
+                "abi", "crypto", "debug", "dev", "fs", "kernel", "net", "sunrpc", "user", "vm",
+            ],
+            Dialect::Freebsd => &[
+                "compat", "debug", "dev", "hw", "kern", "kstat", "machdep", "net", "p1003_1b",
+                "security", "sysctl", "user", "vfs", "vm",
+            ],
+        }
+    }
+}
+
+impl Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// そのOSに無い名前空間のキー
+pub fn check_namespaces(
+    values: &IndexMap<String, SysctlValue>,
+    dialect: Dialect,
+) -> Vec<ValidationError> {
+    values
+        .keys()
+        .filter(|key| {
+            let namespace = key.split('.').next().unwrap_or_default();
+            !dialect.namespaces().contains(&namespace)
+        })
+        .map(|key| ValidationError::ForeignNamespace {
+            key_name: key.clone(),
+            dialect,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_sysctl_with;
+
+    #[test]
+    fn test_freebsd_syntax() {
+        let input = "kern.ipc.somaxconn=1024 # listen queue\nkern.hostname=\"bsd host\"\n";
+        let values = parse_sysctl_with(input, &Dialect::Freebsd.parse_options()).unwrap();
+        assert_eq!(values["kern.ipc.somaxconn"].value, "1024");
+        assert_eq!(values["kern.hostname"].value, "bsd host");
+
+        // ; はコメントではない
+        let options = Dialect::Freebsd.parse_options();
+        assert!(parse_sysctl_with("; comment\n", &options).is_err());
+        let values = parse_sysctl_with("kern.hostname=a ;b\n", &options).unwrap();
+        assert_eq!(values["kern.hostname"].value, "a ;b");
+    }
+
+    #[test]
+    fn test_check_namespaces() {
+        let values = parse_sysctl_with(
+            "kern.ipc.somaxconn = 1024\nnet.inet.tcp.sendspace = 65536\nkernel.shmmax = 1\n",
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            check_namespaces(&values, Dialect::Freebsd),
+            vec![ValidationError::ForeignNamespace {
+                key_name: "kernel.shmmax".to_owned(),
+                dialect: Dialect::Freebsd,
+            }]
+        );
+        assert_eq!(check_namespaces(&values, Dialect::Linux).len(), 1);
+    }
+}
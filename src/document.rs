@@ -0,0 +1,294 @@
+use indexmap::IndexMap;
+use nom::Offset;
+use std::fmt::{self, Display};
+
+use crate::parser::{
+    canonical_key, invalid_key_position, logical_lines, raw_key_value, to_value, ParseOptions,
+};
+use crate::types::SysctlValue;
+
+// コメントや空行、空白、行末の改行まで含めて、書かれたとおりに保持した設定ファイル
+// 書き出すと元の入力とバイト単位で一致する。整形やキーの書き換えなど、ファイルを編集する機能の土台にする
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SysctlDocument {
+    // 先頭にBOMがあったか。書き出すときに付け直す
+    pub bom: bool,
+    pub lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub content: LineContent,
+    // "\n" か "\r\n"。ファイル末尾に改行が無ければ空
+    pub ending: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineContent {
+    // 空白だけの行
+    Blank(String),
+    // 前の空白と # や ; を含めたコメント
+    Comment(String),
+    Entry(Entry),
+    // 文法に誤りがある行。書かれたまま保持する
+    Invalid(String),
+}
+
+// key = value の行。prefix + key + separator + value + suffix が行の内容になる
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    // 行頭の空白と、エラーを無視する -
+    pub prefix: String,
+    pub key: String,
+    // = とその前後の空白
+    pub separator: String,
+    // 前後の空白を除いた値。引用符や、行末の \ による行の継続も含めて書かれたまま
+    pub value: String,
+    // 値の後ろの空白
+    pub suffix: String,
+}
+
+impl Entry {
+    pub fn ignore_error(&self) -> bool {
+        self.prefix.contains('-')
+    }
+
+    // / 区切りで書かれたキーも、. 区切りにしたもの
+    pub fn canonical_key(&self) -> String {
+        canonical_key(&self.key)
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}{}{}",
+            self.prefix, self.key, self.separator, self.value, self.suffix
+        )
+    }
+}
+
+impl SysctlDocument {
+    // parse_sysctlと同じ文法で1行ずつ読み込む。誤りのある行もInvalidとして残すため、失敗しない
+    // 行末の \ で継続した行は、まとめて1行として扱う
+    pub fn parse(input: &str) -> Self {
+        let (bom, input) = match input.strip_prefix('\u{feff}') {
+            Some(rest) => (true, rest),
+            None => (false, input),
+        };
+        let lines = logical_lines(input, &['#', ';'])
+            .map(|(_, line)| {
+                let text = line.trim_end_matches(['\r', '\n']);
+                Line {
+                    content: parse_line(text),
+                    ending: line[text.len()..].to_owned(),
+                }
+            })
+            .collect();
+        SysctlDocument { bom, lines }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.lines.iter().filter_map(|line| match &line.content {
+            LineContent::Entry(entry) => Some(entry),
+            _ => None,
+        })
+    }
+
+    // 同じキーが複数回ある場合は、parse_sysctlと同じく最後のもの
+    // キーは . 区切りでも / 区切りでもよい
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        let key = canonical_key(key);
+        self.entries()
+            .filter(|entry| entry.canonical_key() == key)
+            .last()
+    }
+
+    // キーの値を書き換える。同じキーが複数回ある場合は、有効な最後のものを書き換える
+    // キーが無ければ、ファイルの末尾に key = value の行を加える
+    pub fn set(&mut self, key: &str, value: &str) {
+        let canonical = canonical_key(key);
+        let existing = self
+            .lines
+            .iter_mut()
+            .rev()
+            .find_map(|line| match &mut line.content {
+                LineContent::Entry(entry) if entry.canonical_key() == canonical => Some(entry),
+                _ => None,
+            });
+        if let Some(entry) = existing {
+            entry.value = value.to_owned();
+            return;
+        }
+        // 最後の行に改行が無ければ、既存の改行に合わせて付ける
+        let ending = self
+            .lines
+            .iter()
+            .map(|line| line.ending.as_str())
+            .find(|ending| !ending.is_empty())
+            .unwrap_or("\n")
+            .to_owned();
+        if let Some(last) = self.lines.last_mut() {
+            if last.ending.is_empty() {
+                last.ending = ending.clone();
+            }
+        }
+        self.lines.push(Line {
+            content: LineContent::Entry(Entry {
+                prefix: String::new(),
+                key: key.to_owned(),
+                separator: " = ".to_owned(),
+                value: value.to_owned(),
+                suffix: String::new(),
+            }),
+            ending,
+        });
+    }
+
+    // キーの行をすべて取り除く。取り除いた行があればtrue
+    pub fn remove(&mut self, key: &str) -> bool {
+        let key = canonical_key(key);
+        let before = self.lines.len();
+        self.lines.retain(
+            |line| !matches!(&line.content, LineContent::Entry(entry) if entry.canonical_key() == key),
+        );
+        self.lines.len() != before
+    }
+
+    // parse_sysctlと同じ形の、キーと値の組
+    pub fn to_values(&self) -> IndexMap<String, SysctlValue> {
+        self.entries()
+            .map(|entry| {
+                (
+                    entry.canonical_key(),
+                    SysctlValue {
+                        value: to_value(&entry.value),
+                        ignore_error: entry.ignore_error(),
+                        origin: None,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    // 文法に誤りのある行の、1始まりの行番号
+    pub fn invalid_lines(&self) -> Vec<usize> {
+        let mut line_number = 1;
+        let mut invalid = Vec::new();
+        for line in &self.lines {
+            if let LineContent::Invalid(_) = line.content {
+                invalid.push(line_number);
+            }
+            // 継続した行は、その分の行番号を進める
+            line_number += line.text().matches('\n').count() + 1;
+        }
+        invalid
+    }
+}
+
+impl Line {
+    // 改行を除いた行の内容
+    pub fn text(&self) -> String {
+        match &self.content {
+            LineContent::Blank(text) | LineContent::Comment(text) | LineContent::Invalid(text) => {
+                text.clone()
+            }
+            LineContent::Entry(entry) => entry.to_string(),
+        }
+    }
+}
+
+impl Display for SysctlDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bom {
+            write!(f, "\u{feff}")?;
+        }
+        for line in &self.lines {
+            write!(f, "{}{}", line.text(), line.ending)?;
+        }
+        Ok(())
+    }
+}
+
+// 改行を除いた1行を読み込む
+fn parse_line(text: &str) -> LineContent {
+    let trimmed = text.trim_start();
+    if trimmed.is_empty() {
+        return LineContent::Blank(text.to_owned());
+    }
+    if trimmed.starts_with(['#', ';']) {
+        return LineContent::Comment(text.to_owned());
+    }
+    match raw_key_value(&ParseOptions::default())(text) {
+        // 値は行末まで読むため、残りは無い
+        Ok((_, (_, key, value, _))) if invalid_key_position(key).is_none() => {
+            let key_start = text.offset(key);
+            let value_start = text.offset(value);
+            LineContent::Entry(Entry {
+                prefix: text[..key_start].to_owned(),
+                key: key.to_owned(),
+                separator: text[key_start + key.len()..value_start].to_owned(),
+                value: value.to_owned(),
+                suffix: text[value_start + value.len()..].to_owned(),
+            })
+        }
+        _ => LineContent::Invalid(text.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_sysctl;
+
+    #[test]
+    fn test_round_trip() {
+        let input = "# comment\r\n\n  \t\n  - endpoint\t=  localhost:3000  \r\n; c\nlong = a \\\n  b\nbroken line\nkey=value";
+        let document = SysctlDocument::parse(input);
+        assert_eq!(document.to_string(), input);
+        // 継続した行は1行にまとめるが、行番号は入力のまま
+        assert_eq!(document.lines.len(), 8);
+        assert_eq!(document.invalid_lines(), vec![8]);
+        assert_eq!(document.get("long").unwrap().value, "a \\\n  b");
+        let endpoint = document.get("endpoint").unwrap();
+        assert_eq!(endpoint.prefix, "  - ");
+        assert_eq!(endpoint.separator, "\t=  ");
+        assert_eq!(endpoint.value, "localhost:3000");
+        assert_eq!(endpoint.suffix, "  ");
+        assert!(endpoint.ignore_error());
+
+        let input =
+            "a.key = 1\n-b.key = two\n# comment\na.key = 3\nc.key = ' x '\nd.key = 1 \\\n 2\n";
+        assert_eq!(
+            SysctlDocument::parse(input).to_values(),
+            parse_sysctl(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bom() {
+        let input = "\u{feff}# comment\na.key = 1\n";
+        let document = SysctlDocument::parse(input);
+        assert!(document.bom);
+        assert_eq!(
+            document.lines[0].content,
+            LineContent::Comment("# comment".to_owned())
+        );
+        assert_eq!(document.to_string(), input);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut document = SysctlDocument::parse("a.key = 1 # note\r\na.key  =  2\r\nb.key = 3");
+        document.set("a.key", "5");
+        document.set("c.key", "6");
+        assert_eq!(
+            document.to_string(),
+            "a.key = 1 # note\r\na.key  =  5\r\nb.key = 3\r\nc.key = 6\r\n"
+        );
+        assert!(document.remove("a.key"));
+        assert!(!document.remove("missing"));
+        assert_eq!(document.to_string(), "b.key = 3\r\nc.key = 6\r\n");
+    }
+}
@@ -0,0 +1,59 @@
+use std::io;
+
+// Windowsのツールで書き出したファイルは、先頭にBOMが付いていたりUTF-16だったりする
+// 読み込んだバイト列を、BOMを見てUTF-8かUTF-16として文字列にする。BOMは取り除く
+// BOMが無ければUTF-8として読む
+pub fn decode_text(bytes: Vec<u8>) -> io::Result<String> {
+    match bytes.as_slice() {
+        [0xEF, 0xBB, 0xBF, rest @ ..] => utf8(rest.to_vec()),
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        _ => utf8(bytes),
+    }
+}
+
+fn utf8(bytes: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(bytes).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+}
+
+fn utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> io::Result<String> {
+    // 奇数バイトで終わるものはUTF-16として壊れている
+    if !bytes.len().is_multiple_of(2) {
+        return Err(io::Error::from(io::ErrorKind::InvalidData));
+    }
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(decode_text(b"a = 1\n".to_vec()).unwrap(), "a = 1\n");
+        assert_eq!(
+            decode_text(b"\xEF\xBB\xBFa = 1\n".to_vec()).unwrap(),
+            "a = 1\n"
+        );
+
+        let utf16 = |bom: [u8; 2], to_bytes: fn(u16) -> [u8; 2]| {
+            let mut bytes = bom.to_vec();
+            bytes.extend("a = あ\r\n".encode_utf16().flat_map(to_bytes));
+            decode_text(bytes)
+        };
+        assert_eq!(utf16([0xFF, 0xFE], u16::to_le_bytes).unwrap(), "a = あ\r\n");
+        assert_eq!(utf16([0xFE, 0xFF], u16::to_be_bytes).unwrap(), "a = あ\r\n");
+
+        for bytes in [&b"\xFF\xFEa"[..], b"\xFF\xFE\x00\xD8", b"\xC3("] {
+            assert_eq!(
+                decode_text(bytes.to_vec()).unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+        }
+    }
+}
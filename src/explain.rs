@@ -0,0 +1,122 @@
+use crate::types::{Schema, SchemaEntry};
+
+// エントリに関係する制約を、人が読む形式で列挙する
+fn constraints(entry: &SchemaEntry, schema: &Schema) -> Vec<String> {
+    let mut constraints = Vec::new();
+    if let Some(range) = &entry.range {
+        constraints.push(format!("range: {}", range));
+    }
+    if let Some(pattern) = &entry.pattern {
+        constraints.push(format!("pattern: {}", pattern));
+    }
+    if entry.readonly {
+        constraints.push("readonly".to_owned());
+    }
+    if entry.optional {
+        constraints.push("optional".to_owned());
+    }
+    for group in &schema.exclusive_groups {
+        if group.contains(&entry.name) {
+            let others: Vec<&str> = group
+                .iter()
+                .filter(|key| **key != entry.name)
+                .map(String::as_str)
+                .collect();
+            constraints.push(format!("exclusive: {}", others.join(", ")));
+        }
+    }
+    for (name, keys) in &schema.groups {
+        if keys.contains(&entry.name) {
+            constraints.push(format!("group: {}", name));
+        }
+    }
+    let dependencies: Vec<&str> = schema
+        .dependencies
+        .iter()
+        .filter(|(key, _)| *key == entry.name)
+        .map(|(_, dependency)| dependency.as_str())
+        .collect();
+    if !dependencies.is_empty() {
+        constraints.push(format!("depends: {}", dependencies.join(", ")));
+    }
+    constraints
+}
+
+// スキーマの各エントリを1行ずつ説明する
+pub fn explain_text(schema: &Schema) -> String {
+    let mut output = String::new();
+    for entry in &schema.entries {
+        output.push_str(&format!("{}: {}", entry.name, entry.schema_type));
+        let mut constraints = constraints(entry, schema);
+        if let Some(default) = &entry.default {
+            constraints.push(format!("default: {}", default));
+        }
+        if !constraints.is_empty() {
+            output.push_str(&format!(" ({})", constraints.join("; ")));
+        }
+        output.push('\n');
+    }
+    output
+}
+
+// 表のセルの中で意味を持つ文字をエスケープする
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+// スキーマをmarkdownの表として出力する。
+// 既定値は = で書いたもの。説明はスキーマに書けないため、今は空欄にしておく
+pub fn explain_markdown(schema: &Schema) -> String {
+    let mut output = String::from(
+        "| key | type | constraints | default | description |\n| --- | --- | --- | --- | --- |\n",
+    );
+    for entry in &schema.entries {
+        output.push_str(&format!(
+            "| {} | {} | {} | {} |  |\n",
+            markdown_cell(&entry.name),
+            markdown_cell(&entry.schema_type.to_string()),
+            markdown_cell(&constraints(entry, schema).join("<br>")),
+            markdown_cell(entry.default.as_deref().unwrap_or_default()),
+        ));
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_schema;
+
+    #[test]
+    fn test_explain_markdown() {
+        let schema = parse_schema(
+            "a.key: bool\nb.key: number(1..)\nc.key: enum(x, y)\nkernel.ostype: string readonly\nvm.swappiness: number = 60\n%exclusive a.key b.key\n%depends c.key a.key\n",
+        )
+        .unwrap();
+        let markdown = explain_markdown(&schema);
+        let rows: Vec<&str> = markdown.lines().collect();
+        assert_eq!(rows.len(), 2 + schema.entries.len());
+        assert_eq!(
+            rows[0],
+            "| key | type | constraints | default | description |"
+        );
+        assert_eq!(rows[2], "| a.key | bool | exclusive: b.key |  |  |");
+        assert_eq!(
+            rows[3],
+            "| b.key | number | range: 1..<br>exclusive: a.key |  |  |"
+        );
+        assert_eq!(rows[4], "| c.key | enum(x, y) | depends: a.key |  |  |");
+        assert_eq!(rows[5], "| kernel.ostype | string | readonly |  |  |");
+        // 既定値は制約ではなく、default の列に書く
+        assert_eq!(rows[6], "| vm.swappiness | number |  | 60 |  |");
+    }
+
+    #[test]
+    fn test_explain_text() {
+        let schema = parse_schema("a.key: bool\nb.key: string readonly\n").unwrap();
+        assert_eq!(
+            explain_text(&schema),
+            "a.key: bool\nb.key: string (readonly)\n"
+        );
+    }
+}
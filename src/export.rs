@@ -0,0 +1,173 @@
+use indexmap::IndexMap;
+use serde::Serialize;
+
+use crate::types::{SchemaType, SysctlValue};
+
+// 他のツールに渡すための、1つのキーの値
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ExportedValue<'a> {
+    pub value: &'a str,
+    // 先頭に-が付いていて、反映の失敗が無視されるか
+    pub ignore_error: bool,
+    // 値の見た目から推測した型
+    #[serde(rename = "type")]
+    pub inferred_type: String,
+}
+
+// 設定ファイルに書かれた順に並べる
+pub fn export(value: &IndexMap<String, SysctlValue>) -> IndexMap<&str, ExportedValue<'_>> {
+    value
+        .iter()
+        .map(|(key, sysctl_value)| {
+            (
+                key.as_str(),
+                ExportedValue {
+                    value: &sysctl_value.value,
+                    ignore_error: sysctl_value.ignore_error,
+                    inferred_type: SchemaType::infer(&sysctl_value.value).to_string(),
+                },
+            )
+        })
+        .collect()
+}
+
+// 読み込んだデータをJSONで出力する
+pub fn to_json(value: &IndexMap<String, SysctlValue>) -> String {
+    serde_json::to_string_pretty(&export(value)).expect("データのシリアライズに失敗しました。")
+}
+
+// 読み込んだデータを、AnsibleやHelmに渡せるようYAMLで出力する
+pub fn to_yaml(value: &IndexMap<String, SysctlValue>) -> String {
+    serde_yaml::to_string(&export(value)).expect("データのシリアライズに失敗しました。")
+}
+
+// 読み込んだデータを、キーを.で区切った入れ子のTOMLのテーブルとして出力する
+// 例) net.ipv4.ip_forward = 1 -> [net.ipv4] ip_forward = 1
+// 値は推測した型で書く。TOMLでは-の有無は表せないため出力しない
+// kernel と kernel.hostname のように、値を持つキーが他のキーの途中にもなる場合は、
+// そのキーのテーブルの中に、空のキー "" = ... として値を書く
+pub fn to_toml(value: &IndexMap<String, SysctlValue>) -> String {
+    let mut root = toml::Table::new();
+    for (key, sysctl_value) in value {
+        let segments: Vec<&str> = key.split('.').collect();
+        insert_toml(&mut root, &segments, to_toml_value(&sysctl_value.value));
+    }
+    toml::to_string(&root).expect("データのシリアライズに失敗しました。")
+}
+
+// . で区切ったキーの位置に値を入れる
+// 途中の名前に値があれば、その値を空のキーに移してテーブルにする
+fn insert_toml(table: &mut toml::Table, segments: &[&str], value: toml::Value) {
+    match segments {
+        [] => {}
+        [last] => match table.get_mut(*last) {
+            Some(toml::Value::Table(inner)) => {
+                inner.insert(String::new(), value);
+            }
+            _ => {
+                table.insert(last.to_string(), value);
+            }
+        },
+        [first, rest @ ..] => {
+            let mut inner = match table.remove(*first) {
+                Some(toml::Value::Table(inner)) => inner,
+                Some(existing) => {
+                    let mut inner = toml::Table::new();
+                    inner.insert(String::new(), existing);
+                    inner
+                }
+                None => toml::Table::new(),
+            };
+            insert_toml(&mut inner, rest, value);
+            table.insert(first.to_string(), toml::Value::Table(inner));
+        }
+    }
+}
+
+fn to_toml_value(value: &str) -> toml::Value {
+    match SchemaType::infer(value) {
+        SchemaType::Boolean => toml::Value::Boolean(value == "true"),
+        SchemaType::Integer | SchemaType::Number => match value.parse::<i64>() {
+            Ok(integer) => toml::Value::Integer(integer),
+            Err(_) => value
+                .parse::<f64>()
+                .map(toml::Value::Float)
+                .unwrap_or_else(|_| toml::Value::String(value.to_owned())),
+        },
+        _ => toml::Value::String(value.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_sysctl;
+
+    #[test]
+    fn test_to_json() {
+        let value =
+            parse_sysctl("port = 3910\n-debug = true\nlog.file = /var/log/a.log\n").unwrap();
+        let json_str = to_json(&value);
+        // 設定ファイルに書かれた順に出力する
+        assert!(json_str.find("\"port\"") < json_str.find("\"debug\""));
+        let json: serde_json::Value = serde_json::from_str(&json_str).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "debug": { "value": "true", "ignore_error": true, "type": "bool" },
+                "log.file": { "value": "/var/log/a.log", "ignore_error": false, "type": "string" },
+                "port": { "value": "3910", "ignore_error": false, "type": "integer" },
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_toml() {
+        let value = parse_sysctl(
+            "net.ipv4.ip_forward = 1\nnet.ipv4.tcp_syncookies = 1\nkernel.hostname = web01\n\
+             vm.swappiness = 0.5\ndebug = true\n",
+        )
+        .unwrap();
+        assert_eq!(
+            to_toml(&value),
+            "debug = true\n\n[kernel]\nhostname = \"web01\"\n\n\
+             [net.ipv4]\nip_forward = 1\ntcp_syncookies = 1\n\n[vm]\nswappiness = 0.5\n"
+        );
+    }
+
+    #[test]
+    fn test_to_toml_key_used_as_table() {
+        // a.b は a.b.c の途中でもあるため、a.b のテーブルの空のキーに書く
+        let value = parse_sysctl("a.b = 1\na.b.c = 2\n").unwrap();
+        let parsed: toml::Table = to_toml(&value).parse().unwrap();
+        assert_eq!(parsed["a"]["b"][""].as_integer(), Some(1));
+        assert_eq!(parsed["a"]["b"]["c"].as_integer(), Some(2));
+
+        // 1つの名前だけのキーも、書かれた順によらずテーブルにする
+        for input in [
+            "kernel = 1\nkernel.hostname = x\n",
+            "kernel.hostname = x\nkernel = 1\n",
+        ] {
+            let value = parse_sysctl(input).unwrap();
+            let parsed: toml::Table = to_toml(&value).parse().unwrap();
+            assert_eq!(parsed["kernel"][""].as_integer(), Some(1));
+            assert_eq!(parsed["kernel"]["hostname"].as_str(), Some("x"));
+        }
+    }
+
+    #[test]
+    fn test_to_yaml() {
+        let value = parse_sysctl(
+            "port = 3910
+-debug = true
+",
+        )
+        .unwrap();
+        // 値は文字列のまま、書かれた順に出力し、YAMLの数値やboolとして読まれないようにする
+        assert_eq!(
+            to_yaml(&value),
+            "port:\n  value: '3910'\n  ignore_error: false\n  type: integer\n\
+             debug:\n  value: 'true'\n  ignore_error: true\n  type: bool\n"
+        );
+    }
+}
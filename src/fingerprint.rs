@@ -0,0 +1,53 @@
+use indexmap::IndexMap;
+use sha2::{Digest, Sha256};
+
+use crate::types::SysctlValue;
+
+// 設定内容のフィンガープリント(SHA-256の16進数表記)を計算する。
+// キーでソートしてからハッシュするため、ファイル内の順序やコメントには影響されない
+pub fn fingerprint(value: &IndexMap<String, SysctlValue>) -> String {
+    let mut entries: Vec<(&String, &SysctlValue)> = value.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut hasher = Sha256::new();
+    for (key, sysctl_value) in entries {
+        // 区切りが曖昧にならないよう、長さを前置する
+        for field in [key.as_bytes(), sysctl_value.value.as_bytes()] {
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field);
+        }
+        hasher.update([sysctl_value.ignore_error as u8]);
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_sysctl;
+
+    fn fingerprint_of(input: &str) -> String {
+        fingerprint(&parse_sysctl(input).unwrap())
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_order_and_comments() {
+        let a = fingerprint_of("# comment\nkey1 = value1\n-key2 = value2\n");
+        let b = fingerprint_of("-key2=value2\n; another comment\nkey1 =  value1");
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 64);
+    }
+
+    #[test]
+    fn test_fingerprint_detects_changes() {
+        let base = fingerprint_of("key1 = value1\nkey2 = value2\n");
+        assert_ne!(base, fingerprint_of("key1 = value1\nkey2 = value3\n"));
+        assert_ne!(base, fingerprint_of("key1 = value1\n-key2 = value2\n"));
+        assert_ne!(base, fingerprint_of("key1 = value1\n"));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::parser::ParseOptions;
+use crate::types::{Schema, SchemaEntry, SysctlValue};
+use indexmap::IndexMap;
+
+// --fix で、スキーマに既定値のある必須のキーのうち、設定ファイルに無いものを末尾に追記する
+// 既存の行には手を付けず、key = value の行を足すだけにする
+
+// 設定ファイルに無く、既定値で補えるエントリ。スキーマに書かれた順に返す
+pub fn missing_defaults<'a>(
+    values: &IndexMap<String, SysctlValue>,
+    schema: &'a Schema,
+) -> Vec<&'a SchemaEntry> {
+    schema
+        .entries
+        .iter()
+        .filter(|entry| {
+            entry.default.is_some() && entry.is_required() && !values.contains_key(&entry.name)
+        })
+        .collect()
+}
+
+// 設定ファイルの末尾に追記する文字列。ファイルが改行で終わっていなければ、先に改行を入れる
+// セクションの見出しがあるファイルでは、最後のセクションのキーにならないよう [] で見出しの無い状態に戻す
+pub fn format_defaults(input: &str, entries: &[&SchemaEntry], options: &ParseOptions) -> String {
+    let mut appended = String::new();
+    if !input.is_empty() && !input.ends_with('\n') {
+        appended.push('\n');
+    }
+    if options.sections && input.lines().any(|line| line.trim_start().starts_with('[')) {
+        appended.push_str("[]\n");
+    }
+    for entry in entries {
+        if let Some(default) = &entry.default {
+            appended.push_str(&format!(
+                "{} {} {}\n",
+                entry.name, options.separator, default
+            ));
+        }
+    }
+    appended
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_schema, parse_sysctl, parse_sysctl_with};
+
+    #[test]
+    fn test_missing_defaults() {
+        let schema = parse_schema(
+            "vm.swappiness: number = 60\nkernel.hostname: string = web 01\nnet.core.somaxconn: number = 4096\nfs.file-max?: number = 1\nkernel.pid_max: number\n",
+        )
+        .unwrap();
+        let values = parse_sysctl("net.core.somaxconn = 1024").unwrap();
+        let entries = missing_defaults(&values, &schema);
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["vm.swappiness", "kernel.hostname"]);
+
+        let input = "net.core.somaxconn = 1024";
+        let fixed = format!(
+            "{}{}",
+            input,
+            format_defaults(input, &entries, &ParseOptions::default())
+        );
+        assert_eq!(
+            fixed,
+            "net.core.somaxconn = 1024\nvm.swappiness = 60\nkernel.hostname = web 01\n"
+        );
+        let values = parse_sysctl(&fixed).unwrap();
+        assert!(missing_defaults(&values, &schema).is_empty());
+    }
+
+    #[test]
+    fn test_format_defaults_after_section() {
+        let schema =
+            parse_schema("vm.swappiness: number = 60\nnet.ipv4.ip_forward: number\n").unwrap();
+        let options = ParseOptions {
+            sections: true,
+            ..Default::default()
+        };
+        let input = "[net.ipv4]\nip_forward = 1\n";
+        let values = parse_sysctl_with(input, &options).unwrap();
+        let entries = missing_defaults(&values, &schema);
+        let fixed = format!("{}{}", input, format_defaults(input, &entries, &options));
+        assert_eq!(
+            fixed,
+            "[net.ipv4]\nip_forward = 1\n[]\nvm.swappiness = 60\n"
+        );
+        let values = parse_sysctl_with(&fixed, &options).unwrap();
+        assert_eq!(values["vm.swappiness"].value, "60");
+        assert!(missing_defaults(&values, &schema).is_empty());
+    }
+}
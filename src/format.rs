@@ -0,0 +1,230 @@
+use indexmap::IndexMap;
+use std::borrow::Cow;
+
+use crate::key::{normalize_key, KeyStyle};
+use crate::types::{Schema, SchemaType, SysctlValue};
+
+// bool型のキーの値をどの表記で出力するか
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoolStyle {
+    // 0 / 1 (カーネルが受け付ける形式)
+    Kernel,
+    // true / false
+    Words,
+}
+
+impl BoolStyle {
+    pub fn from_name(value: &str) -> Option<Self> {
+        match value {
+            "kernel" => Some(BoolStyle::Kernel),
+            "words" => Some(BoolStyle::Words),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FormatOptions {
+    // Noneの場合は書かれた値のまま出力する
+    pub bool_style: Option<BoolStyle>,
+    // キーの区切りの書き方
+    pub key_style: KeyStyle,
+}
+
+// 真偽値として解釈できる表記
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+// そのまま書くと同じ値に読み戻せない値は、"..." で囲んでエスケープする
+// 前後の空白や改行を含む値、引用符やコメントの記号で始まる値、行の継続になる \ で終わる値が該当する
+pub fn quote_value(value: &str) -> Cow<'_, str> {
+    let needs_quotes = value != value.trim()
+        || value.starts_with(['"', '\'', '#', ';'])
+        || value.ends_with('\\')
+        || value.contains(['\r', '\n']);
+    if !needs_quotes {
+        return Cow::Borrowed(value);
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+// bool以外の値は書かれた文字列のまま返す。
+// 数値もf32などに変換してから出力すると 3.14 が 3.1400001 のように変わりうるため、変換しない
+// bool の表記はスキーマの%boolを優先し、読めなければ既定の表記として読む
+fn format_value<'a>(
+    value: &'a str,
+    schema: Option<&Schema>,
+    schema_type: Option<&SchemaType>,
+    options: &FormatOptions,
+) -> &'a str {
+    let parsed = schema
+        .and_then(|schema| schema.parse_bool(value))
+        .or_else(|| parse_bool(value));
+    match (schema_type, options.bool_style, parsed) {
+        (Some(SchemaType::Boolean), Some(BoolStyle::Kernel), Some(b)) => {
+            if b {
+                "1"
+            } else {
+                "0"
+            }
+        }
+        (Some(SchemaType::Boolean), Some(BoolStyle::Words), Some(b)) => {
+            if b {
+                "true"
+            } else {
+                "false"
+            }
+        }
+        _ => value,
+    }
+}
+
+// sysctl.conf形式で出力する。キーはソートされ、コメントは出力されない
+pub fn format_sysctl(
+    value: &IndexMap<String, SysctlValue>,
+    schema: Option<&Schema>,
+    options: &FormatOptions,
+) -> String {
+    let mut keys: Vec<&String> = value.keys().collect();
+    keys.sort();
+
+    let mut output = String::new();
+    for key in keys {
+        let sysctl_value = &value[key];
+        let schema_entry = schema.and_then(|schema| schema.entry_for(key));
+        let schema_type = schema_entry.as_ref().map(|entry| &entry.schema_type);
+        if sysctl_value.ignore_error {
+            output.push('-');
+        }
+        output.push_str(&normalize_key(key, options.key_style));
+        output.push_str(" = ");
+        output.push_str(&quote_value(format_value(
+            &sysctl_value.value,
+            schema,
+            schema_type,
+            options,
+        )));
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_schema, parse_sysctl};
+
+    fn format(input: &str, bool_style: Option<BoolStyle>) -> String {
+        let value = parse_sysctl(input).unwrap();
+        let schema = parse_schema("flag: bool\nother: string\nnum: number\n").unwrap();
+        format_sysctl(
+            &value,
+            Some(&schema),
+            &FormatOptions {
+                bool_style,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_format_sysctl() {
+        assert_eq!(
+            format("other=true\n-flag =  true", None),
+            "-flag = true\nother = true\n"
+        );
+    }
+
+    #[test]
+    fn test_format_bool_kernel_style() {
+        assert_eq!(
+            format("flag = true\nother = true", Some(BoolStyle::Kernel)),
+            "flag = 1\nother = true\n"
+        );
+        assert_eq!(format("flag = off", Some(BoolStyle::Kernel)), "flag = 0\n");
+    }
+
+    #[test]
+    fn test_format_bool_words_style() {
+        assert_eq!(
+            format("flag = 1\nother = 1", Some(BoolStyle::Words)),
+            "flag = true\nother = 1\n"
+        );
+        assert_eq!(
+            format("flag = no", Some(BoolStyle::Words)),
+            "flag = false\n"
+        );
+        // 真偽値として解釈できない値はそのまま
+        assert_eq!(
+            format("flag = maybe", Some(BoolStyle::Words)),
+            "flag = maybe\n"
+        );
+    }
+
+    #[test]
+    fn test_format_bool_schema_words() {
+        let value = parse_sysctl("flag = enabled\nother = disabled\n").unwrap();
+        let schema = parse_schema("%bool disabled/enabled\nflag: bool\nother: bool\n").unwrap();
+        assert_eq!(
+            format_sysctl(
+                &value,
+                Some(&schema),
+                &FormatOptions {
+                    bool_style: Some(BoolStyle::Kernel),
+                    ..Default::default()
+                },
+            ),
+            "flag = 1\nother = 0\n"
+        );
+    }
+
+    #[test]
+    fn test_format_quoted_round_trip() {
+        let input = concat!(
+            "a = \"  padded\"\n",
+            "b = \"# not a comment\"\n",
+            "c = \"line\\nbreak \\\"q\\\" \\\\\"\n",
+            "d = |/usr/bin/handler %p # kept\n",
+            "e = \"C:\\\\\"\n",
+        );
+        let output = format(input, None);
+        assert_eq!(output, input);
+        assert_eq!(parse_sysctl(&output), parse_sysctl(input));
+    }
+
+    #[test]
+    fn test_format_number_round_trip() {
+        for number in [
+            "3.14",
+            "0.1",
+            "16777217",
+            "1e10",
+            "1.50",
+            "-0",
+            "007",
+            "0.30000000000000004",
+        ] {
+            let input = format!("num = {}\n", number);
+            for bool_style in [None, Some(BoolStyle::Kernel), Some(BoolStyle::Words)] {
+                assert_eq!(format(&input, bool_style), input);
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::cell::Cell;
+
+// メッセージの言語
+// 各メッセージは tr! で日本語と英語を並べて書き、表示時に現在の言語の方を選ぶ
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum Lang {
+    #[default]
+    Ja,
+    En,
+}
+
+impl Lang {
+    // --lang の値から言語を選ぶ
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ja" => Some(Lang::Ja),
+            "en" => Some(Lang::En),
+            _ => None,
+        }
+    }
+
+    // ja_JP.UTF-8 や en_US.UTF-8 のようなロケール名から言語を選ぶ
+    // C や POSIX など、どちらでもないロケールではNone
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let language = locale.split(['_', '.', '@']).next()?;
+        Self::from_name(language)
+    }
+
+    // 環境変数から言語を選ぶ。LC_ALL、LC_MESSAGES、LANG の順に、空でない最初のものを見る
+    pub fn from_env() -> Option<Self> {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|locale| !locale.is_empty())
+            .and_then(|locale| Self::from_locale(&locale))
+    }
+}
+
+thread_local! {
+    // CLIは1つのスレッドで動くため、スレッドごとに持てば十分
+    // テストごとに別の言語を設定しても互いに影響しない
+    static LANG: Cell<Lang> = const { Cell::new(Lang::Ja) };
+}
+
+// 以降のメッセージの言語を設定する
+pub fn set_lang(lang: Lang) {
+    LANG.with(|current| current.set(lang));
+}
+
+// 現在のメッセージの言語
+pub fn lang() -> Lang {
+    LANG.with(Cell::get)
+}
+
+// 日本語と英語のメッセージのうち、現在の言語の方をformat!する
+// 例) tr!("'{}'が存在しません", "'{}' does not exist", key)
+#[macro_export]
+macro_rules! tr {
+    ($ja:literal, $en:literal $(, $arg:expr)* $(,)?) => {
+        match $crate::i18n::lang() {
+            $crate::i18n::Lang::Ja => format!($ja $(, $arg)*),
+            $crate::i18n::Lang::En => format!($en $(, $arg)*),
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_locale() {
+        assert_eq!(Lang::from_locale("ja_JP.UTF-8"), Some(Lang::Ja));
+        assert_eq!(Lang::from_locale("en_US.UTF-8"), Some(Lang::En));
+        assert_eq!(Lang::from_locale("en"), Some(Lang::En));
+        assert_eq!(Lang::from_locale("C.UTF-8"), None);
+        assert_eq!(Lang::from_locale("POSIX"), None);
+    }
+
+    #[test]
+    fn tr_follows_current_lang() {
+        let key = "net.ipv4.ip_forward";
+        assert_eq!(
+            tr!("'{}'です", "it is '{}'", key),
+            "'net.ipv4.ip_forward'です"
+        );
+        set_lang(Lang::En);
+        assert_eq!(
+            tr!("'{}'です", "it is '{}'", key),
+            "it is 'net.ipv4.ip_forward'"
+        );
+        set_lang(Lang::Ja);
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// コマンドラインで指定されたファイルとディレクトリを、読み込むファイルの一覧に展開する
+// ファイルは指定された順に並べる。ディレクトリは再帰的に *.conf を探し、
+// systemd-sysctl と同じようにファイル名の辞書順(同じ名前ならパスの順)でその位置に並べる
+pub fn expand_inputs(paths: &[String]) -> io::Result<Vec<String>> {
+    let mut inputs = Vec::new();
+    for path in paths {
+        if Path::new(path).is_dir() {
+            let mut found = Vec::new();
+            collect_conf_files(Path::new(path), &mut found)?;
+            found.sort_by(|a, b| a.file_name().cmp(&b.file_name()).then_with(|| a.cmp(b)));
+            inputs.extend(found.iter().map(|path| path.to_string_lossy().into_owned()));
+        } else {
+            inputs.push(path.clone());
+        }
+    }
+    Ok(inputs)
+}
+
+fn collect_conf_files(dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_conf_files(&path, found)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "conf")
+        {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("sysctl.d/nested")).unwrap();
+        for file in [
+            "sysctl.conf",
+            "sysctl.d/99-local.conf",
+            "sysctl.d/10-base.conf",
+            "sysctl.d/nested/50-net.conf",
+            "sysctl.d/README",
+            "sysctl.d/10-base.conf.schema",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let path = |file: &str| root.join(file).to_string_lossy().into_owned();
+
+        let inputs = expand_inputs(&[path("sysctl.conf"), path("sysctl.d")]).unwrap();
+        assert_eq!(
+            inputs,
+            vec![
+                path("sysctl.conf"),
+                path("sysctl.d/10-base.conf"),
+                path("sysctl.d/nested/50-net.conf"),
+                path("sysctl.d/99-local.conf"),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_file_is_kept() {
+        // 存在しないファイルは、読み込む時点で報告する
+        assert_eq!(
+            expand_inputs(&["no_such.conf".to_owned()]).unwrap(),
+            vec!["no_such.conf"]
+        );
+    }
+}
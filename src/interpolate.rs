@@ -0,0 +1,144 @@
+use crate::parser::{parse_error, parse_sysctl_spanned_with, ParseOptions};
+use crate::types::{ParseError, ParseErrorKind, SysctlValue};
+use indexmap::IndexMap;
+
+// 値の中の ${NAME} を変数の値に置き換える (--expand-env)
+// デプロイ時に環境変数から値を埋めるテンプレートの設定ファイルのため。$${NAME} は置き換えずに ${NAME} にする
+// 変数名は英字か _ で始まり、英数字と _ だけからなる。それ以外の ${ は書かれたまま残す
+// 定義されていない変数は、--vars のテンプレートと同じく、入力中の ${NAME} の位置を指す文法の誤りにする
+
+// すべての値の変数を置き換える。lookupは変数の値を返し、定義されていなければNone
+// inputとoptionsは値を読み込んだ入力とその文法で、エラーの位置を求めるのに使う
+// 定義されていない変数があれば、その値は書き換えずに、すべてのエラーを返す
+pub fn interpolate(
+    values: &mut IndexMap<String, SysctlValue>,
+    input: &str,
+    options: &ParseOptions,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<(), Vec<ParseError>> {
+    let mut undefined = Vec::new();
+    for (key, value) in values.iter_mut() {
+        match expand(&value.value, &lookup) {
+            Ok(expanded) => value.value = expanded,
+            Err(names) => undefined.extend(names.into_iter().map(|name| (key.clone(), name))),
+        }
+    }
+    if undefined.is_empty() {
+        return Ok(());
+    }
+    let entries = parse_sysctl_spanned_with(input, options).ok();
+    let mut errors: Vec<ParseError> = undefined
+        .iter()
+        .map(|(key, name)| {
+            let placeholder = format!("${{{}}}", name);
+            // 値の中の ${NAME} を指す。include で取り込んだ値など、入力に無い値は最初に現れる位置を指す
+            let value_start = entries
+                .as_ref()
+                .and_then(|entries| entries.get(key))
+                .map_or(0, |entry| entry.value_span.0);
+            let offset = input[value_start..]
+                .find(&placeholder)
+                .map(|i| value_start + i)
+                .or_else(|| input.find(&placeholder))
+                .unwrap_or(value_start);
+            parse_error(input, &input[offset..], ParseErrorKind::UndefinedVariable)
+        })
+        .collect();
+    errors.sort_by_key(|error| (error.line, error.column));
+    Err(errors)
+}
+
+// プロセスの環境変数で置き換える
+pub fn interpolate_env(
+    values: &mut IndexMap<String, SysctlValue>,
+    input: &str,
+    options: &ParseOptions,
+) -> Result<(), Vec<ParseError>> {
+    interpolate(values, input, options, |name| std::env::var(name).ok())
+}
+
+// 1つの値を置き換える。定義されていない変数があれば、その名前を返す
+fn expand(value: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String, Vec<String>> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut undefined = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(escaped) = after.strip_prefix("${") {
+            expanded.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        match variable_name(after) {
+            Some((name, len)) => {
+                match lookup(name) {
+                    Some(variable) => expanded.push_str(&variable),
+                    None => undefined.push(name.to_owned()),
+                }
+                rest = &after[len..];
+            }
+            None => {
+                expanded.push('$');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    if undefined.is_empty() {
+        Ok(expanded)
+    } else {
+        Err(undefined)
+    }
+}
+
+// {NAME} の変数名と、} までの長さ
+fn variable_name(input: &str) -> Option<(&str, usize)> {
+    let body = input.strip_prefix('{')?;
+    let end = body.find('}')?;
+    let name = &body[..end];
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    let valid = (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some((name, end + 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_sysctl;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "SHMMAX" => Some("68719476736".to_owned()),
+            "HOST" => Some("db1".to_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let input =
+            "kernel.shmmax = ${SHMMAX}\nkernel.hostname = ${HOST}-${HOST}\nprice = $5 ${ x } $${HOST}\n";
+        let mut values = parse_sysctl(input).unwrap();
+        interpolate(&mut values, input, &ParseOptions::default(), lookup).unwrap();
+        assert_eq!(values["kernel.shmmax"].value, "68719476736");
+        assert_eq!(values["kernel.hostname"].value, "db1-db1");
+        assert_eq!(values["price"].value, "$5 ${ x } ${HOST}");
+    }
+
+    #[test]
+    fn test_undefined() {
+        let input = "a.key = ${MISSING}\nb.key = ${HOST} ${OTHER}\n";
+        let mut values = parse_sysctl(input).unwrap();
+        let errors = interpolate(&mut values, input, &ParseOptions::default(), lookup).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, ParseErrorKind::UndefinedVariable);
+        assert_eq!(errors[0].kind.code(), "SL0106");
+        assert_eq!((errors[0].line, errors[0].column), (1, 9));
+        assert_eq!((errors[1].line, errors[1].column), (2, 17));
+        assert_eq!(errors[1].snippet, "b.key = ${HOST} ${OTHER}");
+        assert_eq!(values["a.key"].value, "${MISSING}");
+    }
+}
@@ -0,0 +1,86 @@
+use indexmap::IndexMap;
+use std::borrow::Cow;
+
+// キーの区切りの書き方。カーネルは net.ipv4.ip_forward と net/ipv4/ip_forward のどちらも受け付ける
+// パースしたキーは . 区切りに揃えて持ち、出力するときだけ選んだ書き方にする
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyStyle {
+    #[default]
+    Dots,
+    Slashes,
+}
+
+impl KeyStyle {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dots" => Some(KeyStyle::Dots),
+            "slashes" => Some(KeyStyle::Slashes),
+            _ => None,
+        }
+    }
+
+    fn separator(self) -> char {
+        match self {
+            KeyStyle::Dots => '.',
+            KeyStyle::Slashes => '/',
+        }
+    }
+}
+
+// キーを指定した書き方にする
+// sysctl(8)と同じく最初の区切りで書き方を判断し、違っていれば . と / をすべて入れ替える
+// 名前の中の . は、/ 区切りでは / で書く
+// 例) net/ipv4/conf/eth0.100/forwarding -> net.ipv4.conf.eth0/100.forwarding
+pub fn normalize_key(key: &str, style: KeyStyle) -> Cow<'_, str> {
+    match key.find(['.', '/']) {
+        Some(index) if !key[index..].starts_with(style.separator()) => Cow::Owned(
+            key.chars()
+                .map(|c| match c {
+                    '.' => '/',
+                    '/' => '.',
+                    c => c,
+                })
+                .collect(),
+        ),
+        _ => Cow::Borrowed(key),
+    }
+}
+
+// すべてのキーを指定した書き方にしたもの。順序はそのまま
+pub fn normalize_keys<V: Clone>(
+    entries: &IndexMap<String, V>,
+    style: KeyStyle,
+) -> IndexMap<String, V> {
+    entries
+        .iter()
+        .map(|(key, value)| (normalize_key(key, style).into_owned(), value.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_key() {
+        let dots = |key| normalize_key(key, KeyStyle::Dots);
+        let slashes = |key| normalize_key(key, KeyStyle::Slashes);
+        assert_eq!(dots("net/ipv4/ip_forward"), "net.ipv4.ip_forward");
+        assert_eq!(dots("net.ipv4.ip_forward"), "net.ipv4.ip_forward");
+        assert_eq!(slashes("net.ipv4.ip_forward"), "net/ipv4/ip_forward");
+        assert_eq!(
+            dots("net/ipv4/conf/eth0.100/forwarding"),
+            "net.ipv4.conf.eth0/100.forwarding"
+        );
+        // すでに . 区切りなら、名前の中の / はそのまま
+        assert_eq!(
+            dots("net.ipv4.conf.eth0/100.forwarding"),
+            "net.ipv4.conf.eth0/100.forwarding"
+        );
+        assert_eq!(
+            slashes("net.ipv4.conf.eth0/100.forwarding"),
+            "net/ipv4/conf/eth0.100/forwarding"
+        );
+        assert_eq!(dots("hostname"), "hostname");
+    }
+}
@@ -0,0 +1,47 @@
+// sysctl.conf形式のファイルのパーサーと、スキーマによる検証
+// 他のプログラムからバイナリを呼ばずに使えるよう、主な関数と型をここから公開する
+//
+// 例)
+// let value = load_sysctl::parse_sysctl("net.ipv4.ip_forward = 1\n").unwrap();
+// let schema = load_sysctl::parse_schema("net.ipv4.ip_forward: bool\n").unwrap();
+// load_sysctl::validate_by_schema(&value, &schema, &Default::default()).unwrap();
+
+pub mod allowlist;
+pub mod analysis;
+pub mod apply;
+pub mod baseline;
+pub mod bundle;
+pub mod checkstyle;
+pub mod conditional;
+pub mod diagnostics;
+pub mod dialect;
+pub mod document;
+pub mod encoding;
+pub mod explain;
+pub mod export;
+pub mod fingerprint;
+pub mod fix;
+pub mod format;
+pub mod i18n;
+pub mod inputs;
+pub mod interpolate;
+pub mod key;
+pub mod merge;
+pub mod parser;
+pub mod pattern;
+pub mod policy;
+pub mod repl;
+pub mod report;
+pub mod sarif;
+pub mod schema_diff;
+pub mod staged;
+pub mod style;
+pub mod tap;
+pub mod targets;
+pub mod template;
+pub mod types;
+pub mod validation;
+
+pub use parser::{parse_schema, parse_sysctl};
+pub use types::*;
+pub use validation::{validate_by_schema, ValidationOptions};
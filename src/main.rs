@@ -1,97 +1,1470 @@
-use parser::{parse_schema, parse_sysctl};
-use std::fs::File;
-use std::io::{self, Read};
-use std::{env, path::Path};
-use validation::validate_by_schema;
+mod cli;
 
-mod parser;
-mod types;
-mod validation;
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Options};
+use indexmap::IndexMap;
+use load_sysctl::allowlist::{check_allowlist, parse_allowlist};
+use load_sysctl::analysis::{
+    find_disabled_dependencies, find_outliers, ignore_error_keys, schema_coverage,
+};
+use load_sysctl::apply::{apply_checked, ApplyFailure};
+use load_sysctl::baseline::Baseline;
+use load_sysctl::bundle::{bundle, load_bundle};
+use load_sysctl::checkstyle::to_checkstyle;
+use load_sysctl::conditional::{evaluate_conditionals, has_directives, KernelVersion};
+use load_sysctl::diagnostics::render_parse_error;
+use load_sysctl::dialect::check_namespaces;
+use load_sysctl::encoding::decode_text;
+use load_sysctl::explain::{explain_markdown, explain_text};
+use load_sysctl::export::{to_json, to_toml, to_yaml};
+use load_sysctl::fingerprint::fingerprint;
+use load_sysctl::fix::{format_defaults, missing_defaults};
+use load_sysctl::format::{format_sysctl, FormatOptions};
+use load_sysctl::i18n::{set_lang, Lang};
+use load_sysctl::inputs::expand_inputs;
+use load_sysctl::interpolate::interpolate_env;
+use load_sysctl::key::normalize_keys;
+use load_sysctl::merge::{find_untrusted_sources, format_merged, merge};
+use load_sysctl::parser::{
+    extract_annotated_schema, extract_embedded_schema, find_duplicate_keys, key_origins,
+    parse_schema, parse_sysctl, parse_sysctl_as, parse_sysctl_documented, parse_sysctl_file,
+    parse_sysctl_lenient, parse_sysctl_recovering_with, parse_sysctl_spanned_with, IncludeError,
+    ParseOptions,
+};
+use load_sysctl::policy::{evaluate_policy, parse_policy, Finding, Rule, Severity};
+use load_sysctl::repl::run_repl;
+use load_sysctl::report::{
+    reports_to_json, reports_to_json_report, write_failed_keys, write_github_annotations,
+    write_omitted_errors, write_parse_warnings, write_text_report, Report, Results, Source,
+};
+use load_sysctl::sarif::to_sarif;
+use load_sysctl::schema_diff::diff_schemas;
+use load_sysctl::staged::{read_staged, staged_conf_files};
+use load_sysctl::style::{paint, set_color, Style};
+use load_sysctl::tap::to_tap;
+use load_sysctl::targets::{
+    parse_target_spec, partially_supported_keys, validate_targets, TargetSchema,
+};
+use load_sysctl::template::{parse_vars, render_template};
+use load_sysctl::tr;
+use load_sysctl::types::{ParseError, ParseWarning, Schema, SchemaEntry};
+use load_sysctl::validation::{validate_by_schema, Validator};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+use tracing::{debug, info, Level};
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <input_file>", args[0]);
-        std::process::exit(1);
+// 終了コード。READMEの表と合わせる
+const EXIT_USAGE: i32 = 1;
+const EXIT_SYNTAX_ERROR: i32 = 2;
+const EXIT_SCHEMA_VIOLATION: i32 = 3;
+const EXIT_IO_ERROR: i32 = 4;
+const EXIT_APPLY_ERROR: i32 = 5;
+
+// 入力ファイルの代わりに標準入力を読み込むときのパス
+const STDIN_PATH: &str = "-";
+
+fn main() {
+    // 標準入力の読み込みや出力の書き込みに失敗した場合
+    if let Err(error) = run() {
+        eprintln!(
+            "{}",
+            tr!(
+                "入出力に失敗しました: {}",
+                "an I/O error occurred: {}",
+                io_error_reason(&error)
+            )
+        );
+        std::process::exit(EXIT_IO_ERROR);
+    }
+}
+
+fn run() -> io::Result<()> {
+    // 引数の誤りのメッセージにも使うため、先に環境変数のロケールから言語を選んでおく
+    // どちらでもないロケールでは日本語にする
+    set_lang(Lang::from_env().unwrap_or_default());
+    let cli = Cli::try_parse().unwrap_or_else(|error| {
+        // --help と --version は標準出力に書いて正常終了する
+        let code = if error.use_stderr() { EXIT_USAGE } else { 0 };
+        let _ = error.print();
+        std::process::exit(code);
+    });
+    let Some((files, options)) = cli.into_invocation() else {
+        eprint!("{}", Cli::command().render_help());
+        std::process::exit(EXIT_USAGE);
+    };
+    // --lang ja|en は環境変数より優先する
+    if let Some(lang) = options.lang {
+        set_lang(lang);
+    }
+    // エラーのキーや型、重要度に色を付ける。--color が auto なら標準出力が端末で NO_COLOR が無い場合だけ
+    let no_color = std::env::var("NO_COLOR").ok();
+    set_color(
+        options
+            .color
+            .enabled(io::stdout().is_terminal(), no_color.as_deref()),
+    );
+    // --verbose で開いたファイルやパース・検証の件数と時間を、--trace か -vv でキーごとの処理も標準エラー出力に表示する
+    let log_level = if options.trace {
+        Some(Level::TRACE)
+    } else if options.verbose {
+        Some(Level::DEBUG)
+    } else {
+        None
+    };
+    if let Some(level) = log_level {
+        tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_writer(io::stderr)
+            .with_ansi(io::stderr().is_terminal())
+            .init();
+    }
+    // スキーマの差分や説明、対話的な検証、マージは入力ファイルを1つだけ受け取る
+    let single_file_mode = options.diff_schema.is_some()
+        || options.explain
+        || options.repl
+        || options.stream
+        || options.from_bundle
+        || !options.merge_paths.is_empty();
+    if single_file_mode && files.len() > 1 {
+        eprintln!(
+            "{}",
+            tr!(
+                "このモードでは入力ファイルを1つだけ指定してください。",
+                "this mode takes exactly one input file."
+            )
+        );
+        std::process::exit(EXIT_USAGE);
+    }
+    // --staged では、ステージされたファイルを複数まとめて検証する
+    if single_file_mode && options.staged {
+        eprintln!(
+            "{}",
+            tr!(
+                "このモードでは --staged を指定できません。",
+                "--staged cannot be used in this mode."
+            )
+        );
+        std::process::exit(EXIT_USAGE);
+    }
+    // 標準入力は一度しか読めず、--repl と --stream では検証する行を読むのに使う
+    let stdin_count = files.iter().filter(|path| *path == STDIN_PATH).count();
+    if stdin_count > 1 || (stdin_count > 0 && (options.repl || options.stream)) {
+        eprintln!(
+            "{}",
+            tr!(
+                "標準入力(-)は1回だけ、--repl と --stream 以外で指定できます。",
+                "standard input (-) can be given only once, and not with --repl or --stream."
+            )
+        );
+        std::process::exit(EXIT_USAGE);
+    }
+    // --fix は入力ファイルに追記するため、標準入力とは併用できない
+    if stdin_count > 0 && options.fix {
+        eprintln!(
+            "{}",
+            tr!(
+                "--fix では標準入力(-)を指定できません。",
+                "--fix cannot be used with standard input (-)."
+            )
+        );
+        std::process::exit(EXIT_USAGE);
+    }
+    let input_file_path = &files[0];
+    let use_no_schema = options.no_schema;
+    let validation_options = options.validation_options();
+
+    // --diff-schema <new> の場合は、入力ファイルを古いスキーマとして比較する
+    if let Some(new_schema_path) = &options.diff_schema {
+        let old_schema = read_schema(input_file_path);
+        let new_schema = read_schema(new_schema_path);
+        let diff = diff_schemas(&old_schema, &new_schema);
+        if diff.is_empty() {
+            println!(
+                "{}",
+                tr!(
+                    "スキーマに差分はありません。",
+                    "the schemas have no differences."
+                )
+            );
+        } else {
+            print!("{}", diff);
+        }
+        return Ok(());
+    }
+
+    // --explain の場合は、入力ファイルをスキーマとして、その内容を説明する
+    // --format markdown でmarkdownの表として出力する
+    if options.explain {
+        let schema = read_schema(input_file_path);
+        match options.format.as_str() {
+            "text" => print!("{}", explain_text(&schema)),
+            "markdown" => print!("{}", explain_markdown(&schema)),
+            format => {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "--explain では --format {} は使えません。text か markdown を指定してください。",
+                        "--explain does not support --format {}; use text or markdown.",
+                        format
+                    )
+                );
+                std::process::exit(EXIT_USAGE);
+            }
+        }
+        return Ok(());
+    }
+
+    // --repl の場合は、入力ファイルをスキーマとして、標準入力の行をその場でパース・検証する
+    // --no-schema を付けるとパースだけを行う
+    if options.repl {
+        let schema = (!use_no_schema).then(|| read_schema(input_file_path));
+        return run_repl(
+            io::stdin().lock(),
+            &mut io::stdout(),
+            schema.as_ref(),
+            &validation_options,
+        );
+    }
+
+    // --stream の場合は、入力ファイルをスキーマとして、標準入力から追記される設定を1行ずつ検証する
+    if options.stream {
+        let schema = read_schema(input_file_path);
+        let mut validator = Validator::new(&schema, validation_options);
+        let mut has_errors = false;
+        for line in io::stdin().lock().lines() {
+            match validator.feed(&line?) {
+                Ok(errors) => {
+                    for error in errors {
+                        has_errors = true;
+                        println!("[{}] {:#}", error.code(), error);
+                    }
+                }
+                Err(warning) => {
+                    has_errors = true;
+                    println!("[{}] {}", ParseWarning::CODE, warning);
+                }
+            }
+        }
+        for error in validator.finish() {
+            has_errors = true;
+            println!("[{}] {:#}", error.code(), error);
+        }
+        if has_errors && options.fail_on.fails("error") {
+            std::process::exit(EXIT_SCHEMA_VIOLATION);
+        }
+        return Ok(());
     }
 
-    let input_file_path = &args[1];
-    let use_validation = match &args.get(2) {
-        Some(v) => {
-            if *v == "--validate" || *v == "-v" {
-                true
-            } else {
-                false
+    // --from-bundle の場合は、入力ファイルを--bundleで出力したJSONとして検証する
+    if options.from_bundle {
+        let bundle_str = read_file_or_exit(input_file_path, &tr!("バンドル", "bundle"));
+        let (sysctl_data, schema) = load_bundle(&bundle_str).unwrap_or_else(|error| {
+            println!(
+                "{}",
+                tr!(
+                    "バンドルの形式に誤りがあります: {}",
+                    "invalid bundle: {}",
+                    error
+                )
+            );
+            std::process::exit(EXIT_SYNTAX_ERROR);
+        });
+        if let Err(validation_errors) =
+            validate_by_schema(&sysctl_data, &schema, &validation_options)
+        {
+            println!(
+                "{}",
+                tr!("スキーマエラーがありました。", "schema errors were found.")
+            );
+            for error in validation_errors {
+                println!("[{}] {:#}", error.code(), error);
+            }
+            if options.fail_on.fails("error") {
+                std::process::exit(EXIT_SCHEMA_VIOLATION);
             }
+            return Ok(());
         }
-        None => false,
+        println!(
+            "{}",
+            tr!(
+                "スキーマエラーはありませんでした。",
+                "no schema errors were found."
+            )
+        );
+        return Ok(());
+    }
+
+    // --merge <file> の場合は、入力ファイルに指定したファイルを順に重ねた結果を出力する
+    let merge_paths = &options.merge_paths;
+    if !merge_paths.is_empty() {
+        let policy = options.merge_comments.unwrap_or_default();
+        let layers = std::iter::once(input_file_path)
+            .chain(merge_paths)
+            .map(|path| {
+                let input_str = read_file_or_exit(path, &tr!("設定ファイル", "configuration file"));
+                let mut entries = parse_sysctl_documented(&input_str).unwrap_or_else(|error| {
+                    println!(
+                        "{}",
+                        tr!(
+                            "{}: [{}] 文法に誤りがあります。{}",
+                            "{}: [{}] syntax error: {}",
+                            path,
+                            error.kind.code(),
+                            error
+                        )
+                    );
+                    std::process::exit(EXIT_SYNTAX_ERROR);
+                });
+                // 上書きした値の設定元を示せるよう、キーごとに設定した行を記録する
+                let mut origins = key_origins(path, &input_str, &ParseOptions::default());
+                for (key, documented) in entries.iter_mut() {
+                    documented.value.origin = origins.swap_remove(key);
+                }
+                (path.to_owned(), entries)
+            })
+            .collect();
+        let merged = merge(layers, policy);
+        // 元のファイルのスキーマに%trustedがあれば、保護されたキーの値の出どころを確認する
+        let schema_paths = if options.schemas.is_empty() {
+            sibling_schema_path(input_file_path).into_iter().collect()
+        } else {
+            options.schemas.clone()
+        };
+        if !use_no_schema && !schema_paths.is_empty() {
+            let mut schema = Schema::default();
+            for path in &schema_paths {
+                schema.merge(read_schema(path));
+            }
+            for untrusted in find_untrusted_sources(&merged, &schema) {
+                eprintln!(
+                    "{}: {}",
+                    paint(tr!("警告", "warning"), Style::Warning),
+                    untrusted
+                );
+            }
+        }
+        print!("{}", format_merged(&merged, options.provenance));
+        return Ok(());
+    }
+
+    // --target-schema version=path で、バージョンごとのスキーマを指定する
+    let target_schemas = read_target_schemas(&options.target_schemas);
+    if let Some(target) = &options.target {
+        if !target_schemas.iter().any(|t| &t.version == target) {
+            eprintln!(
+                "{}",
+                tr!(
+                    "--target '{}' のスキーマが指定されていません。",
+                    "no schema is given for --target '{}'.",
+                    target
+                )
+            );
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+
+    // --staged では、指定したパスのうちステージされた *.conf だけを検証する
+    let inputs = if options.staged {
+        let inputs = staged_conf_files(&files).unwrap_or_else(|error| {
+            eprintln!(
+                "{}",
+                tr!(
+                    "ステージされたファイルを調べられませんでした: {}",
+                    "could not list the staged files: {}",
+                    io_error_reason(&error)
+                )
+            );
+            std::process::exit(EXIT_IO_ERROR);
+        });
+        if inputs.is_empty() {
+            if !options.quiet {
+                println!(
+                    "{}",
+                    tr!(
+                        "ステージされた *.conf はありませんでした。",
+                        "no *.conf files are staged."
+                    )
+                );
+            }
+            return Ok(());
+        }
+        inputs
+    } else {
+        // ディレクトリは、その中の *.conf をsysctl.dと同じ順に読み込む
+        expand_inputs(&files).unwrap_or_else(|error| {
+            eprintln!(
+                "{}",
+                tr!(
+                    "入力ファイルを探せませんでした: {}",
+                    "could not look for input files: {}",
+                    io_error_reason(&error)
+                )
+            );
+            std::process::exit(EXIT_IO_ERROR);
+        })
     };
+    if inputs.len() > 1 && options.output.is_some() {
+        eprintln!(
+            "{}",
+            tr!(
+                "--output は入力ファイルが1つの場合にだけ指定できます。",
+                "--output can only be used with a single input file."
+            )
+        );
+        std::process::exit(EXIT_USAGE);
+    }
+    // 複数のファイルは1つずつ検証し、結果をファイルごとにまとめて出力する
+    // 終了コードは、各ファイルの終了コードのうち最も大きいもの
+    let mut reports = Vec::new();
+    let mut status = 0;
+    let mut baseline = options.baseline.as_deref().map(read_baseline);
+    for (index, input_file_path) in inputs.iter().enumerate() {
+        // --quiet では成功したファイルについて何も出力しないため、見出しも付けない
+        if inputs.len() > 1 && !options.use_report() && !options.quiet {
+            if index > 0 {
+                println!();
+            }
+            println!("==> {} <==", input_file_path);
+        }
+        status = status.max(check_file(
+            input_file_path,
+            &options,
+            &target_schemas,
+            &mut reports,
+            baseline.as_mut(),
+        )?);
+    }
+    // ベースラインのファイルが無かった場合は、今回の違反を記録する
+    if let (Some(baseline_path), Some(baseline)) = (&options.baseline, &baseline) {
+        if baseline.is_recording() {
+            if let Err(error) = fs::write(baseline_path, baseline.to_json() + "\n") {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "ベースラインを書き込めませんでした: {}",
+                        "could not write the baseline: {}",
+                        io_error_reason(&error)
+                    )
+                );
+                std::process::exit(EXIT_IO_ERROR);
+            }
+            eprintln!(
+                "{}",
+                tr!(
+                    "{}件の違反をベースライン {} に記録しました。",
+                    "recorded {} violations in the baseline {}.",
+                    baseline.len(),
+                    baseline_path
+                )
+            );
+        }
+    }
+    // --ci のレポートは、ファイルが1つならそのまま、複数ならJSONの配列として出力する
+    // --format sarif では、すべてのファイルの結果を1つのSARIFにまとめる
+    // --format github では、診断ごとにGitHub Actionsの注釈を1行ずつ出力する
+    // --format json-report では、すべてのファイルの診断をファイル名を付けた1つの一覧にする
+    // --format checkstyle では、ファイルごとの<file>にまとめたCheckstyleのXMLにする
+    // --format tap では、スキーマのキーごとに ok か not ok を出力する
+    if options.use_report() {
+        match (options.format.as_str(), reports.as_slice()) {
+            ("sarif", reports) => println!("{}", to_sarif(reports)),
+            ("github", reports) => write_github_annotations(&mut io::stdout(), reports)?,
+            ("json-report", reports) => println!("{}", reports_to_json_report(reports)),
+            ("checkstyle", reports) => print!("{}", to_checkstyle(reports)),
+            ("tap", reports) => print!("{}", to_tap(reports)),
+            (_, [report]) => println!("{}", report.to_json()),
+            (_, reports) => println!("{}", reports_to_json(reports)),
+        }
+    }
+    if status != 0 {
+        std::process::exit(status);
+    }
+    Ok(())
+}
 
-    let input_str = read_file(&input_file_path).expect("ファイルの読み込みに失敗しました。");
-    let parse_sysctl_result = parse_sysctl(&input_str);
-    if parse_sysctl_result.is_err() {
-        println!("文法に誤りがあります。");
-        std::process::exit(1);
+// 1つの設定ファイルをパース・検証して結果を出力し、終了コードを返す
+// --ci のレポートは出力せずに reports に加える
+fn check_file(
+    input_file_path: &str,
+    options: &Options,
+    target_schemas: &[TargetSchema],
+    reports: &mut Vec<Report>,
+    baseline: Option<&mut Baseline>,
+) -> io::Result<i32> {
+    // --ci は検証を有効にし、結果をJSONレポートとしてのみ出力する
+    let use_ci = options.use_report();
+    // --target で、どのバージョンのスキーマで検証するかを選ぶ
+    let target = options.target.as_ref();
+    // スキーマファイルの代わりに、キーの直前の # @type 注釈からスキーマを作って検証する
+    let use_annotations = options.annotations;
+    let use_validation = use_ci
+        || target.is_some()
+        || use_annotations
+        || options.baseline.is_some()
+        || options.staged
+        || options.fix
+        || options.failed_only
+        || options.validate;
+    let use_profile = options.profile;
+    let use_fingerprint = options.fingerprint;
+    let use_list_ignored = options.list_ignored;
+    // エディタなどのために、各キーの入力中のバイト範囲を出力する
+    let use_key_spans = options.key_spans;
+    let use_pretty = options.pretty;
+    // 成功した場合は何も出力せず、終了コードだけで結果を返す
+    let use_quiet = options.quiet;
+    // 検証に成功したら、データの代わりに設定とスキーマをまとめたJSONを出力する
+    let use_bundle = options.bundle;
+    let use_apply = options.apply;
+    let allow_dangerous = options.allow_dangerous;
+    // 検証の後、エラーになったキーとその問題だけを出力する
+    let use_failed_only = options.failed_only;
+    // procpsかbusyboxのsysctlと同じ規則でパースする
+    let compat = options.compat();
+    // スキーマのキーのうち設定されているものの割合の下限(%)
+    let min_coverage = options.min_coverage;
+    // --validate と併用しても、スキーマを探さない
+    let use_no_schema = options.no_schema;
+    let validation_options = options.validation_options();
+    // --fmt の場合はデバッグ形式の代わりにsysctl.conf形式で出力する
+    let format_options = options.fmt.then_some(FormatOptions {
+        bool_style: options.bool_style,
+        key_style: options.normalize,
+    });
+    // --bool-style でbool型のキーを揃えるため、検証しなくてもスキーマを読む
+    let use_bool_style = format_options
+        .as_ref()
+        .is_some_and(|format_options| format_options.bool_style.is_some());
+
+    let Some(input_str) = read_input_or_report(
+        input_file_path,
+        &tr!("設定ファイル", "configuration file"),
+        options.staged,
+    ) else {
+        return Ok(EXIT_IO_ERROR);
+    };
+    // --vars の変数で {{ name }} を置き換えてから、置き換えた結果をパースして検証する
+    let input_str = match &options.vars {
+        Some(vars_path) => match render_template(&input_str, &read_vars(vars_path)) {
+            Ok(rendered) => rendered,
+            Err(errors) => {
+                report_syntax_errors(input_file_path, &errors, options, reports)?;
+                return Ok(EXIT_SYNTAX_ERROR);
+            }
+        },
+        None => input_str,
+    };
+    // #@if kernel >= 5.10 のブロックを、カーネルのバージョンに合わせて読み込むか読み飛ばす
+    let mut input_str = if has_directives(&input_str) {
+        let Some(kernel_version) = options
+            .kernel_version
+            .clone()
+            .or_else(KernelVersion::running)
+        else {
+            eprintln!(
+                "{}",
+                tr!(
+                    "実行中のカーネルのバージョンが分かりません。--kernel-version で指定してください",
+                    "cannot determine the running kernel version; specify it with --kernel-version"
+                )
+            );
+            return Ok(EXIT_USAGE);
+        };
+        match evaluate_conditionals(&input_str, &kernel_version) {
+            Ok(evaluated) => evaluated,
+            Err(errors) => {
+                report_syntax_errors(input_file_path, &errors, options, reports)?;
+                return Ok(EXIT_SYNTAX_ERROR);
+            }
+        }
+    } else {
+        input_str
+    };
+    let parse_options = options.parse_options();
+    let parse_started = Instant::now();
+    let mut parse_warnings = Vec::new();
+    let mut sysctl_data = if let Some(compat) = compat {
+        let (sysctl_data, warnings) = parse_sysctl_as(&input_str, compat);
+        if !use_quiet {
+            write_parse_warnings(&mut io::stderr(), input_file_path, &warnings)?;
+        }
+        parse_warnings = warnings;
+        sysctl_data
+    } else if options.follow_includes && input_file_path != STDIN_PATH {
+        // 取り込んだファイルの誤りは、そのファイルの名前と合わせて報告する
+        let kernel_version = options
+            .kernel_version
+            .clone()
+            .or_else(KernelVersion::running);
+        match parse_sysctl_file(
+            Path::new(input_file_path),
+            &parse_options,
+            kernel_version.as_ref(),
+        ) {
+            Ok(config) => config.values,
+            Err(IncludeError::Parse { file, errors }) => {
+                // 文字の出力では誤りの行に名前が付かないため、取り込んだファイルの名前を先に出す
+                if file != input_file_path && !options.use_report() && !options.pretty {
+                    println!(
+                        "{}",
+                        tr!(
+                            "'{}'の文法に誤りがあります。",
+                            "syntax errors in '{}':",
+                            file
+                        )
+                    );
+                }
+                report_syntax_errors(&file, &errors, options, reports)?;
+                return Ok(EXIT_SYNTAX_ERROR);
+            }
+            Err(error @ IncludeError::UnknownKernelVersion { .. }) => {
+                eprintln!("{}", error);
+                return Ok(EXIT_USAGE);
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                return Ok(EXIT_IO_ERROR);
+            }
+        }
+    } else if options.lenient {
+        let (sysctl_data, warnings) = parse_sysctl_lenient(&input_str, &parse_options);
+        if !use_quiet {
+            write_parse_warnings(&mut io::stderr(), input_file_path, &warnings)?;
+        }
+        parse_warnings = warnings;
+        sysctl_data
+    } else {
+        // 最初の誤りで止めずに、誤りのある行をすべて報告する
+        let (sysctl_data, syntax_errors) = parse_sysctl_recovering_with(&input_str, &parse_options);
+        if !syntax_errors.is_empty() {
+            report_syntax_errors(input_file_path, &syntax_errors, options, reports)?;
+            return Ok(EXIT_SYNTAX_ERROR);
+        }
+        sysctl_data
+    };
+    // デプロイ時に値を埋めるテンプレートのため、値の中の ${NAME} を環境変数で置き換える
+    if options.expand_env {
+        if let Err(errors) = interpolate_env(&mut sysctl_data, &input_str, &parse_options) {
+            report_syntax_errors(input_file_path, &errors, options, reports)?;
+            return Ok(EXIT_SYNTAX_ERROR);
+        }
+    }
+    if use_profile {
+        eprintln!(
+            "{}",
+            tr!(
+                "[profile] パース時間: {:?}, エントリ数: {}",
+                "[profile] parse time: {:?}, entries: {}",
+                parse_started.elapsed(),
+                sysctl_data.len()
+            )
+        );
     }
-    let sysctl_data = parse_sysctl_result.unwrap().1;
 
-    let schema_file_path = format!("{}.schema", input_file_path);
-    if use_validation && Path::new(&schema_file_path).exists() {
-        let schema_str =
-            read_file(&schema_file_path).expect("スキーマファイルの読み込みに失敗しました。");
+    if use_fingerprint {
+        println!("{}", fingerprint(&sysctl_data));
+        return Ok(0);
+    }
+
+    if use_key_spans {
+        let entries = match parse_sysctl_spanned_with(&input_str, &parse_options) {
+            Ok(entries) => entries,
+            Err(error) => {
+                println!(
+                    "{}",
+                    tr!(
+                        "[{}] 文法に誤りがあります。{}",
+                        "[{}] syntax error: {}",
+                        error.kind.code(),
+                        error
+                    )
+                );
+                return Ok(EXIT_SYNTAX_ERROR);
+            }
+        };
+        let mut spans: Vec<_> = entries
+            .iter()
+            .map(|(key, entry)| (entry.key_span, key))
+            .collect();
+        spans.sort();
+        for ((start, end), key) in spans {
+            println!("{}..{}\t{}", start, end, key);
+        }
+        return Ok(0);
+    }
 
-        let parse_schema_result = parse_schema(&schema_str);
-        if parse_schema_result.is_err() {
-            println!("スキーマファイルの文法に誤りがあります");
-            std::process::exit(1);
+    if use_list_ignored {
+        for key in ignore_error_keys(&sysctl_data) {
+            println!("{}", key);
         }
-        let schema = parse_schema_result.unwrap().1;
+        return Ok(0);
+    }
 
-        if let Err(validation_errors) = validate_by_schema(&sysctl_data, &schema) {
-            println!("スキーマエラーがありました。");
-            for error in validation_errors {
-                match error {
-                    types::ValidationError::MissingKey(key) => {
-                        println!("必要なキーである'{}'が存在しません", key);
-                    }
-                    types::ValidationError::UnknownKey(key) => {
-                        println!("定義されていない'{}'が存在しており、これは不要です", key)
-                    }
-                    types::ValidationError::WrongType {
-                        key_name,
-                        expect,
-                        actual,
-                    } => {
-                        println!(
-                            "'{}'の型が間違っています。{}が必要ですが、{}の形式になっています。",
-                            key_name, expect, actual
+    // <入力ファイル>.schema が無ければ、ファイル内に埋め込まれたスキーマを使う
+    // --schema を指定した場合は、そのファイルを順に重ねたものを使う
+    // --target の場合はそのターゲットのスキーマを使うため、ここでは読まない
+    let mut annotated_schema = None;
+    let schema_sources: Vec<(String, String)> = if !(use_validation
+        || min_coverage.is_some()
+        || use_bool_style)
+        || use_no_schema
+        || target.is_some()
+    {
+        vec![]
+    } else if !options.schemas.is_empty() {
+        options
+            .schemas
+            .iter()
+            .map(|path| {
+                let schema_str = read_file_or_exit(path, &tr!("スキーマファイル", "schema file"));
+                (path.clone(), schema_str)
+            })
+            .collect()
+    } else if use_annotations {
+        // 文法の誤りはパースの時点で報告済み
+        annotated_schema = extract_annotated_schema(&input_str);
+        annotated_schema
+            .iter()
+            .map(|annotated| (input_file_path.to_owned(), annotated.schema.clone()))
+            .collect()
+    } else if let Some(schema_file_path) = sibling_schema_path(input_file_path) {
+        let Some(schema_str) = read_input_or_report(
+            &schema_file_path,
+            &tr!("スキーマファイル", "schema file"),
+            options.staged,
+        ) else {
+            return Ok(EXIT_IO_ERROR);
+        };
+        vec![(schema_file_path, schema_str)]
+    } else {
+        extract_embedded_schema(&input_str)
+            .map(|schema_str| (input_file_path.to_owned(), schema_str))
+            .into_iter()
+            .collect()
+    };
+    if schema_sources.is_empty() {
+        debug!("スキーマを使いません");
+    }
+
+    let mut schema = None;
+    for (schema_file_path, schema_str) in schema_sources {
+        debug!(path = %schema_file_path, "スキーマを使います");
+        match parse_schema(&schema_str) {
+            Ok(parsed) => schema.get_or_insert_with(Schema::default).merge(parsed),
+            Err(error) => {
+                // 注釈から作ったスキーマの誤りは、設定ファイルの注釈の位置で報告する
+                let error = match &annotated_schema {
+                    Some(annotated) => annotated.locate(error),
+                    None => error,
+                };
+                if use_ci {
+                    reports.push(Report::syntax_errors(&schema_file_path, &[error]));
+                } else if use_pretty {
+                    print!("{}", render_parse_error(&schema_file_path, &error));
+                } else {
+                    println!(
+                        "{}",
+                        tr!(
+                            "[{}] スキーマファイルの文法に誤りがあります。{}",
+                            "[{}] syntax error in the schema file: {}",
+                            error.kind.code(),
+                            error
                         )
-                    }
-                    types::ValidationError::TooLongLine(key) => {
-                        println!("'{}'の値の行長が最大である4096を超えています。", key);
-                    }
+                    );
                 }
+                return Ok(EXIT_SYNTAX_ERROR);
             }
-            std::process::exit(1);
-        } else {
+        }
+    }
+
+    // --target のスキーマがあることは、ファイルを読み込む前に確認済み
+    let schema = match target {
+        Some(target) => target_schemas
+            .iter()
+            .find(|t| &t.version == target)
+            .map(|t| t.schema.clone()),
+        None => schema,
+    };
+
+    // --target を指定せずに複数のスキーマを渡した場合は、すべてのターゲットで検証する
+    if target.is_none() && !target_schemas.is_empty() {
+        let mut has_errors = false;
+        for (version, errors) in validate_targets(&sysctl_data, target_schemas, &validation_options)
+        {
+            if errors.is_empty() {
+                if !use_quiet {
+                    println!(
+                        "{}",
+                        tr!(
+                            "[{}] スキーマエラーはありませんでした。",
+                            "[{}] no schema errors were found.",
+                            version
+                        )
+                    );
+                }
+                continue;
+            }
+            has_errors = true;
+            println!(
+                "{}",
+                tr!(
+                    "[{}] スキーマエラーがありました。",
+                    "[{}] schema errors were found.",
+                    version
+                )
+            );
+            for error in errors {
+                println!("[{}] {:#}", error.code(), error);
+            }
+        }
+        let partial_keys = partially_supported_keys(&sysctl_data, target_schemas);
+        if !partial_keys.is_empty() && !use_quiet {
             println!(
-                "スキーマエラーはありませんでした。読み込んだデータをRust形式で出力します。{:#?}",
-                &sysctl_data
+                "{}",
+                tr!(
+                    "一部のターゲットでのみ有効なキーがあります。",
+                    "some keys are only valid for some targets."
+                )
+            );
+            for (key, versions) in partial_keys {
+                println!(
+                    "{}",
+                    tr!(
+                        "'{}'は{}でのみ有効です。",
+                        "'{}' is only valid for {}.",
+                        key,
+                        versions.join(", ")
+                    )
+                );
+            }
+        }
+        let failed = has_errors && options.fail_on.fails("error");
+        return Ok(if failed { EXIT_SCHEMA_VIOLATION } else { 0 });
+    }
+
+    // --fix では、既定値で補えるキーを設定ファイルの末尾に追記し、追記した後の内容を読み直して検証する
+    if options.fix {
+        let Some(schema) = &schema else {
+            eprintln!(
+                "{}",
+                tr!("--fix にはスキーマが必要です。", "--fix requires a schema.")
+            );
+            return Ok(EXIT_USAGE);
+        };
+        let entries = missing_defaults(&sysctl_data, schema);
+        if !entries.is_empty() {
+            let appended = match append_defaults(input_file_path, &entries, &parse_options) {
+                Ok(appended) => appended,
+                Err(error) => {
+                    eprintln!(
+                        "{}",
+                        tr!(
+                            "'{}'に既定値を追記できませんでした: {}",
+                            "cannot append the defaults to '{}': {}",
+                            input_file_path,
+                            io_error_reason(&error)
+                        )
+                    );
+                    return Ok(EXIT_IO_ERROR);
+                }
+            };
+            // 追記した行が実際にどのキーとして読まれるかは、読み直さないと分からない
+            // 元の行の誤りは報告済みのため、ここでは読み飛ばす
+            input_str.push_str(&appended);
+            let (fixed_data, _) = match compat {
+                Some(compat) => parse_sysctl_as(&input_str, compat),
+                None => parse_sysctl_lenient(&input_str, &parse_options),
+            };
+            for (key, value) in fixed_data {
+                if !sysctl_data.contains_key(&key) {
+                    sysctl_data.insert(key, value);
+                }
+            }
+            for entry in &entries {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "{}: '{}'に既定値'{}'を追記しました。",
+                        "{}: appended '{}' with the default '{}'.",
+                        input_file_path,
+                        entry.name,
+                        entry.default.as_deref().unwrap_or_default()
+                    )
+                );
+            }
+        }
+    }
+
+    let mut validation_errors = Vec::new();
+    let mut disabled_dependencies = Vec::new();
+    if let Some(schema) = schema.as_ref().filter(|_| use_validation) {
+        disabled_dependencies = find_disabled_dependencies(&sysctl_data, schema);
+        let validate_started = Instant::now();
+        if let Err(errors) = validate_by_schema(&sysctl_data, schema, &validation_options) {
+            validation_errors = errors;
+        }
+        if use_profile {
+            eprintln!(
+                "{}",
+                tr!(
+                    "[profile] 検証時間: {:?}, スキーマエントリ数: {}",
+                    "[profile] validation time: {:?}, schema entries: {}",
+                    validate_started.elapsed(),
+                    schema.entries.len()
+                )
             );
         }
+    }
+
+    // --allowlist には許可するキーを1行に1つ書いたファイルを指定する。型は検証しない
+    if let Some(allowlist_path) = &options.allowlist {
+        let allowlist_str = read_file_or_exit(allowlist_path, &tr!("許可リスト", "allowlist"));
+        validation_errors.extend(check_allowlist(
+            &sysctl_data,
+            &parse_allowlist(&allowlist_str),
+        ));
+    }
+
+    // --dialect を指定した場合は、そのOSのsysctlに無い名前空間のキーもエラーにする
+    if let Some(dialect) = options.dialect.filter(|_| use_validation) {
+        validation_errors.extend(check_namespaces(&sysctl_data, dialect));
+    }
+
+    // 同じキーが複数回あると最後の値だけが残るため、既定では警告する
+    let mut duplicate_keys = find_duplicate_keys(&input_str, &parse_options);
+    if options.duplicate_keys == Severity::Error {
+        validation_errors.append(&mut duplicate_keys);
+    }
+
+    let mut policy_findings = match &options.policy {
+        Some(policy_path) => evaluate_policy(&sysctl_data, &read_policy(policy_path)),
+        None => vec![],
+    };
+
+    // --defaults には既定値をsysctl.confと同じ形式で書いたファイルを指定する
+    let outliers = match &options.defaults {
+        Some(defaults_path) => find_outliers(
+            &sysctl_data,
+            &read_defaults(defaults_path),
+            options.outlier_ratio,
+        ),
+        None => vec![],
+    };
+
+    // 下限を下回った場合だけ、そのカバレッジを持つ
+    let coverage_shortfall = match (min_coverage, &schema) {
+        (Some(min_coverage), Some(schema)) => {
+            let coverage = schema_coverage(&sysctl_data, schema);
+            (coverage.percent() < min_coverage).then(|| {
+                tr!(
+                    "{}カバレッジの下限{}%を下回っています。",
+                    "{} The coverage is below the minimum of {}%.",
+                    coverage,
+                    min_coverage
+                )
+            })
+        }
+        (Some(_), None) => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "--min-coverage にはスキーマが必要です。",
+                    "--min-coverage requires a schema."
+                )
+            );
+            return Ok(EXIT_USAGE);
+        }
+        (None, _) => None,
+    };
+
+    // ベースラインに記録済みの違反は取り除き、新しい違反だけを報告する
+    if let Some(baseline) = baseline {
+        baseline.suppress(input_file_path, &mut validation_errors, |error| {
+            (error.code(), Some(error.key_name().to_owned()))
+        });
+        baseline.suppress(input_file_path, &mut policy_findings, |finding| {
+            (Finding::CODE, Some(finding.key.clone()))
+        });
+    }
+
+    // 壊れた大きなファイルでエラーが多すぎる場合は、--max-errors の件数で打ち切り、残りは数だけを伝える
+    // どのエラーを残すかが実行ごとに変わらないよう、キーの順に並べてから打ち切る
+    let mut omitted_errors = 0;
+    if let Some(max_errors) = options.max_errors.map(|max| max as usize) {
+        if validation_errors.len() > max_errors {
+            validation_errors.sort_by(|a, b| a.key_name().cmp(b.key_name()));
+            omitted_errors = validation_errors.split_off(max_errors).len();
+        }
+    }
+
+    let has_errors = !validation_errors.is_empty()
+        || coverage_shortfall.is_some()
+        || policy_findings
+            .iter()
+            .any(|finding| finding.severity == Severity::Error);
+    // エラーの位置を求めるため、キーと値の範囲を改めてパースする
+    // 寛容なパースで読み飛ばした行がある場合など、位置が分からなければ行番号は付けない
+    let source = if validation_errors.is_empty() {
+        None
     } else {
+        parse_sysctl_spanned_with(&input_str, &parse_options)
+            .ok()
+            .map(|entries| Source {
+                file: input_file_path,
+                text: &input_str,
+                entries,
+                snippets: use_pretty,
+            })
+    };
+    let results = Results {
+        parse_warnings: &parse_warnings,
+        validation_errors: &validation_errors,
+        duplicate_keys: &duplicate_keys,
+        policy_findings: &policy_findings,
+        outliers: &outliers,
+        disabled_dependencies: &disabled_dependencies,
+        coverage_shortfall: coverage_shortfall.as_deref(),
+        omitted_errors,
+        source: source.as_ref(),
+        values: Some(&sysctl_data),
+    };
+    // --fail-on で選んだ重大度の診断があれば失敗にする
+    // エラーがあれば、失敗にしない場合もデータの出力や反映はしない
+    let failed = results
+        .diagnostics()
+        .iter()
+        .any(|diagnostic| options.fail_on.fails(diagnostic.severity));
+    let violation_status = if failed { EXIT_SCHEMA_VIOLATION } else { 0 };
+    if use_ci {
+        let mut report = Report::new(input_file_path, results.diagnostics());
+        report.omitted = omitted_errors;
+        // グロブのエントリはキーとして数えず、代わりにそれに一致した設定ファイルのキーを並べる
+        if let Some(schema) = &schema {
+            report.schema_keys = schema
+                .entries
+                .iter()
+                .filter(|entry| !entry.is_glob())
+                .map(|entry| entry.name.clone())
+                .collect();
+            let matched: Vec<String> = sysctl_data
+                .keys()
+                .filter(|key| !report.schema_keys.contains(key) && schema.entry_for(key).is_some())
+                .cloned()
+                .collect();
+            report.schema_keys.extend(matched);
+        }
+        reports.push(report);
+        if has_errors || failed {
+            return Ok(violation_status);
+        }
+    } else if use_failed_only {
+        write_failed_keys(&mut io::stdout(), &results)?;
+        if has_errors || failed {
+            return Ok(violation_status);
+        }
+    } else {
+        if has_errors || failed || !use_quiet {
+            write_text_report(&mut io::stdout(), &mut io::stderr(), &results)?;
+        }
+        if has_errors || failed {
+            return Ok(violation_status);
+        }
+
+        // 出力するデータ。Noneの場合はRust形式で出力する
+        let rendered = if use_bundle {
+            match &schema {
+                Some(schema) => Some(format!("{}\n", bundle(&sysctl_data, schema))),
+                None => {
+                    eprintln!(
+                        "{}",
+                        tr!(
+                            "--bundle には --validate とスキーマが必要です。",
+                            "--bundle requires --validate and a schema."
+                        )
+                    );
+                    return Ok(EXIT_USAGE);
+                }
+            }
+        } else if let Some(options) = &format_options {
+            Some(format_sysctl(&sysctl_data, schema.as_ref(), options))
+        } else {
+            // --normalize slashes では、キーを / 区切りにして出力する
+            let output_data = normalize_keys(&sysctl_data, options.normalize);
+            match options.format.as_str() {
+                "json" => Some(format!("{}\n", to_json(&output_data))),
+                "yaml" => Some(to_yaml(&output_data)),
+                "toml" => Some(to_toml(&output_data)),
+                _ => None,
+            }
+        };
+
+        // --no-print では出力せず、--output <file> ではデータだけをファイルに書き込む
+        if options.no_print {
+            // 検証の結果だけを終了コードで返す
+        } else if let Some(output_path) = &options.output {
+            let rendered = rendered.unwrap_or_else(|| format!("{:#?}\n", sysctl_data));
+            if let Err(error) = fs::write(output_path, rendered) {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "出力ファイル'{}'に書き込めませんでした: {}",
+                        "cannot write the output file '{}': {}",
+                        output_path,
+                        io_error_reason(&error)
+                    )
+                );
+                return Ok(EXIT_IO_ERROR);
+            }
+        } else if use_quiet {
+            // 成功した場合は何も出力しない
+        } else if let Some(rendered) = rendered {
+            print!("{}", rendered);
+        } else if schema.is_some() && use_validation {
+            println!(
+                "{}",
+                tr!(
+                    "スキーマエラーはありませんでした。読み込んだデータをRust形式で出力します。{:#?}",
+                    "no schema errors were found. The loaded data in Rust format: {:#?}",
+                    &sysctl_data
+                )
+            );
+        } else {
+            println!(
+                "{}",
+                tr!(
+                    "読み込んだデータをRust形式で出力します。{:#?}",
+                    "the loaded data in Rust format: {:#?}",
+                    &sysctl_data
+                )
+            );
+        }
+    }
+
+    if use_apply {
+        match apply_checked(&sysctl_data, Path::new("/"), allow_dangerous) {
+            Ok(_) if use_quiet => {}
+            Ok(outcome) => {
+                println!(
+                    "{}",
+                    tr!(
+                        "{}件のキーを反映しました。",
+                        "applied {} keys.",
+                        outcome.applied.len()
+                    )
+                );
+                for key in outcome.ignored_failures {
+                    println!(
+                        "{}",
+                        tr!(
+                            "'{}'の反映に失敗しましたが、無視しました。",
+                            "failed to apply '{}', but ignored it.",
+                            key
+                        )
+                    );
+                }
+            }
+            Err(ApplyFailure::Dangerous(settings)) => {
+                for setting in &settings {
+                    eprintln!("{}", setting);
+                }
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "反映するには --allow-dangerous を指定してください。",
+                        "pass --allow-dangerous to apply them."
+                    )
+                );
+                return Ok(EXIT_USAGE);
+            }
+            Err(ApplyFailure::Write(error)) => {
+                eprintln!("{}", error);
+                for key in &error.rolled_back {
+                    eprintln!(
+                        "{}",
+                        tr!(
+                            "'{}'を元の値に戻しました。",
+                            "restored '{}' to its previous value.",
+                            key
+                        )
+                    );
+                }
+                for key in &error.rollback_failures {
+                    eprintln!(
+                        "{}",
+                        tr!(
+                            "'{}'を元の値に戻せませんでした。",
+                            "could not restore '{}' to its previous value.",
+                            key
+                        )
+                    );
+                }
+                return Ok(EXIT_APPLY_ERROR);
+            }
+        }
+    }
+
+    Ok(0)
+}
+
+fn read_schema(file_path: &str) -> Schema {
+    let schema_str = read_file_or_exit(file_path, &tr!("スキーマファイル", "schema file"));
+    match parse_schema(&schema_str) {
+        Ok(schema) => schema,
+        Err(error) => {
+            println!(
+                "{}",
+                tr!(
+                    "[{}] スキーマファイルの文法に誤りがあります。{}",
+                    "[{}] syntax error in the schema file: {}",
+                    error.kind.code(),
+                    error
+                )
+            );
+            std::process::exit(EXIT_SYNTAX_ERROR);
+        }
+    }
+}
+
+fn read_target_schemas(specs: &[String]) -> Vec<TargetSchema> {
+    specs
+        .iter()
+        .map(|spec| {
+            let Some((version, path)) = parse_target_spec(spec) else {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "--target-schema は version=path の形式で指定してください。",
+                        "--target-schema must be in the form of version=path."
+                    )
+                );
+                std::process::exit(EXIT_USAGE);
+            };
+            TargetSchema {
+                version: version.to_owned(),
+                schema: read_schema(path),
+            }
+        })
+        .collect()
+}
+
+// ファイルが無ければ、今回の違反を記録するための空のベースラインにする
+fn read_baseline(file_path: &str) -> Baseline {
+    if !Path::new(file_path).exists() {
+        return Baseline::recording();
+    }
+    let baseline_str = read_file_or_exit(file_path, &tr!("ベースライン", "baseline"));
+    Baseline::parse(&baseline_str).unwrap_or_else(|error| {
         println!(
-            "読み込んだデータをRust形式で出力します。{:#?}",
-            &sysctl_data
+            "{}",
+            tr!(
+                "ベースラインの形式に誤りがあります: {}",
+                "invalid baseline: {}",
+                error
+            )
         );
+        std::process::exit(EXIT_SYNTAX_ERROR);
+    })
+}
+
+// 設定ファイルの文法の誤りを、--ci ならレポートに加え、それ以外は表示する
+// 壊れた大きなファイルでは、スキーマエラーと同じく --max-errors の件数で打ち切る
+// 誤りは行の順に並んでいるため、先頭の行から残す
+fn report_syntax_errors(
+    input_file_path: &str,
+    errors: &[ParseError],
+    options: &Options,
+    reports: &mut Vec<Report>,
+) -> io::Result<()> {
+    let max_errors = options
+        .max_errors
+        .map_or(errors.len(), |max| (max as usize).min(errors.len()));
+    let (errors, omitted) = (&errors[..max_errors], errors.len() - max_errors);
+    if options.use_report() {
+        let mut report = Report::syntax_errors(input_file_path, errors);
+        report.omitted = omitted;
+        reports.push(report);
+        return Ok(());
+    }
+    if options.pretty {
+        for error in errors {
+            print!("{}", render_parse_error(input_file_path, error));
+        }
+    } else {
+        for error in errors {
+            println!(
+                "{}",
+                tr!(
+                    "[{}] 文法に誤りがあります。{}",
+                    "[{}] syntax error: {}",
+                    error.kind.code(),
+                    error
+                )
+            );
+        }
     }
+    write_omitted_errors(&mut io::stdout(), omitted)
+}
 
-    Ok(())
+fn read_vars(file_path: &str) -> IndexMap<String, String> {
+    let vars_str = read_file_or_exit(file_path, &tr!("変数ファイル", "variables file"));
+    parse_vars(&vars_str).unwrap_or_else(|error| {
+        println!(
+            "{}",
+            tr!(
+                "変数ファイルの形式に誤りがあります: {}",
+                "invalid variables file: {}",
+                error
+            )
+        );
+        std::process::exit(EXIT_SYNTAX_ERROR);
+    })
+}
+
+fn read_policy(file_path: &str) -> Vec<Rule> {
+    let policy_str = read_file_or_exit(file_path, &tr!("ポリシーファイル", "policy file"));
+    parse_policy(&policy_str).unwrap_or_else(|error| {
+        println!(
+            "{}",
+            tr!(
+                "ポリシーファイルの形式に誤りがあります: {}",
+                "invalid policy file: {}",
+                error
+            )
+        );
+        std::process::exit(EXIT_SYNTAX_ERROR);
+    })
+}
+
+// 設定ファイルの末尾に、既定値の key = value の行を追記し、追記した文字列を返す
+fn append_defaults(
+    file_path: &str,
+    entries: &[&SchemaEntry],
+    parse_options: &ParseOptions,
+) -> io::Result<String> {
+    let existing = fs::read(file_path)?;
+    let appended = format_defaults(&String::from_utf8_lossy(&existing), entries, parse_options);
+    OpenOptions::new()
+        .append(true)
+        .open(file_path)?
+        .write_all(appended.as_bytes())?;
+    Ok(appended)
+}
+
+fn read_defaults(file_path: &str) -> HashMap<String, f64> {
+    let defaults_str = read_file_or_exit(file_path, &tr!("既定値ファイル", "defaults file"));
+    let defaults = match parse_sysctl(&defaults_str) {
+        Ok(defaults) => defaults,
+        Err(error) => {
+            println!(
+                "{}",
+                tr!(
+                    "[{}] 既定値ファイルの文法に誤りがあります。{}",
+                    "[{}] syntax error in the defaults file: {}",
+                    error.kind.code(),
+                    error
+                )
+            );
+            std::process::exit(EXIT_SYNTAX_ERROR);
+        }
+    };
+    defaults
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.value.parse::<f64>().ok()?)))
+        .collect()
+}
+
+// 入力ファイルと同じ場所にある <入力ファイル>.schema のパス。無ければNone
+// 標準入力から読み込む場合は、--schema か埋め込まれたスキーマを使う
+fn sibling_schema_path(input_file_path: &str) -> Option<String> {
+    if input_file_path == STDIN_PATH {
+        return None;
+    }
+    let schema_file_path = format!("{}.schema", input_file_path);
+    Path::new(&schema_file_path)
+        .exists()
+        .then_some(schema_file_path)
 }
 
+// - の場合は標準入力を読み込む
+// BOMが付いていれば取り除き、UTF-16なら文字列に変換する
 fn read_file(file_path: &str) -> io::Result<String> {
-    let mut buffer = String::new();
-    let mut file = File::open(file_path)?;
-    file.read_to_string(&mut buffer)?;
+    let mut bytes = Vec::new();
+    if file_path == STDIN_PATH {
+        io::stdin().lock().read_to_end(&mut bytes)?;
+    } else {
+        File::open(file_path)?.read_to_end(&mut bytes)?;
+    }
+    let buffer = decode_text(bytes)?;
+    info!(
+        path = file_path,
+        bytes = buffer.len(),
+        "ファイルを開きました"
+    );
     Ok(buffer)
 }
+
+// ファイルを読み込む。読み込めなければ、何のファイルかと理由を表示して終了する
+fn read_file_or_exit(file_path: &str, description: &str) -> String {
+    read_file_or_report(file_path, description).unwrap_or_else(|| std::process::exit(EXIT_IO_ERROR))
+}
+
+// ファイルを読み込む。読み込めなければ、何のファイルかと理由を表示してNoneを返す
+// 複数の入力ファイルのうち1つを読み込めなくても、残りのファイルは検証する
+fn read_file_or_report(file_path: &str, description: &str) -> Option<String> {
+    read_file(file_path)
+        .map_err(|error| {
+            eprintln!(
+                "{}",
+                tr!(
+                    "{}'{}'を読み込めませんでした: {}",
+                    "cannot read the {} '{}': {}",
+                    description,
+                    file_path,
+                    io_error_reason(&error)
+                )
+            );
+        })
+        .ok()
+}
+
+// --staged では、ステージされていない変更に左右されないよう、インデックスにある内容を読み込む
+// スキーマなどインデックスに無いファイルは、作業ツリーから読み込む
+fn read_input_or_report(file_path: &str, description: &str, staged: bool) -> Option<String> {
+    if staged {
+        match read_staged(file_path) {
+            Ok(Some(content)) => return Some(content),
+            Ok(None) => {}
+            Err(error) => {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "{}'{}'を読み込めませんでした: {}",
+                        "cannot read the {} '{}': {}",
+                        description,
+                        file_path,
+                        io_error_reason(&error)
+                    )
+                );
+                return None;
+            }
+        }
+    }
+    read_file_or_report(file_path, description)
+}
+
+// よくある失敗は、OSのメッセージの代わりに現在の言語で説明する
+fn io_error_reason(error: &io::Error) -> String {
+    match error.kind() {
+        io::ErrorKind::NotFound => tr!("ファイルが存在しません", "no such file"),
+        io::ErrorKind::PermissionDenied => tr!("権限がありません", "permission denied"),
+        io::ErrorKind::IsADirectory => tr!("ディレクトリです", "it is a directory"),
+        io::ErrorKind::InvalidData => tr!(
+            "UTF-8かUTF-16のテキストではありません",
+            "it is not UTF-8 or UTF-16 text"
+        ),
+        _ => error.to_string(),
+    }
+}
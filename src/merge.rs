@@ -0,0 +1,254 @@
+use indexmap::IndexMap;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::format::quote_value;
+use crate::tr;
+use crate::types::{DocumentedValue, Origin, Schema, SysctlValue};
+
+// 複数のファイルに同じキーがある場合に、どのコメントを残すか
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CommentPolicy {
+    // 値が採用されたファイルのコメントだけを残す
+    #[default]
+    Winner,
+    // キーがあったすべてのファイルのコメントを、ファイルの順に並べる
+    Concatenate,
+}
+
+impl CommentPolicy {
+    pub fn from_name(value: &str) -> Option<Self> {
+        match value {
+            "winner" => Some(CommentPolicy::Winner),
+            "concat" => Some(CommentPolicy::Concatenate),
+            _ => None,
+        }
+    }
+}
+
+// マージした結果の1エントリ
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergedEntry {
+    pub value: SysctlValue,
+    pub comments: Vec<String>,
+    // 値が採用されたファイル
+    pub source: String,
+    // 後のファイルで上書きされた値の設定元。ファイルの順
+    pub overridden: Vec<Origin>,
+}
+
+// (ファイル名, 内容)を順に重ね、後のファイルの値で上書きする
+pub fn merge(
+    layers: Vec<(String, IndexMap<String, DocumentedValue>)>,
+    policy: CommentPolicy,
+) -> BTreeMap<String, MergedEntry> {
+    let mut merged: BTreeMap<String, MergedEntry> = BTreeMap::new();
+    for (source, entries) in layers {
+        for (key, documented) in entries {
+            let previous = merged.remove(&key);
+            let mut overridden = Vec::new();
+            if let Some(previous) = &previous {
+                overridden.extend(previous.overridden.iter().cloned());
+                overridden.extend(previous.value.origin.iter().cloned());
+            }
+            let comments = match (policy, previous) {
+                (CommentPolicy::Concatenate, Some(previous)) => {
+                    let mut comments = previous.comments;
+                    comments.extend(documented.comments);
+                    comments
+                }
+                _ => documented.comments,
+            };
+            merged.insert(
+                key,
+                MergedEntry {
+                    value: documented.value,
+                    comments,
+                    source: source.clone(),
+                    overridden,
+                },
+            );
+        }
+    }
+    merged
+}
+
+// マージした結果を、コメント付きのsysctl.conf形式で出力する。キーはソートされる
+// provenanceなら、各キーの前に値の設定元と、上書きした設定元をコメントで書く
+pub fn format_merged(merged: &BTreeMap<String, MergedEntry>, provenance: bool) -> String {
+    let mut output = String::new();
+    for (key, entry) in merged {
+        for comment in &entry.comments {
+            output.push_str(comment);
+            output.push('\n');
+        }
+        if provenance {
+            output.push_str(&format!("# {}\n", provenance_comment(entry)));
+        }
+        if entry.value.ignore_error {
+            output.push('-');
+        }
+        output.push_str(&format!("{} = {}\n", key, quote_value(&entry.value.value)));
+    }
+    output
+}
+
+fn provenance_comment(entry: &MergedEntry) -> String {
+    let origin = match &entry.value.origin {
+        Some(origin) => origin.to_string(),
+        None => entry.source.clone(),
+    };
+    if entry.overridden.is_empty() {
+        return tr!("設定元: {}", "from {}", origin);
+    }
+    let overridden: Vec<String> = entry.overridden.iter().map(Origin::to_string).collect();
+    tr!(
+        "設定元: {} ({}を上書き)",
+        "from {} (overrides {})",
+        origin,
+        overridden.join(", ")
+    )
+}
+
+// %trustedで保護されたキーの値が、信頼できるファイル以外から来ている
+#[derive(Debug, PartialEq)]
+pub struct UntrustedSource {
+    pub key: String,
+    // 実際に値が採用されたファイル
+    pub source: String,
+    pub trusted: String,
+}
+
+impl Display for UntrustedSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = tr!(
+            "'{}'の値は'{}'から設定される必要がありますが、'{}'の値が使われています。",
+            "'{}' must be set from '{}', but the value from '{}' is used.",
+            self.key,
+            self.trusted,
+            self.source
+        );
+        write!(f, "{}", message)
+    }
+}
+
+// sourceがtrustedと同じパスか、ファイル名がtrustedと一致するか
+fn is_trusted(source: &str, trusted: &str) -> bool {
+    source == trusted
+        || Path::new(source)
+            .file_name()
+            .is_some_and(|name| name == trusted)
+}
+
+// マージした結果のうち、%trustedのキーの値が信頼できないファイルから来ているもの
+pub fn find_untrusted_sources(
+    merged: &BTreeMap<String, MergedEntry>,
+    schema: &Schema,
+) -> Vec<UntrustedSource> {
+    schema
+        .trusted_sources
+        .iter()
+        .filter_map(|(key, trusted)| {
+            let entry = merged.get(key)?;
+            (!is_trusted(&entry.source, trusted)).then(|| UntrustedSource {
+                key: key.to_owned(),
+                source: entry.source.clone(),
+                trusted: trusted.to_owned(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_schema, parse_sysctl_documented};
+
+    fn layers() -> Vec<(String, IndexMap<String, DocumentedValue>)> {
+        let base = "# base comment\nvm.swappiness = 60\n# only in base\nkernel.pid_max = 4096\n";
+        let overlay = "# tuned for databases\nvm.swappiness = 10\n";
+        vec![
+            (
+                "base.conf".to_owned(),
+                parse_sysctl_documented(base).unwrap(),
+            ),
+            (
+                "overlay.conf".to_owned(),
+                parse_sysctl_documented(overlay).unwrap(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_merge_winner_comments() {
+        let merged = merge(layers(), CommentPolicy::Winner);
+        let swappiness = &merged["vm.swappiness"];
+        assert_eq!(swappiness.value.value, "10");
+        assert_eq!(swappiness.comments, vec!["# tuned for databases"]);
+        assert_eq!(swappiness.source, "overlay.conf");
+        assert_eq!(merged["kernel.pid_max"].comments, vec!["# only in base"]);
+        assert_eq!(
+            format_merged(&merged, false),
+            "# only in base\nkernel.pid_max = 4096\n# tuned for databases\nvm.swappiness = 10\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_concatenate_comments() {
+        let merged = merge(layers(), CommentPolicy::Concatenate);
+        assert_eq!(
+            merged["vm.swappiness"].comments,
+            vec!["# base comment", "# tuned for databases"]
+        );
+        assert_eq!(merged["vm.swappiness"].source, "overlay.conf");
+    }
+
+    #[test]
+    fn test_merge_provenance() {
+        let mut layers = layers();
+        for (file, entries) in &mut layers {
+            for (line, value) in entries.values_mut().enumerate() {
+                value.value.origin = Some(Origin {
+                    file: file.clone(),
+                    line: line + 1,
+                });
+            }
+        }
+        let merged = merge(layers, CommentPolicy::Winner);
+        assert_eq!(
+            merged["vm.swappiness"].overridden,
+            vec![Origin {
+                file: "base.conf".to_owned(),
+                line: 1,
+            }]
+        );
+        assert_eq!(
+            format_merged(&merged, true),
+            "# only in base\n# 設定元: base.conf:2\nkernel.pid_max = 4096\n\
+             # tuned for databases\n# 設定元: overlay.conf:1 (base.conf:1を上書き)\nvm.swappiness = 10\n"
+        );
+    }
+
+    #[test]
+    fn test_find_untrusted_sources() {
+        let merged = merge(layers(), CommentPolicy::Winner);
+        // overlay.confで上書きされている
+        let schema =
+            parse_schema("%trusted vm.swappiness base.conf\n%trusted kernel.pid_max base.conf\n")
+                .unwrap();
+        assert_eq!(
+            find_untrusted_sources(&merged, &schema),
+            vec![UntrustedSource {
+                key: "vm.swappiness".to_owned(),
+                source: "overlay.conf".to_owned(),
+                trusted: "base.conf".to_owned(),
+            }]
+        );
+
+        let schema = parse_schema("%trusted vm.swappiness overlay.conf\n").unwrap();
+        assert!(find_untrusted_sources(&merged, &schema).is_empty());
+        assert!(is_trusted("/etc/sysctl.d/base.conf", "base.conf"));
+        assert!(!is_trusted("/etc/sysctl.d/base.conf.bak", "base.conf"));
+    }
+}
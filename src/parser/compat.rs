@@ -0,0 +1,282 @@
+use indexmap::IndexMap;
+use tracing::debug;
+
+use super::sysctl::canonical_key;
+use super::util::skip_bom;
+use crate::tr;
+use crate::types::{ParseWarning, SysctlValue};
+
+// 起動時に設定を読み込む実装。実装ごとの癖まで合わせてパースし、起動時に実際に設定される値で検証する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compat {
+    Procps,
+    Busybox,
+}
+
+impl Compat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "procps" => Some(Compat::Procps),
+            "busybox" => Some(Compat::Busybox),
+            _ => None,
+        }
+    }
+}
+
+// procpsはfgetsで4096バイトのバッファに読むため、改行を含めて4095バイトを超える行は途中で分かれる
+const PROCPS_LINE_BUFFER: usize = 4095;
+
+// 指定した実装と同じ規則でパースする
+pub fn parse_sysctl_as(
+    input: &str,
+    compat: Compat,
+) -> (IndexMap<String, SysctlValue>, Vec<ParseWarning>) {
+    match compat {
+        Compat::Procps => parse_sysctl_compat(input),
+        Compat::Busybox => parse_sysctl_busybox(input),
+    }
+}
+
+// procpsのsysctl -pと同じ規則でパースする。
+// - 4095バイトを超える行は、そこで分かれて別の行として読まれる
+// - 行の前後の空白を除いた後、2文字未満の行と、# か ; で始まる行は無視する
+// - 行頭の - は、反映の失敗を無視する印として取り除く
+// - strtokで区切るため、キーの前の = は読み飛ばされる
+// - 次の = より前を空白をtrimしてキー、後ろを空白をtrimして値とする(キーの途中の空白も許す)
+// - = が無い行や、キーや値が空の行は警告を出して読み飛ばす
+// - 同じキーが複数回現れた場合は、後のものが有効になる
+pub fn parse_sysctl_compat(input: &str) -> (IndexMap<String, SysctlValue>, Vec<ParseWarning>) {
+    let mut values = IndexMap::new();
+    let mut warnings = Vec::new();
+
+    for (index, raw) in skip_bom(input).lines().enumerate() {
+        for chunk in procps_chunks(raw) {
+            let line = chunk.trim();
+            if line.len() < 2 || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            let (ignore_error, line) = match line.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let stripped = line.trim_start_matches('=');
+            let parsed = match stripped.split_once('=') {
+                // = で始まり、ほかに = の無い行は、キーが無いものとする
+                None if stripped.len() != line.len() => Err(tr!("キーが空です", "empty key")),
+                None => Err(tr!("=がありません", "missing '='")),
+                Some((key, _)) if key.trim().is_empty() => Err(tr!("キーが空です", "empty key")),
+                Some((_, value)) if value.trim().is_empty() => {
+                    Err(tr!("値が空です", "empty value"))
+                }
+                Some((key, value)) => Ok((key.trim(), value.trim())),
+            };
+            match parsed {
+                Ok((key, value)) => {
+                    values.insert(
+                        canonical_key(key),
+                        SysctlValue {
+                            value: value.to_owned(),
+                            ignore_error,
+                            origin: None,
+                        },
+                    );
+                }
+                Err(reason) => warnings.push(ParseWarning {
+                    line: index + 1,
+                    reason,
+                    raw: chunk.trim_end_matches('\r').to_owned(),
+                }),
+            }
+        }
+    }
+
+    debug!(
+        entries = values.len(),
+        warnings = warnings.len(),
+        "sysctl互換の規則で設定をパースしました"
+    );
+    (values, warnings)
+}
+
+// 1行を、procpsが1回のfgetsで読む長さごとに分ける。文字の途中では分けない
+fn procps_chunks(line: &str) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = line;
+    while rest.len() >= PROCPS_LINE_BUFFER {
+        let mut end = PROCPS_LINE_BUFFER;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        chunks.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    chunks.push(rest);
+    chunks
+}
+
+// busyboxのsysctl -pと同じ規則でパースする
+// - # から行末まではコメント。行の途中でも同じで、; はコメントにならない
+// - 空白、タブ、= のどれかで区切り、最初をキー、続く区切りを読み飛ばした残りを値とする。= が無くてもよい
+// - 行頭の - は印ではなく、キーの一部になる
+// - キーか値の無い行は警告を出して読み飛ばす
+pub fn parse_sysctl_busybox(input: &str) -> (IndexMap<String, SysctlValue>, Vec<ParseWarning>) {
+    let mut values = IndexMap::new();
+    let mut warnings = Vec::new();
+    let is_delimiter = |c: char| matches!(c, ' ' | '\t' | '=');
+
+    for (index, raw) in skip_bom(input).lines().enumerate() {
+        let line = raw.split('#').next().unwrap_or_default();
+        let line = line.trim_matches(|c: char| is_delimiter(c) || c == '\r');
+        if line.is_empty() {
+            continue;
+        }
+        match line.split_once(is_delimiter) {
+            Some((key, value)) => {
+                values.insert(
+                    canonical_key(key),
+                    SysctlValue {
+                        value: value.trim_start_matches(is_delimiter).to_owned(),
+                        ignore_error: false,
+                        origin: None,
+                    },
+                );
+            }
+            None => warnings.push(ParseWarning {
+                line: index + 1,
+                reason: tr!("値がありません", "missing value"),
+                raw: raw.trim_end_matches('\r').to_owned(),
+            }),
+        }
+    }
+
+    debug!(
+        entries = values.len(),
+        warnings = warnings.len(),
+        "busyboxの規則で設定をパースしました"
+    );
+    (values, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(value: &str, ignore_error: bool) -> SysctlValue {
+        SysctlValue {
+            value: value.to_owned(),
+            ignore_error,
+            origin: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_sysctl_compat_tricky_lines() {
+        let (values, warnings) = parse_sysctl_compat(
+            "  # indented comment\n\
+             \t; another comment\n\
+             -  net.ipv4.ip_forward   =   1   \n\
+             kernel.printk = 4 4 1 7\n\
+             a b c = hoge\n\
+             no_equals_line\n\
+             empty.value =   \n\
+             = no key\n\
+             dup = first\r\n\
+             dup = second\n\
+             url = http://example.com/?a=b\n",
+        );
+        assert_eq!(
+            values,
+            [
+                ("net.ipv4.ip_forward", value("1", true)),
+                // 値の途中の空白はそのまま
+                ("kernel.printk", value("4 4 1 7", false)),
+                // procpsはキーの途中の空白を受け入れる
+                ("a b c", value("hoge", false)),
+                ("dup", value("second", false)),
+                // 2つ目以降の = は値の一部
+                ("url", value("http://example.com/?a=b", false)),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v))
+            .collect::<IndexMap<_, _>>()
+        );
+        assert_eq!(
+            warnings.iter().map(|w| w.line).collect::<Vec<_>>(),
+            vec![6, 7, 8]
+        );
+    }
+
+    #[test]
+    fn test_parse_sysctl_compat_structured_warnings() {
+        let (values, warnings) = parse_sysctl_compat("a = 1\n  broken line\r\nb = 2\n= 3\n");
+        assert_eq!(values.len(), 2);
+        assert_eq!(
+            warnings,
+            vec![
+                ParseWarning {
+                    line: 2,
+                    reason: "=がありません".to_owned(),
+                    raw: "  broken line".to_owned(),
+                },
+                ParseWarning {
+                    line: 4,
+                    reason: "キーが空です".to_owned(),
+                    raw: "= 3".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_sysctl_compat_trailing_comment_is_value() {
+        // 行の途中のコメントはサポートされず、値に含まれる
+        let (values, _) = parse_sysctl_compat("vm.swappiness = 10 # comment");
+        assert_eq!(values["vm.swappiness"], value("10 # comment", false));
+    }
+
+    #[test]
+    fn test_parse_sysctl_compat_procps_quirks() {
+        // 2文字未満の行は警告も出さずに無視し、キーの前の = は読み飛ばす
+        let (values, warnings) = parse_sysctl_compat("x\n==a.key=1\n");
+        assert_eq!(values["a.key"], value("1", false));
+        assert!(warnings.is_empty());
+
+        // 長すぎる行は途中で分かれ、残りは別の行として読まれる
+        let long = format!("a.long = {}b.key = 2\n", "x".repeat(PROCPS_LINE_BUFFER - 9));
+        let (values, warnings) = parse_sysctl_compat(&long);
+        assert_eq!(values["a.long"].value.len(), PROCPS_LINE_BUFFER - 9);
+        assert_eq!(values["b.key"], value("2", false));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_sysctl_busybox() {
+        let (values, warnings) = parse_sysctl_busybox(
+            "; not a comment = 1\n\
+             net.ipv4.ip_forward = 1 # comment\n\
+             kernel.printk 4 4 1 7\n\
+             -vm.swappiness=10\n\
+             a b c = hoge\n\
+             no_value\n\
+             = no key\n",
+        );
+        assert_eq!(
+            values,
+            [
+                (";", value("not a comment = 1", false)),
+                ("net.ipv4.ip_forward", value("1", false)),
+                // = が無くても、空白で区切る
+                ("kernel.printk", value("4 4 1 7", false)),
+                // - は印ではなくキーの一部
+                ("-vm.swappiness", value("10", false)),
+                ("a", value("b c = hoge", false)),
+                ("no", value("key", false)),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v))
+            .collect::<IndexMap<_, _>>()
+        );
+        assert_eq!(warnings.iter().map(|w| w.line).collect::<Vec<_>>(), vec![6]);
+    }
+}
@@ -0,0 +1,322 @@
+use indexmap::IndexMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::sysctl::{
+    logical_lines, parse_sysctl_recovering_with, parse_sysctl_spanned_with, ParseOptions,
+};
+use crate::conditional::{evaluate_conditionals, has_directives, KernelVersion};
+use crate::encoding::decode_text;
+use crate::pattern::glob_match;
+use crate::tr;
+use crate::types::{Origin, ParseError, SysctlValue};
+
+// include で取り込んだファイルも含めて読み込んだ設定。各値には、値を設定した場所を記録する
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IncludedConfig {
+    pub values: IndexMap<String, SysctlValue>,
+}
+
+#[derive(Debug)]
+pub enum IncludeError {
+    Io {
+        file: String,
+        error: io::Error,
+    },
+    // 1つのファイルの中の、誤りのある行すべて
+    Parse {
+        file: String,
+        errors: Vec<ParseError>,
+    },
+    // 取り込んでいる途中のファイルを、もう一度取り込もうとした
+    Cycle {
+        file: String,
+    },
+    // #@if の指示があるが、比べるカーネルのバージョンが分からない
+    UnknownKernelVersion {
+        file: String,
+    },
+}
+
+impl Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            IncludeError::Io { file, error } => tr!(
+                "'{}'を読み込めませんでした: {}",
+                "cannot read '{}': {}",
+                file,
+                error
+            ),
+            IncludeError::Parse { file, errors } => errors
+                .iter()
+                .map(|error| {
+                    tr!(
+                        "'{}'の文法に誤りがあります。[{}] {}",
+                        "syntax error in '{}': [{}] {}",
+                        file,
+                        error.kind.code(),
+                        error
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            IncludeError::Cycle { file } => tr!(
+                "'{}'が自身を取り込んでいます。",
+                "'{}' includes itself.",
+                file
+            ),
+            IncludeError::UnknownKernelVersion { file } => tr!(
+                "'{}'の#@ifの指示を評価するカーネルのバージョンが分かりません。--kernel-version で指定してください",
+                "cannot determine the kernel version to evaluate the #@if directives in '{}'; specify it with --kernel-version",
+                file
+            ),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+// ファイルを読み込み、include の行で指定されたファイルもその位置で取り込んでまとめる
+// 例) include /etc/sysctl.d/*.conf
+// 後から設定された値が有効になる。取り込んだファイルの中の include も同じように取り込む
+// #@if kernel >= 5.10 のブロックは、それぞれのファイルでkernel_versionに合わせて読み込むか読み飛ばす
+pub fn parse_sysctl_file(
+    path: &Path,
+    options: &ParseOptions,
+    kernel_version: Option<&KernelVersion>,
+) -> Result<IncludedConfig, IncludeError> {
+    let mut config = IncludedConfig::default();
+    load(path, options, kernel_version, &mut Vec::new(), &mut config)?;
+    Ok(config)
+}
+
+// 1つのファイルの、キーの設定か取り込み
+enum Step<'a> {
+    Entry(String, SysctlValue),
+    Include(&'a str),
+}
+
+fn load(
+    path: &Path,
+    options: &ParseOptions,
+    kernel_version: Option<&KernelVersion>,
+    including: &mut Vec<PathBuf>,
+    config: &mut IncludedConfig,
+) -> Result<(), IncludeError> {
+    let file = path.to_string_lossy().into_owned();
+    let io_error = |error| IncludeError::Io {
+        file: file.clone(),
+        error,
+    };
+    let canonical = fs::canonicalize(path).map_err(io_error)?;
+    if including.contains(&canonical) {
+        return Err(IncludeError::Cycle { file });
+    }
+    let mut input = fs::read(path).and_then(decode_text).map_err(io_error)?;
+    if has_directives(&input) {
+        let kernel_version = kernel_version
+            .ok_or_else(|| IncludeError::UnknownKernelVersion { file: file.clone() })?;
+        input = evaluate_conditionals(&input, kernel_version).map_err(|errors| {
+            IncludeError::Parse {
+                file: file.clone(),
+                errors,
+            }
+        })?;
+    }
+    let (text, includes) = strip_includes(&input, options);
+    // 誤りがあれば、最初の1つで止めずに誤りのある行をすべて集める
+    let entries = parse_sysctl_spanned_with(&text, options).map_err(|_| IncludeError::Parse {
+        file: file.clone(),
+        errors: parse_sysctl_recovering_with(&text, options).1,
+    })?;
+
+    // 行の順に、キーの設定と取り込みを重ねる
+    let mut steps: Vec<(usize, Step)> = entries
+        .into_iter()
+        .map(|(key, entry)| (entry.line, Step::Entry(key, entry.value)))
+        .chain(
+            includes
+                .into_iter()
+                .map(|(line, pattern)| (line, Step::Include(pattern))),
+        )
+        .collect();
+    steps.sort_by_key(|(line, _)| *line);
+
+    including.push(canonical);
+    for (line, step) in steps {
+        match step {
+            Step::Entry(key, value) => {
+                let origin = Origin {
+                    file: file.clone(),
+                    line,
+                };
+                config.values.insert(
+                    key,
+                    SysctlValue {
+                        origin: Some(origin),
+                        ..value
+                    },
+                );
+            }
+            Step::Include(pattern) => {
+                let paths = expand(path, pattern).map_err(|error| IncludeError::Io {
+                    file: pattern.to_owned(),
+                    error,
+                })?;
+                for included in paths {
+                    load(&included, options, kernel_version, including, config)?;
+                }
+            }
+        }
+    }
+    including.pop();
+    Ok(())
+}
+
+// include の行を、行番号や位置が変わらないよう空白に置き換える
+// 置き換えた入力と、(行番号, 取り込むパス)を返す
+fn strip_includes<'a>(input: &'a str, options: &ParseOptions) -> (String, Vec<(usize, &'a str)>) {
+    let mut text = String::with_capacity(input.len());
+    let mut includes = Vec::new();
+    for (line_number, line) in logical_lines(input, &options.comment_chars) {
+        match include_pattern(line, options) {
+            Some(pattern) => {
+                includes.push((line_number, pattern));
+                let content = line.trim_end_matches(['\r', '\n']);
+                text.push_str(&" ".repeat(content.len()));
+                text.push_str(&line[content.len()..]);
+            }
+            None => text.push_str(line),
+        }
+    }
+    (text, includes)
+}
+
+// include <パス> の行なら、そのパス。include = 1 のような include という名前のキーは除く
+fn include_pattern<'a>(line: &'a str, options: &ParseOptions) -> Option<&'a str> {
+    let rest = line.trim().strip_prefix("include")?;
+    if !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    let pattern = rest.trim();
+    (!pattern.is_empty() && !pattern.starts_with(options.separator)).then_some(pattern)
+}
+
+// 取り込むファイルのパス。相対パスは、取り込む側のファイルのディレクトリからのパスになる
+// * と ? はファイル名の部分だけに使え、一致したファイルを名前の順に返す
+fn expand(including: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let pattern = including.parent().unwrap_or(Path::new("")).join(pattern);
+    let Some(name) = pattern
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| name.contains(['*', '?']))
+    else {
+        return Ok(vec![pattern]);
+    };
+    let dir = match pattern.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file()
+            && path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .is_some_and(|file_name| glob_match(name, file_name))
+        {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sysctl_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| fs::write(dir.path().join(name), content).unwrap();
+        fs::create_dir(dir.path().join("conf.d")).unwrap();
+        write(
+            "main.conf",
+            "a.key = 1\nb.key = 1\ninclude conf.d/*.conf\nb.key = 3\ninclude = 4\n",
+        );
+        write("conf.d/10-first.conf", "a.key = 2\nc.key = 2\n");
+        write("conf.d/20-second.conf", "\n  include ../extra.conf\n");
+        write("conf.d/ignored.txt", "a.key = 9\n");
+        write("extra.conf", "d.key = 5\n");
+
+        let main = dir.path().join("main.conf");
+        let config = parse_sysctl_file(&main, &ParseOptions::default(), None).unwrap();
+        let values: Vec<(&str, &str)> = config
+            .values
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.value.as_str()))
+            .collect();
+        // 取り込んだ位置より後の値が有効になる
+        assert_eq!(
+            values,
+            vec![
+                ("a.key", "2"),
+                ("c.key", "2"),
+                ("d.key", "5"),
+                ("b.key", "3"),
+                ("include", "4")
+            ]
+        );
+        let origin = |key: &str| config.values[key].origin.clone().unwrap();
+        assert!(origin("a.key").file.ends_with("10-first.conf"));
+        assert_eq!(origin("a.key").line, 1);
+        assert_eq!(origin("b.key").line, 4);
+        assert!(origin("d.key").file.ends_with("extra.conf"));
+
+        write("extra.conf", "include main.conf\n");
+        assert!(matches!(
+            parse_sysctl_file(&main, &ParseOptions::default(), None),
+            Err(IncludeError::Cycle { .. })
+        ));
+        write("extra.conf", "include missing.conf\n");
+        assert!(matches!(
+            parse_sysctl_file(&main, &ParseOptions::default(), None),
+            Err(IncludeError::Io { .. })
+        ));
+        write("extra.conf", "bad line\nd.key = 5\nanother bad line\n");
+        match parse_sysctl_file(&main, &ParseOptions::default(), None) {
+            Err(IncludeError::Parse { file, errors }) => {
+                assert!(file.ends_with("extra.conf"));
+                let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+                assert_eq!(lines, vec![1, 3]);
+            }
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_conditionals_in_included_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| fs::write(dir.path().join(name), content).unwrap();
+        write(
+            "main.conf",
+            "#@if kernel >= 5.10\na.key = 2\n#@else\na.key = 3\n#@endif\ninclude extra.conf\n",
+        );
+        write("extra.conf", "#@if kernel < 6\nb.key = 1\n#@endif\n");
+
+        let main = dir.path().join("main.conf");
+        let version = KernelVersion::parse("6.1").unwrap();
+        let config = parse_sysctl_file(&main, &ParseOptions::default(), Some(&version)).unwrap();
+        assert_eq!(config.values["a.key"].value, "2");
+        assert_eq!(config.values["a.key"].origin.as_ref().unwrap().line, 2);
+        assert!(!config.values.contains_key("b.key"));
+
+        assert!(matches!(
+            parse_sysctl_file(&main, &ParseOptions::default(), None),
+            Err(IncludeError::UnknownKernelVersion { .. })
+        ));
+    }
+}
@@ -1,6 +1,20 @@
+mod compat;
+mod include;
 mod schema;
 mod sysctl;
 mod util;
 
-pub use schema::parse_schema;
-pub use sysctl::parse_sysctl;
+pub use compat::{parse_sysctl_as, parse_sysctl_busybox, parse_sysctl_compat, Compat};
+pub use include::{parse_sysctl_file, IncludeError, IncludedConfig};
+pub use schema::{
+    extract_annotated_schema, extract_embedded_schema, parse_schema, AnnotatedSchema,
+};
+pub(crate) use sysctl::{
+    canonical_key, invalid_key_position, logical_lines, raw_key_value, to_value,
+};
+pub use sysctl::{
+    find_duplicate_keys, key_origins, parse_sysctl, parse_sysctl_documented, parse_sysctl_lenient,
+    parse_sysctl_recovering, parse_sysctl_recovering_with, parse_sysctl_spanned,
+    parse_sysctl_spanned_with, parse_sysctl_with, ParseOptions, DEFAULT_MAX_LINE_LENGTH,
+};
+pub(crate) use util::{parse_error, skip_bom};
@@ -1,15 +1,54 @@
+use std::collections::{HashMap, HashSet};
+use tracing::debug;
+
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while},
-    combinator::{eof, map},
-    multi::many0,
-    sequence::{preceded, separated_pair, terminated},
+    bytes::complete::{is_not, tag, take_till, take_while, take_while1},
+    character::complete::{char, digit1, i64, satisfy, space0, space1},
+    combinator::{eof, map, map_opt, map_res, not, opt, recognize, verify},
+    multi::{many0, many1, separated_list1},
+    sequence::{delimited, preceded, separated_pair, terminated, tuple},
     IResult,
 };
 
-use crate::types::{Schema, SchemaEntry, SchemaType};
+use crate::types::{
+    Arity, ParseError, ParseErrorKind, Pattern, Range, Schema, SchemaEntry, SchemaType, SysctlValue,
+};
+use crate::validation::{check_entry, ValidationOptions};
 
-use super::util::{colon, skip0, token};
+use super::sysctl::{canonical_key, parse_sysctl_documented, parse_sysctl_spanned};
+use super::util::{colon, equals, finish, parse_error, skip0, skip_bom, token};
+
+// 型の指定。組み込み型か、%typeで定義された型の名前への参照のどちらか
+#[derive(Debug, Clone, PartialEq)]
+enum TypeExpr<'a> {
+    Builtin(SchemaType),
+    Named(&'a str),
+}
+
+// key: type [修飾子] の宣言
+#[derive(Debug, PartialEq)]
+struct EntryDecl<'a> {
+    key: &'a str,
+    expr: TypeExpr<'a>,
+    range: Option<Range>,
+    pattern: Option<&'a str>,
+    readonly: bool,
+    optional: bool,
+    default: Option<&'a str>,
+}
+
+// スキーマファイルの1要素
+#[derive(Debug, PartialEq)]
+enum SchemaItem<'a> {
+    Entry(EntryDecl<'a>),
+    TypeDef(&'a str, TypeExpr<'a>),
+    Exclusive(Vec<&'a str>),
+    Depends(&'a str, Vec<&'a str>),
+    Trusted(&'a str, &'a str),
+    Group(&'a str, Vec<&'a str>),
+    Bool(Vec<(&'a str, &'a str)>),
+}
 
 // : や空白以外の任意の連続した文字
 // 例) hoge, console.log /var/log
@@ -18,34 +57,570 @@ fn schema_key(input: &str) -> IResult<&str, &str> {
 }
 
 // スキーマの型部分をパーサー
-// 空白なども飛ばさず、純粋に文字が
+// 後ろに[個数]を付けると、その型の値を空白かカンマ区切りで並べたリストになる
+// list<型> は個数を問わないリスト。(型, 型, ...) は位置ごとに型の決まったタプル
+// 例) number[3], number[1..], string[2..4], list<number>, (integer, integer, integer)
 fn schema_type(input: &str) -> IResult<&str, SchemaType> {
+    alt((
+        map(
+            delimited(
+                token(tag("(")),
+                separated_list1(tag(","), delimited(space0, scalar_type, space0)),
+                tag(")"),
+            ),
+            SchemaType::Tuple,
+        ),
+        map(
+            delimited(token(tag("list<")), scalar_type, tag(">")),
+            |element| SchemaType::List {
+                element: Box::new(element),
+                arity: Arity { min: 0, max: None },
+            },
+        ),
+        map(
+            tuple((scalar_type, opt(arity))),
+            |(element, arity)| match arity {
+                Some(arity) => SchemaType::List {
+                    element: Box::new(element),
+                    arity,
+                },
+                None => element,
+            },
+        ),
+    ))(input)
+}
+
+// [3], [1..], [2..4] の部分
+fn arity(input: &str) -> IResult<&str, Arity> {
+    let number = || map_res(digit1, str::parse::<usize>);
+    delimited(
+        tag("["),
+        alt((
+            map(
+                verify(
+                    separated_pair(number(), tag(".."), opt(number())),
+                    |(min, max)| max.is_none_or(|max| *min <= max),
+                ),
+                |(min, max)| Arity { min, max },
+            ),
+            map(number(), |len| Arity {
+                min: len,
+                max: Some(len),
+            }),
+        )),
+        tag("]"),
+    )(input)
+}
+
+// 要素1つ分の型
+fn scalar_type(input: &str) -> IResult<&str, SchemaType> {
     token(alt((
-        map(token(tag("string")), |_| SchemaType::String),
-        map(token(tag("bool")), |_| SchemaType::Boolean),
-        map(token(tag("number")), |_| SchemaType::Number),
+        map(token(keyword("string")), |_| SchemaType::String),
+        map(token(keyword("bool")), |_| SchemaType::Boolean),
+        map(token(keyword("number")), |_| SchemaType::Number),
+        map(token(keyword("integer")), |_| SchemaType::Integer),
+        map(token(keyword("size")), |_| SchemaType::Size),
+        map(token(keyword("duration")), |_| SchemaType::Duration),
+        map(token(keyword("hostname")), |_| SchemaType::Hostname),
+        map(
+            preceded(token(keyword("path")), opt(token(tag("(exists)")))),
+            |exists| SchemaType::Path {
+                exists: exists.is_some(),
+            },
+        ),
+        map(
+            delimited(
+                tag("enum("),
+                separated_list1(tag(","), delimited(space0, enum_choice, space0)),
+                tag(")"),
+            ),
+            |choices| SchemaType::Enum(choices.into_iter().map(str::to_owned).collect()),
+        ),
     )))(input)
 }
 
-// key: type の部分
+// enum(...)の選択肢1つ。空白や , ) は含められない
+fn enum_choice(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| !c.is_whitespace() && c != ',' && c != ')')(input)
+}
+
+// %typeで定義する型の名前
+// 例) port, ipv4-addr
+fn type_name(input: &str) -> IResult<&str, &str> {
+    token(take_while1(is_type_name_char))(input)
+}
+
+fn is_type_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+// 組み込み型の名前。numbers や pathish のように、続けて型名の文字があれば定義済みの型名として読む
+fn keyword<'a>(name: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(tag(name), not(satisfy(is_type_name_char)))
+}
+
+// 組み込み型、または定義済みの型名
+fn type_expr(input: &str) -> IResult<&str, TypeExpr<'_>> {
+    alt((
+        map(schema_type, TypeExpr::Builtin),
+        map(type_name, TypeExpr::Named),
+    ))(input)
+}
+
+// 型の後ろに同じ行で続く修飾子
+fn modifier<'a>(name: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    preceded(
+        space1,
+        terminated(tag(name), not(satisfy(|c: char| !c.is_whitespace()))),
+    )
+}
+
+// 型の直後の(min..max)の部分。両端は省略できる。最小が最大より大きい範囲は受け付けない
+// 例) (0..100), (1..), (..10)
+fn range(input: &str) -> IResult<&str, Range> {
+    map(
+        delimited(
+            tag("("),
+            verify(
+                separated_pair(opt(i64), tag(".."), opt(i64)),
+                |(min, max)| match (min, max) {
+                    (Some(min), Some(max)) => min <= max,
+                    _ => true,
+                },
+            ),
+            tag(")"),
+        ),
+        |(min, max)| Range { min, max },
+    )(input)
+}
+
+// 型の直後の(/正規表現/)の部分。正規表現の中の / は \/ と書く
+// 例) (/^[a-z]+$/), (/^\|?\/[\w\/%. -]+$/)
+fn pattern(input: &str) -> IResult<&str, &str> {
+    delimited(
+        tag("(/"),
+        recognize(many0(alt((
+            tag("\\/"),
+            tag("\\\\"),
+            is_not("\\/\r\n"),
+            tag("\\"),
+        )))),
+        tag("/)"),
+    )(input)
+}
+
+// 行末までの = value の部分。値は前後の空白を除いた残りのすべて
+fn default_value(input: &str) -> IResult<&str, &str> {
+    map(
+        preceded(
+            tuple((space0, char('='), space0)),
+            verify(take_till(|c| c == '\r' || c == '\n'), |value: &str| {
+                !value.trim().is_empty()
+            }),
+        ),
+        str::trim_end,
+    )(input)
+}
+
+// key[?]: type[(min..max)][(/regex/)] [readonly] [optional] [= default] の部分
+// キーの後ろの ? か optional を付けたキーは、設定ファイルに無くてもよい
+// = の後ろの既定値は、キーが無いときに --fix で追記する値
 // 例) endpoint: string
-fn schema_entry(input: &str) -> IResult<&str, SchemaEntry> {
+//     vm.swappiness: number(0..100) = 60
+//     kernel.ostype: string readonly
+//     kernel.hostname: string(/^[a-z0-9-]+$/)
+//     net.core.somaxconn?: number
+fn schema_entry(input: &str) -> IResult<&str, SchemaItem<'_>> {
     map(
-        separated_pair(schema_key, colon, schema_type),
-        |(key, schema_type)| SchemaEntry {
-            name: key.to_owned(),
-            schema_type,
+        tuple((
+            schema_key,
+            colon,
+            type_expr,
+            opt(range),
+            opt(pattern),
+            many0(alt((modifier("readonly"), modifier("optional")))),
+            opt(default_value),
+        )),
+        |(key, _, expr, range, pattern, modifiers, default)| {
+            let (key, marked) = match key.strip_suffix('?') {
+                Some(key) => (key, true),
+                None => (key, false),
+            };
+            SchemaItem::Entry(EntryDecl {
+                key,
+                expr,
+                range,
+                pattern,
+                readonly: modifiers.contains(&"readonly"),
+                optional: marked || modifiers.contains(&"optional"),
+                default,
+            })
         },
     )(input)
 }
 
-pub fn parse_schema(input: &str) -> IResult<&str, Schema> {
+// %type name = type の部分
+// 例) %type port = number
+fn type_def(input: &str) -> IResult<&str, SchemaItem<'_>> {
     map(
-        terminated(many0(schema_entry), preceded(skip0, eof)),
-        |entries| Schema { entries },
+        tuple((token(tag("%type")), type_name, equals, type_expr)),
+        |(_, name, _, expr)| SchemaItem::TypeDef(name, expr),
     )(input)
 }
 
+// 同じ行に空白区切りで並んだキー
+fn key_list(input: &str) -> IResult<&str, Vec<&str>> {
+    many1(preceded(space1, take_while1(|c: char| !c.is_whitespace())))(input)
+}
+
+// %exclusive key1 key2 ... の部分。キーは同じ行に2つ以上並べる
+// 例) %exclusive net.ipv4.tcp_syncookies net.ipv4.tcp_syn_retries
+fn exclusive(input: &str) -> IResult<&str, SchemaItem<'_>> {
+    map(
+        preceded(
+            token(tag("%exclusive")),
+            verify(key_list, |keys: &Vec<&str>| keys.len() >= 2),
+        ),
+        SchemaItem::Exclusive,
+    )(input)
+}
+
+// %depends key dependency1 dependency2 ... の部分
+// 例) %depends net.ipv4.tcp_fastopen_key net.ipv4.tcp_fastopen
+fn depends(input: &str) -> IResult<&str, SchemaItem<'_>> {
+    map(
+        preceded(
+            token(tag("%depends")),
+            verify(key_list, |keys: &Vec<&str>| keys.len() >= 2),
+        ),
+        |keys| SchemaItem::Depends(keys[0], keys[1..].to_vec()),
+    )(input)
+}
+
+// %trusted key source の部分。sourceはファイル名かパス
+// 例) %trusted kernel.kptr_restrict base.conf
+fn trusted(input: &str) -> IResult<&str, SchemaItem<'_>> {
+    map(
+        preceded(
+            token(tag("%trusted")),
+            verify(key_list, |items: &Vec<&str>| items.len() == 2),
+        ),
+        |items| SchemaItem::Trusted(items[0], items[1]),
+    )(input)
+}
+
+// %group name key1 key2 ... の部分。キーは同じ行に2つ以上並べ、全て揃えて設定する
+// 例) %group fastopen net.ipv4.tcp_fastopen net.ipv4.tcp_fastopen_key
+fn group(input: &str) -> IResult<&str, SchemaItem<'_>> {
+    map(
+        preceded(
+            token(tag("%group")),
+            verify(key_list, |items: &Vec<&str>| items.len() >= 3),
+        ),
+        |items| SchemaItem::Group(items[0], items[1..].to_vec()),
+    )(input)
+}
+
+// %bool false/true ... の部分。/ の左が偽、右が真の表記で、組は同じ行にいくつでも並べられる
+// 例) %bool 0/1 no/yes off/on
+fn bool_words(input: &str) -> IResult<&str, SchemaItem<'_>> {
+    map_opt(preceded(token(tag("%bool")), key_list), |items| {
+        items
+            .iter()
+            .map(|item| {
+                item.split_once('/').filter(|(falsy, truthy)| {
+                    !falsy.is_empty() && !truthy.is_empty() && !truthy.contains('/')
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(SchemaItem::Bool)
+    })(input)
+}
+
+// 型の参照を組み込み型まで辿って解決する。
+// 未定義の型や循環参照があれば、その型名をエラーとして返す
+fn resolve_type<'a>(
+    expr: TypeExpr<'a>,
+    defs: &HashMap<&'a str, TypeExpr<'a>>,
+) -> Result<SchemaType, &'a str> {
+    let mut visited = HashSet::new();
+    let mut current = expr;
+    loop {
+        match current {
+            TypeExpr::Builtin(schema_type) => return Ok(schema_type),
+            TypeExpr::Named(name) => {
+                if !visited.insert(name) {
+                    return Err(name);
+                }
+                current = defs.get(name).cloned().ok_or(name)?;
+            }
+        }
+    }
+}
+
+// スキーマの各行。コメントや空行は読み飛ばす
+fn schema_items(input: &str) -> IResult<&str, Vec<SchemaItem<'_>>> {
+    terminated(
+        many0(alt((
+            type_def,
+            exclusive,
+            depends,
+            trusted,
+            group,
+            bool_words,
+            schema_entry,
+        ))),
+        preceded(skip0, eof),
+    )(input)
+}
+
+pub fn parse_schema(input: &str) -> Result<Schema, ParseError> {
+    let items = finish(
+        input,
+        schema_items(skip_bom(input)),
+        ParseErrorKind::InvalidSchema,
+    )?;
+
+    let mut defs = HashMap::new();
+    for item in &items {
+        if let SchemaItem::TypeDef(name, expr) = item {
+            if defs.insert(*name, expr.clone()).is_some() {
+                // 同じ名前の型を二重に定義している
+                return Err(parse_error(input, name, ParseErrorKind::InvalidTypeName));
+            }
+        }
+    }
+
+    let exclusive_groups = items
+        .iter()
+        .filter_map(|item| match item {
+            SchemaItem::Exclusive(keys) => {
+                Some(keys.iter().map(|key| canonical_key(key)).collect())
+            }
+            _ => None,
+        })
+        .collect();
+    let dependencies = items
+        .iter()
+        .flat_map(|item| match item {
+            SchemaItem::Depends(key, dependencies) => dependencies
+                .iter()
+                .map(|dependency| (canonical_key(key), canonical_key(dependency)))
+                .collect(),
+            _ => vec![],
+        })
+        .collect();
+
+    let trusted_sources = items
+        .iter()
+        .filter_map(|item| match item {
+            SchemaItem::Trusted(key, source) => Some((canonical_key(key), source.to_string())),
+            _ => None,
+        })
+        .collect();
+
+    let groups = items
+        .iter()
+        .filter_map(|item| match item {
+            SchemaItem::Group(name, keys) => Some((
+                name.to_string(),
+                keys.iter().map(|key| canonical_key(key)).collect(),
+            )),
+            _ => None,
+        })
+        .collect();
+
+    let bool_words = items
+        .iter()
+        .flat_map(|item| match item {
+            SchemaItem::Bool(pairs) => pairs
+                .iter()
+                .map(|(falsy, truthy)| (falsy.to_string(), truthy.to_string()))
+                .collect(),
+            _ => vec![],
+        })
+        .collect();
+
+    let mut entries = Vec::new();
+    // 既定値と、その入力中の位置
+    let mut defaults = Vec::new();
+    for item in items {
+        if let SchemaItem::Entry(decl) = item {
+            if let Some(default) = decl.default {
+                defaults.push((entries.len(), default));
+            }
+            entries.push(SchemaEntry {
+                // 設定ファイルと同じく、/ 区切りのキーも . 区切りに揃える
+                name: canonical_key(decl.key),
+                schema_type: resolve_type(decl.expr, &defs)
+                    .map_err(|name| parse_error(input, name, ParseErrorKind::InvalidTypeName))?,
+                readonly: decl.readonly,
+                optional: decl.optional,
+                range: decl.range,
+                pattern: decl
+                    .pattern
+                    .map(|source| {
+                        Pattern::new(&source.replace("\\/", "/"))
+                            .map_err(|_| parse_error(input, source, ParseErrorKind::InvalidPattern))
+                    })
+                    .transpose()?,
+                default: decl.default.map(str::to_owned),
+            });
+        }
+    }
+
+    debug!(
+        entries = entries.len(),
+        types = defs.len(),
+        "スキーマをパースしました"
+    );
+    let schema = Schema {
+        entries,
+        exclusive_groups,
+        dependencies,
+        trusted_sources,
+        groups,
+        bool_words,
+    };
+
+    // 既定値は --fix で設定ファイルに書き込むため、そのキーの検証に通る値でなければならない
+    // ファイルの存在は検証するマシンによって変わるため確かめない
+    let options = ValidationOptions {
+        check_path_exists: false,
+        ..Default::default()
+    };
+    for (index, default) in defaults {
+        let value = SysctlValue {
+            value: default.to_owned(),
+            ignore_error: false,
+            origin: None,
+        };
+        if !check_entry(&schema, &schema.entries[index], &value, &options).is_empty() {
+            return Err(parse_error(input, default, ParseErrorKind::InvalidDefault));
+        }
+    }
+    Ok(schema)
+}
+
+// @type 注釈から作ったスキーマ
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnotatedSchema {
+    pub schema: String,
+    // スキーマの各行の型を書いた注釈の位置。注釈の無いキーの行はNone
+    positions: Vec<Option<AnnotationPosition>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AnnotationPosition {
+    // 注釈のある行の、1始まりの行番号
+    line: usize,
+    // 注釈の行での、型の1始まりの列番号(文字単位)
+    column: usize,
+    // スキーマの行で、型の前にある「キー: 」の文字数
+    prefix: usize,
+    snippet: String,
+}
+
+impl AnnotatedSchema {
+    // スキーマの文法の誤りの位置を、注釈を書いた設定ファイルの中の位置に直す
+    // 例) スキーマの「z: numbr」の誤りを、設定ファイルの「# @type numbr」の行の位置にする
+    pub fn locate(&self, error: ParseError) -> ParseError {
+        match self.positions.get(error.line.wrapping_sub(1)) {
+            Some(Some(position)) => ParseError {
+                line: position.line,
+                column: position.column + error.column.saturating_sub(position.prefix + 1),
+                snippet: position.snippet.clone(),
+                ..error
+            },
+            _ => error,
+        }
+    }
+}
+
+// 設定ファイルのキーの直前のコメントに書かれた @type 注釈から、スキーマを組み立てる。
+// 注釈の無いキーはstringとして扱う。設定ファイルの文法に誤りがあればNone
+// 例)
+// # @type number(0..100)
+// vm.swappiness = 60
+pub fn extract_annotated_schema(input: &str) -> Option<AnnotatedSchema> {
+    let entries = parse_sysctl_documented(input).ok()?;
+    let spanned = parse_sysctl_spanned(input).ok()?;
+    let lines: Vec<&str> = input.lines().collect();
+    let mut keys: Vec<&String> = entries.keys().collect();
+    keys.sort();
+
+    let mut schema = String::new();
+    let mut positions = Vec::new();
+    for key in keys {
+        let annotation = entries[key]
+            .comments
+            .iter()
+            .rev()
+            .find_map(|comment| type_annotation(comment));
+        schema.push_str(&format!("{}: {}\n", key, annotation.unwrap_or("string")));
+        // 注釈は、キーの行から前のキーまでの間のコメントで、キーに最も近いもの
+        let position = annotation.and_then(|_| {
+            lines[..spanned.get(key)?.line - 1]
+                .iter()
+                .enumerate()
+                .rev()
+                .map_while(|(index, line)| {
+                    let trimmed = line.trim_start();
+                    (trimmed.is_empty() || trimmed.starts_with(['#', ';'])).then_some((index, line))
+                })
+                .find_map(|(index, line)| {
+                    let annotation = type_annotation(line.trim_start())?;
+                    let offset = annotation.as_ptr() as usize - line.as_ptr() as usize;
+                    Some(AnnotationPosition {
+                        line: index + 1,
+                        column: line[..offset].chars().count() + 1,
+                        prefix: key.chars().count() + 2,
+                        snippet: line.trim_end_matches('\r').to_owned(),
+                    })
+                })
+        });
+        positions.push(position);
+    }
+    Some(AnnotatedSchema { schema, positions })
+}
+
+// # @type <型> のコメントなら、その型
+fn type_annotation(comment: &str) -> Option<&str> {
+    comment
+        .get(1..)?
+        .trim()
+        .strip_prefix("@type")
+        .filter(|rest| rest.starts_with(char::is_whitespace))
+        .map(str::trim)
+}
+
+// 設定ファイルに埋め込まれたスキーマを取り出す。
+// #schema-begin と #schema-end の行で囲まれたコメント行から、先頭の # か ; を1文字取り除いたもの
+// 例)
+// #schema-begin
+// # port: number
+// ## スキーマ側のコメント
+// #schema-end
+pub fn extract_embedded_schema(input: &str) -> Option<String> {
+    let mut lines = skip_bom(input).lines().map(str::trim);
+    lines.find(|line| *line == "#schema-begin")?;
+
+    let mut schema = String::new();
+    for line in lines {
+        if line == "#schema-end" {
+            return Some(schema);
+        }
+        let line = line
+            .strip_prefix('#')
+            .or_else(|| line.strip_prefix(';'))
+            .unwrap_or(line);
+        schema.push_str(line);
+        schema.push('\n');
+    }
+    // 終わりの無いブロックはスキーマとして扱わない
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,68 +635,603 @@ mod tests {
         assert_eq!(schema_type("string"), Ok(("", SchemaType::String)));
         assert_eq!(schema_type("bool"), Ok(("", SchemaType::Boolean)));
         assert_eq!(schema_type("number"), Ok(("", SchemaType::Number)));
+        assert_eq!(schema_type("integer"), Ok(("", SchemaType::Integer)));
+        assert_eq!(schema_type("size"), Ok(("", SchemaType::Size)));
+        assert_eq!(schema_type("duration"), Ok(("", SchemaType::Duration)));
+        assert_eq!(
+            schema_type("(integer, number,string)"),
+            Ok((
+                "",
+                SchemaType::Tuple(vec![
+                    SchemaType::Integer,
+                    SchemaType::Number,
+                    SchemaType::String
+                ])
+            ))
+        );
+        assert!(schema_type("()").is_err());
+        assert_eq!(schema_type("hostname"), Ok(("", SchemaType::Hostname)));
+        assert_eq!(
+            schema_type("path"),
+            Ok(("", SchemaType::Path { exists: false }))
+        );
+        assert_eq!(
+            schema_type("path(exists)"),
+            Ok(("", SchemaType::Path { exists: true }))
+        );
+        assert_eq!(
+            schema_type("enum(foo, bar,baz)"),
+            Ok((
+                "",
+                SchemaType::Enum(vec!["foo".to_owned(), "bar".to_owned(), "baz".to_owned()])
+            ))
+        );
+        assert!(schema_type("enum()").is_err());
+        assert_eq!(
+            schema_type("number[3]"),
+            Ok((
+                "",
+                SchemaType::List {
+                    element: Box::new(SchemaType::Number),
+                    arity: Arity {
+                        min: 3,
+                        max: Some(3)
+                    }
+                }
+            ))
+        );
+        assert_eq!(
+            schema_type("number[1..]"),
+            Ok((
+                "",
+                SchemaType::List {
+                    element: Box::new(SchemaType::Number),
+                    arity: Arity { min: 1, max: None }
+                }
+            ))
+        );
+        assert_eq!(
+            schema_type("string[2..4]").map(|(_, t)| t.to_string()),
+            Ok("string[2..4]".to_owned())
+        );
+        // 個数の無い[]や、範囲が逆のものはリストにならない
+        assert_eq!(schema_type("number[]"), Ok(("[]", SchemaType::Number)));
+        assert_eq!(
+            schema_type("number[4..2]"),
+            Ok(("[4..2]", SchemaType::Number))
+        );
         assert!(schema_type("invalid").is_err(),);
     }
 
+    #[test]
+    fn test_pattern() {
+        assert_eq!(pattern("(/^[a-z]+$/)"), Ok(("", "^[a-z]+$")));
+        assert_eq!(
+            pattern(r"(/^\|?\/[\w\/%. -]+$/) readonly"),
+            Ok((" readonly", r"^\|?\/[\w\/%. -]+$"))
+        );
+        // エスケープされていない / で終わる
+        assert!(pattern("(/a/b/)").is_err());
+
+        let schema =
+            parse_schema("kernel.core_pattern: string(/^\\|?\\/[\\w\\/%. -]+$/)\n").unwrap();
+        let pattern = schema.entries[0].pattern.as_ref().unwrap();
+        assert_eq!(pattern.as_str(), r"^\|?/[\w/%. -]+$");
+        assert!(pattern.is_match("|/usr/lib/systemd/systemd-coredump %P %u"));
+        assert!(pattern.is_match("/var/crash/core.%e.%p"));
+        assert!(!pattern.is_match("core"));
+        // 表示はスキーマの書き方に戻す
+        assert_eq!(
+            schema.entries[0].to_string(),
+            r"kernel.core_pattern: string(/^\|?\/[\w\/%. -]+$/)"
+        );
+
+        let error = parse_schema("a: string\nb: string(/[a-/)\n").unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::InvalidPattern);
+        assert_eq!((error.line, error.column), (2, 12));
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(
+            range("(0..100)"),
+            Ok((
+                "",
+                Range {
+                    min: Some(0),
+                    max: Some(100)
+                }
+            ))
+        );
+        assert_eq!(
+            range("(-1..)"),
+            Ok((
+                "",
+                Range {
+                    min: Some(-1),
+                    max: None
+                }
+            ))
+        );
+        assert!(range("(0-100)").is_err());
+        assert!(range("(100..0)").is_err());
+        assert!(parse_schema("vm.swappiness: number(100..0)\n").is_err());
+        let schema = parse_schema("vm.swappiness: number(0..100)\n").unwrap();
+        assert_eq!(
+            schema.entries[0].range,
+            Some(Range {
+                min: Some(0),
+                max: Some(100)
+            })
+        );
+        assert_eq!(
+            schema.entries[0].to_string(),
+            "vm.swappiness: number(0..100)"
+        );
+    }
+
+    #[test]
+    fn test_extract_annotated_schema() {
+        let input = "# @type number(0..100)\nvm.swappiness = 60\n# just a comment\nkernel.hostname = host\n;@type bool\nflag = 1\n";
+        assert_eq!(
+            extract_annotated_schema(input).map(|annotated| annotated.schema),
+            Some("flag: bool\nkernel.hostname: string\nvm.swappiness: number(0..100)\n".to_owned())
+        );
+        assert_eq!(extract_annotated_schema("not valid"), None);
+
+        // スキーマの誤りは、注釈を書いた行の位置で報告する
+        let input = "a = 1\n\n  # @type  numbr\nzz = 2\n";
+        let annotated = extract_annotated_schema(input).unwrap();
+        let error = parse_schema(&annotated.schema).unwrap_err();
+        assert_eq!((error.line, error.column), (2, 5));
+        let error = annotated.locate(error);
+        assert_eq!((error.line, error.column), (3, 12));
+        assert_eq!(error.snippet, "  # @type  numbr");
+    }
+
     #[test]
     fn test_schema_entry() {
         assert_eq!(
             schema_entry("key: string"),
             Ok((
                 "",
-                SchemaEntry {
-                    name: "key".to_owned(),
-                    schema_type: SchemaType::String
-                }
+                SchemaItem::Entry(EntryDecl {
+                    key: "key",
+                    expr: TypeExpr::Builtin(SchemaType::String),
+                    range: None,
+                    pattern: None,
+                    readonly: false,
+                    optional: false,
+                    default: None
+                })
             ))
         );
         assert_eq!(
             schema_entry("key : bool"),
             Ok((
                 "",
-                SchemaEntry {
-                    name: "key".to_owned(),
-                    schema_type: SchemaType::Boolean
-                }
+                SchemaItem::Entry(EntryDecl {
+                    key: "key",
+                    expr: TypeExpr::Builtin(SchemaType::Boolean),
+                    range: None,
+                    pattern: None,
+                    readonly: false,
+                    optional: false,
+                    default: None
+                })
             ))
         );
         assert_eq!(
             schema_entry("key  :number"),
             Ok((
                 "",
-                SchemaEntry {
-                    name: "key".to_owned(),
-                    schema_type: SchemaType::Number
-                }
+                SchemaItem::Entry(EntryDecl {
+                    key: "key",
+                    expr: TypeExpr::Builtin(SchemaType::Number),
+                    range: None,
+                    pattern: None,
+                    readonly: false,
+                    optional: false,
+                    default: None
+                })
+            ))
+        );
+        assert_eq!(
+            schema_entry("key: port"),
+            Ok((
+                "",
+                SchemaItem::Entry(EntryDecl {
+                    key: "key",
+                    expr: TypeExpr::Named("port"),
+                    range: None,
+                    pattern: None,
+                    readonly: false,
+                    optional: false,
+                    default: None
+                })
+            ))
+        );
+        assert!(schema_entry("key: ").is_err());
+        assert_eq!(
+            schema_entry("kernel.ostype: string readonly"),
+            Ok((
+                "",
+                SchemaItem::Entry(EntryDecl {
+                    key: "kernel.ostype",
+                    expr: TypeExpr::Builtin(SchemaType::String),
+                    range: None,
+                    pattern: None,
+                    readonly: true,
+                    optional: false,
+                    default: None
+                })
+            ))
+        );
+        assert_eq!(
+            schema_entry("net.core.somaxconn?: number(1..)"),
+            Ok((
+                "",
+                SchemaItem::Entry(EntryDecl {
+                    key: "net.core.somaxconn",
+                    expr: TypeExpr::Builtin(SchemaType::Number),
+                    range: Some(Range {
+                        min: Some(1),
+                        max: None
+                    }),
+                    pattern: None,
+                    readonly: false,
+                    optional: true,
+                    default: None
+                })
+            ))
+        );
+        assert_eq!(
+            schema_entry("key: bool optional readonly"),
+            Ok((
+                "",
+                SchemaItem::Entry(EntryDecl {
+                    key: "key",
+                    expr: TypeExpr::Builtin(SchemaType::Boolean),
+                    range: None,
+                    pattern: None,
+                    readonly: true,
+                    optional: true,
+                    default: None
+                })
+            ))
+        );
+        // 次の行のreadonlyという名前のキーは修飾子ではない
+        assert_eq!(
+            schema_entry("key: string\nreadonly: bool"),
+            Ok((
+                "\nreadonly: bool",
+                SchemaItem::Entry(EntryDecl {
+                    key: "key",
+                    expr: TypeExpr::Builtin(SchemaType::String),
+                    range: None,
+                    pattern: None,
+                    readonly: false,
+                    optional: false,
+                    default: None
+                })
+            ))
+        );
+        // = の後ろは行末までが既定値
+        assert_eq!(
+            schema_entry("vm.swappiness: number(0..100) = 60 \nnet.ipv4.tcp_rmem: number[3] = 4096 87380 6291456"),
+            Ok((
+                "\nnet.ipv4.tcp_rmem: number[3] = 4096 87380 6291456",
+                SchemaItem::Entry(EntryDecl {
+                    key: "vm.swappiness",
+                    expr: TypeExpr::Builtin(SchemaType::Number),
+                    range: Some(Range {
+                        min: Some(0),
+                        max: Some(100)
+                    }),
+                    pattern: None,
+                    readonly: false,
+                    optional: false,
+                    default: Some("60")
+                })
+            ))
+        );
+        assert_eq!(
+            parse_schema("net.ipv4.tcp_rmem: number[3] = 4096 87380 6291456\n")
+                .unwrap()
+                .entries[0]
+                .default
+                .as_deref(),
+            Some("4096 87380 6291456")
+        );
+        assert!(parse_schema("vm.swappiness: number =\n").is_err());
+
+        // 型や範囲に合わない既定値はスキーマの誤り
+        for input in [
+            "a: bool\nvm.swappiness: number(0..100) = 500\n",
+            "a: bool\nvm.swappiness: integer = 1.5\n",
+            "a: bool\nnet.ipv4.tcp_rmem: number[3] = 4096 87380\n",
+            "a: bool\nkernel.core_pattern: string(/^\\//) = core\n",
+        ] {
+            let error = parse_schema(input).unwrap_err();
+            assert_eq!(error.kind, ParseErrorKind::InvalidDefault, "{}", input);
+            assert_eq!(error.line, 2, "{}", input);
+        }
+        // %boolの表記も既定値に使える
+        assert!(parse_schema("%bool 0/1\nnet.ipv4.ip_forward: bool = 1\n").is_ok());
+        assert!(parse_schema("net.ipv4.ip_forward: bool = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_type_def() {
+        assert_eq!(
+            type_def("%type port = number"),
+            Ok((
+                "",
+                SchemaItem::TypeDef("port", TypeExpr::Builtin(SchemaType::Number))
             ))
         );
-        assert!(schema_entry("key: invalid").is_err());
+        assert_eq!(
+            type_def("%type alias=port"),
+            Ok(("", SchemaItem::TypeDef("alias", TypeExpr::Named("port"))))
+        );
+    }
+
+    #[test]
+    fn test_parse_schema_with_type_defs() {
+        assert_eq!(
+            parse_schema(
+                "
+                %type port = number
+                ssh.port: port
+                %type flag = bool
+                http.port: port
+                debug: flag
+                "
+            ),
+            Ok(Schema {
+                entries: vec![
+                    SchemaEntry {
+                        name: "ssh.port".to_owned(),
+                        schema_type: SchemaType::Number,
+                        ..Default::default()
+                    },
+                    SchemaEntry {
+                        name: "http.port".to_owned(),
+                        schema_type: SchemaType::Number,
+                        ..Default::default()
+                    },
+                    SchemaEntry {
+                        name: "debug".to_owned(),
+                        schema_type: SchemaType::Boolean,
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            })
+        );
+        // 定義を経由した参照も解決される
+        assert_eq!(
+            parse_schema("%type a = number\n%type b = a\nkey: b"),
+            Ok(Schema {
+                entries: vec![SchemaEntry {
+                    name: "key".to_owned(),
+                    schema_type: SchemaType::Number,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+        );
+        // 組み込み型の名前で始まる型名も、定義済みの型名として読む
+        let schema = parse_schema(
+            "%type numbers = integer
+%type pathish = string
+z: numbers
+p: pathish",
+        )
+        .unwrap();
+        assert_eq!(schema.entries[0].schema_type, SchemaType::Integer);
+        assert_eq!(schema.entries[1].schema_type, SchemaType::String);
+    }
+
+    #[test]
+    fn test_parse_schema_type_def_errors() {
+        // 未定義の型
+        assert_eq!(
+            parse_schema("ssh.port: port"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidTypeName,
+                line: 1,
+                column: 11,
+                snippet: "ssh.port: port".to_owned(),
+            })
+        );
+        // 循環参照
+        assert!(parse_schema("%type a = b\n%type b = a\nkey: a").is_err());
+        // 自己参照
+        assert!(parse_schema("%type a = a\nkey: a").is_err());
+        // 二重定義
+        assert!(parse_schema("%type a = bool\n%type a = number\nkey: a").is_err());
+    }
+
+    #[test]
+    fn test_parse_schema_syntax_error() {
+        assert_eq!(
+            parse_schema("a.key: bool\n# comment\nb.key number\n"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidSchema,
+                line: 3,
+                column: 1,
+                snippet: "b.key number".to_owned(),
+            })
+        );
     }
 
     #[test]
     fn test_parse_schema() {
         assert_eq!(
             parse_schema("key1: string key2: number key3: bool"),
+            Ok(Schema {
+                entries: vec![
+                    SchemaEntry {
+                        name: "key1".to_owned(),
+                        schema_type: SchemaType::String,
+                        ..Default::default()
+                    },
+                    SchemaEntry {
+                        name: "key2".to_owned(),
+                        schema_type: SchemaType::Number,
+                        ..Default::default()
+                    },
+                    SchemaEntry {
+                        name: "key3".to_owned(),
+                        schema_type: SchemaType::Boolean,
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            })
+        );
+        assert_eq!(parse_schema(""), Ok(Schema::default()));
+    }
+
+    #[test]
+    fn test_extract_embedded_schema() {
+        let input = "
+            #schema-begin
+            # port: number
+            ## comment
+            ; debug: bool
+            #schema-end
+            port = 3910
+            debug = true
+        ";
+        let schema_str = extract_embedded_schema(input).unwrap();
+        assert_eq!(
+            parse_schema(&schema_str),
+            Ok(Schema {
+                entries: vec![
+                    SchemaEntry {
+                        name: "port".to_owned(),
+                        schema_type: SchemaType::Number,
+                        ..Default::default()
+                    },
+                    SchemaEntry {
+                        name: "debug".to_owned(),
+                        schema_type: SchemaType::Boolean,
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            })
+        );
+        assert_eq!(extract_embedded_schema("port = 3910"), None);
+        assert_eq!(
+            extract_embedded_schema("#schema-begin\n# port: number"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_exclusive() {
+        assert_eq!(
+            exclusive("%exclusive a.key b.key\nc: string"),
+            Ok(("\nc: string", SchemaItem::Exclusive(vec!["a.key", "b.key"])))
+        );
+        // キーが1つだけの場合は意味が無いのでエラー
+        assert!(exclusive("%exclusive a.key\nb.key: string").is_err());
+        assert_eq!(
+            parse_schema("a.key: bool\n%exclusive a.key b.key c.key\nb.key: bool\n")
+                .unwrap()
+                .exclusive_groups,
+            vec![vec![
+                "a.key".to_owned(),
+                "b.key".to_owned(),
+                "c.key".to_owned()
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_trusted() {
+        assert_eq!(
+            trusted("%trusted a.key base.conf"),
+            Ok(("", SchemaItem::Trusted("a.key", "base.conf")))
+        );
+        assert!(trusted("%trusted a.key").is_err());
+        assert!(trusted("%trusted a.key base.conf other.conf").is_err());
+        assert_eq!(
+            parse_schema("a.key: bool\n%trusted a.key /etc/sysctl.d/base.conf\n")
+                .unwrap()
+                .trusted_sources,
+            vec![("a.key".to_owned(), "/etc/sysctl.d/base.conf".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_group() {
+        assert_eq!(
+            group("%group fastopen a.key b.key\nc: string"),
             Ok((
-                "",
-                Schema {
-                    entries: vec![
-                        SchemaEntry {
-                            name: "key1".to_owned(),
-                            schema_type: SchemaType::String
-                        },
-                        SchemaEntry {
-                            name: "key2".to_owned(),
-                            schema_type: SchemaType::Number
-                        },
-                        SchemaEntry {
-                            name: "key3".to_owned(),
-                            schema_type: SchemaType::Boolean
-                        },
-                    ]
-                }
+                "\nc: string",
+                SchemaItem::Group("fastopen", vec!["a.key", "b.key"])
             ))
         );
-        assert_eq!(parse_schema(""), Ok(("", Schema { entries: vec![] })));
+        assert!(group("%group fastopen a.key").is_err());
+        assert_eq!(
+            parse_schema("a.key: bool\n%group g a.key b.key c.key\nb.key: bool\n")
+                .unwrap()
+                .groups,
+            vec![(
+                "g".to_string(),
+                vec![
+                    "a.key".to_string(),
+                    "b.key".to_string(),
+                    "c.key".to_string()
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_bool_words() {
+        assert_eq!(
+            bool_words("%bool 0/1 no/yes\nc: string"),
+            Ok((
+                "\nc: string",
+                SchemaItem::Bool(vec![("0", "1"), ("no", "yes")])
+            ))
+        );
+        assert!(bool_words("%bool on").is_err());
+        assert!(bool_words("%bool /on").is_err());
+        assert!(bool_words("%bool a/b/c").is_err());
+        let schema = parse_schema("%bool 0/1\na.key: bool\n%bool off/on\n").unwrap();
+        assert_eq!(
+            schema.bool_words,
+            vec![
+                ("0".to_string(), "1".to_string()),
+                ("off".to_string(), "on".to_string())
+            ]
+        );
+        assert_eq!(schema.parse_bool("on"), Some(true));
+        assert_eq!(schema.parse_bool("0"), Some(false));
+        assert_eq!(schema.parse_bool("true"), None);
+    }
+
+    #[test]
+    fn test_depends() {
+        assert_eq!(
+            depends("%depends a.key b.key c.key"),
+            Ok(("", SchemaItem::Depends("a.key", vec!["b.key", "c.key"])))
+        );
+        assert!(depends("%depends a.key").is_err());
+        assert_eq!(
+            parse_schema("%depends a.key b.key c.key\n")
+                .unwrap()
+                .dependencies,
+            vec![
+                ("a.key".to_owned(), "b.key".to_owned()),
+                ("a.key".to_owned(), "c.key".to_owned())
+            ]
+        );
     }
 }
@@ -1,53 +1,676 @@
-use super::util::{equals, hyphen, skip0, token};
-use crate::types::SysctlValue;
+use super::util::{finish, parse_error, skip0, skip_bom};
+use crate::key::{normalize_key, KeyStyle};
+use crate::types::{
+    DocumentedValue, Origin, ParseError, ParseErrorKind, ParseWarning, SpannedEntry, SysctlValue,
+    ValidationError,
+};
+use indexmap::IndexMap;
 use nom::{
-    bytes::complete::{take_till, take_while},
-    combinator::{eof, map, opt},
+    branch::alt,
+    bytes::complete::{tag, take_till, take_while},
+    character::complete::{char, line_ending, multispace0, space0},
+    combinator::{eof, map, opt, peek, recognize},
+    error::{Error, ErrorKind},
     multi::many0,
-    sequence::{delimited, terminated, tuple},
-    IResult,
+    sequence::{delimited, preceded, terminated, tuple},
+    IResult, Offset,
 };
 use std::collections::HashMap;
+use tracing::{debug, trace};
+
+// sysctl(8)は4096バイトのバッファで1行ずつ読むため、改行を除いてこれ以上の長さの行は読み込めない
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 4096;
+
+// 設定ファイルの文法のうち、変えられるもの。既定ではsysctl.confの文法になる
+// sysctl.confに近い key=value 形式の、別のツールの設定ファイルも読み込めるようにする
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseOptions {
+    // 行頭に書くと、その行がコメントになる文字
+    pub comment_chars: Vec<char>,
+    // キーと値の区切り
+    pub separator: char,
+    // 行頭の - を、反映の失敗を無視する印として受け付けるか
+    pub ignore_error_prefix: bool,
+    // 値の後ろの、空白に続くコメントの文字から行末までを、コメントとして値から除く
+    // 例) vm.swappiness = 10  # DBサーバー向け
+    pub inline_comments: bool,
+    // 改行を除いた行の長さの上限(バイト数、この値は含まない)。Noneなら制限しない
+    pub max_line_length: Option<usize>,
+    // [net.ipv4] のようなセクションの見出しを受け付け、続くキーの前に名前を付ける
+    // 例) [net.ipv4] の後の ip_forward = 1 は net.ipv4.ip_forward になる。[] で見出しの無い状態に戻る
+    pub sections: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            comment_chars: vec!['#', ';'],
+            separator: '=',
+            ignore_error_prefix: true,
+            inline_comments: false,
+            max_line_length: Some(DEFAULT_MAX_LINE_LENGTH),
+            sections: false,
+        }
+    }
+}
+
+// コメントの行や空白、改行を0個以上読み飛ばすパーサー
+fn skip<'a>(options: &ParseOptions) -> impl FnMut(&'a str) -> IResult<&'a str, ()> {
+    let comment_chars = options.comment_chars.clone();
+    move |input: &'a str| {
+        let mut rest = input;
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', '\r', '\n']);
+            if !rest.starts_with(comment_chars.as_slice()) {
+                return Ok((rest, ()));
+            }
+            rest = rest.find('\n').map_or("", |i| &rest[i + 1..]);
+        }
+    }
+}
+
+// パーサーの前に、コメントや空白を読み飛ばす
+fn token<'a, O>(
+    options: &ParseOptions,
+    mut parser: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    let mut skip = skip(options);
+    move |input: &'a str| {
+        let (rest, _) = skip(input)?;
+        parser(rest)
+    }
+}
 
-// = や空白以外の任意の連続した文字
+// 区切りや空白以外の任意の連続した文字
 // 例) hoge, console.log /var/log
-fn parse_key(input: &str) -> IResult<&str, &str> {
-    token(take_while(|c: char| !c.is_whitespace() && c != '='))(input)
+fn parse_key<'a>(options: &ParseOptions) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    let separator = options.separator;
+    token(
+        options,
+        take_while(move |c: char| !c.is_whitespace() && c != separator),
+    )
+}
+
+// 反映の失敗を無視する印の -。受け付けない文法では、- もキーの一部になる
+fn ignore_error_mark<'a>(
+    options: &ParseOptions,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Option<&'a str>> {
+    let allowed = options.ignore_error_prefix;
+    let mut hyphen = opt(token(options, tag("-")));
+    move |input: &'a str| {
+        if allowed {
+            hyphen(input)
+        } else {
+            Ok((input, None))
+        }
+    }
+}
+
+// (値, 値の後ろのコメント)。いずれも入力のスライス
+type RawValue<'a> = (&'a str, Option<&'a str>);
+
+// 引用符で囲まれた値か、行の終わりまでを読み込んでtrimした値
+// 値は入力のスライスのまま返す。引用符や行の継続はto_valueで解釈する
+fn parse_value<'a>(
+    options: &ParseOptions,
+) -> impl FnMut(&'a str) -> IResult<&'a str, RawValue<'a>> {
+    let inline_comments = options.inline_comments;
+    let comment_chars = options.comment_chars.clone();
+    let mut value = {
+        let comment_chars = comment_chars.clone();
+        token(
+            options,
+            alt((
+                move |input| quoted_value(input, inline_comments.then_some(&comment_chars)),
+                unquoted_value,
+            )),
+        )
+    };
+    move |input: &'a str| {
+        let (rest, value) = value(input)?;
+        if !inline_comments {
+            return Ok((rest, (value, None)));
+        }
+        if value.starts_with(['"', '\'']) {
+            // 閉じた引用符の後は、空白を除くとコメントか行末だけが残っている
+            let (rest, comment) = take_till(|c: char| c == '\r' || c == '\n')(rest)?;
+            let comment = comment.trim_end();
+            return Ok((rest, (value, (!comment.is_empty()).then_some(comment))));
+        }
+        match inline_comment_start(value, &comment_chars) {
+            Some(start) => Ok((rest, (value[..start].trim_end(), Some(&value[start..])))),
+            None => Ok((rest, (value, None))),
+        }
+    }
+}
+
+// 引用符で囲まれていない値の中の、空白に続く最初のコメントの文字の位置
+fn inline_comment_start(value: &str, comment_chars: &[char]) -> Option<usize> {
+    value
+        .match_indices(comment_chars)
+        .map(|(index, _)| index)
+        .find(|&index| value[..index].ends_with([' ', '\t']))
+}
+
+// 行の終わりまでの値。行末の \ の直後で改行すると、次の行も値に含める
+// 例) ports = 1000,\\\n  2000
+fn unquoted_value(input: &str) -> IResult<&str, &str> {
+    let mut end = 0;
+    loop {
+        let line_end = input[end..]
+            .find(['\r', '\n'])
+            .map_or(input.len(), |i| end + i);
+        let newline = match &input[line_end..] {
+            rest if rest.starts_with("\r\n") => 2,
+            rest if rest.starts_with('\n') => 1,
+            _ => 0,
+        };
+        if newline > 0 && input[..line_end].ends_with('\\') {
+            end = line_end + newline;
+            continue;
+        }
+        return Ok((&input[line_end..], input[..line_end].trim()));
+    }
+}
+
+// "..." か '...' で囲んだ値。# や前後の空白も値に含められる。囲みの中では \ の次の文字をエスケープする
+// 例) "|/usr/bin/handler %p %e"
+// 閉じていない場合や、閉じた後に空白以外が続く場合は、他の解釈を試さずに失敗する
+// comment_charsがあれば、閉じた後に空白とコメントが続いてもよい
+fn quoted_value<'a>(
+    input: &'a str,
+    comment_chars: Option<&Vec<char>>,
+) -> IResult<&'a str, &'a str> {
+    let quote = match input.chars().next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        _ => return Err(nom::Err::Error(Error::new(input, ErrorKind::Char))),
+    };
+    let mut chars = input.char_indices().skip(1);
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\r' | '\n' => break,
+            // \ の直後の改行は、次の行に続ける
+            '\\' => match chars.next() {
+                Some((_, '\n')) => {}
+                Some((_, '\r')) if matches!(chars.next(), Some((_, '\n'))) => {}
+                Some((_, '\r')) | None => break,
+                Some(_) => {}
+            },
+            c if c == quote => {
+                let (quoted, rest) = input.split_at(index + 1);
+                let rest = rest.trim_start_matches([' ', '\t']);
+                if rest.is_empty() || rest.starts_with(['\r', '\n']) {
+                    return Ok((rest, quoted));
+                }
+                let spaced = rest.len() < input.len() - quoted.len();
+                if let Some(comment_chars) = comment_chars {
+                    if spaced && rest.starts_with(comment_chars.as_slice()) {
+                        return Ok((rest, quoted));
+                    }
+                }
+                break;
+            }
+            _ => {}
+        }
+    }
+    Err(nom::Err::Failure(Error::new(input, ErrorKind::Char)))
 }
 
-fn parse_value(input: &str) -> IResult<&str, &str> {
-    // 行の終わりまで読み込んでtrimする
+// parse_valueで読んだ値を解釈する
+// 引用符で囲まれた値は囲みを外してエスケープを戻し、それ以外は継続した行をつなげてからtrimする
+pub(crate) fn to_value(raw: &str) -> String {
+    if raw.len() >= 2 && raw.starts_with(['"', '\'']) {
+        unquote(raw)
+    } else {
+        raw.replace("\\\r\n", "")
+            .replace("\\\n", "")
+            .trim()
+            .to_owned()
+    }
+}
+
+// \n、\t、\r 以外は次の文字そのもの。行末の \ は改行と合わせて取り除く
+fn unquote(value: &str) -> String {
+    let mut unquoted = String::new();
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unquoted.push('\n'),
+            Some('t') => unquoted.push('\t'),
+            Some('r') => unquoted.push('\r'),
+            Some('\n') => {}
+            Some('\r') => {
+                chars.next();
+            }
+            Some(c) => unquoted.push(c),
+            None => {}
+        }
+    }
+    unquoted
+}
+
+// (先頭の-, キー, 値, 値の後ろのコメント)。いずれも入力のスライス
+pub(crate) type RawKeyValue<'a> = (Option<&'a str>, &'a str, &'a str, Option<&'a str>);
+
+// key = value の部分を、入力のスライスのまま返す
+// 例) -endpoint = localhost:3000 -> (Some("-"), "endpoint", "localhost:3000", None)
+pub(crate) fn raw_key_value<'a>(
+    options: &ParseOptions,
+) -> impl FnMut(&'a str) -> IResult<&'a str, RawKeyValue<'a>> {
     map(
-        token(take_till(|c: char| c == '\r' || c == '\n')),
-        |s: &str| s.trim(),
-    )(input)
+        tuple((
+            ignore_error_mark(options),
+            parse_key(options),
+            token(options, char(options.separator)),
+            parse_value(options),
+        )),
+        |(opt_hyphen, k, _, (v, comment))| (opt_hyphen, k, v, comment),
+    )
+}
+
+// セクションの見出しか key = value の1項目
+#[derive(Debug, PartialEq)]
+enum RawItem<'a> {
+    // 見出しの名前。入力のスライスのまま
+    Section(&'a str),
+    Entry(RawKeyValue<'a>),
+}
+
+// [name] の見出し。後ろには空白と行末だけを書ける
+fn section_header<'a>(options: &ParseOptions) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    token(
+        options,
+        terminated(
+            delimited(
+                char('['),
+                take_till(|c: char| c == ']' || c == '\r' || c == '\n'),
+                char(']'),
+            ),
+            peek(preceded(space0, alt((line_ending, eof)))),
+        ),
+    )
+}
+
+// sectionsの場合だけ、見出しも読む
+fn raw_item<'a>(options: &ParseOptions) -> impl FnMut(&'a str) -> IResult<&'a str, RawItem<'a>> {
+    let sections = options.sections;
+    let mut header = section_header(options);
+    let mut entry = raw_key_value(options);
+    move |input: &'a str| {
+        if sections {
+            if let Ok((rest, name)) = header(input) {
+                return Ok((rest, RawItem::Section(name.trim())));
+            }
+        }
+        let (rest, kv) = entry(input)?;
+        Ok((rest, RawItem::Entry(kv)))
+    }
+}
+
+// 見出しの中のキーは、見出しの名前を前に付けたキーになる
+fn full_key(section: Option<&str>, key: &str) -> String {
+    match section {
+        Some(section) => format!("{}.{}", canonical_key(section), canonical_key(key)),
+        None => canonical_key(key),
+    }
+}
+
+// キーか見出しの名前に誤りがあれば、その位置を指すエラーにする
+fn check_key(input: &str, key: &str) -> Result<(), ParseError> {
+    match invalid_key_position(key) {
+        Some(position) => Err(parse_error(
+            input,
+            &key[position..],
+            ParseErrorKind::InvalidKey,
+        )),
+        None => Ok(()),
+    }
+}
+
+// 見出しを読んだ後の、今の見出しの名前
+fn next_section<'a>(input: &str, name: &'a str) -> Result<Option<&'a str>, ParseError> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+    check_key(input, name)?;
+    Ok(Some(name))
+}
+
+// キーに使える文字。カーネルのsysctlの名前と、インターフェース名などに使われる記号
+fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | ':' | '@' | '+')
+}
+
+// キーの名前に誤りがあれば、その箇所のキー中のバイト位置を返す
+// 使えない文字か、先頭や末尾の区切り、続いた区切りによる空の名前を誤りとする
+// 例) net..ipv4 -> Some(4)
+pub(crate) fn invalid_key_position(key: &str) -> Option<usize> {
+    if let Some((index, _)) = key.char_indices().find(|&(_, c)| !is_key_char(c)) {
+        return Some(index);
+    }
+    let mut after_separator = true;
+    for (index, c) in key.char_indices() {
+        let separator = c == '.' || c == '/';
+        if separator && after_separator {
+            return Some(index);
+        }
+        after_separator = separator;
+    }
+    // 空のキーか、区切りで終わるキー
+    after_separator.then(|| key.len().saturating_sub(1))
+}
+
+// net/ipv4/ip_forward のような / 区切りのキーも、. 区切りに揃えて持つ
+pub(crate) fn canonical_key(key: &str) -> String {
+    normalize_key(key, KeyStyle::Dots).into_owned()
+}
+
+fn to_sysctl_value(opt_hyphen: Option<&str>, value: &str) -> SysctlValue {
+    SysctlValue {
+        value: to_value(value),
+        ignore_error: opt_hyphen.is_some(),
+        origin: None,
+    }
 }
 
 // key = value の部分
 // 例) endpoint = localhost:3000
-fn parse_key_value(input: &str) -> IResult<&str, (String, SysctlValue)> {
+// キーは位置を計算できるよう、入力のスライスのまま返す
+fn parse_key_value(input: &str) -> IResult<&str, (&str, SysctlValue)> {
     map(
-        tuple((opt(hyphen), parse_key, equals, parse_value)),
-        |(opt_hyphen, k, _, v)| {
-            let ignore_error = opt_hyphen.is_some();
+        raw_key_value(&ParseOptions::default()),
+        |(opt_hyphen, k, v, _)| (k, to_sysctl_value(opt_hyphen, v)),
+    )(input)
+}
+
+pub fn parse_sysctl(input: &str) -> Result<IndexMap<String, SysctlValue>, ParseError> {
+    parse_sysctl_with(input, &ParseOptions::default())
+}
+
+// parse_sysctlと同じだが、optionsで選んだ文法も受け付ける
+pub fn parse_sysctl_with(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<IndexMap<String, SysctlValue>, ParseError> {
+    parse_sysctl_spanned_with(input, options).map(|entries| {
+        entries
+            .into_iter()
+            .map(|(key, entry)| (key, entry.value))
+            .collect()
+    })
+}
+
+// key = value (と見出し)の並び。コメントや空行は読み飛ばす
+fn raw_items<'a>(
+    options: &ParseOptions,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<RawItem<'a>>> {
+    terminated(
+        many0(delimited(skip(options), raw_item(options), skip(options))),
+        preceded(skip(options), eof),
+    )
+}
+
+// parse_sysctlと同じ文法で、各キーの入力中のバイト範囲も返す
+// 同じキーが複数回ある場合は、値と同じく最後のものの範囲になる
+pub fn parse_sysctl_spanned(input: &str) -> Result<IndexMap<String, SpannedEntry>, ParseError> {
+    parse_sysctl_spanned_with(input, &ParseOptions::default())
+}
+
+pub fn parse_sysctl_spanned_with(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<IndexMap<String, SpannedEntry>, ParseError> {
+    if let Some(error) = long_lines(input, options).into_iter().next() {
+        return Err(error);
+    }
+    let items = finish(
+        input,
+        raw_items(options)(skip_bom(input)),
+        ParseErrorKind::InvalidLine,
+    )?;
+    let mut section = None;
+    let mut kvs = Vec::new();
+    for item in items {
+        match item {
+            RawItem::Section(name) => section = next_section(input, name)?,
+            RawItem::Entry(kv) => {
+                check_key(input, kv.1)?;
+                kvs.push((section, kv));
+            }
+        }
+    }
+    // エントリは入力の順に並んでいるので、前のキーからの改行を数えて行番号を求める
+    let mut line = 1;
+    let mut counted = 0;
+    let entries: IndexMap<String, SpannedEntry> = kvs
+        .into_iter()
+        .map(|(section, (opt_hyphen, key, value, comment))| {
+            let key_start = input.offset(key);
+            let value_start = input.offset(value);
+            line += input[counted..key_start].matches('\n').count();
+            counted = key_start;
+            trace!(key, value, line, "エントリを読み込みました");
             (
-                k.to_owned(),
-                SysctlValue {
-                    value: v.to_string(),
-                    ignore_error,
+                full_key(section, key),
+                SpannedEntry {
+                    value: to_sysctl_value(opt_hyphen, value),
+                    key_span: (key_start, key_start + key.len()),
+                    value_span: (value_start, value_start + value.len()),
+                    line,
+                    comment: comment.map(str::to_owned),
                 },
             )
-        },
+        })
+        .collect();
+    debug!(entries = entries.len(), "設定をパースしました");
+    Ok(entries)
+}
+
+// ファイルから読み込んだ各キーの、有効な値を設定した場所。文法に誤りがあれば空
+pub fn key_origins(file: &str, input: &str, options: &ParseOptions) -> IndexMap<String, Origin> {
+    parse_sysctl_spanned_with(input, options)
+        .map(|entries| {
+            entries
+                .into_iter()
+                .map(|(key, entry)| {
+                    let origin = Origin {
+                        file: file.to_owned(),
+                        line: entry.line,
+                    };
+                    (key, origin)
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// parse_sysctlと同じ文法で、誤りのある行を読み飛ばしながら最後までパースする
+// 読めたエントリと、読み飛ばした行ごとの誤りを返す。誤りが無ければparse_sysctlと同じ結果になる
+pub fn parse_sysctl_recovering(input: &str) -> (IndexMap<String, SysctlValue>, Vec<ParseError>) {
+    parse_sysctl_recovering_with(input, &ParseOptions::default())
+}
+
+// sysctl(8)と同じく、誤りのある行を警告にして読み飛ばし、残りの行だけを読み込む
+pub fn parse_sysctl_lenient(
+    input: &str,
+    options: &ParseOptions,
+) -> (IndexMap<String, SysctlValue>, Vec<ParseWarning>) {
+    let (entries, errors) = parse_sysctl_recovering_with(input, options);
+    (
+        entries,
+        errors.iter().map(ParseWarning::from_error).collect(),
+    )
+}
+
+pub fn parse_sysctl_recovering_with(
+    input: &str,
+    options: &ParseOptions,
+) -> (IndexMap<String, SysctlValue>, Vec<ParseError>) {
+    let mut entries = IndexMap::new();
+    let mut errors = long_lines(input, options);
+    let mut rest = skip_bom(input);
+    let mut section = None;
+    loop {
+        // skipは失敗しない
+        let (line, _) = skip(options)(rest).unwrap_or((rest, ()));
+        if line.is_empty() {
+            break;
+        }
+        match raw_item(options)(line) {
+            Ok((next, RawItem::Section(name))) => {
+                // 誤りのある見出しの後のキーは、見出しの無いキーとして読む
+                section = next_section(input, name).unwrap_or_else(|error| {
+                    errors.push(error);
+                    None
+                });
+                rest = next;
+            }
+            Ok((next, RawItem::Entry((opt_hyphen, key, value, _)))) => {
+                match check_key(input, key) {
+                    Ok(()) => {
+                        trace!(key, value, "エントリを読み込みました");
+                        entries.insert(full_key(section, key), to_sysctl_value(opt_hyphen, value));
+                    }
+                    Err(error) => errors.push(error),
+                }
+                rest = next;
+            }
+            Err(_) => {
+                let error = parse_error(input, line, ParseErrorKind::InvalidLine);
+                trace!(line = error.line, "誤りのある行を読み飛ばしました");
+                errors.push(error);
+                // 次の行から再開する
+                rest = line.find('\n').map_or("", |i| &line[i + 1..]);
+            }
+        }
+    }
+    errors.sort_by_key(|error| error.line);
+    debug!(
+        entries = entries.len(),
+        errors = errors.len(),
+        "設定をパースしました"
+    );
+    (entries, errors)
+}
+
+// 長さの上限を超える行ごとの誤り。キーや値の行に限らず、コメントや継続した行も1行ずつ数える
+fn long_lines(input: &str, options: &ParseOptions) -> Vec<ParseError> {
+    let Some(max) = options.max_line_length else {
+        return Vec::new();
+    };
+    skip_bom(input)
+        .split_inclusive('\n')
+        .filter(|line| line.trim_end_matches(['\r', '\n']).len() >= max)
+        .map(|line| parse_error(input, line, ParseErrorKind::LineTooLong { max }))
+        .collect()
+}
+
+// 同じキーが複数回設定されている箇所を探す。parse_sysctlでは最後の値だけが残る
+// 3回以上ある場合は、それぞれ直前の設定と組にする。誤りのある行は読み飛ばす
+pub fn find_duplicate_keys(input: &str, options: &ParseOptions) -> Vec<ValidationError> {
+    let mut lines: HashMap<String, usize> = HashMap::new();
+    let mut duplicates = Vec::new();
+    let mut section = None;
+    for (line_number, line) in logical_lines(skip_bom(input), &options.comment_chars) {
+        let key = match preceded(skip(options), raw_item(options))(line) {
+            Ok((_, RawItem::Section(name))) => {
+                section = next_section(input, name).unwrap_or(None);
+                continue;
+            }
+            Ok((_, RawItem::Entry((_, key, _, _)))) => full_key(section, key),
+            Err(_) => continue,
+        };
+        if let Some(first_line) = lines.insert(key.clone(), line_number) {
+            duplicates.push(ValidationError::DuplicateKey {
+                key_name: key,
+                first_line,
+                second_line: line_number,
+            });
+        }
+    }
+    duplicates
+}
+
+// 入力を、行末の \ で継続した行をまとめた1行ずつに分ける。各行は改行を含む
+// 行番号は、まとめた最初の行の1始まりの番号。コメントや空行は継続しない
+pub(crate) fn logical_lines<'a>(
+    input: &'a str,
+    comment_chars: &[char],
+) -> impl Iterator<Item = (usize, &'a str)> {
+    let comment_chars = comment_chars.to_vec();
+    let mut physical = input.split_inclusive('\n').enumerate().peekable();
+    std::iter::from_fn(move || {
+        let (index, first) = physical.next()?;
+        let start = input.offset(first);
+        let mut end = start + first.len();
+        let is_entry = !first.trim_start().is_empty()
+            && !first.trim_start().starts_with(comment_chars.as_slice());
+        while is_entry && input[..end].trim_end_matches(['\r', '\n']).ends_with('\\') {
+            let Some((_, next)) = physical.next() else {
+                break;
+            };
+            end += next.len();
+        }
+        Some((index + 1, &input[start..end]))
+    })
+}
+
+// 空白の後に続くコメント1行。# や ; も含めて返す
+fn doc_comment(input: &str) -> IResult<&str, &str> {
+    preceded(
+        multispace0,
+        recognize(tuple((
+            alt((tag("#"), tag(";"))),
+            take_till(|c: char| c == '\r' || c == '\n'),
+        ))),
     )(input)
 }
 
-pub fn parse_sysctl(input: &str) -> IResult<&str, HashMap<String, SysctlValue>> {
+// key = value と、その前にあるコメント。キーは入力のスライスのまま返す
+fn documented_key_value(input: &str) -> IResult<&str, (&str, DocumentedValue)> {
     map(
-        terminated(many0(delimited(skip0, parse_key_value, skip0)), eof),
-        |kvs| kvs.into_iter().collect::<HashMap<_, _>>(),
+        tuple((many0(doc_comment), parse_key_value)),
+        |(comments, (k, value))| {
+            (
+                k,
+                DocumentedValue {
+                    value,
+                    comments: comments
+                        .into_iter()
+                        .map(|comment| comment.trim_end().to_owned())
+                        .collect(),
+                },
+            )
+        },
     )(input)
 }
 
+// parse_sysctlと同じ文法で、各キーの直前(前のキーとの間)にあるコメントも返す
+// ファイル末尾の、どのキーにも続かないコメントは捨てる
+pub fn parse_sysctl_documented(
+    input: &str,
+) -> Result<IndexMap<String, DocumentedValue>, ParseError> {
+    let kvs = finish(
+        input,
+        terminated(many0(documented_key_value), preceded(skip0, eof))(skip_bom(input)),
+        ParseErrorKind::InvalidLine,
+    )?;
+    kvs.into_iter()
+        .map(|(key, value)| match invalid_key_position(key) {
+            Some(position) => Err(parse_error(
+                input,
+                &key[position..],
+                ParseErrorKind::InvalidKey,
+            )),
+            None => Ok((canonical_key(key), value)),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -55,6 +678,7 @@ mod tests {
 
     #[test]
     fn test_key() {
+        let mut parse_key = parse_key(&ParseOptions::default());
         assert_eq!(parse_key("key=value"), Ok(("=value", "key")));
         assert_eq!(parse_key("-key=value"), Ok(("=value", "-key")));
         assert_eq!(parse_key(" key=value"), Ok(("=value", "key")));
@@ -64,10 +688,11 @@ mod tests {
 
     #[test]
     fn test_value() {
-        assert_eq!(parse_value("value\n"), Ok(("\n", "value")));
-        assert_eq!(parse_value("value "), Ok(("", "value")));
-        assert_eq!(parse_value(" value "), Ok(("", "value")));
-        assert_eq!(parse_value(" value\n"), Ok(("\n", "value")));
+        let mut parse_value = parse_value(&ParseOptions::default());
+        assert_eq!(parse_value("value\n"), Ok(("\n", ("value", None))));
+        assert_eq!(parse_value("value "), Ok(("", ("value", None))));
+        assert_eq!(parse_value(" value "), Ok(("", ("value", None))));
+        assert_eq!(parse_value(" value\n"), Ok(("\n", ("value", None))));
     }
 
     #[test]
@@ -77,10 +702,11 @@ mod tests {
             Ok((
                 "\n",
                 (
-                    "key".to_string(),
+                    "key",
                     SysctlValue {
                         value: "value".to_string(),
-                        ignore_error: true
+                        ignore_error: true,
+                        origin: None,
                     }
                 )
             ))
@@ -90,10 +716,11 @@ mod tests {
             Ok((
                 "\n",
                 (
-                    "key".to_string(),
+                    "key",
                     SysctlValue {
                         value: "value".to_string(),
-                        ignore_error: false
+                        ignore_error: false,
+                        origin: None,
                     }
                 )
             ))
@@ -103,10 +730,11 @@ mod tests {
             Ok((
                 "\n",
                 (
-                    "key".to_string(),
+                    "key",
                     SysctlValue {
                         value: "value".to_string(),
-                        ignore_error: false
+                        ignore_error: false,
+                        origin: None,
                     }
                 )
             ))
@@ -116,10 +744,11 @@ mod tests {
             Ok((
                 "",
                 (
-                    "key".to_string(),
+                    "key",
                     SysctlValue {
                         value: "value".to_string(),
-                        ignore_error: true
+                        ignore_error: true,
+                        origin: None,
                     }
                 )
             ))
@@ -142,6 +771,7 @@ mod tests {
                 SysctlValue {
                     value: "value1".to_string(),
                     ignore_error: false,
+                    origin: None,
                 },
             ),
             (
@@ -149,6 +779,7 @@ mod tests {
                 SysctlValue {
                     value: "value2".to_string(),
                     ignore_error: true,
+                    origin: None,
                 },
             ),
             (
@@ -156,6 +787,7 @@ mod tests {
                 SysctlValue {
                     value: "value3".to_string(),
                     ignore_error: false,
+                    origin: None,
                 },
             ),
             (
@@ -163,11 +795,394 @@ mod tests {
                 SysctlValue {
                     value: "value4".to_string(),
                     ignore_error: false,
+                    origin: None,
                 },
             ),
         ]
         .into_iter()
-        .collect::<HashMap<_, _>>();
-        assert_eq!(parse_sysctl(input), Ok(("", expected_output)));
+        .collect::<IndexMap<_, _>>();
+        assert_eq!(parse_sysctl(input), Ok(expected_output));
+        // コメントだけのファイル
+        assert_eq!(parse_sysctl("# comment"), Ok(IndexMap::new()));
+    }
+
+    #[test]
+    fn test_parse_sysctl_spanned() {
+        let input = "# comment\nfirst.key = 1\n  -second.key=two\n; end\nthird = 3";
+        let entries = parse_sysctl_spanned(input).unwrap();
+        for key in ["first.key", "second.key", "third"] {
+            let (start, end) = entries[key].key_span;
+            assert_eq!(&input[start..end], key);
+        }
+        assert_eq!(entries["first.key"].key_span, (10, 19));
+        // 先頭の-はキーの範囲に含めない
+        assert_eq!(entries["second.key"].key_span, (27, 37));
+        assert_eq!(entries["second.key"].value.value, "two");
+        // 値の範囲は前後の空白を含まない
+        assert_eq!(entries["first.key"].value_span, (22, 23));
+        assert_eq!(entries["first.key"].line, 2);
+        assert_eq!(entries["second.key"].line, 3);
+        assert_eq!(entries["third"].line, 5);
+        let (start, end) = entries["second.key"].value_span;
+        assert_eq!(&input[start..end], "two");
+        assert!(entries["second.key"].value.ignore_error);
+    }
+
+    #[test]
+    fn test_parse_sysctl_error_position() {
+        let error =
+            parse_sysctl("a.key = 1\r\n  # comment\n  broken line\r\nb.key = 2").unwrap_err();
+        assert_eq!(
+            error,
+            ParseError {
+                kind: ParseErrorKind::InvalidLine,
+                line: 3,
+                column: 3,
+                snippet: "  broken line".to_owned(),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "3行3列目: key = value の形式になっていません:   broken line"
+        );
+    }
+
+    #[test]
+    fn test_parse_sysctl_recovering() {
+        let input = "a.key = 1\nbroken\nb.key = 2\n  also broken\n# comment\nc d = 3\nc.key = 4";
+        let (entries, errors) = parse_sysctl_recovering(input);
+        let mut keys: Vec<&String> = entries.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["a.key", "b.key", "c.key"]);
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| (error.line, error.column, error.snippet.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(2, 1, "broken"), (4, 3, "  also broken"), (6, 1, "c d = 3")]
+        );
+        // 最初の誤りはparse_sysctlと同じ
+        assert_eq!(parse_sysctl(input).unwrap_err(), errors[0]);
+
+        let input = "a.key = 1\n-b.key = two\n";
+        assert_eq!(
+            parse_sysctl_recovering(input),
+            (parse_sysctl(input).unwrap(), vec![])
+        );
+    }
+
+    #[test]
+    fn test_parse_sysctl_lenient() {
+        let (entries, warnings) =
+            parse_sysctl_lenient("a.key = 1\nbroken\nb..key = 2\n", &ParseOptions::default());
+        assert_eq!(entries.keys().collect::<Vec<_>>(), vec!["a.key"]);
+        assert_eq!(
+            warnings,
+            vec![
+                ParseWarning {
+                    line: 2,
+                    reason: ParseErrorKind::InvalidLine.to_string(),
+                    raw: "broken".to_owned(),
+                },
+                ParseWarning {
+                    line: 3,
+                    reason: ParseErrorKind::InvalidKey.to_string(),
+                    raw: "b..key = 2".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_quoted_value() {
+        let input = concat!(
+            "kernel.core_pattern = \"|/usr/bin/handler %p %e\"  \n",
+            "a = '  # not a comment'\n",
+            "b = \"say \\\"hi\\\"\\tnow\\\\\"\n",
+            "c = it's\n",
+        );
+        let entries = parse_sysctl(input).unwrap();
+        assert_eq!(
+            entries["kernel.core_pattern"].value,
+            "|/usr/bin/handler %p %e"
+        );
+        assert_eq!(entries["a"].value, "  # not a comment");
+        assert_eq!(entries["b"].value, "say \"hi\"\tnow\\");
+        // 引用符で始まらない値はそのまま
+        assert_eq!(entries["c"].value, "it's");
+
+        // 位置は引用符を含めた範囲
+        let spanned = parse_sysctl_spanned(input).unwrap();
+        let (start, end) = spanned["kernel.core_pattern"].value_span;
+        assert_eq!(&input[start..end], "\"|/usr/bin/handler %p %e\"");
+
+        // 閉じていない引用符や、閉じた後に続く値は誤り
+        for input in ["a = \"open\nb = 1\n", "a = \"x\" y\n", "a = 'x\\'\n"] {
+            let error = parse_sysctl(input).unwrap_err();
+            assert_eq!((error.line, error.column), (1, 5), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_inline_comments() {
+        let input = concat!(
+            "vm.swappiness = 10  # tuned for DB hosts\n",
+            "a = \"x # y\" ; quoted\n",
+            "b = c#d\n",
+            "e = \\\n  f # continued\n",
+        );
+        let options = ParseOptions {
+            inline_comments: true,
+            ..Default::default()
+        };
+        let entries = parse_sysctl_spanned_with(input, &options).unwrap();
+        assert_eq!(entries["vm.swappiness"].value.value, "10");
+        assert_eq!(
+            entries["vm.swappiness"].comment.as_deref(),
+            Some("# tuned for DB hosts")
+        );
+        let (start, end) = entries["vm.swappiness"].value_span;
+        assert_eq!(&input[start..end], "10");
+        assert_eq!(entries["a"].value.value, "x # y");
+        assert_eq!(entries["a"].comment.as_deref(), Some("; quoted"));
+        // 空白に続かない # は値の一部
+        assert_eq!(entries["b"].value.value, "c#d");
+        assert_eq!(entries["b"].comment, None);
+        assert_eq!(entries["e"].value.value, "f");
+        assert_eq!(entries["e"].comment.as_deref(), Some("# continued"));
+
+        // 既定では値に含めるため、閉じた引用符の後のコメントは誤り
+        assert_eq!(parse_sysctl(input).unwrap_err().line, 2);
+        assert_eq!(
+            parse_sysctl("vm.swappiness = 10  # x\n").unwrap()["vm.swappiness"].value,
+            "10  # x"
+        );
+    }
+
+    #[test]
+    fn test_parse_long_lines() {
+        let long = format!("a.key = {}\n", "x".repeat(DEFAULT_MAX_LINE_LENGTH - 8));
+        let error = parse_sysctl(&format!("b.key = 1\n{}", long)).unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::LineTooLong { max: 4096 });
+        assert_eq!((error.line, error.column), (2, 1));
+        // 上限より1バイト短ければ読み込める
+        assert!(parse_sysctl(&long.replacen("a.key", "akey", 1)).is_ok());
+        // コメントの行も数える
+        assert!(parse_sysctl(&format!("#{}", long)).is_err());
+
+        let (entries, errors) =
+            parse_sysctl_recovering(&format!("{}bad line\n{}", long, long.replace('a', "c")));
+        assert_eq!(entries.len(), 2);
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![1, 2, 3]);
+
+        let options = ParseOptions {
+            max_line_length: None,
+            ..Default::default()
+        };
+        assert!(parse_sysctl_with(&long, &options).is_ok());
+    }
+
+    #[test]
+    fn test_parse_slash_keys() {
+        let input = "net/ipv4/ip_forward = 1\nnet.ipv4.ip_forward = 0\n";
+        let entries = parse_sysctl_spanned(input).unwrap();
+        assert_eq!(
+            entries.keys().collect::<Vec<_>>(),
+            vec!["net.ipv4.ip_forward"]
+        );
+        assert_eq!(entries["net.ipv4.ip_forward"].value.value, "0");
+        // 書き方が違っても同じキーとして重複を見つける
+        assert_eq!(
+            find_duplicate_keys(input, &ParseOptions::default()),
+            vec![ValidationError::DuplicateKey {
+                key_name: "net.ipv4.ip_forward".to_owned(),
+                first_line: 1,
+                second_line: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        for (input, column) in [
+            ("net..ipv4 = 1\n", 5),
+            (".net = 1\n", 1),
+            ("net.ipv4. = 1\n", 9),
+            ("  net.ipv4/\u{7}x = 1\n", 12),
+            ("= 1\n", 1),
+            ("キー = 1\n", 1),
+        ] {
+            let error = parse_sysctl(input).unwrap_err();
+            assert_eq!(error.kind, ParseErrorKind::InvalidKey, "{}", input);
+            assert_eq!((error.line, error.column), (1, column), "{}", input);
+        }
+        for key in [
+            "net.ipv4.conf.eth0/100.rp_filter",
+            "fs.binfmt_misc.qemu-arm",
+            "a:b@c+d",
+        ] {
+            assert_eq!(invalid_key_position(key), None, "{}", key);
+        }
+
+        let (entries, errors) = parse_sysctl_recovering("a..b = 1\nc = 2\n");
+        assert_eq!(entries.keys().collect::<Vec<_>>(), vec!["c"]);
+        assert_eq!(errors[0].kind, ParseErrorKind::InvalidKey);
+        assert_eq!(
+            parse_sysctl_documented("a..b = 1\n").unwrap_err().kind,
+            ParseErrorKind::InvalidKey
+        );
+    }
+
+    #[test]
+    fn test_parse_dialect() {
+        let options = ParseOptions {
+            comment_chars: vec!['!'],
+            separator: ':',
+            ignore_error_prefix: false,
+            inline_comments: true,
+            ..Default::default()
+        };
+        let input = "! comment\n#x: 1\n";
+        let error = parse_sysctl_spanned_with(input, &options).unwrap_err();
+        // # はコメントにならず、キーに使えない文字になる
+        assert_eq!((error.kind, error.line), (ParseErrorKind::InvalidKey, 2));
+
+        let input = "! comment\n-key: value ! note\nurl: a=b\n";
+        let entries = parse_sysctl_spanned_with(input, &options).unwrap();
+        assert_eq!(entries["-key"].value.value, "value");
+        assert!(!entries["-key"].value.ignore_error);
+        assert_eq!(entries["-key"].comment.as_deref(), Some("! note"));
+        assert_eq!(entries["url"].value.value, "a=b");
+        assert_eq!(entries["url"].line, 3);
+        assert!(parse_sysctl_with("a = 1\n", &options).is_err());
+    }
+
+    #[test]
+    fn test_parse_sections() {
+        let options = ParseOptions {
+            sections: true,
+            ..Default::default()
+        };
+        let input = "kernel.pid_max = 4096\n[net.ipv4]\nip_forward = 1\n  [ net/core ]  \nsomaxconn = 1024\n[]\nvm.swappiness = 10\n";
+        let entries = parse_sysctl_spanned_with(input, &options).unwrap();
+        assert_eq!(
+            entries.keys().collect::<Vec<_>>(),
+            vec![
+                "kernel.pid_max",
+                "net.ipv4.ip_forward",
+                "net.core.somaxconn",
+                "vm.swappiness"
+            ]
+        );
+        assert_eq!(entries["net.core.somaxconn"].line, 5);
+        assert_eq!(
+            parse_sysctl_recovering_with(input, &options).0,
+            parse_sysctl_with(input, &options).unwrap()
+        );
+        assert_eq!(
+            find_duplicate_keys("[a]\nb = 1\n[]\na.b = 2\n", &options)[0],
+            ValidationError::DuplicateKey {
+                key_name: "a.b".to_owned(),
+                first_line: 2,
+                second_line: 4,
+            }
+        );
+
+        let error = parse_sysctl_with("[net..ipv4]\n", &options).unwrap_err();
+        assert_eq!((error.kind, error.column), (ParseErrorKind::InvalidKey, 6));
+        // 見出しの後に続けて書いたものや、既定の文法では誤り
+        assert!(parse_sysctl_with("[net] a = 1\n", &options).is_err());
+        assert!(parse_sysctl(input).is_err());
+    }
+
+    #[test]
+    fn test_parse_bom() {
+        let input = "\u{feff}a.key = 1\n";
+        assert_eq!(parse_sysctl(input).unwrap()["a.key"].value, "1");
+        let (start, end) = parse_sysctl_spanned(input).unwrap()["a.key"].key_span;
+        assert_eq!(&input[start..end], "a.key");
+        // 桁はBOMの後から数える
+        let error = parse_sysctl("\u{feff}bad line\n").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 1));
+        assert_eq!(error.snippet, "bad line");
+    }
+
+    #[test]
+    fn test_parse_continued_lines() {
+        let input = concat!(
+            "net.ipv4.ip_local_reserved_ports = 1000,\\\n",
+            "    2000,\\\r\n",
+            "    3000  \n",
+            "quoted = \"a \\\n b\"\n",
+            "# comment \\\n",
+            "last = 1\n",
+        );
+        let entries = parse_sysctl(input).unwrap();
+        assert_eq!(
+            entries["net.ipv4.ip_local_reserved_ports"].value,
+            "1000,    2000,    3000"
+        );
+        assert_eq!(entries["quoted"].value, "a  b");
+        // コメントは継続しない
+        assert_eq!(entries["last"].value, "1");
+        let spanned = parse_sysctl_spanned(input).unwrap();
+        assert_eq!(spanned["quoted"].line, 4);
+        assert_eq!(spanned["last"].line, 7);
+
+        let input = "a = 1 \\\n  2\na = 3\n";
+        assert_eq!(
+            find_duplicate_keys(input, &ParseOptions::default()),
+            vec![ValidationError::DuplicateKey {
+                key_name: "a".to_owned(),
+                first_line: 1,
+                second_line: 3,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_sysctl_keeps_order() {
+        let input = "z.key = 1\na.key = 2\nm.key = 3\na.key = 4\n";
+        let entries = parse_sysctl(input).unwrap();
+        // 重複したキーは最初の位置のまま、最後の値になる
+        assert_eq!(
+            entries.keys().collect::<Vec<_>>(),
+            vec!["z.key", "a.key", "m.key"]
+        );
+        assert_eq!(entries["a.key"].value, "4");
+        let (entries, _) = parse_sysctl_recovering(input);
+        assert_eq!(
+            entries.keys().collect::<Vec<_>>(),
+            vec!["z.key", "a.key", "m.key"]
+        );
+    }
+
+    #[test]
+    fn test_find_duplicate_keys() {
+        let input = "a.key = 1\n# a.key = 0\nbroken\n  a.key=2\nb.key = 1\n-a.key = 3\n";
+        let duplicate = |first_line, second_line| ValidationError::DuplicateKey {
+            key_name: "a.key".to_owned(),
+            first_line,
+            second_line,
+        };
+        assert_eq!(
+            find_duplicate_keys(input, &ParseOptions::default()),
+            vec![duplicate(1, 4), duplicate(4, 6)]
+        );
+        assert!(find_duplicate_keys("a.key = 1\nb.key = 1\n", &ParseOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_parse_sysctl_documented() {
+        let input =
+            "# first\n; second\na.key = 1\nb.key = 2\n\n  # about c\n-c.key = 3\n# trailing\n";
+        let entries = parse_sysctl_documented(input).unwrap();
+        assert_eq!(entries["a.key"].comments, vec!["# first", "; second"]);
+        assert!(entries["b.key"].comments.is_empty());
+        assert_eq!(entries["c.key"].comments, vec!["# about c"]);
+        assert!(entries["c.key"].value.ignore_error);
+        assert_eq!(entries.len(), 3);
+        assert!(parse_sysctl_documented("a.key").is_err());
     }
 }
@@ -5,11 +5,13 @@ use nom::{
     combinator::{eof, map},
     multi::many0,
     sequence::tuple,
-    IResult, Parser,
+    IResult, Offset, Parser,
 };
 
+use crate::types::{ParseError, ParseErrorKind};
+
 // コメントをスキップして残りを返すパーサー
-pub fn comment<'a>(s: &str) -> IResult<&str, ()> {
+pub fn comment(s: &str) -> IResult<&str, ()> {
     map(
         tuple((
             alt((tag(";"), tag("#"))),
@@ -39,10 +41,6 @@ where
     }
 }
 
-pub fn hyphen(input: &str) -> IResult<&str, &str> {
-    token(tag("-"))(input)
-}
-
 pub fn equals(input: &str) -> IResult<&str, &str> {
     token(tag("="))(input)
 }
@@ -51,6 +49,46 @@ pub fn colon(input: &str) -> IResult<&str, &str> {
     token(tag(":"))(input)
 }
 
+// 先頭のBOMを読み飛ばす。残りは入力のスライスなので、位置は元の入力から求められる
+pub fn skip_bom(input: &str) -> &str {
+    input.strip_prefix('\u{feff}').unwrap_or(input)
+}
+
+// 入力のスライスatの位置で失敗したことを表すParseErrorを作る
+pub fn parse_error(input: &str, at: &str, kind: ParseErrorKind) -> ParseError {
+    let offset = input.offset(at);
+    // 1行目の桁は、BOMの後から数える
+    let first_line_start = (input.len() - skip_bom(input).len()).min(offset);
+    let line_start = input[..offset]
+        .rfind('\n')
+        .map_or(first_line_start, |i| i + 1);
+    let line_end = input[offset..]
+        .find(['\r', '\n'])
+        .map_or(input.len(), |i| offset + i);
+    ParseError {
+        kind,
+        line: input[..offset].matches('\n').count() + 1,
+        column: input[line_start..offset].chars().count() + 1,
+        snippet: input[line_start..line_end].to_owned(),
+    }
+}
+
+// nomのパーサーの結果を、失敗した位置を含むParseErrorに変換する
+pub fn finish<'a, T>(
+    input: &'a str,
+    result: IResult<&'a str, T>,
+    kind: ParseErrorKind,
+) -> Result<T, ParseError> {
+    match result {
+        Ok((_, value)) => Ok(value),
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => {
+            Err(parse_error(input, error.input, kind))
+        }
+        // completeなパーサーしか使っていないため、入力の終わりで失敗したものとして扱う
+        Err(nom::Err::Incomplete(_)) => Err(parse_error(input, &input[input.len()..], kind)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
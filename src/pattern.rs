@@ -0,0 +1,50 @@
+// * を任意の文字列(空文字列を含む)に、? を任意の1文字にマッチさせる簡易的なグロブ
+// 例) net.ipv4.conf.*.rp_filter
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // 直前の * の位置と、その * にマッチさせ始めたテキストの位置
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // * にもう1文字多くマッチさせてやり直す
+            backtrack = Some((star_p, star_t + 1));
+            p = star_p + 1;
+            t = star_t + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("net.ipv4.ip_forward", "net.ipv4.ip_forward"));
+        assert!(!glob_match("net.ipv4.ip_forward", "net.ipv4.ip_forwarding"));
+        assert!(glob_match("net.ipv4.*", "net.ipv4.ip_forward"));
+        assert!(glob_match(
+            "net.ipv4.conf.*.rp_filter",
+            "net.ipv4.conf.eth0.rp_filter"
+        ));
+        assert!(!glob_match(
+            "net.ipv4.conf.*.rp_filter",
+            "net.ipv4.conf.eth0.accept_redirects"
+        ));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("vm.?wappiness", "vm.swappiness"));
+        assert!(!glob_match("vm.?", "vm."));
+    }
+}
@@ -0,0 +1,252 @@
+use indexmap::IndexMap;
+use serde::Deserialize;
+use std::fmt::Display;
+
+use crate::pattern::glob_match;
+use crate::tr;
+use crate::types::SysctlValue;
+
+// ポリシーファイルはルールのJSON配列
+// 例)
+// [
+//   { "key": "kernel.sysrq", "condition": "forbidden", "severity": "warning" },
+//   { "key": "net.ipv4.conf.*.rp_filter", "condition": { "min": 1 } },
+//   { "key": "kernel.randomize_va_space", "condition": { "equals": "2" }, "message": "ASLRを無効にしないでください" }
+// ]
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Rule {
+    // 対象のキー。* と ? のグロブが使える
+    pub key: String,
+    pub condition: Condition,
+    #[serde(default)]
+    pub severity: Severity,
+    // 違反時に表示するメッセージ。省略時は条件から生成する
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    // キーを設定してはいけない
+    Forbidden,
+    // パターンに一致するキーが1つ以上設定されている必要がある
+    Required,
+    // 値が数値で、指定した値以上である必要がある
+    Min(f64),
+    // 値が数値で、指定した値以下である必要がある
+    Max(f64),
+    // 値が指定した文字列と一致する必要がある
+    Equals(String),
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(Severity::Error),
+            "warning" => Some(Severity::Warning),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    pub key: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    pub const CODE: &'static str = "SL0204";
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.severity.as_str(), self.message)
+    }
+}
+
+pub fn parse_policy(input: &str) -> serde_json::Result<Vec<Rule>> {
+    serde_json::from_str(input)
+}
+
+// 条件を満たしていればNone、違反していればその説明を返す
+fn check_condition(key: &str, value: &str, condition: &Condition) -> Option<String> {
+    let number = value.parse::<f64>();
+    match condition {
+        Condition::Forbidden => Some(tr!(
+            "'{}'は設定してはいけません",
+            "'{}' must not be set",
+            key
+        )),
+        Condition::Required => None,
+        Condition::Min(_) | Condition::Max(_) if number.is_err() => Some(tr!(
+            "'{}'の値'{}'は数値である必要があります",
+            "the value '{1}' of '{0}' must be a number",
+            key,
+            value
+        )),
+        Condition::Min(min) if *number.as_ref().unwrap() < *min => Some(tr!(
+            "'{}'の値{}は{}以上である必要があります",
+            "the value {1} of '{0}' must be at least {2}",
+            key,
+            value,
+            min
+        )),
+        Condition::Max(max) if *number.as_ref().unwrap() > *max => Some(tr!(
+            "'{}'の値{}は{}以下である必要があります",
+            "the value {1} of '{0}' must be at most {2}",
+            key,
+            value,
+            max
+        )),
+        Condition::Equals(expected) if value != expected => Some(tr!(
+            "'{}'の値は'{}'である必要がありますが、'{}'になっています",
+            "the value of '{}' must be '{}', but it is '{}'",
+            key,
+            expected,
+            value
+        )),
+        Condition::Min(_) | Condition::Max(_) | Condition::Equals(_) => None,
+    }
+}
+
+// 設定をポリシーのルールで検査し、違反を列挙する
+pub fn evaluate_policy(value: &IndexMap<String, SysctlValue>, rules: &[Rule]) -> Vec<Finding> {
+    let mut keys: Vec<&String> = value.keys().collect();
+    keys.sort();
+
+    let mut findings = Vec::new();
+    for rule in rules {
+        let matched: Vec<&&String> = keys
+            .iter()
+            .filter(|key| glob_match(&rule.key, key))
+            .collect();
+        let mut violations: Vec<(String, String)> = matched
+            .iter()
+            .filter_map(|key| {
+                check_condition(key, &value[**key].value, &rule.condition)
+                    .map(|message| (key.to_string(), message))
+            })
+            .collect();
+        if rule.condition == Condition::Required && matched.is_empty() {
+            violations.push((
+                rule.key.clone(),
+                tr!("'{}'を設定する必要があります", "'{}' must be set", rule.key),
+            ));
+        }
+
+        findings.extend(violations.into_iter().map(|(key, message)| Finding {
+            key,
+            severity: rule.severity,
+            message: rule.message.clone().unwrap_or(message),
+        }));
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_sysctl;
+
+    fn evaluate(config: &str, policy: &str) -> Vec<Finding> {
+        evaluate_policy(
+            &parse_sysctl(config).unwrap(),
+            &parse_policy(policy).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(
+            parse_policy(
+                r#"[
+                    { "key": "kernel.sysrq", "condition": "forbidden", "severity": "warning" },
+                    { "key": "vm.*", "condition": { "max": 100 }, "message": "too large" }
+                ]"#
+            )
+            .unwrap(),
+            vec![
+                Rule {
+                    key: "kernel.sysrq".to_owned(),
+                    condition: Condition::Forbidden,
+                    severity: Severity::Warning,
+                    message: None,
+                },
+                Rule {
+                    key: "vm.*".to_owned(),
+                    condition: Condition::Max(100.0),
+                    severity: Severity::Error,
+                    message: Some("too large".to_owned()),
+                },
+            ]
+        );
+        assert!(parse_policy(r#"[{ "key": "a", "condition": "unknown" }]"#).is_err());
+    }
+
+    #[test]
+    fn test_policy_satisfied() {
+        let policy = r#"[
+            { "key": "kernel.sysrq", "condition": "forbidden" },
+            { "key": "net.ipv4.conf.*.rp_filter", "condition": { "min": 1 } },
+            { "key": "kernel.randomize_va_space", "condition": "required" },
+            { "key": "kernel.randomize_va_space", "condition": { "equals": "2" } }
+        ]"#;
+        assert_eq!(
+            evaluate(
+                "net.ipv4.conf.eth0.rp_filter = 1\nkernel.randomize_va_space = 2\n",
+                policy
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_policy_violated() {
+        let policy = r#"[
+            { "key": "kernel.sysrq", "condition": "forbidden", "severity": "warning" },
+            { "key": "net.ipv4.conf.*.rp_filter", "condition": { "min": 1 } },
+            { "key": "kernel.randomize_va_space", "condition": "required", "message": "ASLR must be configured" }
+        ]"#;
+        assert_eq!(
+            evaluate(
+                "kernel.sysrq = 1\nnet.ipv4.conf.eth0.rp_filter = 0\nnet.ipv4.conf.eth1.rp_filter = 1\n",
+                policy
+            ),
+            vec![
+                Finding {
+                    key: "kernel.sysrq".to_owned(),
+                    severity: Severity::Warning,
+                    message: "'kernel.sysrq'は設定してはいけません".to_owned(),
+                },
+                Finding {
+                    key: "net.ipv4.conf.eth0.rp_filter".to_owned(),
+                    severity: Severity::Error,
+                    message: "'net.ipv4.conf.eth0.rp_filter'の値0は1以上である必要があります"
+                        .to_owned(),
+                },
+                Finding {
+                    key: "kernel.randomize_va_space".to_owned(),
+                    severity: Severity::Error,
+                    message: "ASLR must be configured".to_owned(),
+                },
+            ]
+        );
+    }
+}
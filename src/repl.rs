@@ -0,0 +1,79 @@
+use std::io::{self, BufRead, Write};
+
+use crate::parser::parse_sysctl;
+use crate::tr;
+use crate::types::Schema;
+use crate::validation::{validate_entry, ValidationOptions};
+
+// 1行ずつ読み込み、パースの結果と、スキーマがあればそのキーの検証結果をすぐに出力する。
+// 空行やコメントだけの行には何も出力しない
+pub fn run_repl(
+    input: impl BufRead,
+    out: &mut dyn Write,
+    schema: Option<&Schema>,
+    options: &ValidationOptions,
+) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        let entries = match parse_sysctl(&line) {
+            Ok(entries) => entries,
+            Err(error) => {
+                let message = tr!("文法に誤りがあります。", "syntax error.");
+                writeln!(out, "[{}] {}", error.kind.code(), message)?;
+                continue;
+            }
+        };
+        for (key, sysctl_value) in entries {
+            let prefix = if sysctl_value.ignore_error { "-" } else { "" };
+            writeln!(out, "{}{} = {}", prefix, key, sysctl_value.value)?;
+            let Some(schema) = schema else {
+                continue;
+            };
+            let errors = validate_entry(&key, &sysctl_value, schema, options);
+            if errors.is_empty() {
+                writeln!(out, "  OK")?;
+            }
+            for error in errors {
+                writeln!(out, "  [{}] {:#}", error.code(), error)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_schema;
+
+    #[test]
+    fn test_run_repl() {
+        let schema = parse_schema("a.key: bool\n").unwrap();
+        let input = "a.key = true\n# comment\n\na.key = many\nnot a valid line\n-b.key = 1\n";
+        let mut out = Vec::new();
+        run_repl(
+            input.as_bytes(),
+            &mut out,
+            Some(&schema),
+            &ValidationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a.key = true\n  OK\n\
+             a.key = many\n  [SL0003] 'a.key'の型が間違っています。boolが必要ですが、stringの形式になっています。\n\
+             [SL0101] 文法に誤りがあります。\n\
+             -b.key = 1\n  [SL0002] 定義されていない'b.key'が存在しており、これは不要です\n"
+        );
+
+        let mut out = Vec::new();
+        run_repl(
+            "a.key = true\n".as_bytes(),
+            &mut out,
+            None,
+            &ValidationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a.key = true\n");
+    }
+}
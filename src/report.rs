@@ -0,0 +1,841 @@
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::analysis::{DependencyDisabled, Outlier};
+use crate::diagnostics::render_validation_error;
+use crate::policy::Finding;
+use crate::style::{paint, Style};
+use crate::tr;
+use crate::types::{
+    Origin, ParseError, ParseWarning, SpannedEntry, SpannedError, SysctlValue, ValidationError,
+};
+use crate::validation::attach_spans;
+
+// カバレッジが下限を下回った場合のエラーコード
+const COVERAGE_CODE: &str = "SL0205";
+
+// どの重大度の診断があれば、0以外の終了コードで失敗にするか
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum FailOn {
+    #[default]
+    Error,
+    // 警告でも失敗にする
+    Warning,
+    // 診断があっても失敗にしない
+    Never,
+}
+
+impl FailOn {
+    // --fail-on の値から選ぶ
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(FailOn::Error),
+            "warning" => Some(FailOn::Warning),
+            "never" => Some(FailOn::Never),
+            _ => None,
+        }
+    }
+
+    // その重大度の診断で失敗にするか
+    pub fn fails(self, severity: &str) -> bool {
+        match self {
+            FailOn::Error => severity == "error",
+            FailOn::Warning => severity == "error" || severity == "warning",
+            FailOn::Never => false,
+        }
+    }
+}
+
+// CIなど機械で処理するための診断結果1件
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Diagnostic {
+    pub severity: &'static str,
+    // 種類ごとに固定のエラーコード。例) SL0001
+    pub code: &'static str,
+    pub kind: &'static str,
+    pub key: Option<String>,
+    // 値を設定したファイル (--follow-includes)。Noneならレポートのファイル
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    // ファイル中の行番号(1始まり)。行に結びつかない診断ではNone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    // 型の誤りでの、必要な型と実際の値の型
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<String>,
+    // 値の後ろに書かれていたコメント (--inline-comments)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    // 診断の対象のファイル。取り込んだファイルで設定された値なら、そのファイル
+    pub fn file_or<'a>(&'a self, report_file: &'a str) -> &'a str {
+        self.file.as_deref().unwrap_or(report_file)
+    }
+}
+
+impl From<&ValidationError> for Diagnostic {
+    fn from(error: &ValidationError) -> Self {
+        let (expected, actual) = match error {
+            ValidationError::WrongType { expect, actual, .. } => {
+                (Some(expect.to_string()), Some(actual.to_string()))
+            }
+            ValidationError::InvalidElement { error, .. } => {
+                let element = Diagnostic::from(error.as_ref());
+                (element.expected, element.actual)
+            }
+            _ => (None, None),
+        };
+        Diagnostic {
+            severity: "error",
+            code: error.code(),
+            kind: error.kind(),
+            key: Some(error.key_name().to_owned()),
+            file: None,
+            line: None,
+            expected,
+            actual,
+            comment: None,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<&SpannedError> for Diagnostic {
+    fn from(spanned: &SpannedError) -> Self {
+        Diagnostic {
+            line: spanned.span.map(|span| span.line),
+            ..Diagnostic::from(&spanned.error)
+        }
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        Diagnostic {
+            severity: "error",
+            code: error.kind.code(),
+            kind: "syntax_error",
+            key: None,
+            file: None,
+            line: Some(error.line),
+            expected: None,
+            actual: None,
+            comment: None,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<&Finding> for Diagnostic {
+    fn from(finding: &Finding) -> Self {
+        Diagnostic {
+            severity: finding.severity.as_str(),
+            code: Finding::CODE,
+            kind: "policy",
+            key: Some(finding.key.clone()),
+            file: None,
+            line: None,
+            expected: None,
+            actual: None,
+            comment: None,
+            message: finding.message.clone(),
+        }
+    }
+}
+
+impl From<&Outlier> for Diagnostic {
+    fn from(outlier: &Outlier) -> Self {
+        Diagnostic {
+            severity: "warning",
+            code: Outlier::CODE,
+            kind: "outlier",
+            key: Some(outlier.key.clone()),
+            file: None,
+            line: None,
+            expected: None,
+            actual: None,
+            comment: None,
+            message: outlier.to_string(),
+        }
+    }
+}
+
+impl From<&DependencyDisabled> for Diagnostic {
+    fn from(disabled: &DependencyDisabled) -> Self {
+        Diagnostic {
+            severity: "warning",
+            code: DependencyDisabled::CODE,
+            kind: "dependency_disabled",
+            key: Some(disabled.key.clone()),
+            file: None,
+            line: None,
+            expected: None,
+            actual: None,
+            comment: None,
+            message: disabled.to_string(),
+        }
+    }
+}
+
+impl From<&ParseWarning> for Diagnostic {
+    fn from(warning: &ParseWarning) -> Self {
+        Diagnostic {
+            severity: "warning",
+            code: ParseWarning::CODE,
+            kind: "parse_warning",
+            key: None,
+            file: None,
+            line: Some(warning.line),
+            expected: None,
+            actual: None,
+            comment: None,
+            message: format!("{}: {}", warning.reason, warning.raw),
+        }
+    }
+}
+
+// 1ファイル分の診断結果をまとめたもの
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Report {
+    pub file: String,
+    pub ok: bool,
+    pub diagnostics: Vec<Diagnostic>,
+    // --max-errors を超えたため、diagnostics に含めなかったエラーの数
+    #[serde(skip_serializing_if = "is_zero")]
+    pub omitted: usize,
+    // 検証に使ったスキーマのキー。グロブのエントリは、それに一致した設定ファイルのキーに置き換える
+    // TAPでキーごとの結果を出力するのに使う
+    #[serde(skip)]
+    pub schema_keys: Vec<String>,
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+impl Report {
+    pub fn new(file: &str, diagnostics: Vec<Diagnostic>) -> Self {
+        Report {
+            file: file.to_owned(),
+            ok: diagnostics.iter().all(|d| d.severity != "error"),
+            diagnostics,
+            omitted: 0,
+            schema_keys: vec![],
+        }
+    }
+
+    pub fn syntax_errors(file: &str, errors: &[ParseError]) -> Self {
+        Report::new(file, errors.iter().map(Diagnostic::from).collect())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("レポートのシリアライズに失敗しました。")
+    }
+}
+
+// 複数のファイルのレポートを、JSONの配列として出力する
+pub fn reports_to_json(reports: &[Report]) -> String {
+    serde_json::to_string_pretty(reports).expect("レポートのシリアライズに失敗しました。")
+}
+
+// すべてのファイルの診断を、ファイル名を付けた1つの一覧としてJSONで出力する
+// --ci のファイルごとのレポートと違い、ラッパーのツールが一覧をそのまま処理できる
+pub fn reports_to_json_report(reports: &[Report]) -> String {
+    #[derive(Serialize)]
+    struct FileDiagnostic<'a> {
+        file: &'a str,
+        #[serde(flatten)]
+        diagnostic: Diagnostic,
+    }
+    #[derive(Serialize)]
+    struct JsonReport<'a> {
+        ok: bool,
+        diagnostics: Vec<FileDiagnostic<'a>>,
+    }
+
+    let json_report = JsonReport {
+        ok: reports.iter().all(|report| report.ok),
+        diagnostics: reports
+            .iter()
+            .flat_map(|report| {
+                // ファイルは一覧の側に書くため、診断からは外す
+                report.diagnostics.iter().map(|diagnostic| FileDiagnostic {
+                    file: diagnostic.file_or(&report.file),
+                    diagnostic: Diagnostic {
+                        file: None,
+                        ..diagnostic.clone()
+                    },
+                })
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&json_report).expect("レポートのシリアライズに失敗しました。")
+}
+
+// GitHub Actionsのワークフローコマンドとして出力する。プルリクエストの該当する行に注釈が付く
+// 例) ::error file=a.conf,line=2,title=SL0003::'port'の型が間違っています。
+pub fn write_github_annotations(out: &mut dyn Write, reports: &[Report]) -> io::Result<()> {
+    for report in reports {
+        for diagnostic in &report.diagnostics {
+            let command = if diagnostic.severity == "error" {
+                "error"
+            } else {
+                "warning"
+            };
+            let mut properties =
+                format!("file={}", escape_property(diagnostic.file_or(&report.file)));
+            if let Some(line) = diagnostic.line {
+                properties.push_str(&format!(",line={}", line));
+            }
+            writeln!(
+                out,
+                "::{} {},title={}::{}",
+                command,
+                properties,
+                diagnostic.code,
+                escape_data(&diagnostic.message)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// ワークフローコマンドのメッセージでは、%と改行をエスケープする
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+// プロパティの値では、さらに:と,もエスケープする
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+// 1ファイル分の検証や検査の結果。出力の形式や出力先には依存しない
+#[derive(Debug, Default)]
+pub struct Results<'a> {
+    // 寛容なパース(--sysctl-compat)で読み飛ばした行
+    pub parse_warnings: &'a [ParseWarning],
+    pub validation_errors: &'a [ValidationError],
+    // 警告として扱う、重複したキー。エラーとして扱う場合は validation_errors に含める
+    pub duplicate_keys: &'a [ValidationError],
+    pub policy_findings: &'a [Finding],
+    pub outliers: &'a [Outlier],
+    pub disabled_dependencies: &'a [DependencyDisabled],
+    // カバレッジが下限を下回った場合のメッセージ
+    pub coverage_shortfall: Option<&'a str>,
+    // --max-errors を超えたため、validation_errors に含めなかったエラーの数
+    pub omitted_errors: usize,
+    // 指定されていれば、エラーの前にファイル名と行番号を付ける
+    pub source: Option<&'a Source<'a>>,
+    // sourceで位置が分からないエラーは、値に記録された設定元のファイルと行を付ける
+    // include で取り込んだファイルの値など、入力ファイル以外で設定された値のため
+    pub values: Option<&'a IndexMap<String, SysctlValue>>,
+}
+
+// エラー箇所の表示に使う、検証した設定ファイルの内容
+#[derive(Debug)]
+pub struct Source<'a> {
+    pub file: &'a str,
+    pub text: &'a str,
+    pub entries: IndexMap<String, SpannedEntry>,
+    // エラーを、該当する行とヒントを添えて表示する (--pretty)
+    pub snippets: bool,
+}
+
+impl Source<'_> {
+    pub fn locate(&self, errors: &[ValidationError]) -> Vec<SpannedError> {
+        attach_spans(errors, &self.entries)
+    }
+
+    // キーの値の後ろに書かれていたコメント
+    pub fn comment(&self, key: &str) -> Option<String> {
+        self.entries.get(key)?.comment.clone()
+    }
+}
+
+impl Results<'_> {
+    // キーの値を設定したファイルと行
+    fn origin(&self, key: &str) -> Option<&Origin> {
+        self.values?.get(key)?.origin.as_ref()
+    }
+
+    // 取り込んだファイルで設定された値なら、そのファイルと行を診断に付ける
+    fn with_origin(&self, diagnostic: Diagnostic) -> Diagnostic {
+        match diagnostic.key.as_deref().and_then(|key| self.origin(key)) {
+            Some(origin) => Diagnostic {
+                file: Some(origin.file.clone()),
+                line: Some(origin.line),
+                ..diagnostic
+            },
+            None => diagnostic,
+        }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        // 位置が分かれば行番号も付ける
+        let validation_errors: Vec<Diagnostic> = match self.source {
+            Some(source) => source
+                .locate(self.validation_errors)
+                .iter()
+                .map(|spanned| Diagnostic {
+                    comment: source.comment(spanned.error.key_name()),
+                    ..Diagnostic::from(spanned)
+                })
+                .collect(),
+            None => self
+                .validation_errors
+                .iter()
+                .map(Diagnostic::from)
+                .collect(),
+        };
+        let with_origin = |diagnostic| self.with_origin(diagnostic);
+        self.parse_warnings
+            .iter()
+            .map(Diagnostic::from)
+            .chain(validation_errors.into_iter().map(with_origin))
+            .chain(self.duplicate_keys.iter().map(|error| {
+                let line = match error {
+                    ValidationError::DuplicateKey { second_line, .. } => Some(*second_line),
+                    _ => None,
+                };
+                Diagnostic {
+                    severity: "warning",
+                    line,
+                    ..Diagnostic::from(error)
+                }
+            }))
+            .chain(
+                self.policy_findings
+                    .iter()
+                    .map(Diagnostic::from)
+                    .map(with_origin),
+            )
+            .chain(self.outliers.iter().map(Diagnostic::from).map(with_origin))
+            .chain(
+                self.disabled_dependencies
+                    .iter()
+                    .map(Diagnostic::from)
+                    .map(with_origin),
+            )
+            .chain(self.coverage_shortfall.iter().map(|message| Diagnostic {
+                severity: "error",
+                code: COVERAGE_CODE,
+                kind: "coverage",
+                key: None,
+                file: None,
+                line: None,
+                expected: None,
+                actual: None,
+                comment: None,
+                message: message.to_string(),
+            }))
+            .collect()
+    }
+}
+
+// パース時の警告をCLIと同じ形式で書き出す
+pub fn write_parse_warnings(
+    err: &mut dyn Write,
+    file: &str,
+    warnings: &[ParseWarning],
+) -> io::Result<()> {
+    for warning in warnings {
+        writeln!(
+            err,
+            "{}: {}({}): [{}] {}: {}",
+            paint(tr!("警告", "warning"), Style::Warning),
+            file,
+            warning.line,
+            ParseWarning::CODE,
+            warning.reason,
+            warning.raw
+        )?;
+    }
+    Ok(())
+}
+
+// 結果を人が読む形式で書き出す。エラーはout、警告はerrに書く
+pub fn write_text_report(
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+    results: &Results,
+) -> io::Result<()> {
+    if !results.validation_errors.is_empty() {
+        writeln!(
+            out,
+            "{}",
+            paint(
+                tr!("スキーマエラーがありました。", "schema errors were found."),
+                Style::Error
+            )
+        )?;
+        match results.source {
+            Some(source) => {
+                for SpannedError { error, span } in source.locate(results.validation_errors) {
+                    match span {
+                        Some(span) if source.snippets => write!(
+                            out,
+                            "{}",
+                            render_validation_error(source.file, source.text, &error, &span)
+                        )?,
+                        Some(span) => {
+                            write!(
+                                out,
+                                "{}:{}: [{}] {:#}",
+                                source.file,
+                                span.line,
+                                error.code(),
+                                error
+                            )?;
+                            // 値の後ろのコメントも、設定した理由の手がかりとして添える
+                            match source.comment(error.key_name()) {
+                                Some(comment) => writeln!(out, "  {}", comment)?,
+                                None => writeln!(out)?,
+                            }
+                        }
+                        None => write_located_error(out, results, &error)?,
+                    }
+                }
+            }
+            None => {
+                for error in results.validation_errors {
+                    write_located_error(out, results, error)?;
+                }
+            }
+        }
+        write_omitted_errors(out, results.omitted_errors)?;
+    }
+    if !results.policy_findings.is_empty() {
+        writeln!(
+            out,
+            "{}",
+            tr!(
+                "ポリシーの検査で問題が見つかりました。",
+                "the policy check found problems."
+            )
+        )?;
+        for finding in results.policy_findings {
+            writeln!(out, "[{}] {}", Finding::CODE, finding)?;
+        }
+    }
+    for error in results.duplicate_keys {
+        let label = paint(tr!("警告", "warning"), Style::Warning);
+        writeln!(err, "{}: [{}] {:#}", label, error.code(), error)?;
+    }
+    for outlier in results.outliers {
+        let label = paint(tr!("警告", "warning"), Style::Warning);
+        writeln!(err, "{}: [{}] {}", label, Outlier::CODE, outlier)?;
+    }
+    for disabled in results.disabled_dependencies {
+        let label = paint(tr!("警告", "warning"), Style::Warning);
+        writeln!(
+            err,
+            "{}: [{}] {}",
+            label,
+            DependencyDisabled::CODE,
+            disabled
+        )?;
+    }
+    if let Some(message) = results.coverage_shortfall {
+        writeln!(out, "[{}] {}", COVERAGE_CODE, message)?;
+    }
+    Ok(())
+}
+
+// 値の設定元が分かれば、その場所をエラーの前に付ける
+fn write_located_error(
+    out: &mut dyn Write,
+    results: &Results,
+    error: &ValidationError,
+) -> io::Result<()> {
+    match results.origin(error.key_name()) {
+        Some(origin) => writeln!(out, "{}: [{}] {:#}", origin, error.code(), error),
+        None => writeln!(out, "[{}] {:#}", error.code(), error),
+    }
+}
+
+// エラーになったキーだけを、キーごとにまとめて書き出す。問題の無いキーや警告は出さない
+// キーに結びつかないエラーは最後にまとめる
+pub fn write_failed_keys(out: &mut dyn Write, results: &Results) -> io::Result<()> {
+    let mut by_key: BTreeMap<Option<String>, Vec<String>> = BTreeMap::new();
+    for diagnostic in results.diagnostics() {
+        if diagnostic.severity == "error" {
+            by_key
+                .entry(diagnostic.key)
+                .or_default()
+                .push(format!("[{}] {}", diagnostic.code, diagnostic.message));
+        }
+    }
+    let (keyed, unkeyed): (Vec<_>, Vec<_>) = by_key.into_iter().partition(|(key, _)| key.is_some());
+    for (key, messages) in keyed.into_iter().chain(unkeyed) {
+        if let Some(key) = key {
+            writeln!(out, "{}:", paint(key, Style::Key))?;
+        }
+        for message in messages {
+            writeln!(out, "  {}", message)?;
+        }
+    }
+    write_omitted_errors(out, results.omitted_errors)
+}
+
+pub fn write_omitted_errors(out: &mut dyn Write, omitted: usize) -> io::Result<()> {
+    if omitted > 0 {
+        writeln!(
+            out,
+            "{}",
+            tr!(
+                "…ほかに{}件のエラーがあります。",
+                "…and {} more errors",
+                omitted
+            )
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fail_on() {
+        assert!(FailOn::Error.fails("error"));
+        assert!(!FailOn::Error.fails("warning"));
+        assert!(FailOn::Warning.fails("error"));
+        assert!(FailOn::Warning.fails("warning"));
+        assert!(!FailOn::Never.fails("error"));
+        assert_eq!(FailOn::from_name("warning"), Some(FailOn::Warning));
+        assert_eq!(FailOn::from_name("info"), None);
+    }
+    use crate::types::SchemaType;
+
+    #[test]
+    fn test_report_to_json() {
+        let report = Report::new(
+            "a.conf",
+            vec![
+                (&ValidationError::MissingKey("key1".to_owned())).into(),
+                (&ValidationError::WrongType {
+                    key_name: "key2".to_owned(),
+                    expect: SchemaType::Number,
+                    actual: SchemaType::String,
+                    trailing: None,
+                })
+                    .into(),
+            ],
+        );
+        assert!(!report.ok);
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["file"], "a.conf");
+        assert_eq!(json["ok"], false);
+        assert_eq!(json["diagnostics"][0]["kind"], "missing_key");
+        assert_eq!(json["diagnostics"][0]["key"], "key1");
+        assert_eq!(json["diagnostics"][1]["kind"], "wrong_type");
+        assert_eq!(json["diagnostics"][1]["severity"], "error");
+    }
+
+    #[test]
+    fn test_empty_report_is_ok() {
+        assert!(Report::new("a.conf", vec![]).ok);
+    }
+
+    #[test]
+    fn test_write_github_annotations() {
+        let report = Report::new(
+            "dir,1/a.conf",
+            vec![
+                Diagnostic {
+                    severity: "error",
+                    code: "SL0003",
+                    kind: "wrong_type",
+                    key: Some("port".to_owned()),
+                    file: None,
+                    line: Some(2),
+                    expected: None,
+                    actual: None,
+                    comment: None,
+                    message: "'port'の型が間違っています。".to_owned(),
+                },
+                Diagnostic {
+                    severity: "warning",
+                    code: "SL0202",
+                    kind: "outlier",
+                    key: Some("vm.swappiness".to_owned()),
+                    file: None,
+                    line: None,
+                    expected: None,
+                    actual: None,
+                    comment: None,
+                    message: "100%\nabove".to_owned(),
+                },
+            ],
+        );
+        let mut out = Vec::new();
+        write_github_annotations(&mut out, &[report]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "::error file=dir%2C1/a.conf,line=2,title=SL0003::'port'の型が間違っています。\n\
+             ::warning file=dir%2C1/a.conf,title=SL0202::100%25%0Aabove\n"
+        );
+    }
+
+    #[test]
+    fn test_write_text_report() {
+        let validation_errors = vec![ValidationError::UnknownKey("key1".to_owned())];
+        let outliers = vec![Outlier {
+            key: "key2".to_owned(),
+            value: 600.0,
+            default: 60.0,
+            ratio: 10.0,
+        }];
+        let results = Results {
+            validation_errors: &validation_errors,
+            outliers: &outliers,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        write_text_report(&mut out, &mut err, &results).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "スキーマエラーがありました。\n[SL0002] {}\n",
+                validation_errors[0]
+            )
+        );
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            format!("警告: [SL0202] {}\n", outliers[0])
+        );
+
+        // --max-errors で省いたエラーは件数だけを書く
+        let results = Results {
+            validation_errors: &validation_errors,
+            omitted_errors: 240,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_text_report(&mut out, &mut Vec::new(), &results).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("…ほかに240件のエラーがあります。\n"));
+
+        let mut err = Vec::new();
+        write_parse_warnings(
+            &mut err,
+            "a.conf",
+            &[ParseWarning {
+                line: 3,
+                reason: "reason".to_owned(),
+                raw: "raw line".to_owned(),
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "警告: a.conf(3): [SL0201] reason: raw line\n"
+        );
+    }
+
+    #[test]
+    fn test_write_text_report_pretty() {
+        let text = "a.key = 1\n\tb.key = abc\n";
+        let mut source = Source {
+            file: "a.conf",
+            text,
+            entries: crate::parser::parse_sysctl_spanned(text).unwrap(),
+            snippets: true,
+        };
+        let validation_errors = vec![
+            ValidationError::WrongType {
+                key_name: "b.key".to_owned(),
+                expect: SchemaType::Number,
+                actual: SchemaType::String,
+                trailing: None,
+            },
+            ValidationError::MissingKey("c.key".to_owned()),
+        ];
+        let results = Results {
+            validation_errors: &validation_errors,
+            source: Some(&source),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_text_report(&mut out, &mut Vec::new(), &results).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "スキーマエラーがありました。\n{}[SL0001] {}\n",
+                render_validation_error(
+                    "a.conf",
+                    text,
+                    &validation_errors[0],
+                    &source.locate(&validation_errors)[0].span.unwrap()
+                ),
+                validation_errors[1]
+            )
+        );
+
+        // --prettyが無くても、位置が分かればファイル名と行番号を付ける
+        source.snippets = false;
+        let results = Results {
+            validation_errors: &validation_errors,
+            source: Some(&source),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_text_report(&mut out, &mut Vec::new(), &results).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "スキーマエラーがありました。\na.conf:2: [SL0003] {}\n[SL0001] {}\n",
+                validation_errors[0], validation_errors[1]
+            )
+        );
+        let lines: Vec<Option<usize>> = results
+            .diagnostics()
+            .into_iter()
+            .map(|diagnostic| diagnostic.line)
+            .collect();
+        assert_eq!(lines, vec![Some(2), None]);
+    }
+
+    #[test]
+    fn test_write_failed_keys() {
+        let validation_errors = vec![
+            ValidationError::UnknownKey("b.key".to_owned()),
+            ValidationError::MissingKey("a.key".to_owned()),
+            ValidationError::ReadOnlyKey("b.key".to_owned()),
+        ];
+        let outliers = vec![Outlier {
+            key: "c.key".to_owned(),
+            value: 600.0,
+            default: 60.0,
+            ratio: 10.0,
+        }];
+        let results = Results {
+            validation_errors: &validation_errors,
+            outliers: &outliers,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_failed_keys(&mut out, &results).unwrap();
+        // 警告だけのc.keyは出力しない
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "a.key:\n  [SL0001] {}\nb.key:\n  [SL0002] {}\n  [SL0014] {}\n",
+                validation_errors[1], validation_errors[0], validation_errors[2]
+            )
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::report::Report;
+
+// 検証結果をSARIF 2.1.0で出力する。GitHubのcode scanningなどでそのまま表示できる
+// エラーコードをルールIDに、行番号をregionにする
+pub fn to_sarif(reports: &[Report]) -> String {
+    // ルールはコードの順に、1つずつ並べる
+    let rules: BTreeMap<&str, &str> = reports
+        .iter()
+        .flat_map(|report| &report.diagnostics)
+        .map(|diagnostic| (diagnostic.code, diagnostic.kind))
+        .collect();
+    let results: Vec<Value> = reports
+        .iter()
+        .flat_map(|report| {
+            report.diagnostics.iter().map(|diagnostic| {
+                let mut location = json!({
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": diagnostic.file_or(&report.file) },
+                    },
+                });
+                if let Some(line) = diagnostic.line {
+                    location["physicalLocation"]["region"] = json!({ "startLine": line });
+                }
+                json!({
+                    "ruleId": diagnostic.code,
+                    "level": diagnostic.severity,
+                    "message": { "text": diagnostic.message },
+                    "locations": [location],
+                })
+            })
+        })
+        .collect();
+    let sarif = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules
+                        .iter()
+                        .map(|(code, kind)| json!({ "id": code, "name": kind }))
+                        .collect::<Vec<_>>(),
+                },
+            },
+            "results": results,
+        }],
+    });
+    serde_json::to_string_pretty(&sarif).expect("SARIFのシリアライズに失敗しました。")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Diagnostic;
+
+    #[test]
+    fn test_to_sarif() {
+        let reports = vec![Report::new(
+            "a.conf",
+            vec![
+                Diagnostic {
+                    severity: "error",
+                    code: "SL0003",
+                    kind: "wrong_type",
+                    key: Some("port".to_owned()),
+                    file: None,
+                    line: Some(2),
+                    expected: None,
+                    actual: None,
+                    comment: None,
+                    message: "message".to_owned(),
+                },
+                Diagnostic {
+                    severity: "warning",
+                    code: "SL0202",
+                    kind: "outlier",
+                    key: Some("vm.swappiness".to_owned()),
+                    file: None,
+                    line: None,
+                    expected: None,
+                    actual: None,
+                    comment: None,
+                    message: "outlier".to_owned(),
+                },
+            ],
+        )];
+        let sarif: Value = serde_json::from_str(&to_sarif(&reports)).unwrap();
+        let run = &sarif["runs"][0];
+        assert_eq!(
+            run["tool"]["driver"]["rules"],
+            json!([
+                { "id": "SL0003", "name": "wrong_type" },
+                { "id": "SL0202", "name": "outlier" },
+            ])
+        );
+        assert_eq!(
+            run["results"][0],
+            json!({
+                "ruleId": "SL0003",
+                "level": "error",
+                "message": { "text": "message" },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": "a.conf" },
+                        "region": { "startLine": 2 },
+                    },
+                }],
+            })
+        );
+        // 行の無い診断にはregionを付けない
+        assert!(run["results"][1]["locations"][0]["physicalLocation"]
+            .get("region")
+            .is_none());
+    }
+}
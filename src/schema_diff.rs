@@ -0,0 +1,124 @@
+use std::fmt::Display;
+
+use crate::types::{Schema, SchemaEntry};
+
+// 同じキーで、型などの定義が変わったエントリ
+#[derive(Debug, PartialEq)]
+pub struct SchemaChange {
+    pub old: SchemaEntry,
+    pub new: SchemaEntry,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SchemaDiff {
+    pub added: Vec<SchemaEntry>,
+    pub removed: Vec<SchemaEntry>,
+    pub changed: Vec<SchemaChange>,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// 2つのスキーマを比較する。
+// added と changed は new の順、removed は old の順に並ぶ
+pub fn diff_schemas(old: &Schema, new: &Schema) -> SchemaDiff {
+    let find = |schema: &Schema, name: &str| {
+        schema
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .cloned()
+    };
+
+    let mut diff = SchemaDiff::default();
+    for new_entry in &new.entries {
+        match find(old, &new_entry.name) {
+            None => diff.added.push(new_entry.clone()),
+            Some(old_entry) if old_entry != *new_entry => diff.changed.push(SchemaChange {
+                old: old_entry,
+                new: new_entry.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for old_entry in &old.entries {
+        if find(new, &old_entry.name).is_none() {
+            diff.removed.push(old_entry.clone());
+        }
+    }
+    diff
+}
+
+impl Display for SchemaDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.added {
+            writeln!(f, "+ {}", entry)?;
+        }
+        for entry in &self.removed {
+            writeln!(f, "- {}", entry)?;
+        }
+        for change in &self.changed {
+            writeln!(f, "~ {} -> {}", change.old, change.new.schema_type)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_schema;
+    use crate::types::SchemaType;
+
+    fn schema(input: &str) -> Schema {
+        parse_schema(input).unwrap()
+    }
+
+    #[test]
+    fn test_diff_schemas() {
+        let old = schema("kept: string\nremoved: bool\nretyped: string\n");
+        let new = schema("kept: string\nretyped: number\nadded: bool\n");
+        let diff = diff_schemas(&old, &new);
+        assert_eq!(
+            diff,
+            SchemaDiff {
+                added: vec![SchemaEntry {
+                    name: "added".to_owned(),
+                    schema_type: SchemaType::Boolean,
+                    ..Default::default()
+                }],
+                removed: vec![SchemaEntry {
+                    name: "removed".to_owned(),
+                    schema_type: SchemaType::Boolean,
+                    ..Default::default()
+                }],
+                changed: vec![SchemaChange {
+                    old: SchemaEntry {
+                        name: "retyped".to_owned(),
+                        schema_type: SchemaType::String,
+                        ..Default::default()
+                    },
+                    new: SchemaEntry {
+                        name: "retyped".to_owned(),
+                        schema_type: SchemaType::Number,
+                        ..Default::default()
+                    },
+                }],
+            }
+        );
+        assert_eq!(
+            diff.to_string(),
+            "+ added: bool\n- removed: bool\n~ retyped: string -> number\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_identical_schemas() {
+        let old = schema("a: string\nb: number\n");
+        let new = schema("b: number\na: string\n");
+        assert!(diff_schemas(&old, &new).is_empty());
+    }
+}
@@ -0,0 +1,48 @@
+use crate::encoding::decode_text;
+use std::io;
+use std::process::Command;
+
+// git commit の前に、ステージされた内容だけを検証するための関数 (--staged)
+// 作業ツリーのステージされていない変更に左右されないよう、内容もインデックスから読み込む
+
+// ステージされた変更のうち、追加・変更された *.conf のパスを返す
+// パスは現在のディレクトリからの相対パスで、pathspecs に一致するものだけにする
+pub fn staged_conf_files(pathspecs: &[String]) -> io::Result<Vec<String>> {
+    let mut args = vec![
+        "diff",
+        "--cached",
+        "--name-only",
+        "--relative",
+        "--diff-filter=ACMR",
+        "-z",
+        "--",
+    ];
+    args.extend(pathspecs.iter().map(String::as_str));
+    let output = git(&args)?;
+    Ok(String::from_utf8_lossy(&output)
+        .split('\0')
+        .filter(|path| path.ends_with(".conf"))
+        .map(str::to_owned)
+        .collect())
+}
+
+// インデックスにあるファイルの内容を読み込む。インデックスに無ければNone
+pub fn read_staged(path: &str) -> io::Result<Option<String>> {
+    // :./ から始めると、リポジトリのルートではなく現在のディレクトリからのパスになる
+    let output = Command::new("git")
+        .args(["show", &format!(":./{}", path)])
+        .output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    decode_text(output.stdout).map(Some)
+}
+
+fn git(args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        return Err(io::Error::other(message));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,106 @@
+use std::cell::Cell;
+use std::fmt::Display;
+
+// 端末に出力するメッセージの色付け
+// エラーのキーや型、重要度を色で目立たせる。JSONなど機械向けの出力には使わない
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum ColorChoice {
+    // 出力先が端末で、NO_COLOR が設定されていなければ色を付ける
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    // --color の値から選ぶ
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    // 色を付けるかどうか。always と never は NO_COLOR より優先する
+    // NO_COLOR は空でない値が設定されている場合だけ見る (https://no-color.org/)
+    pub fn enabled(self, is_terminal: bool, no_color: Option<&str>) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && no_color.is_none_or(str::is_empty),
+        }
+    }
+}
+
+thread_local! {
+    // 言語と同じく、CLIは1つのスレッドで動くためスレッドごとに持つ
+    static COLOR: Cell<bool> = const { Cell::new(false) };
+}
+
+// 以降のメッセージに色を付けるかを設定する
+pub fn set_color(enabled: bool) {
+    COLOR.with(|current| current.set(enabled));
+}
+
+pub fn color_enabled() -> bool {
+    COLOR.with(Cell::get)
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Style {
+    // エラーの見出しと印
+    Error,
+    Warning,
+    Help,
+    // エラーになったキー
+    Key,
+    // 必要な型
+    Type,
+}
+
+impl Style {
+    // SGRのパラメータ
+    fn sgr(self) -> &'static str {
+        match self {
+            Style::Error => "1;31",
+            Style::Warning => "1;33",
+            Style::Help => "1;36",
+            Style::Key => "1",
+            Style::Type => "32",
+        }
+    }
+}
+
+// 色付けが有効なら、textをエスケープシーケンスで囲む
+pub fn paint(text: impl Display, style: Style) -> String {
+    if color_enabled() {
+        format!("\x1b[{}m{}\x1b[0m", style.sgr(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enabled() {
+        assert!(ColorChoice::Auto.enabled(true, None));
+        assert!(ColorChoice::Auto.enabled(true, Some("")));
+        assert!(!ColorChoice::Auto.enabled(true, Some("1")));
+        assert!(!ColorChoice::Auto.enabled(false, None));
+        assert!(ColorChoice::Always.enabled(false, Some("1")));
+        assert!(!ColorChoice::Never.enabled(true, None));
+    }
+
+    #[test]
+    fn test_paint() {
+        assert_eq!(paint("port", Style::Key), "port");
+        set_color(true);
+        assert_eq!(paint("port", Style::Key), "\x1b[1mport\x1b[0m");
+        set_color(false);
+    }
+}
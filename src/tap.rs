@@ -0,0 +1,146 @@
+use std::fmt::Write;
+
+use crate::report::{Diagnostic, Report};
+
+// 検証結果をTAP (Test Anything Protocol) version 13で出力する。proveなどでそのまま扱える
+// スキーマのキーごとに1つのテストとし、そのキーにエラーがあれば not ok にする
+// スキーマに無いキーや、キーに結びつかないエラーも、それぞれ not ok のテストにする
+// 警告はテストを失敗させず、# から始まるコメントとして添える
+pub fn to_tap(reports: &[Report]) -> String {
+    let mut tap = String::from("TAP version 13\n");
+    let mut number = 0;
+    for report in reports {
+        // グロブのエントリは、一致したキーに置き換えてschema_keysに入っている
+        let is_schema_key = |key: &Option<String>| {
+            key.as_ref()
+                .is_some_and(|key| report.schema_keys.contains(key))
+        };
+        for key in &report.schema_keys {
+            let diagnostics: Vec<&Diagnostic> = report
+                .diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.key.as_ref() == Some(key))
+                .collect();
+            number += 1;
+            write_test(
+                &mut tap,
+                number,
+                &format!("{}: {}", report.file, key),
+                &diagnostics,
+            );
+        }
+        for diagnostic in &report.diagnostics {
+            if is_schema_key(&diagnostic.key) {
+                continue;
+            }
+            number += 1;
+            let description = match &diagnostic.key {
+                Some(key) => format!("{}: {}", report.file, key),
+                None => report.file.clone(),
+            };
+            write_test(&mut tap, number, &description, &[diagnostic]);
+        }
+    }
+    // テストの数は最後に分かるため、計画は末尾に書く
+    writeln!(tap, "1..{}", number).unwrap();
+    tap
+}
+
+fn write_test(tap: &mut String, number: usize, description: &str, diagnostics: &[&Diagnostic]) {
+    let errors: Vec<&&Diagnostic> = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == "error")
+        .collect();
+    let status = if errors.is_empty() { "ok" } else { "not ok" };
+    // # はディレクティブの始まりになるため、説明には含めない
+    writeln!(
+        tap,
+        "{} {} - {}",
+        status,
+        number,
+        description.replace('#', "\\#")
+    )
+    .unwrap();
+    if !errors.is_empty() {
+        // 失敗の詳細はYAMLブロックで添える
+        writeln!(tap, "  ---").unwrap();
+        writeln!(tap, "  errors:").unwrap();
+        for error in errors {
+            writeln!(
+                tap,
+                "    - code: {}\n      message: {}",
+                error.code,
+                yaml_string(&error.message)
+            )
+            .unwrap();
+        }
+        writeln!(tap, "  ...").unwrap();
+    }
+    for diagnostic in diagnostics {
+        if diagnostic.severity != "error" {
+            writeln!(
+                tap,
+                "# {}: [{}] {}",
+                diagnostic.severity, diagnostic.code, diagnostic.message
+            )
+            .unwrap();
+        }
+    }
+}
+
+// YAMLブロックの値として書けるよう、JSONの文字列としてエスケープする
+fn yaml_string(text: &str) -> String {
+    serde_json::to_string(text).expect("文字列のシリアライズに失敗しました。")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(severity: &'static str, key: Option<&str>, message: &str) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code: "SL0003",
+            kind: "wrong_type",
+            key: key.map(str::to_owned),
+            file: None,
+            line: None,
+            expected: None,
+            actual: None,
+            comment: None,
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_to_tap() {
+        let mut report = Report::new(
+            "a.conf",
+            vec![
+                diagnostic("error", Some("port"), "wrong type"),
+                diagnostic("warning", Some("debug"), "note"),
+                diagnostic("error", Some("extra"), "unknown"),
+            ],
+        );
+        report.schema_keys = vec!["port".to_owned(), "debug".to_owned()];
+        assert_eq!(
+            to_tap(&[report]),
+            "TAP version 13\n\
+             not ok 1 - a.conf: port\n  \
+             ---\n  \
+             errors:\n    \
+             - code: SL0003\n      \
+             message: \"wrong type\"\n  \
+             ...\n\
+             ok 2 - a.conf: debug\n\
+             # warning: [SL0003] note\n\
+             not ok 3 - a.conf: extra\n  \
+             ---\n  \
+             errors:\n    \
+             - code: SL0003\n      \
+             message: \"unknown\"\n  \
+             ...\n\
+             1..3\n"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use indexmap::IndexMap;
+
+use crate::types::{Schema, SysctlValue, ValidationError};
+use crate::validation::{validate_by_schema, ValidationOptions};
+
+// カーネルのバージョンなど、名前を付けたスキーマ
+#[derive(Debug)]
+pub struct TargetSchema {
+    pub version: String,
+    pub schema: Schema,
+}
+
+// --target-schema に渡す version=path の形式を分解する
+pub fn parse_target_spec(spec: &str) -> Option<(&str, &str)> {
+    spec.split_once('=')
+        .filter(|(version, path)| !version.is_empty() && !path.is_empty())
+}
+
+// すべてのターゲットについて検証し、ターゲットごとのエラーを返す
+pub fn validate_targets<'a>(
+    value: &IndexMap<String, SysctlValue>,
+    targets: &'a [TargetSchema],
+    options: &ValidationOptions,
+) -> Vec<(&'a str, Vec<ValidationError>)> {
+    targets
+        .iter()
+        .map(|target| {
+            let errors = validate_by_schema(value, &target.schema, options).err();
+            (target.version.as_str(), errors.unwrap_or_default())
+        })
+        .collect()
+}
+
+// 設定されているキーのうち、一部のターゲットのスキーマにしか無いもの。
+// キーと、そのキーがあるターゲットの一覧を、キーの順に返す
+pub fn partially_supported_keys<'a>(
+    value: &IndexMap<String, SysctlValue>,
+    targets: &'a [TargetSchema],
+) -> Vec<(String, Vec<&'a str>)> {
+    let mut keys: Vec<(String, Vec<&str>)> = value
+        .keys()
+        .filter_map(|key| {
+            let supported: Vec<&str> = targets
+                .iter()
+                .filter(|target| target.schema.entry_for(key).is_some())
+                .map(|target| target.version.as_str())
+                .collect();
+            (!supported.is_empty() && supported.len() < targets.len())
+                .then(|| (key.to_owned(), supported))
+        })
+        .collect();
+    keys.sort();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_schema, parse_sysctl};
+
+    fn targets() -> Vec<TargetSchema> {
+        vec![
+            TargetSchema {
+                version: "v1".to_owned(),
+                schema: parse_schema("a.key: bool\n").unwrap(),
+            },
+            TargetSchema {
+                version: "v2".to_owned(),
+                schema: parse_schema("a.key: bool\nnew.key: number\n").unwrap(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_parse_target_spec() {
+        assert_eq!(parse_target_spec("v1=a.schema"), Some(("v1", "a.schema")));
+        assert_eq!(parse_target_spec("a.schema"), None);
+        assert_eq!(parse_target_spec("=a.schema"), None);
+    }
+
+    #[test]
+    fn test_validate_targets() {
+        let targets = targets();
+        let value = parse_sysctl("a.key = true\nnew.key = 1").unwrap();
+        let results = validate_targets(&value, &targets, &ValidationOptions::default());
+        assert_eq!(
+            results,
+            vec![
+                (
+                    "v1",
+                    vec![ValidationError::UnknownKey("new.key".to_owned())]
+                ),
+                ("v2", vec![]),
+            ]
+        );
+        assert_eq!(
+            partially_supported_keys(&value, &targets),
+            vec![("new.key".to_owned(), vec!["v2"])]
+        );
+
+        let value = parse_sysctl("a.key = true").unwrap();
+        assert!(partially_supported_keys(&value, &targets).is_empty());
+    }
+}
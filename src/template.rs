@@ -0,0 +1,124 @@
+use crate::parser::{parse_error, skip_bom};
+use crate::types::{ParseError, ParseErrorKind};
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+// {{ name }} を変数ファイルの値に置き換えてから読み込むテンプレート (--vars)
+// 1つのテンプレートから、環境ごとに値の違う設定を作って、それぞれスキーマで検証できるようにする
+// 置き換えはパースの前にテキストとして行う。変数名は英字か _ で始まり、英数字と _ . - だけからなる
+
+// 変数ファイルに書ける値。YAMLのスカラーだけを受け付ける
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Scalar {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl Scalar {
+    fn into_string(self) -> String {
+        match self {
+            Scalar::Bool(value) => value.to_string(),
+            Scalar::Integer(value) => value.to_string(),
+            Scalar::Float(value) => value.to_string(),
+            Scalar::String(value) => value,
+        }
+    }
+}
+
+// name: value を並べたYAMLの変数ファイルを読み込む
+pub fn parse_vars(input: &str) -> serde_yaml::Result<IndexMap<String, String>> {
+    let vars: IndexMap<String, Scalar> = serde_yaml::from_str(input)?;
+    Ok(vars
+        .into_iter()
+        .map(|(name, value)| (name, value.into_string()))
+        .collect())
+}
+
+// テンプレートの {{ name }} を置き換える。{{ の直後と }} の直前の空白は無視する
+// 定義されていない変数があれば、そのすべての位置をエラーとして返す
+pub fn render_template(
+    input: &str,
+    vars: &IndexMap<String, String>,
+) -> Result<String, Vec<ParseError>> {
+    let body = skip_bom(input);
+    let mut rendered = String::with_capacity(input.len());
+    rendered.push_str(&input[..input.len() - body.len()]);
+    let mut errors = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        match placeholder_name(placeholder) {
+            Some((name, len)) => {
+                match vars.get(name) {
+                    Some(value) => rendered.push_str(value),
+                    None => errors.push(parse_error(
+                        input,
+                        placeholder,
+                        ParseErrorKind::UndefinedVariable,
+                    )),
+                }
+                rest = &placeholder[len..];
+            }
+            None => {
+                rendered.push_str("{{");
+                rest = &placeholder[2..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    if errors.is_empty() {
+        Ok(rendered)
+    } else {
+        Err(errors)
+    }
+}
+
+// {{ name }} の変数名と、}} までの長さ。}} が無いか、変数名が不正ならNone
+fn placeholder_name(input: &str) -> Option<(&str, usize)> {
+    let inner = &input[2..];
+    let end = inner.find("}}")?;
+    let name = inner[..end].trim_matches([' ', '\t']);
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    let valid = (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    valid.then_some((name, end + 4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vars() {
+        let vars = parse_vars("somaxconn: 4096\nforward: true\nratio: 0.5\nname: prod\n").unwrap();
+        assert_eq!(vars["somaxconn"], "4096");
+        assert_eq!(vars["forward"], "true");
+        assert_eq!(vars["ratio"], "0.5");
+        assert_eq!(vars["name"], "prod");
+        assert!(parse_vars("nested:\n  a: 1\n").is_err());
+    }
+
+    #[test]
+    fn test_render_template() {
+        let vars = parse_vars("somaxconn: 4096\nenv: prod\n").unwrap();
+        assert_eq!(
+            render_template(
+                "net.core.somaxconn = {{ somaxconn }}\nkernel.hostname = db-{{env}}\nb = {{ x y }}\n",
+                &vars
+            )
+            .unwrap(),
+            "net.core.somaxconn = 4096\nkernel.hostname = db-prod\nb = {{ x y }}\n"
+        );
+
+        let errors = render_template("a = 1\nb = {{ missing }}\n", &vars).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ParseErrorKind::UndefinedVariable);
+        assert_eq!((errors[0].line, errors[0].column), (2, 5));
+        assert_eq!(errors[0].snippet, "b = {{ missing }}");
+    }
+}
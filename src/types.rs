@@ -1,38 +1,336 @@
+use crate::dialect::Dialect;
+use crate::pattern::glob_match;
+use crate::style::{paint, Style};
+use crate::tr;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
 use std::fmt::Display;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SysctlValue {
     pub value: String,
     pub ignore_error: bool,
+    // 値を設定したファイルと行。ファイルから読み込んだ場合だけ記録する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+// キーの値を設定した場所
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Origin {
+    pub file: String,
+    // 1始まりの行番号
+    pub line: usize,
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+// パースしたエントリと、入力中のキーと値のバイト範囲(開始, 終了)
+// エディタでのハイライトやキーの名前変更、エラー箇所の表示に使う
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedEntry {
+    pub value: SysctlValue,
+    pub key_span: (usize, usize),
+    // 値の入力中のバイト範囲。前後の空白は含まない
+    pub value_span: (usize, usize),
+    // キーのある行の、1始まりの行番号
+    pub line: usize,
+    // 値の後ろに書かれたコメント(# や ; を含む)。ParseOptions::inline_commentsの場合だけ
+    pub comment: Option<String>,
+}
+
+// 入力中の位置。行番号(1始まり)と、その箇所のバイト範囲
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// 検証エラーと、その原因になった入力中の位置
+// 足りないキーなど、入力中に位置の無いエラーではspanはNone
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SpannedError {
+    pub error: ValidationError,
+    pub span: Option<Span>,
+}
+
+// パースしたエントリと、その直前に書かれていたコメント(# や ; を含む)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentedValue {
+    pub value: SysctlValue,
+    pub comments: Vec<String>,
+}
+
+// パースは続けられたが、読み飛ばした行などについての警告
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct ParseWarning {
+    // 1始まりの行番号
+    pub line: usize,
+    // 読み飛ばした理由
+    pub reason: String,
+    // 読み飛ばした行そのもの(改行を除く)
+    pub raw: String,
+}
+
+impl ParseWarning {
+    // エラーコード。ValidationError::codeを参照
+    pub const CODE: &'static str = "SL0201";
+
+    // 読み飛ばした文法の誤りを、警告として扱う
+    pub fn from_error(error: &ParseError) -> Self {
+        ParseWarning {
+            line: error.line,
+            reason: error.kind.to_string(),
+            raw: error.snippet.clone(),
+        }
+    }
+}
+
+impl Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = tr!("{}行目", "line {}", self.line);
+        write!(f, "{}: {}: {}", message, self.reason, self.raw)
+    }
+}
+
+// パースを続けられなかった誤りの種類
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ParseErrorKind {
+    // 設定ファイルの key = value の形式になっていない行
+    InvalidLine,
+    // スキーマファイルの文法に合わない行
+    InvalidSchema,
+    // 未定義、循環参照、二重定義などで解決できない型名
+    InvalidTypeName,
+    // sysctl(8)が読み込めない長さの行。maxは改行を除いた行の長さの上限(バイト数、この値は含まない)
+    LineTooLong { max: usize },
+    // 使えない文字を含むか、区切りが続くなどして空の名前があるキー
+    InvalidKey,
+    // テンプレートの {{ name }} や値の ${NAME} で参照している、定義されていない変数
+    UndefinedVariable,
+    // 読めない #@ の指示か、対応する #@if や #@endif の無い指示
+    InvalidDirective,
+    // スキーマの (/.../) に書かれた、コンパイルできない正規表現
+    InvalidPattern,
+    // スキーマの = の後ろに書かれた、そのキーの型や範囲に合わない既定値
+    InvalidDefault,
+}
+
+impl ParseErrorKind {
+    // エラーコード。ValidationError::codeを参照
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseErrorKind::InvalidLine => "SL0101",
+            ParseErrorKind::InvalidSchema => "SL0102",
+            ParseErrorKind::InvalidTypeName => "SL0103",
+            ParseErrorKind::LineTooLong { .. } => "SL0104",
+            ParseErrorKind::InvalidKey => "SL0105",
+            ParseErrorKind::UndefinedVariable => "SL0106",
+            ParseErrorKind::InvalidDirective => "SL0108",
+            ParseErrorKind::InvalidPattern => "SL0109",
+            ParseErrorKind::InvalidDefault => "SL0110",
+        }
+    }
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            ParseErrorKind::InvalidLine => tr!(
+                "key = value の形式になっていません",
+                "not in the form of key = value"
+            ),
+            ParseErrorKind::InvalidSchema => {
+                tr!("スキーマの文法に誤りがあります", "invalid schema syntax")
+            }
+            ParseErrorKind::InvalidTypeName => {
+                tr!("型名を解決できません", "cannot resolve the type name")
+            }
+            ParseErrorKind::LineTooLong { max } => tr!(
+                "行が長すぎます。{}バイト未満にしてください",
+                "the line is too long; it must be shorter than {} bytes",
+                max
+            ),
+            ParseErrorKind::InvalidKey => tr!(
+                "キーに使えない文字か、空の名前があります",
+                "the key has an invalid character or an empty component"
+            ),
+            ParseErrorKind::UndefinedVariable => {
+                tr!("定義されていない変数です", "undefined variable")
+            }
+            ParseErrorKind::InvalidDirective => tr!(
+                "指示が読めないか、対応する #@if か #@endif がありません",
+                "invalid directive, or no matching #@if or #@endif"
+            ),
+            ParseErrorKind::InvalidPattern => {
+                tr!("正規表現に誤りがあります", "invalid regular expression")
+            }
+            ParseErrorKind::InvalidDefault => tr!(
+                "既定値がキーの型や範囲に合っていません",
+                "the default value does not match the type or range of the key"
+            ),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+// パースに失敗した位置と理由
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    // 1始まりの行番号
+    pub line: usize,
+    // 1始まりの列番号(文字単位)
+    pub column: usize,
+    // 失敗した行そのもの(改行を除く)
+    pub snippet: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let position = tr!("{}行{}列目", "line {}, column {}", self.line, self.column);
+        write!(f, "{}: {}: {}", position, self.kind, self.snippet)
+    }
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SchemaType {
+    #[default]
     String,
     Boolean,
+    // 小数も含む数値
     Number,
+    // 整数。ほとんどのsysctlはこちら
+    Integer,
+    // バイト数。64k, 16M, 1G のように単位(1024倍ずつ)を付けてもよい
+    Size,
+    // 時間。単位の無い値は秒で、250ms, 30s, 5m, 2h, 1d のように単位を付けてもよい
+    Duration,
+    // カーネルのホスト名(kernel.hostnameなど)。最大64バイトの文字列
+    Hostname,
+    // ファイルパス。existsがtrueの場合は、検証時にファイルが存在するかも確認する
+    Path {
+        exists: bool,
+    },
+    // 列挙された値のいずれか
+    Enum(Vec<String>),
+    // 空白区切りで複数の値を並べたもの。例) net.ipv4.tcp_rmem = 4096 131072 6291456
+    List {
+        element: Box<SchemaType>,
+        arity: Arity,
+    },
+    // 位置ごとに型の決まった、決まった個数の値。例) (integer, integer, integer)
+    Tuple(Vec<SchemaType>),
+}
+
+// 数値の範囲の制約(両端を含む)。Noneの側は制限なし
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Range {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl Range {
+    pub fn contains(&self, value: f64) -> bool {
+        self.min.is_none_or(|min| min as f64 <= value)
+            && self.max.is_none_or(|max| value <= max as f64)
+    }
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(min) = self.min {
+            write!(f, "{}", min)?;
+        }
+        write!(f, "..")?;
+        if let Some(max) = self.max {
+            write!(f, "{}", max)?;
+        }
+        Ok(())
+    }
+}
+
+// リストの要素数の制約
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Arity {
+    pub min: usize,
+    // Noneの場合は上限なし
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn contains(&self, len: usize) -> bool {
+        self.min <= len && self.max.is_none_or(|max| len <= max)
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{}..{}", self.min, max),
+            None => write!(f, "{}..", self.min),
+        }
+    }
 }
 
 impl SchemaType {
-    pub fn from_str(value: &str) -> Self {
+    // 値の見た目から型を推測する
+    pub fn infer(value: &str) -> Self {
         if value == "true" || value == "false" {
             return SchemaType::Boolean;
         }
+        // u64の最大値のように、i64に収まらない整数もある
+        let digits = value.strip_prefix(['-', '+']).unwrap_or(value);
+        if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+            return SchemaType::Integer;
+        }
         if value.parse::<f32>().is_ok() {
             return SchemaType::Number;
         }
 
         SchemaType::String
     }
+
+    // カーネル側で決まっている、値の最大長(バイト数)
+    pub fn max_len(&self) -> Option<usize> {
+        match self {
+            // __NEW_UTS_LEN
+            SchemaType::Hostname => Some(64),
+            SchemaType::String
+            | SchemaType::Boolean
+            | SchemaType::Number
+            | SchemaType::Integer
+            | SchemaType::Size
+            | SchemaType::Duration
+            | SchemaType::Path { .. }
+            | SchemaType::Enum(_)
+            | SchemaType::List { .. }
+            | SchemaType::Tuple(_) => None,
+        }
+    }
 }
 
 #[test]
-fn schema_type_from_str() {
-    assert_eq!(SchemaType::from_str("true"), SchemaType::Boolean);
-    assert_eq!(SchemaType::from_str("false"), SchemaType::Boolean);
-    assert_eq!(SchemaType::from_str("42"), SchemaType::Number);
-    assert_eq!(SchemaType::from_str("3.14"), SchemaType::Number);
-    assert_eq!(SchemaType::from_str("hello"), SchemaType::String);
+fn schema_type_infer() {
+    assert_eq!(SchemaType::infer("true"), SchemaType::Boolean);
+    assert_eq!(SchemaType::infer("false"), SchemaType::Boolean);
+    assert_eq!(SchemaType::infer("42"), SchemaType::Integer);
+    assert_eq!(SchemaType::infer("-1"), SchemaType::Integer);
+    assert_eq!(
+        SchemaType::infer("18446744073709551615"),
+        SchemaType::Integer
+    );
+    assert_eq!(SchemaType::infer("3.14"), SchemaType::Number);
+    assert_eq!(SchemaType::infer("1e3"), SchemaType::Number);
+    assert_eq!(SchemaType::infer("hello"), SchemaType::String);
 }
 
 impl Display for SchemaType {
@@ -41,29 +339,594 @@ impl Display for SchemaType {
             SchemaType::String => write!(f, "string"),
             SchemaType::Boolean => write!(f, "bool"),
             SchemaType::Number => write!(f, "number"),
+            SchemaType::Integer => write!(f, "integer"),
+            SchemaType::Size => write!(f, "size"),
+            SchemaType::Duration => write!(f, "duration"),
+            SchemaType::Hostname => write!(f, "hostname"),
+            SchemaType::Path { exists: false } => write!(f, "path"),
+            SchemaType::Path { exists: true } => write!(f, "path(exists)"),
+            SchemaType::Enum(choices) => write!(f, "enum({})", choices.join(", ")),
+            SchemaType::List { element, arity } => write!(f, "{}[{}]", element, arity),
+            SchemaType::Tuple(elements) => {
+                let elements: Vec<String> = elements.iter().map(ToString::to_string).collect();
+                write!(f, "({})", elements.join(", "))
+            }
         }
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+// 値が一致する必要のある正規表現。スキーマを読み込むときに一度だけコンパイルする
+// 値の一部に一致すればよいため、全体を縛る場合は ^ と $ を書く
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Self, regex::Error> {
+        Regex::new(source).map(Pattern)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        self.0.is_match(value)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+// スキーマと同じく、/ で囲み、中の / は \/ と書く
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "/{}/", self.as_str().replace('/', "\\/"))
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Pattern::new(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct SchemaEntry {
     pub name: String,
     pub schema_type: SchemaType,
+    // 読み取り専用のキー。設定ファイルに書かれていてはいけない
+    #[serde(default)]
+    pub readonly: bool,
+    // 設定ファイルに無くてもよいキー。書かれていれば型などを検証する
+    #[serde(default)]
+    pub optional: bool,
+    // 数値の範囲。リストの場合は各要素に適用する
+    #[serde(default)]
+    pub range: Option<Range>,
+    // 値が一致する必要のある正規表現。リストの場合は値全体に適用する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<Pattern>,
+    // キーが無いときに --fix で追記する値
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+impl SchemaEntry {
+    // 設定ファイルに書かれていなければいけないキーか
+    // グロブのキーは、それに一致するキーがいくつあってもよいため必須にしない
+    pub fn is_required(&self) -> bool {
+        !self.readonly && !self.optional && !self.is_glob()
+    }
+
+    // net.ipv4.conf.*.rp_filter のように、* か ? で複数のキーに一致するエントリか
+    pub fn is_glob(&self) -> bool {
+        self.name.contains(['*', '?'])
+    }
+}
+
+impl Display for SchemaEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.schema_type)?;
+        if let Some(range) = &self.range {
+            write!(f, "({})", range)?;
+        }
+        if let Some(pattern) = &self.pattern {
+            write!(f, "({})", pattern)?;
+        }
+        if self.readonly {
+            write!(f, " readonly")?;
+        }
+        if self.optional {
+            write!(f, " optional")?;
+        }
+        if let Some(default) = &self.default {
+            write!(f, " = {}", default)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Schema {
     pub entries: Vec<SchemaEntry>,
+    // 同時に設定してはいけないキーの組 (%exclusive)
+    pub exclusive_groups: Vec<Vec<String>>,
+    // (キー, 依存先のキー)の組。キーを設定する場合は依存先も設定する必要がある (%depends)
+    pub dependencies: Vec<(String, String)>,
+    // (キー, 信頼できるファイル)の組。マージ時にキーの値はそのファイルから来る必要がある (%trusted)
+    #[serde(default)]
+    pub trusted_sources: Vec<(String, String)>,
+    // (グループ名, キー)の組。どれか1つを設定する場合は全て設定する必要がある (%group)
+    #[serde(default)]
+    pub groups: Vec<(String, Vec<String>)>,
+    // bool型で受け付ける(偽, 真)の表記の組 (%bool)。空なら false と true だけを受け付ける
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bool_words: Vec<(String, String)>,
+}
+
+impl Schema {
+    // 別のスキーマを重ねる。同じキーのエントリはotherの定義で置き換え、%exclusiveなどの制約は足し合わせる
+    // 共通のスキーマの後にホストごとのスキーマを重ねる場合などに使う
+    pub fn merge(&mut self, other: Schema) {
+        for entry in other.entries {
+            match self.entries.iter_mut().find(|e| e.name == entry.name) {
+                Some(existing) => *existing = entry,
+                None => self.entries.push(entry),
+            }
+        }
+        self.exclusive_groups.extend(other.exclusive_groups);
+        self.dependencies.extend(other.dependencies);
+        self.trusted_sources.extend(other.trusted_sources);
+        self.groups.extend(other.groups);
+        self.bool_words.extend(other.bool_words);
+    }
+
+    // キーに対応するエントリ。同じ名前のエントリを優先し、無ければ最初に一致したグロブのエントリを返す
+    // グロブのエントリは、エラーが実際のキーを指すように名前をそのキーに置き換える
+    pub fn entry_for(&self, key: &str) -> Option<Cow<'_, SchemaEntry>> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.name == key) {
+            return Some(Cow::Borrowed(entry));
+        }
+        self.entries
+            .iter()
+            .find(|entry| entry.is_glob() && glob_match(&entry.name, key))
+            .map(|entry| {
+                Cow::Owned(SchemaEntry {
+                    name: key.to_owned(),
+                    ..entry.clone()
+                })
+            })
+    }
+
+    // bool型の値として読めれば、その真偽を返す
+    pub fn parse_bool(&self, value: &str) -> Option<bool> {
+        if self.bool_words.is_empty() {
+            return match value {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => None,
+            };
+        }
+        self.bool_words
+            .iter()
+            .find_map(|(falsy, truthy)| match value {
+                _ if value == truthy => Some(true),
+                _ if value == falsy => Some(false),
+                _ => None,
+            })
+    }
+}
+
+#[test]
+fn schema_merge() {
+    let entry = |name: &str, schema_type| SchemaEntry {
+        name: name.to_owned(),
+        schema_type,
+        ..Default::default()
+    };
+    let mut schema = Schema {
+        entries: vec![
+            entry("a", SchemaType::Number),
+            entry("b", SchemaType::String),
+        ],
+        dependencies: vec![("a".to_owned(), "b".to_owned())],
+        ..Default::default()
+    };
+    schema.merge(Schema {
+        entries: vec![
+            entry("b", SchemaType::Boolean),
+            entry("c", SchemaType::String),
+        ],
+        dependencies: vec![("c".to_owned(), "a".to_owned())],
+        ..Default::default()
+    });
+    assert_eq!(
+        schema.entries,
+        vec![
+            entry("a", SchemaType::Number),
+            entry("b", SchemaType::Boolean),
+            entry("c", SchemaType::String),
+        ]
+    );
+    assert_eq!(schema.dependencies.len(), 2);
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ValidationError {
     MissingKey(String),
+    // スキーマに既定値がある必須のキーが無い。--fix で既定値を追記できる
+    MissingKeyWithDefault {
+        key_name: String,
+        default: String,
+    },
     UnknownKey(String),
     WrongType {
         key_name: String,
         expect: SchemaType,
         actual: SchemaType,
+        // 数値の後ろに続いている余分な文字。10fooのfooなど
+        trailing: Option<String>,
+    },
+    ValueTooLong {
+        key_name: String,
+        max_len: usize,
+        actual_len: usize,
     },
-    TooLongLine(String),
+    PathNotFound {
+        key_name: String,
+        path: String,
+    },
+    // 同時に設定されている、%exclusiveで指定されたキー
+    MutuallyExclusive {
+        keys: Vec<String>,
+    },
+    // %dependsで指定された依存先のキーが設定されていない
+    MissingDependency {
+        key_name: String,
+        dependency: String,
+    },
+    // %groupで指定されたキーの一部しか設定されていない
+    IncompleteGroup {
+        group: String,
+        missing: Vec<String>,
+    },
+    // 数値が範囲の外にある
+    OutOfRange {
+        key_name: String,
+        value: String,
+        range: Range,
+    },
+    // リストの要素数が合っていない
+    WrongArity {
+        key_name: String,
+        expected: Arity,
+        actual: usize,
+    },
+    // enumの選択肢に無い値が設定されている
+    InvalidChoice {
+        key_name: String,
+        value: String,
+        choices: Vec<String>,
+    },
+    // スキーマの正規表現に一致しない値が設定されている
+    PatternMismatch {
+        key_name: String,
+        value: String,
+        pattern: Pattern,
+    },
+    // readonlyのキーが設定されている
+    ReadOnlyKey(String),
+    // --dialect で指定したOSに無い名前空間のキー
+    ForeignNamespace {
+        key_name: String,
+        dialect: Dialect,
+    },
+    // リストの要素の誤り。positionは1始まりの要素の位置で、errorは要素を検証したエラー
+    InvalidElement {
+        key_name: String,
+        position: usize,
+        error: Box<ValidationError>,
+    },
+    // 同じキーが複数回設定されている。行番号は1始まり
+    DuplicateKey {
+        key_name: String,
+        first_line: usize,
+        second_line: usize,
+    },
+}
+
+impl ValidationError {
+    // エラーの種類を表す識別子。JSONレポートなど機械向けの出力で使う
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ValidationError::MissingKey(_) | ValidationError::MissingKeyWithDefault { .. } => {
+                "missing_key"
+            }
+            ValidationError::UnknownKey(_) => "unknown_key",
+            ValidationError::WrongType { .. } => "wrong_type",
+            ValidationError::ValueTooLong { .. } => "value_too_long",
+            ValidationError::PathNotFound { .. } => "path_not_found",
+            ValidationError::MutuallyExclusive { .. } => "mutually_exclusive",
+            ValidationError::MissingDependency { .. } => "missing_dependency",
+            ValidationError::DuplicateKey { .. } => "duplicate_key",
+            ValidationError::IncompleteGroup { .. } => "incomplete_group",
+            ValidationError::OutOfRange { .. } => "out_of_range",
+            ValidationError::WrongArity { .. } => "wrong_arity",
+            ValidationError::InvalidChoice { .. } => "invalid_choice",
+            ValidationError::PatternMismatch { .. } => "pattern_mismatch",
+            ValidationError::InvalidElement { error, .. } => error.kind(),
+            ValidationError::ReadOnlyKey(_) => "read_only_key",
+            ValidationError::ForeignNamespace { .. } => "foreign_namespace",
+        }
+    }
+
+    // 種類ごとに固定のエラーコード。CIでの絞り込みやドキュメントからの参照に使うため、一度決めたら変えない
+    // SL00xx は検証エラー、SL01xx はパースの誤り、SL02xx は警告など
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::MissingKey(_) | ValidationError::MissingKeyWithDefault { .. } => {
+                "SL0001"
+            }
+            ValidationError::UnknownKey(_) => "SL0002",
+            ValidationError::WrongType { .. } => "SL0003",
+            ValidationError::ValueTooLong { .. } => "SL0005",
+            ValidationError::PathNotFound { .. } => "SL0006",
+            ValidationError::MutuallyExclusive { .. } => "SL0007",
+            ValidationError::MissingDependency { .. } => "SL0008",
+            ValidationError::DuplicateKey { .. } => "SL0009",
+            ValidationError::IncompleteGroup { .. } => "SL0010",
+            ValidationError::OutOfRange { .. } => "SL0011",
+            ValidationError::WrongArity { .. } => "SL0012",
+            ValidationError::InvalidChoice { .. } => "SL0013",
+            ValidationError::ReadOnlyKey(_) => "SL0014",
+            ValidationError::ForeignNamespace { .. } => "SL0015",
+            ValidationError::PatternMismatch { .. } => "SL0016",
+            // 要素のエラーと同じコードにする
+            ValidationError::InvalidElement { error, .. } => error.code(),
+        }
+    }
+
+    // 値に問題があるエラーかどうか。位置を示す際、値を指すかキーを指すかの判断に使う
+    pub fn points_at_value(&self) -> bool {
+        matches!(
+            self,
+            ValidationError::WrongType { .. }
+                | ValidationError::ValueTooLong { .. }
+                | ValidationError::PathNotFound { .. }
+                | ValidationError::OutOfRange { .. }
+                | ValidationError::WrongArity { .. }
+                | ValidationError::InvalidChoice { .. }
+                | ValidationError::PatternMismatch { .. }
+                | ValidationError::InvalidElement { .. }
+        )
+    }
+
+    pub fn key_name(&self) -> &str {
+        match self {
+            ValidationError::MissingKey(key_name) => key_name,
+            ValidationError::MissingKeyWithDefault { key_name, .. } => key_name,
+            ValidationError::UnknownKey(key_name) => key_name,
+            ValidationError::WrongType { key_name, .. } => key_name,
+            ValidationError::ValueTooLong { key_name, .. } => key_name,
+            ValidationError::PathNotFound { key_name, .. } => key_name,
+            ValidationError::MutuallyExclusive { keys } => &keys[0],
+            ValidationError::MissingDependency { key_name, .. } => key_name,
+            ValidationError::DuplicateKey { key_name, .. } => key_name,
+            ValidationError::IncompleteGroup { missing, .. } => &missing[0],
+            ValidationError::OutOfRange { key_name, .. } => key_name,
+            ValidationError::WrongArity { key_name, .. } => key_name,
+            ValidationError::InvalidChoice { key_name, .. } => key_name,
+            ValidationError::PatternMismatch { key_name, .. } => key_name,
+            ValidationError::InvalidElement { key_name, .. } => key_name,
+            ValidationError::ReadOnlyKey(key_name) => key_name,
+            ValidationError::ForeignNamespace { key_name, .. } => key_name,
+        }
+    }
+}
+
+impl Display for ValidationError {
+    // {:#} で表示すると、色付けが有効ならキーと必要な型に色を付ける
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let styled = f.alternate();
+        let paint_key = |key: &str| {
+            if styled {
+                paint(key, Style::Key)
+            } else {
+                key.to_owned()
+            }
+        };
+        let paint_type = |schema_type: &SchemaType| {
+            if styled {
+                paint(schema_type, Style::Type)
+            } else {
+                schema_type.to_string()
+            }
+        };
+        let message = match self {
+            ValidationError::MissingKey(key) => {
+                tr!(
+                    "必要なキーである'{}'が存在しません",
+                    "required key '{}' is missing",
+                    paint_key(key)
+                )
+            }
+            ValidationError::MissingKeyWithDefault { key_name, default } => tr!(
+                "必要なキーである'{}'が存在しません。既定値は'{}'です",
+                "required key '{}' is missing; its default is '{}'",
+                paint_key(key_name),
+                default
+            ),
+            ValidationError::UnknownKey(key) => tr!(
+                "定義されていない'{}'が存在しており、これは不要です",
+                "'{}' is not defined in the schema and is not allowed",
+                paint_key(key)
+            ),
+            ValidationError::WrongType {
+                key_name,
+                expect,
+                trailing: Some(trailing),
+                ..
+            } => tr!(
+                "'{}'の型が間違っています。{}が必要ですが、数値の後ろに余分な'{}'があります。",
+                "'{}' has the wrong type. Expected {}, but found extra '{}' after the number.",
+                paint_key(key_name),
+                paint_type(expect),
+                trailing
+            ),
+            ValidationError::WrongType {
+                key_name,
+                expect,
+                actual,
+                trailing: None,
+            } => tr!(
+                "'{}'の型が間違っています。{}が必要ですが、{}の形式になっています。",
+                "'{}' has the wrong type. Expected {}, but found {}.",
+                paint_key(key_name),
+                paint_type(expect),
+                actual
+            ),
+            ValidationError::ValueTooLong {
+                key_name,
+                max_len,
+                actual_len,
+            } => tr!(
+                "'{}'の値が長すぎます。最大{}バイトですが、{}バイトあります。",
+                "the value of '{}' is too long. The maximum is {} bytes, but it has {} bytes.",
+                paint_key(key_name),
+                max_len,
+                actual_len
+            ),
+            ValidationError::PathNotFound { key_name, path } => tr!(
+                "'{}'に指定された'{}'が存在しません。",
+                "'{1}' given for '{0}' does not exist.",
+                paint_key(key_name),
+                path
+            ),
+            ValidationError::MutuallyExclusive { keys } => tr!(
+                "{}は同時に設定できません。",
+                "{} cannot be set at the same time.",
+                quote_all(keys, &tr!("と", " and "), styled)
+            ),
+            ValidationError::MissingDependency {
+                key_name,
+                dependency,
+            } => tr!(
+                "'{}'を設定するには、'{}'も設定する必要があります。",
+                "setting '{}' requires '{}' to be set as well.",
+                paint_key(key_name),
+                paint_key(dependency)
+            ),
+            ValidationError::DuplicateKey {
+                key_name,
+                first_line,
+                second_line,
+            } => tr!(
+                "'{}'が{}行目と{}行目で重複して設定されています。",
+                "'{}' is set twice, on line {} and line {}.",
+                paint_key(key_name),
+                first_line,
+                second_line
+            ),
+            ValidationError::IncompleteGroup { group, missing } => tr!(
+                "グループ'{}'のキーが揃っていません。{}も設定する必要があります。",
+                "group '{}' is incomplete. {} must be set as well.",
+                group,
+                quote_all(missing, &tr!("と", " and "), styled)
+            ),
+            ValidationError::OutOfRange {
+                key_name,
+                value,
+                range,
+            } => tr!(
+                "'{}'の値{}は範囲{}の外にあります。",
+                "the value {1} of '{0}' is out of the range {2}.",
+                paint_key(key_name),
+                value,
+                range
+            ),
+            ValidationError::WrongArity {
+                key_name,
+                expected,
+                actual,
+            } => tr!(
+                "'{}'の値の個数が間違っています。{}個が必要ですが、{}個あります。",
+                "'{}' has the wrong number of values. Expected {}, but found {}.",
+                paint_key(key_name),
+                expected,
+                actual
+            ),
+            ValidationError::InvalidChoice {
+                key_name,
+                value,
+                choices,
+            } => tr!(
+                "'{}'の値'{}'は選択肢({})のいずれでもありません。",
+                "the value '{1}' of '{0}' is not one of the choices ({2}).",
+                paint_key(key_name),
+                value,
+                choices.join(", ")
+            ),
+            ValidationError::PatternMismatch {
+                key_name,
+                value,
+                pattern,
+            } => tr!(
+                "'{}'の値'{}'はパターン{}に一致しません。",
+                "the value '{1}' of '{0}' does not match the pattern {2}.",
+                paint_key(key_name),
+                value,
+                pattern
+            ),
+            ValidationError::ReadOnlyKey(key) => tr!(
+                "'{}'は読み取り専用のため、設定できません。",
+                "'{}' is read-only and cannot be set.",
+                paint_key(key)
+            ),
+            ValidationError::ForeignNamespace { key_name, dialect } => tr!(
+                "'{}'は{}のsysctlに無い名前空間のキーです。",
+                "'{}' is not in a sysctl namespace of {}.",
+                paint_key(key_name),
+                dialect
+            ),
+            ValidationError::InvalidElement {
+                position, error, ..
+            } => tr!(
+                "{}番目の要素: {}",
+                "element {}: {}",
+                position,
+                if styled {
+                    format!("{:#}", error)
+                } else {
+                    error.to_string()
+                }
+            ),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+// キーをそれぞれ''で囲み、区切り文字でつなげる。styledなら色付けもする
+fn quote_all(keys: &[String], separator: &str, styled: bool) -> String {
+    keys.iter()
+        .map(|key| {
+            if styled {
+                format!("'{}'", paint(key, Style::Key))
+            } else {
+                format!("'{}'", key)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(separator)
 }
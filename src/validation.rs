@@ -1,59 +1,421 @@
-use std::collections::{HashMap, HashSet};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Instant;
+use tracing::{debug, trace};
 
-use crate::types::{Schema, SchemaType, SysctlValue, ValidationError};
+use crate::parser::parse_sysctl;
+use crate::tr;
+use crate::types::{
+    Arity, ParseWarning, Schema, SchemaEntry, SchemaType, Span, SpannedEntry, SpannedError,
+    SysctlValue, ValidationError,
+};
 
-pub fn validate_by_schema(
-    value: &HashMap<String, SysctlValue>,
+#[derive(Debug, Clone)]
+pub struct ValidationOptions {
+    // path(exists)のファイルが実際に存在するか確認する。
+    // 対象のマシン以外で検証する場合はfalseにする
+    pub check_path_exists: bool,
+    // enumの値を前後の空白も含めて完全一致で比較する。
+    // falseの場合は前後の空白を除いてから比較する
+    pub exact_enum: bool,
+    // (exists)の無いpathも含めて、すべてのpathのファイルが存在するか確認する (--check-paths)
+    pub check_all_paths: bool,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        ValidationOptions {
+            check_path_exists: true,
+            exact_enum: false,
+            check_all_paths: false,
+        }
+    }
+}
+
+// パスとして書けるかどうか。/ で始まる絶対パスで、NULや制御文字を含まず、
+// PATH_MAX(4096)とNAME_MAX(255)を超えないこと
+// カーネルは相対パスを実行時のカレントディレクトリから解決するため、意図しない場所を指しやすい
+fn is_valid_path(value: &str) -> bool {
+    value.starts_with('/')
+        && value.len() < 4096
+        && !value.chars().any(|c| c.is_control())
+        && value.split('/').all(|component| component.len() <= 255)
+}
+
+// 値の先頭のうち、数値として読める最も長い部分のバイト数。
+// 10fooなら2。先頭が数値でなければNone
+fn numeric_prefix_len(value: &str) -> Option<usize> {
+    if !value.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '-' | '+' | '.')) {
+        return None;
+    }
+    value
+        .char_indices()
+        .rev()
+        .map(|(index, _)| index)
+        .find(|&index| index > 0 && value[..index].parse::<f32>().is_ok())
+}
+
+// size型の値をバイト数にする。k, M, G, T (大文字小文字を問わない)はそれぞれ1024倍ずつ
+// 例) 4096, 64k, 16M, 1G。読めなければNone
+pub fn parse_size(value: &str) -> Option<u64> {
+    let digits_len = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let number: u64 = value[..digits_len].parse().ok()?;
+    let shift = match &value[digits_len..] {
+        "" => 0,
+        "k" | "K" => 10,
+        "m" | "M" => 20,
+        "g" | "G" => 30,
+        "t" | "T" => 40,
+        _ => return None,
+    };
+    number.checked_mul(1 << shift)
+}
+
+// duration型の値を秒にする。単位の無い値は秒で、ms, s, m, h, d を付けられる
+// 例) 30, 30s, 5m, 250ms。読めなければNone
+pub fn parse_duration(value: &str) -> Option<f64> {
+    let digits_len = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let number: u64 = value[..digits_len].parse().ok()?;
+    let seconds = match &value[digits_len..] {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return None,
+    };
+    Some(number as f64 * seconds)
+}
+
+// リストやタプルの値を要素に分ける。空白かカンマで区切る。例) 4096 131072 6291456, 8080,9000
+fn split_items(value: &str) -> Vec<&str> {
+    value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// 要素を要素の型で検証し、エラーには何番目の要素かを付ける
+fn check_element(
     schema: &Schema,
-) -> Result<(), Vec<ValidationError>> {
-    let value_keys: HashSet<&String> = value.keys().into_iter().collect();
-    let schema_keys: HashSet<&String> = schema.entries.iter().map(|entry| &entry.name).collect();
-    let missing_keys = schema_keys.difference(&value_keys);
-    let unknown_keys = value_keys.difference(&schema_keys);
-    let common_keys = schema_keys.union(&value_keys);
-    let mut wrong_types = Vec::new();
-    for common_key in common_keys {
-        if let Some(schema_entry) = schema
-            .entries
-            .iter()
-            .find(|entry| &entry.name == *common_key)
-        {
-            let expected_type = schema_entry.schema_type;
-            if let Some(sysctl_value) = value.get(*common_key) {
-                let actual_type = SchemaType::from_str(&sysctl_value.value);
-                match expected_type {
-                    SchemaType::String => {
-                        // boolやnumber形式であったとしても、stringとして許可する
-                        // 4096文字を超える行長がないかどうかだけチェックする
-                        if sysctl_value
-                            .value
-                            .lines()
-                            .any(|line| line.chars().count() >= 4096)
-                        {
-                            wrong_types.push(ValidationError::TooLongLine(common_key.to_string()))
-                        }
+    schema_entry: &SchemaEntry,
+    element: &SchemaType,
+    sysctl_value: &SysctlValue,
+    index: usize,
+    item: &str,
+    options: &ValidationOptions,
+) -> Vec<ValidationError> {
+    let element_entry = SchemaEntry {
+        name: schema_entry.name.clone(),
+        schema_type: element.clone(),
+        range: schema_entry.range,
+        ..Default::default()
+    };
+    let item_value = SysctlValue {
+        value: item.to_owned(),
+        ignore_error: sysctl_value.ignore_error,
+        origin: None,
+    };
+    check_entry(schema, &element_entry, &item_value, options)
+        .into_iter()
+        .map(|error| ValidationError::InvalidElement {
+            key_name: schema_entry.name.clone(),
+            position: index + 1,
+            error: Box::new(error),
+        })
+        .collect()
+}
+
+// 1つのエントリの値が、スキーマの型に合っているかを検証する
+// bool型の表記はスキーマの%boolに従う。スキーマの既定値の検証にも使う
+pub(crate) fn check_entry(
+    schema: &Schema,
+    schema_entry: &SchemaEntry,
+    sysctl_value: &SysctlValue,
+    options: &ValidationOptions,
+) -> Vec<ValidationError> {
+    let key_name = &schema_entry.name;
+    if schema_entry.readonly {
+        // 読み取り専用のキーは値に関係なく設定すること自体がエラー
+        return vec![ValidationError::ReadOnlyKey(key_name.to_owned())];
+    }
+    let expected_type = &schema_entry.schema_type;
+    let actual_type = SchemaType::infer(&sysctl_value.value);
+    let mut errors = Vec::new();
+    match expected_type {
+        SchemaType::String | SchemaType::Hostname => {
+            // boolやnumber形式であったとしても、stringとして許可する
+            // 行の長さはパース時に確かめている
+            // 型ごとにカーネルが定める最大長を超えていないか
+            if let Some(max_len) = expected_type.max_len() {
+                let actual_len = sysctl_value.value.len();
+                if actual_len > max_len {
+                    errors.push(ValidationError::ValueTooLong {
+                        key_name: key_name.to_string(),
+                        max_len,
+                        actual_len,
+                    });
+                }
+            }
+        }
+        SchemaType::Path { exists } => {
+            let path = &sysctl_value.value;
+            if !is_valid_path(path) {
+                errors.push(ValidationError::WrongType {
+                    key_name: key_name.to_string(),
+                    expect: expected_type.clone(),
+                    actual: actual_type,
+                    trailing: None,
+                });
+            } else if ((*exists && options.check_path_exists) || options.check_all_paths)
+                && !Path::new(path).exists()
+            {
+                errors.push(ValidationError::PathNotFound {
+                    key_name: key_name.to_string(),
+                    path: path.to_string(),
+                });
+            }
+        }
+        SchemaType::Boolean => {
+            if schema.parse_bool(&sysctl_value.value).is_none() {
+                // %boolで true/false を使わないと決めた場合、true はboolではなく文字列として報告する
+                let actual = match actual_type {
+                    SchemaType::Boolean => SchemaType::String,
+                    actual_type => actual_type,
+                };
+                errors.push(ValidationError::WrongType {
+                    key_name: key_name.to_string(),
+                    expect: expected_type.clone(),
+                    actual,
+                    trailing: None,
+                });
+            }
+        }
+        SchemaType::Number | SchemaType::Integer => {
+            // numberは整数も受け付ける
+            let matches = match expected_type {
+                SchemaType::Number => {
+                    matches!(actual_type, SchemaType::Number | SchemaType::Integer)
+                }
+                _ => *expected_type == actual_type,
+            };
+            if !matches {
+                let value = &sysctl_value.value;
+                let trailing = match expected_type {
+                    SchemaType::Number => {
+                        numeric_prefix_len(value).map(|len| value[len..].to_string())
                     }
-                    SchemaType::Boolean | SchemaType::Number => {
-                        if schema_entry.schema_type != actual_type {
-                            wrong_types.push(ValidationError::WrongType {
-                                key_name: common_key.to_string(),
-                                expect: schema_entry.schema_type,
-                                actual: actual_type,
-                            });
-                        }
+                    // 3.14 のような小数は、余分な文字ではなく型の違いとして報告する
+                    SchemaType::Integer if actual_type == SchemaType::String => {
+                        numeric_prefix_len(value)
+                            .filter(|len| SchemaType::infer(&value[..*len]) == SchemaType::Integer)
+                            .map(|len| value[len..].to_string())
                     }
+                    _ => None,
+                };
+                errors.push(ValidationError::WrongType {
+                    key_name: key_name.to_string(),
+                    expect: expected_type.clone(),
+                    actual: actual_type,
+                    trailing,
+                });
+            }
+        }
+        SchemaType::Size | SchemaType::Duration => {
+            let valid = match expected_type {
+                SchemaType::Size => parse_size(&sysctl_value.value).is_some(),
+                _ => parse_duration(&sysctl_value.value).is_some(),
+            };
+            if !valid {
+                errors.push(ValidationError::WrongType {
+                    key_name: key_name.to_string(),
+                    expect: expected_type.clone(),
+                    actual: actual_type,
+                    trailing: None,
+                });
+            }
+        }
+        SchemaType::List { element, arity } => {
+            let items = split_items(&sysctl_value.value);
+            if !arity.contains(items.len()) {
+                errors.push(ValidationError::WrongArity {
+                    key_name: key_name.to_string(),
+                    expected: *arity,
+                    actual: items.len(),
+                });
+            } else {
+                for (index, item) in items.into_iter().enumerate() {
+                    errors.extend(check_element(
+                        schema,
+                        schema_entry,
+                        element,
+                        sysctl_value,
+                        index,
+                        item,
+                        options,
+                    ));
+                }
+            }
+        }
+        SchemaType::Tuple(elements) => {
+            let items = split_items(&sysctl_value.value);
+            if items.len() != elements.len() {
+                errors.push(ValidationError::WrongArity {
+                    key_name: key_name.to_string(),
+                    expected: Arity {
+                        min: elements.len(),
+                        max: Some(elements.len()),
+                    },
+                    actual: items.len(),
+                });
+            } else {
+                for (index, (element, item)) in elements.iter().zip(items).enumerate() {
+                    errors.extend(check_element(
+                        schema,
+                        schema_entry,
+                        element,
+                        sysctl_value,
+                        index,
+                        item,
+                        options,
+                    ));
                 }
             }
         }
+        SchemaType::Enum(choices) => {
+            // パース時に前後の空白は除かれるが、クォートなどで空白が残った値は
+            // exact_enumでなければ除いてから比較する
+            let value = if options.exact_enum {
+                sysctl_value.value.as_str()
+            } else {
+                sysctl_value.value.trim()
+            };
+            if !choices.iter().any(|choice| choice == value) {
+                errors.push(ValidationError::InvalidChoice {
+                    key_name: key_name.to_string(),
+                    value: sysctl_value.value.to_string(),
+                    choices: choices.clone(),
+                });
+            }
+        }
+    }
+    if let Some(pattern) = &schema_entry.pattern {
+        if !pattern.is_match(&sysctl_value.value) {
+            errors.push(ValidationError::PatternMismatch {
+                key_name: key_name.to_string(),
+                value: sysctl_value.value.clone(),
+                pattern: pattern.clone(),
+            });
+        }
+    }
+    // 範囲は数値として読める値にだけ適用する。リストは要素ごとに検証済み
+    // sizeは単位を付けた値もバイト数に、durationは秒に直してから比べる
+    if let (Some(range), false) = (
+        &schema_entry.range,
+        matches!(
+            expected_type,
+            SchemaType::List { .. } | SchemaType::Tuple(_)
+        ),
+    ) {
+        let number = match expected_type {
+            SchemaType::Size => parse_size(&sysctl_value.value).map(|bytes| bytes as f64),
+            SchemaType::Duration => parse_duration(&sysctl_value.value),
+            _ => sysctl_value.value.parse::<f64>().ok(),
+        };
+        if let Some(number) = number {
+            if !range.contains(number) {
+                errors.push(ValidationError::OutOfRange {
+                    key_name: key_name.to_string(),
+                    value: sysctl_value.value.to_string(),
+                    range: *range,
+                });
+            }
+        }
+    }
+    errors
+}
+
+// 必須のキーが無いエラー。既定値があれば、それも伝える
+fn missing_key(entry: &SchemaEntry) -> ValidationError {
+    match &entry.default {
+        Some(default) => ValidationError::MissingKeyWithDefault {
+            key_name: entry.name.clone(),
+            default: default.clone(),
+        },
+        None => ValidationError::MissingKey(entry.name.clone()),
+    }
+}
+
+// %groupで指定されたキーのうち、1つでも設定されているのに揃っていないグループを探す
+fn check_groups(schema: &Schema, contains: impl Fn(&str) -> bool) -> Vec<ValidationError> {
+    schema
+        .groups
+        .iter()
+        .filter(|(_, keys)| keys.iter().any(|key| contains(key)))
+        .filter_map(|(group, keys)| {
+            let missing: Vec<String> = keys.iter().filter(|key| !contains(key)).cloned().collect();
+            (!missing.is_empty()).then(|| ValidationError::IncompleteGroup {
+                group: group.to_owned(),
+                missing,
+            })
+        })
+        .collect()
+}
+
+pub fn validate_by_schema(
+    value: &IndexMap<String, SysctlValue>,
+    schema: &Schema,
+    options: &ValidationOptions,
+) -> Result<(), Vec<ValidationError>> {
+    let started = Instant::now();
+    // readonlyのキーは書かれていないのが正しく、optionalのキーは無くてもよいため、必須のキーから除く
+    let missing_keys: Vec<&SchemaEntry> = schema
+        .entries
+        .iter()
+        .filter(|entry| entry.is_required() && !value.contains_key(&entry.name))
+        .collect();
+    // グロブのエントリに一致するキーも、スキーマにあるキーとして扱う
+    let mut unknown_keys = Vec::new();
+    let mut wrong_types = Vec::new();
+    for (key, sysctl_value) in value {
+        match schema.entry_for(key) {
+            Some(schema_entry) => {
+                trace!(key = %key, schema_type = %schema_entry.schema_type, "キーを検証します");
+                wrong_types.extend(check_entry(schema, &schema_entry, sysctl_value, options));
+            }
+            None => unknown_keys.push(key),
+        }
+    }
+
+    for group in &schema.exclusive_groups {
+        let present: Vec<String> = group
+            .iter()
+            .filter(|key| value.contains_key(*key))
+            .cloned()
+            .collect();
+        if present.len() > 1 {
+            wrong_types.push(ValidationError::MutuallyExclusive { keys: present });
+        }
+    }
+
+    wrong_types.extend(check_groups(schema, |key| value.contains_key(key)));
+
+    for (key, dependency) in &schema.dependencies {
+        if value.contains_key(key) && !value.contains_key(dependency) {
+            wrong_types.push(ValidationError::MissingDependency {
+                key_name: key.to_owned(),
+                dependency: dependency.to_owned(),
+            });
+        }
     }
 
     let mut errors = Vec::new();
-    errors.extend(
-        missing_keys
-            .into_iter()
-            .map(|x| ValidationError::MissingKey(x.to_string()))
-            .collect::<Vec<_>>(),
-    );
+    errors.extend(missing_keys.into_iter().map(missing_key));
     errors.extend(
         unknown_keys
             .into_iter()
@@ -61,6 +423,13 @@ pub fn validate_by_schema(
             .collect::<Vec<_>>(),
     );
     errors.extend(wrong_types);
+    debug!(
+        keys = value.len(),
+        schema_entries = schema.entries.len(),
+        errors = errors.len(),
+        elapsed = ?started.elapsed(),
+        "検証しました"
+    );
 
     if errors.is_empty() {
         Ok(())
@@ -69,43 +438,158 @@ pub fn validate_by_schema(
     }
 }
 
+// 検証エラーに、parse_sysctl_spannedで得た入力中の位置を付ける
+// 値に問題があるエラーは値の範囲を、それ以外はキーの範囲を指す
+pub fn attach_spans(
+    errors: &[ValidationError],
+    entries: &IndexMap<String, SpannedEntry>,
+) -> Vec<SpannedError> {
+    errors
+        .iter()
+        .map(|error| SpannedError {
+            error: error.clone(),
+            span: entries.get(error.key_name()).map(|entry| {
+                let (start, end) = if error.points_at_value() {
+                    entry.value_span
+                } else {
+                    entry.key_span
+                };
+                Span {
+                    line: entry.line,
+                    start,
+                    end,
+                }
+            }),
+        })
+        .collect()
+}
+
+// 1つのキーだけを検証する。スキーマに無いキーはUnknownKeyになる
+pub fn validate_entry(
+    key: &str,
+    sysctl_value: &SysctlValue,
+    schema: &Schema,
+    options: &ValidationOptions,
+) -> Vec<ValidationError> {
+    match schema.entry_for(key) {
+        Some(schema_entry) => check_entry(schema, &schema_entry, sysctl_value, options),
+        None => vec![ValidationError::UnknownKey(key.to_owned())],
+    }
+}
+
+// 追記されていく設定を1行ずつ受け取り、その行で新たに発生した問題を報告する検証器。
+// 依存先のキーは依存元より前の行で設定されている必要がある
+pub struct Validator<'a> {
+    schema: &'a Schema,
+    options: ValidationOptions,
+    values: IndexMap<String, SysctlValue>,
+    // キーが最後に設定された行番号
+    lines: HashMap<String, usize>,
+    line: usize,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(schema: &'a Schema, options: ValidationOptions) -> Self {
+        Validator {
+            schema,
+            options,
+            values: IndexMap::new(),
+            lines: HashMap::new(),
+            line: 0,
+        }
+    }
+
+    // 1行を受け取って検証する。文法に誤りがある行は何も反映せずに警告を返す
+    pub fn feed(&mut self, line: &str) -> Result<Vec<ValidationError>, ParseWarning> {
+        self.line += 1;
+        let entries = parse_sysctl(line).map_err(|_| ParseWarning {
+            line: self.line,
+            reason: tr!("文法に誤りがあります", "syntax error"),
+            raw: line.to_owned(),
+        })?;
+
+        let mut errors = Vec::new();
+        for (key, sysctl_value) in entries {
+            if let Some(first_line) = self.lines.insert(key.clone(), self.line) {
+                errors.push(ValidationError::DuplicateKey {
+                    key_name: key.clone(),
+                    first_line,
+                    second_line: self.line,
+                });
+            }
+
+            errors.extend(validate_entry(
+                &key,
+                &sysctl_value,
+                self.schema,
+                &self.options,
+            ));
+            self.values.insert(key.clone(), sysctl_value);
+
+            for group in &self.schema.exclusive_groups {
+                if !group.contains(&key) {
+                    continue;
+                }
+                let present: Vec<String> = group
+                    .iter()
+                    .filter(|member| self.values.contains_key(*member))
+                    .cloned()
+                    .collect();
+                if present.len() > 1 {
+                    errors.push(ValidationError::MutuallyExclusive { keys: present });
+                }
+            }
+            for (dependent, dependency) in &self.schema.dependencies {
+                if *dependent == key && !self.values.contains_key(dependency) {
+                    errors.push(ValidationError::MissingDependency {
+                        key_name: key.clone(),
+                        dependency: dependency.to_owned(),
+                    });
+                }
+            }
+        }
+        Ok(errors)
+    }
+
+    // 入力の終わりで、最後まで設定されなかった必須のキーと、揃わなかったグループを報告する
+    pub fn finish(self) -> Vec<ValidationError> {
+        let mut errors: Vec<ValidationError> = self
+            .schema
+            .entries
+            .iter()
+            .filter(|entry| entry.is_required() && !self.values.contains_key(&entry.name))
+            .map(missing_key)
+            .collect();
+        errors.extend(check_groups(self.schema, |key| {
+            self.values.contains_key(key)
+        }));
+        errors
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::types::SchemaEntry;
-
     impl PartialOrd for ValidationError {
         fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-            let key_name = match self {
-                ValidationError::MissingKey(key_name) => key_name,
-                ValidationError::UnknownKey(key_name) => key_name,
-                ValidationError::WrongType { key_name, .. } => key_name,
-                ValidationError::TooLongLine(key_name) => key_name,
-            };
-            let other_key_name = match other {
-                ValidationError::MissingKey(key_name) => key_name,
-                ValidationError::UnknownKey(key_name) => key_name,
-                ValidationError::WrongType { key_name, .. } => key_name,
-                ValidationError::TooLongLine(key_name) => key_name,
-            };
-
-            Some(key_name.cmp(other_key_name))
+            Some(self.cmp(other))
         }
     }
     impl Ord for ValidationError {
         fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-            self.partial_cmp(other).unwrap()
+            self.key_name().cmp(other.key_name())
         }
     }
 
     use super::*;
     #[test]
     fn validate_by_schema_success() {
-        let value: HashMap<String, SysctlValue> = [
+        let value: IndexMap<String, SysctlValue> = [
             (
                 "key1".to_string(),
                 SysctlValue {
                     value: "value1".to_string(),
                     ignore_error: false,
+                    origin: None,
                 },
             ),
             (
@@ -113,6 +597,7 @@ mod tests {
                 SysctlValue {
                     value: "false".to_string(),
                     ignore_error: false,
+                    origin: None,
                 },
             ),
             (
@@ -120,6 +605,7 @@ mod tests {
                 SysctlValue {
                     value: "3.14".to_string(),
                     ignore_error: false,
+                    origin: None,
                 },
             ),
         ]
@@ -131,19 +617,23 @@ mod tests {
                 SchemaEntry {
                     name: "key1".to_string(),
                     schema_type: SchemaType::String,
+                    ..Default::default()
                 },
                 SchemaEntry {
                     name: "key2".to_string(),
                     schema_type: SchemaType::Boolean,
+                    ..Default::default()
                 },
                 SchemaEntry {
                     name: "key3".to_string(),
                     schema_type: SchemaType::Number,
+                    ..Default::default()
                 },
             ],
+            ..Default::default()
         };
 
-        let result = validate_by_schema(&value, &schema);
+        let result = validate_by_schema(&value, &schema, &ValidationOptions::default());
         assert!(result.is_ok());
     }
 
@@ -158,6 +648,7 @@ mod tests {
                             // valid as string
                             value: "true".to_string(),
                             ignore_error: false,
+                            origin: None,
                         },
                     ),
                     (
@@ -165,6 +656,7 @@ mod tests {
                         SysctlValue {
                             value: "true?".to_string(),
                             ignore_error: false,
+                            origin: None,
                         },
                     ),
                     (
@@ -172,6 +664,7 @@ mod tests {
                         SysctlValue {
                             value: "3..14".to_string(),
                             ignore_error: false,
+                            origin: None,
                         },
                     ),
                 ]
@@ -182,17 +675,22 @@ mod tests {
                         SchemaEntry {
                             name: "key1".to_string(),
                             schema_type: SchemaType::String,
+                            ..Default::default()
                         },
                         SchemaEntry {
                             name: "key2".to_string(),
                             schema_type: SchemaType::Boolean,
+                            ..Default::default()
                         },
                         SchemaEntry {
                             name: "key3".to_string(),
                             schema_type: SchemaType::Number,
+                            ..Default::default()
                         },
                     ],
-                }
+                    ..Default::default()
+                },
+                &ValidationOptions::default()
             )
             .map_err(|errors| {
                 let mut es = errors.clone();
@@ -204,11 +702,13 @@ mod tests {
                     key_name: "key2".to_string(),
                     expect: SchemaType::Boolean,
                     actual: SchemaType::String,
+                    trailing: None,
                 },
                 ValidationError::WrongType {
                     key_name: "key3".to_string(),
                     expect: SchemaType::Number,
                     actual: SchemaType::String,
+                    trailing: Some(".14".to_string()),
                 },
             ],)
         );
@@ -221,6 +721,7 @@ mod tests {
                         // valid as string
                         value: "true".to_string(),
                         ignore_error: false,
+                        origin: None,
                     },
                 ),]
                 .into_iter()
@@ -230,13 +731,17 @@ mod tests {
                         SchemaEntry {
                             name: "key1".to_string(),
                             schema_type: SchemaType::String,
+                            ..Default::default()
                         },
                         SchemaEntry {
                             name: "key2".to_string(),
                             schema_type: SchemaType::Boolean,
+                            ..Default::default()
                         },
                     ],
-                }
+                    ..Default::default()
+                },
+                &ValidationOptions::default()
             ),
             Err(vec![ValidationError::MissingKey("key2".to_string())])
         );
@@ -250,6 +755,7 @@ mod tests {
                             // valid as string
                             value: "true".to_string(),
                             ignore_error: false,
+                            origin: None,
                         },
                     ),
                     (
@@ -257,6 +763,7 @@ mod tests {
                         SysctlValue {
                             value: "true?".to_string(),
                             ignore_error: false,
+                            origin: None,
                         },
                     ),
                 ]
@@ -266,10 +773,961 @@ mod tests {
                     entries: vec![SchemaEntry {
                         name: "key1".to_string(),
                         schema_type: SchemaType::String,
-                    }]
-                }
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                &ValidationOptions::default()
             ),
             Err(vec![ValidationError::UnknownKey("key2".to_string())])
         )
     }
+
+    #[test]
+    fn validate_by_schema_builtin_max_len() {
+        let schema = Schema {
+            entries: vec![SchemaEntry {
+                name: "kernel.hostname".to_string(),
+                schema_type: SchemaType::Hostname,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let value_of = |hostname: String| -> IndexMap<String, SysctlValue> {
+            [(
+                "kernel.hostname".to_string(),
+                SysctlValue {
+                    value: hostname,
+                    ignore_error: false,
+                    origin: None,
+                },
+            )]
+            .into_iter()
+            .collect()
+        };
+
+        assert!(validate_by_schema(
+            &value_of("a".repeat(64)),
+            &schema,
+            &ValidationOptions::default()
+        )
+        .is_ok());
+        assert_eq!(
+            validate_by_schema(
+                &value_of("a".repeat(65)),
+                &schema,
+                &ValidationOptions::default()
+            ),
+            Err(vec![ValidationError::ValueTooLong {
+                key_name: "kernel.hostname".to_string(),
+                max_len: 64,
+                actual_len: 65,
+            }])
+        );
+    }
+
+    #[test]
+    fn validate_by_embedded_schema() {
+        use crate::parser::{extract_embedded_schema, parse_schema, parse_sysctl};
+
+        let input = "
+            #schema-begin
+            # port: number
+            # debug: bool
+            #schema-end
+            port = 3910
+            debug = yes
+        ";
+        let value = parse_sysctl(input).unwrap();
+        let schema = parse_schema(&extract_embedded_schema(input).unwrap()).unwrap();
+        assert_eq!(
+            validate_by_schema(&value, &schema, &ValidationOptions::default()),
+            Err(vec![ValidationError::WrongType {
+                key_name: "debug".to_string(),
+                expect: SchemaType::Boolean,
+                actual: SchemaType::String,
+                trailing: None,
+            }])
+        );
+    }
+
+    #[test]
+    fn validate_by_schema_bool_words() {
+        use crate::parser::{parse_schema, parse_sysctl};
+
+        let schema =
+            parse_schema("%bool 0/1 no/yes\nforward: bool\nlog: bool\nrp: bool\n").unwrap();
+        let value = parse_sysctl("forward = 1\nlog = yes\nrp = true\n").unwrap();
+        assert_eq!(
+            validate_by_schema(&value, &schema, &ValidationOptions::default()),
+            Err(vec![ValidationError::WrongType {
+                key_name: "rp".to_string(),
+                expect: SchemaType::Boolean,
+                actual: SchemaType::String,
+                trailing: None,
+            }])
+        );
+
+        // %boolが無ければ true と false だけを受け付ける
+        let schema = parse_schema("forward: bool\n").unwrap();
+        let value = parse_sysctl("forward = 1\n").unwrap();
+        assert!(validate_by_schema(&value, &schema, &ValidationOptions::default()).is_err());
+    }
+
+    #[test]
+    fn validate_by_schema_glob_keys() {
+        use crate::parser::{parse_schema, parse_sysctl};
+        use crate::types::Range;
+
+        let schema = parse_schema(
+            "net.ipv4.conf.*.rp_filter: integer(0..2)\nnet.ipv4.conf.all.rp_filter: bool\n",
+        )
+        .unwrap();
+        let value = parse_sysctl(
+            "net.ipv4.conf.all.rp_filter = true\nnet.ipv4.conf.eth0.rp_filter = 1\nnet.ipv4.conf.eth1.rp_filter = 3\nnet.ipv4.conf.eth1.forwarding = 1\n",
+        )
+        .unwrap();
+        // グロブのエントリは必須にならず、同じ名前のエントリが優先される
+        // エラーはグロブではなく、実際のキーを指す
+        assert_eq!(
+            validate_by_schema(&value, &schema, &ValidationOptions::default()),
+            Err(vec![
+                ValidationError::UnknownKey("net.ipv4.conf.eth1.forwarding".to_string()),
+                ValidationError::OutOfRange {
+                    key_name: "net.ipv4.conf.eth1.rp_filter".to_string(),
+                    range: Range {
+                        min: Some(0),
+                        max: Some(2),
+                    },
+                    value: "3".to_string(),
+                },
+            ])
+        );
+        assert_eq!(
+            validate_entry(
+                "net.ipv4.conf.lo.rp_filter",
+                &value["net.ipv4.conf.eth0.rp_filter"],
+                &schema,
+                &ValidationOptions::default(),
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn validate_by_schema_path() {
+        let schema = Schema {
+            entries: vec![
+                SchemaEntry {
+                    name: "kernel.modprobe".to_string(),
+                    schema_type: SchemaType::Path { exists: false },
+                    ..Default::default()
+                },
+                SchemaEntry {
+                    name: "kernel.poweroff_cmd".to_string(),
+                    schema_type: SchemaType::Path { exists: true },
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let value_of = |modprobe: &str, poweroff_cmd: &str| -> IndexMap<String, SysctlValue> {
+            [
+                ("kernel.modprobe", modprobe),
+                ("kernel.poweroff_cmd", poweroff_cmd),
+            ]
+            .into_iter()
+            .map(|(key, value)| {
+                (
+                    key.to_string(),
+                    SysctlValue {
+                        value: value.to_string(),
+                        ignore_error: false,
+                        origin: None,
+                    },
+                )
+            })
+            .collect()
+        };
+        let existing = env!("CARGO_MANIFEST_DIR");
+
+        assert!(validate_by_schema(
+            &value_of("/sbin/modprobe", existing),
+            &schema,
+            &ValidationOptions::default()
+        )
+        .is_ok());
+        assert_eq!(
+            validate_by_schema(
+                &value_of("/sbin/modprobe", "/nonexistent/poweroff"),
+                &schema,
+                &ValidationOptions::default()
+            ),
+            Err(vec![ValidationError::PathNotFound {
+                key_name: "kernel.poweroff_cmd".to_string(),
+                path: "/nonexistent/poweroff".to_string(),
+            }])
+        );
+        // --check-paths では(exists)の無いpathも確認する
+        assert_eq!(
+            validate_by_schema(
+                &value_of("/nonexistent/modprobe", existing),
+                &schema,
+                &ValidationOptions {
+                    check_all_paths: true,
+                    ..Default::default()
+                }
+            ),
+            Err(vec![ValidationError::PathNotFound {
+                key_name: "kernel.modprobe".to_string(),
+                path: "/nonexistent/modprobe".to_string(),
+            }])
+        );
+        // 相対パスは受け付けない
+        assert!(matches!(
+            validate_by_schema(
+                &value_of("sbin/modprobe", existing),
+                &schema,
+                &ValidationOptions::default()
+            )
+            .unwrap_err()[..],
+            [ValidationError::WrongType { .. }]
+        ));
+        // 存在確認を無効にした場合
+        assert!(validate_by_schema(
+            &value_of("/sbin/modprobe", "/nonexistent/poweroff"),
+            &schema,
+            &ValidationOptions {
+                check_path_exists: false,
+                ..Default::default()
+            }
+        )
+        .is_ok());
+        assert_eq!(
+            validate_by_schema(
+                &value_of("/sbin/mod\tprobe", existing),
+                &schema,
+                &ValidationOptions::default()
+            ),
+            Err(vec![ValidationError::WrongType {
+                key_name: "kernel.modprobe".to_string(),
+                expect: SchemaType::Path { exists: false },
+                actual: SchemaType::String,
+                trailing: None,
+            }])
+        );
+        assert!(validate_by_schema(
+            &value_of(&"a".repeat(256), existing),
+            &schema,
+            &ValidationOptions::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn validate_by_schema_exclusive() {
+        let schema = Schema {
+            entries: vec![
+                SchemaEntry {
+                    name: "a.key".to_string(),
+                    schema_type: SchemaType::Boolean,
+                    ..Default::default()
+                },
+                SchemaEntry {
+                    name: "b.key".to_string(),
+                    schema_type: SchemaType::Boolean,
+                    ..Default::default()
+                },
+            ],
+            exclusive_groups: vec![vec!["a.key".to_string(), "b.key".to_string()]],
+            ..Default::default()
+        };
+        let value_of = |keys: &[&str]| -> IndexMap<String, SysctlValue> {
+            keys.iter()
+                .map(|key| {
+                    (
+                        key.to_string(),
+                        SysctlValue {
+                            value: "true".to_string(),
+                            ignore_error: false,
+                            origin: None,
+                        },
+                    )
+                })
+                .collect()
+        };
+        let validate = |keys: &[&str]| {
+            validate_by_schema(&value_of(keys), &schema, &ValidationOptions::default())
+                .err()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|error| matches!(error, ValidationError::MutuallyExclusive { .. }))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            validate(&["a.key", "b.key"]),
+            vec![ValidationError::MutuallyExclusive {
+                keys: vec!["a.key".to_string(), "b.key".to_string()]
+            }]
+        );
+        assert_eq!(validate(&["a.key"]), vec![]);
+        assert_eq!(validate(&[]), vec![]);
+    }
+
+    #[test]
+    fn validate_by_schema_dependencies() {
+        let schema =
+            crate::parser::parse_schema("a.key: bool\nb.key: bool\n%depends b.key a.key\n")
+                .unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        assert!(validate("a.key = true\nb.key = false").is_ok());
+        // 一括の検証では順序は問わない
+        assert!(validate("b.key = false\na.key = true").is_ok());
+        assert!(validate("b.key = false").unwrap_err().contains(
+            &ValidationError::MissingDependency {
+                key_name: "b.key".to_string(),
+                dependency: "a.key".to_string(),
+            }
+        ));
+    }
+
+    #[test]
+    fn attach_spans_points_at_key_or_value() {
+        let input = "a.key = 1\n\nb.key = abc\n";
+        let entries = crate::parser::parse_sysctl_spanned(input).unwrap();
+        let errors = vec![
+            ValidationError::WrongType {
+                key_name: "b.key".to_string(),
+                expect: SchemaType::Number,
+                actual: SchemaType::String,
+                trailing: None,
+            },
+            ValidationError::UnknownKey("a.key".to_string()),
+            ValidationError::MissingKey("c.key".to_string()),
+        ];
+        let spans: Vec<Option<Span>> = attach_spans(&errors, &entries)
+            .into_iter()
+            .map(|spanned| spanned.span)
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                Some(Span {
+                    line: 3,
+                    start: 19,
+                    end: 22
+                }),
+                Some(Span {
+                    line: 1,
+                    start: 0,
+                    end: 5
+                }),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_by_schema_groups() {
+        let schema = crate::parser::parse_schema(
+            "%group fastopen a.key b.key c.key\na.key: bool\nb.key: bool\nc.key: bool\n",
+        )
+        .unwrap();
+        // 必須キーの不足とは別に、グループの不足だけを取り出す
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|error| matches!(error, ValidationError::IncompleteGroup { .. }))
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(validate("a.key = true\nb.key = true\nc.key = true"), vec![]);
+        assert_eq!(
+            validate("b.key = true"),
+            vec![ValidationError::IncompleteGroup {
+                group: "fastopen".to_string(),
+                missing: vec!["a.key".to_string(), "c.key".to_string()],
+            }]
+        );
+        assert_eq!(validate(""), vec![]);
+    }
+
+    #[test]
+    fn validate_by_schema_number_trailing() {
+        let schema = crate::parser::parse_schema("key: number\n").unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        assert!(validate("key = 10").is_ok());
+        // 末尾の空白はパース時に除かれる
+        assert!(validate("key = 10 ").is_ok());
+        let errors = validate("key = 10foo").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ValidationError::WrongType {
+                key_name: "key".to_string(),
+                expect: SchemaType::Number,
+                actual: SchemaType::String,
+                trailing: Some("foo".to_string()),
+            }]
+        );
+        assert!(errors[0].to_string().contains("'foo'"));
+        // 数値で始まらない値は従来どおり
+        assert_eq!(
+            validate("key = many").unwrap_err(),
+            vec![ValidationError::WrongType {
+                key_name: "key".to_string(),
+                expect: SchemaType::Number,
+                actual: SchemaType::String,
+                trailing: None,
+            }]
+        );
+        assert_eq!(numeric_prefix_len("1.5e3ms"), Some(5));
+        assert_eq!(numeric_prefix_len("inf"), None);
+    }
+
+    #[test]
+    fn validate_by_schema_integer() {
+        let schema = crate::parser::parse_schema("a: integer(0..100)\nb: number\n").unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        // numberは整数も小数も受け付けるが、integerは整数だけ
+        assert!(validate("a = 60\nb = 1").is_ok());
+        assert!(validate("a = 60\nb = 3.14").is_ok());
+        assert_eq!(
+            validate("a = 3.14\nb = 1").unwrap_err(),
+            vec![ValidationError::WrongType {
+                key_name: "a".to_string(),
+                expect: SchemaType::Integer,
+                actual: SchemaType::Number,
+                trailing: None,
+            }]
+        );
+        assert_eq!(
+            validate("a = 10ms\nb = 1").unwrap_err(),
+            vec![ValidationError::WrongType {
+                key_name: "a".to_string(),
+                expect: SchemaType::Integer,
+                actual: SchemaType::String,
+                trailing: Some("ms".to_string()),
+            }]
+        );
+        assert!(matches!(
+            validate("a = 101\nb = 1").unwrap_err()[..],
+            [ValidationError::OutOfRange { .. }]
+        ));
+    }
+
+    #[test]
+    fn validate_by_schema_list_arity() {
+        use crate::types::Arity;
+
+        let schema = crate::parser::parse_schema("rmem: number[3]\nports: number[1..]\n").unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        let wrong_arity = |key: &str, min: usize, max: Option<usize>, actual: usize| {
+            Err(vec![ValidationError::WrongArity {
+                key_name: key.to_string(),
+                expected: Arity { min, max },
+                actual,
+            }])
+        };
+
+        assert!(validate("rmem = 4096 131072 6291456\nports = 80").is_ok());
+        assert!(validate("rmem = 4096\t131072  6291456\nports = 80 443 8080").is_ok());
+        // 少なすぎる
+        assert_eq!(
+            validate("rmem = 4096 131072\nports = 80"),
+            wrong_arity("rmem", 3, Some(3), 2)
+        );
+        // 多すぎる
+        assert_eq!(
+            validate("rmem = 1 2 3 4\nports = 80"),
+            wrong_arity("rmem", 3, Some(3), 4)
+        );
+        // 1個以上
+        assert!(Arity { min: 1, max: None }.contains(100));
+        assert!(!Arity { min: 1, max: None }.contains(0));
+        // 要素の型も検証し、何番目の要素かを付ける
+        let errors = validate("rmem = 1 two 3\nports = 80").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ValidationError::InvalidElement {
+                key_name: "rmem".to_string(),
+                position: 2,
+                error: Box::new(ValidationError::WrongType {
+                    key_name: "rmem".to_string(),
+                    expect: SchemaType::Number,
+                    actual: SchemaType::String,
+                    trailing: None,
+                }),
+            }]
+        );
+        assert_eq!(errors[0].code(), "SL0003");
+        assert_eq!(
+            errors[0].to_string(),
+            "2番目の要素: 'rmem'の型が間違っています。numberが必要ですが、stringの形式になっています。"
+        );
+        // カンマでも区切れる
+        assert!(validate("rmem = 4096,131072,6291456\nports = 80, 443").is_ok());
+        assert_eq!(
+            wrong_arity("rmem", 3, Some(3), 2).unwrap_err()[0].to_string(),
+            "'rmem'の値の個数が間違っています。3個が必要ですが、2個あります。"
+        );
+    }
+
+    #[test]
+    fn validate_by_schema_list_type() {
+        let schema = crate::parser::parse_schema(
+            "net.ipv4.ip_local_reserved_ports: list<integer>\nkernel.printk: list<integer(0..7)>\n",
+        );
+        // 範囲は list<> の後ろに付ける
+        assert!(schema.is_err());
+        let schema = crate::parser::parse_schema(
+            "net.ipv4.ip_local_reserved_ports: list<integer>\nkernel.printk: list<integer>(0..7)\n",
+        )
+        .unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        assert!(validate("net.ipv4.ip_local_reserved_ports = 1\nkernel.printk = 4 4 1 7").is_ok());
+        assert!(
+            validate("net.ipv4.ip_local_reserved_ports = 8080,9148\nkernel.printk = 4\t4 1 7")
+                .is_ok()
+        );
+        let errors = validate("net.ipv4.ip_local_reserved_ports = 8080,x\nkernel.printk = 4 4 1 8")
+            .unwrap_err();
+        let positions: Vec<(&str, usize)> = errors
+            .iter()
+            .map(|error| match error {
+                ValidationError::InvalidElement {
+                    key_name, position, ..
+                } => (key_name.as_str(), *position),
+                _ => panic!("{:?}", error),
+            })
+            .collect();
+        assert!(positions.contains(&("net.ipv4.ip_local_reserved_ports", 2)));
+        assert!(positions.contains(&("kernel.printk", 4)));
+    }
+
+    #[test]
+    fn validate_by_schema_tuple() {
+        let schema = crate::parser::parse_schema(
+            "net.ipv4.tcp_rmem: (integer, integer, integer)(1..)\nnet.ipv4.tcp_mem: (integer, bool)\n",
+        )
+        .unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        assert!(
+            validate("net.ipv4.tcp_rmem = 4096 131072 6291456\nnet.ipv4.tcp_mem = 1 true").is_ok()
+        );
+        assert!(matches!(
+            validate("net.ipv4.tcp_rmem = 4096 131072\nnet.ipv4.tcp_mem = 1 true").unwrap_err()[..],
+            [ValidationError::WrongArity { actual: 2, .. }]
+        ));
+        // 位置ごとの型で検証する
+        let errors = validate("net.ipv4.tcp_rmem = 4096 131072 6291456\nnet.ipv4.tcp_mem = true 1")
+            .unwrap_err();
+        let positions: Vec<usize> = errors
+            .iter()
+            .map(|error| match error {
+                ValidationError::InvalidElement { position, .. } => *position,
+                _ => panic!("{:?}", error),
+            })
+            .collect();
+        assert_eq!(positions, vec![1, 2]);
+        // 範囲は各要素に適用する
+        assert!(matches!(
+            &validate("net.ipv4.tcp_rmem = 4096 0 6291456\nnet.ipv4.tcp_mem = 1 true").unwrap_err()[..],
+            [ValidationError::InvalidElement { position: 2, error, .. }]
+                if matches!(**error, ValidationError::OutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("64k"), Some(64 * 1024));
+        assert_eq!(parse_size("16M"), Some(16 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Some(1 << 30));
+        assert_eq!(parse_size("2t"), Some(2 << 40));
+        assert_eq!(parse_size("1.5G"), None);
+        assert_eq!(parse_size("64KB"), None);
+        assert_eq!(parse_size("k"), None);
+        assert_eq!(parse_size("99999999999T"), None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30"), Some(30.0));
+        assert_eq!(parse_duration("30s"), Some(30.0));
+        assert_eq!(parse_duration("5m"), Some(300.0));
+        assert_eq!(parse_duration("250ms"), Some(0.25));
+        assert_eq!(parse_duration("2h"), Some(7200.0));
+        assert_eq!(parse_duration("1d"), Some(86400.0));
+        assert_eq!(parse_duration("5M"), None);
+        assert_eq!(parse_duration("1.5s"), None);
+        assert_eq!(parse_duration("s"), None);
+    }
+
+    #[test]
+    fn validate_by_schema_duration() {
+        let schema =
+            crate::parser::parse_schema("net.ipv4.tcp_keepalive_time: duration(1..7200)\n")
+                .unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        assert!(validate("net.ipv4.tcp_keepalive_time = 600").is_ok());
+        assert!(validate("net.ipv4.tcp_keepalive_time = 10m").is_ok());
+        // 範囲は秒で比べる
+        assert!(matches!(
+            validate("net.ipv4.tcp_keepalive_time = 3h").unwrap_err()[..],
+            [ValidationError::OutOfRange { .. }]
+        ));
+        assert!(matches!(
+            validate("net.ipv4.tcp_keepalive_time = 250ms").unwrap_err()[..],
+            [ValidationError::OutOfRange { .. }]
+        ));
+        assert!(matches!(
+            validate("net.ipv4.tcp_keepalive_time = soon").unwrap_err()[..],
+            [ValidationError::WrongType {
+                expect: SchemaType::Duration,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn validate_by_schema_size() {
+        let schema =
+            crate::parser::parse_schema("vm.min_free_kbytes: size(65536..1073741824)\n").unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        assert!(validate("vm.min_free_kbytes = 65536").is_ok());
+        assert!(validate("vm.min_free_kbytes = 64k").is_ok());
+        assert!(validate("vm.min_free_kbytes = 1G").is_ok());
+        // 単位を付けた値もバイト数にしてから範囲と比べる
+        assert!(matches!(
+            validate("vm.min_free_kbytes = 2G").unwrap_err()[..],
+            [ValidationError::OutOfRange { .. }]
+        ));
+        assert!(matches!(
+            validate("vm.min_free_kbytes = 16 MB").unwrap_err()[..],
+            [ValidationError::WrongType {
+                expect: SchemaType::Size,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn validate_by_schema_range() {
+        use crate::types::Range;
+
+        let schema = crate::parser::parse_schema("a: number(0..100)\nb: number[2](1..)\n").unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+            .map_err(|mut errors| {
+                errors.sort();
+                errors
+            })
+        };
+        assert!(validate("a = 0\nb = 1 2").is_ok());
+        assert!(validate("a = 100\nb = 1 2").is_ok());
+        assert_eq!(
+            validate("a = 101\nb = 1 0"),
+            Err(vec![
+                ValidationError::OutOfRange {
+                    key_name: "a".to_string(),
+                    value: "101".to_string(),
+                    range: Range {
+                        min: Some(0),
+                        max: Some(100)
+                    },
+                },
+                ValidationError::InvalidElement {
+                    key_name: "b".to_string(),
+                    position: 2,
+                    error: Box::new(ValidationError::OutOfRange {
+                        key_name: "b".to_string(),
+                        value: "0".to_string(),
+                        range: Range {
+                            min: Some(1),
+                            max: None
+                        },
+                    }),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn validate_by_schema_pattern() {
+        let schema = crate::parser::parse_schema(
+            "kernel.core_pattern: string(/^\\|?\\/[\\w\\/%. -]+$/)\nb.key: number[2](/^1 /)\n",
+        )
+        .unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        assert!(validate("kernel.core_pattern = |/usr/bin/dump %p\nb.key = 1 2").is_ok());
+        // リストは値全体に適用する
+        let errors = validate("kernel.core_pattern = core\nb.key = 2 1").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        let error = errors
+            .iter()
+            .find(|error| error.key_name() == "kernel.core_pattern")
+            .unwrap();
+        assert_eq!(error.code(), "SL0016");
+        assert_eq!(
+            error.to_string(),
+            r"'kernel.core_pattern'の値'core'はパターン/^\|?\/[\w\/%. -]+$/に一致しません。"
+        );
+    }
+
+    #[test]
+    fn validate_by_schema_enum_whitespace() {
+        let schema_entry = SchemaEntry {
+            name: "key".to_owned(),
+            schema_type: SchemaType::Enum(vec!["foo".to_owned(), "bar".to_owned()]),
+            ..Default::default()
+        };
+        let check = |value: &str, exact_enum: bool| {
+            check_entry(
+                &Schema::default(),
+                &schema_entry,
+                &SysctlValue {
+                    value: value.to_owned(),
+                    ignore_error: false,
+                    origin: None,
+                },
+                &ValidationOptions {
+                    exact_enum,
+                    ..Default::default()
+                },
+            )
+        };
+        let invalid = |value: &str| {
+            vec![ValidationError::InvalidChoice {
+                key_name: "key".to_owned(),
+                value: value.to_owned(),
+                choices: vec!["foo".to_owned(), "bar".to_owned()],
+            }]
+        };
+
+        assert_eq!(check("foo", false), vec![]);
+        assert_eq!(check("foo", true), vec![]);
+        // 既定では前後の空白を除いて比較する
+        assert_eq!(check(" foo ", false), vec![]);
+        assert_eq!(check(" foo ", true), invalid(" foo "));
+        // クォートには対応していないため、"もそのまま値の一部として比較する
+        assert_eq!(check("\"foo \"", false), invalid("\"foo \""));
+        assert_eq!(check("\"foo \"", true), invalid("\"foo \""));
+        assert_eq!(check("baz", false), invalid("baz"));
+
+        // 設定ファイルからパースした値は前後の空白が除かれている
+        let schema = crate::parser::parse_schema("key: enum(foo, bar)\n").unwrap();
+        assert!(validate_by_schema(
+            &parse_sysctl("key =  foo  ").unwrap(),
+            &schema,
+            &ValidationOptions {
+                exact_enum: true,
+                ..Default::default()
+            },
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn validate_by_schema_readonly() {
+        let schema =
+            crate::parser::parse_schema("a.key: bool\nkernel.ostype: string readonly\n").unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        // readonlyのキーは書かれていなくても必須扱いにならない
+        assert!(validate("a.key = true").is_ok());
+        assert_eq!(
+            validate("a.key = true\nkernel.ostype = Linux"),
+            Err(vec![ValidationError::ReadOnlyKey(
+                "kernel.ostype".to_string()
+            )])
+        );
+
+        let mut validator = Validator::new(&schema, ValidationOptions::default());
+        assert_eq!(
+            validator.feed("kernel.ostype = Linux"),
+            Ok(vec![ValidationError::ReadOnlyKey(
+                "kernel.ostype".to_string()
+            )])
+        );
+        assert_eq!(
+            validator.finish(),
+            vec![ValidationError::MissingKey("a.key".to_string())]
+        );
+    }
+
+    #[test]
+    fn validate_by_schema_optional() {
+        let schema = crate::parser::parse_schema(
+            "a.key: bool\nb.key?: number(0..10)\nc.key: string optional\n",
+        )
+        .unwrap();
+        let validate = |input: &str| {
+            validate_by_schema(
+                &parse_sysctl(input).unwrap(),
+                &schema,
+                &ValidationOptions::default(),
+            )
+        };
+        // optionalのキーは書かれていなくてもよいが、書かれていれば検証する
+        assert!(validate("a.key = true").is_ok());
+        assert!(validate("a.key = true\nb.key = 5\nc.key = x").is_ok());
+        assert_eq!(validate("b.key = 5").unwrap_err().len(), 1);
+        assert!(matches!(
+            validate("a.key = true\nb.key = 11").unwrap_err()[..],
+            [ValidationError::OutOfRange { .. }]
+        ));
+
+        let validator = Validator::new(&schema, ValidationOptions::default());
+        assert_eq!(
+            validator.finish(),
+            vec![ValidationError::MissingKey("a.key".to_string())]
+        );
+    }
+
+    #[test]
+    fn validate_by_schema_default() {
+        let schema = crate::parser::parse_schema(
+            "vm.swappiness: number = 60
+b.key?: number = 1
+",
+        )
+        .unwrap();
+        // 既定値のある必須のキーが無ければ、既定値と合わせて報告する
+        assert_eq!(
+            validate_by_schema(
+                &parse_sysctl("").unwrap(),
+                &schema,
+                &ValidationOptions::default()
+            ),
+            Err(vec![ValidationError::MissingKeyWithDefault {
+                key_name: "vm.swappiness".to_string(),
+                default: "60".to_string(),
+            }])
+        );
+        assert_eq!(
+            Validator::new(&schema, ValidationOptions::default()).finish()[0].code(),
+            "SL0001"
+        );
+    }
+
+    #[test]
+    fn validator_feed() {
+        let schema = crate::parser::parse_schema(
+            "a.key: bool\nb.key: number\nc.key: bool\n%depends c.key a.key\n",
+        )
+        .unwrap();
+        let mut validator = Validator::new(&schema, ValidationOptions::default());
+
+        assert_eq!(validator.feed("# comment"), Ok(vec![]));
+        // 依存先のa.keyがまだ設定されていない
+        assert_eq!(
+            validator.feed("c.key = true"),
+            Ok(vec![ValidationError::MissingDependency {
+                key_name: "c.key".to_string(),
+                dependency: "a.key".to_string(),
+            }])
+        );
+        assert_eq!(validator.feed("a.key = true"), Ok(vec![]));
+        assert_eq!(
+            validator.feed("b.key = many"),
+            Ok(vec![ValidationError::WrongType {
+                key_name: "b.key".to_string(),
+                expect: SchemaType::Number,
+                actual: SchemaType::String,
+                trailing: None,
+            }])
+        );
+        // 2回目の設定で重複が見つかる
+        assert_eq!(
+            validator.feed("a.key = false"),
+            Ok(vec![ValidationError::DuplicateKey {
+                key_name: "a.key".to_string(),
+                first_line: 3,
+                second_line: 5,
+            }])
+        );
+        assert_eq!(
+            validator.feed("d.key = 1"),
+            Ok(vec![ValidationError::UnknownKey("d.key".to_string())])
+        );
+        assert_eq!(
+            validator.feed("not a valid line"),
+            Err(ParseWarning {
+                line: 7,
+                reason: "文法に誤りがあります".to_string(),
+                raw: "not a valid line".to_string(),
+            })
+        );
+        assert_eq!(validator.values["a.key"].value, "false");
+        assert_eq!(validator.finish(), vec![]);
+    }
 }
@@ -0,0 +1,21 @@
+use load_sysctl::{
+    parse_schema, parse_sysctl, validate_by_schema, SchemaType, ValidationError, ValidationOptions,
+};
+
+#[test]
+fn parse_and_validate_through_public_api() {
+    let schema = parse_schema("net.ipv4.ip_forward: bool\nkernel.pid_max: number\n").unwrap();
+    let value = parse_sysctl("net.ipv4.ip_forward = true\nkernel.pid_max = 4194304\n").unwrap();
+    assert!(validate_by_schema(&value, &schema, &ValidationOptions::default()).is_ok());
+
+    let value = parse_sysctl("net.ipv4.ip_forward = true\nkernel.pid_max = max\n").unwrap();
+    assert_eq!(
+        validate_by_schema(&value, &schema, &ValidationOptions::default()),
+        Err(vec![ValidationError::WrongType {
+            key_name: "kernel.pid_max".to_owned(),
+            expect: SchemaType::Number,
+            actual: SchemaType::String,
+            trailing: None,
+        }])
+    );
+}
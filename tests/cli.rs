@@ -0,0 +1,1726 @@
+use std::process::{Command, Output};
+
+// 実行する環境のロケールに左右されないよう、言語の環境変数を消しておく
+fn command() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_load_sysctl"));
+    command
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env_remove("LC_ALL")
+        .env_remove("LC_MESSAGES")
+        .env_remove("LANG");
+    command
+}
+
+fn run(args: &[&str]) -> Output {
+    command()
+        .args(args)
+        .output()
+        .expect("バイナリの実行に失敗しました")
+}
+
+#[test]
+fn profile_prints_timings_to_stderr() {
+    let output = run(&["examples/success1.conf", "--validate", "--profile"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("[profile] パース時間:"));
+    assert!(stderr.contains("エントリ数: 5"));
+    assert!(stderr.contains("[profile] 検証時間:"));
+}
+
+#[test]
+fn no_profile_output_without_flag() {
+    let output = run(&["examples/success1.conf", "--validate"]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("[profile]"));
+}
+
+#[test]
+fn ci_reports_failing_config_as_json() {
+    let output = run(&["examples/fail.conf", "--ci"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // デバッグダンプは出力されない
+    assert!(!stdout.contains("Rust形式"));
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(report["file"], "examples/fail.conf");
+    assert_eq!(report["ok"], false);
+    let kinds: Vec<&str> = report["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds.len(), 3);
+    assert!(kinds.iter().all(|k| *k == "wrong_type"));
+}
+
+#[test]
+fn ci_reports_syntax_error_as_json() {
+    let output = run(&["examples/parse_error1.conf", "--ci"]);
+    assert_eq!(output.status.code(), Some(2));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["diagnostics"][0]["kind"], "syntax_error");
+    assert_eq!(report["diagnostics"][0]["code"], "SL0101");
+}
+
+#[test]
+fn reports_every_malformed_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.conf");
+    std::fs::write(
+        &input,
+        "a.key = 1\nbroken\nb.key = 2\nalso broken\nc d = 3\n",
+    )
+    .unwrap();
+
+    let output = run(&[input.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("[SL0101] 文法に誤りがあります。2行1列目"));
+    assert!(lines[1].starts_with("[SL0101] 文法に誤りがあります。4行1列目"));
+    assert!(lines[2].starts_with("[SL0101] 文法に誤りがあります。5行1列目"));
+
+    let output = run(&[input.to_str().unwrap(), "--ci"]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let lines: Vec<&serde_json::Value> = report["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|diagnostic| &diagnostic["line"])
+        .collect();
+    assert_eq!(lines, vec![2, 4, 5]);
+}
+
+#[test]
+fn ci_passes_valid_config() {
+    let output = run(&["examples/success1.conf", "--ci"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["ok"], true);
+    assert_eq!(report["diagnostics"], serde_json::json!([]));
+}
+
+#[test]
+fn fingerprint_prints_only_hash() {
+    let output = run(&["examples/success1.conf", "--fingerprint"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let hash = stdout.trim();
+    assert_eq!(hash.len(), 64);
+    assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+}
+
+#[test]
+fn diff_schema_prints_changes() {
+    let output = run(&[
+        "examples/fail.conf.schema",
+        "--diff-schema",
+        "examples/success1.conf.schema",
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("+ endpoint: string"));
+    assert!(stdout.contains("~ log.file: number -> string"));
+    assert!(!stdout.contains("port"));
+}
+
+#[test]
+fn fmt_normalizes_bool_style() {
+    let output = run(&[
+        "examples/success1.conf",
+        "--validate",
+        "--fmt",
+        "--bool-style",
+        "kernel",
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("debug = 1\n"));
+    assert!(stdout.contains("port = 3910\n"));
+
+    // --validate が無くてもスキーマを読んで揃える
+    let output = run(&["examples/success1.conf", "--fmt", "--bool-style", "kernel"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("debug = 1\n"));
+}
+
+#[test]
+fn policy_findings_are_reported() {
+    let output = run(&["examples/success1.conf", "--policy", "examples/policy.json"]);
+    // warningのみなので成功扱い
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout
+        .contains("[warning] 'debug'の値は'false'である必要がありますが、'true'になっています"));
+
+    let output = run(&["examples/success2.conf", "--policy", "examples/policy.json"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("[error] 'port'を設定する必要があります"));
+}
+
+#[test]
+fn no_schema_ignores_existing_schema_file() {
+    // examples/fail.conf.schema は存在するが、適用されない
+    let output = run(&["examples/fail.conf", "--validate", "--no-schema"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("読み込んだデータをRust形式で出力します。"));
+    assert!(!stdout.contains("スキーマエラー"));
+}
+
+#[test]
+fn sysctl_compat_skips_invalid_lines_with_warning() {
+    let output = run(&["examples/parse_error1.conf"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    // procpsと同様、キーの途中の空白を受け入れる
+    let output = run(&["examples/parse_error1.conf", "--sysctl-compat"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\"a b c\""));
+}
+
+#[test]
+fn sysctl_compat_warnings_are_in_ci_report() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("lenient.conf");
+    std::fs::write(&config, "a = 1\nbroken\nb = 2\n= 3\n").unwrap();
+    let output = run(&[
+        config.to_str().unwrap(),
+        "--ci",
+        "--sysctl-compat",
+        "--no-schema",
+    ]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let diagnostics = json["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0]["kind"], "parse_warning");
+    assert_eq!(diagnostics[0]["severity"], "warning");
+    assert_eq!(diagnostics[0]["line"], 2);
+    assert_eq!(diagnostics[0]["message"], "=がありません: broken");
+    assert_eq!(diagnostics[1]["line"], 4);
+}
+
+#[test]
+fn list_ignored_prints_prefixed_keys() {
+    let output = run(&["examples/success2.conf", "--list-ignored"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "endpoint\n");
+}
+
+#[test]
+fn key_spans_prints_byte_ranges() {
+    let output = run(&["examples/success2.conf", "--key-spans"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1..9\tendpoint\n37..45\tgreeting\n"
+    );
+}
+
+#[test]
+fn defaults_warn_about_outliers() {
+    let output = run(&[
+        "examples/success1.conf",
+        "--defaults",
+        "examples/kernel_defaults.conf",
+    ]);
+    // 警告のみなので成功扱い
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("警告: [SL0202] 'port'の値3910は既定値80から48.9倍離れています。"));
+
+    let output = run(&[
+        "examples/success1.conf",
+        "--defaults",
+        "examples/kernel_defaults.conf",
+        "--outlier-ratio",
+        "100",
+    ]);
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("警告"));
+}
+
+#[test]
+fn stream_reports_problems_per_line() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = command()
+        .args(["examples/success1.conf.schema", "--stream"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"port = 3910\ndebug = maybe\nport = 80\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let lines: Vec<String> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_owned)
+        .collect();
+    assert_eq!(
+        lines[..2],
+        [
+            "[SL0003] 'debug'の型が間違っています。boolが必要ですが、stringの形式になっています。",
+            "[SL0009] 'port'が1行目と3行目で重複して設定されています。",
+        ]
+    );
+    // 最後まで設定されなかったキー
+    assert!(lines[2..]
+        .iter()
+        .all(|line| line.starts_with("[SL0001] 必要なキーである")));
+}
+
+#[test]
+fn bundle_round_trip_validates() {
+    let output = run(&["examples/success1.conf", "--validate", "--bundle"]);
+    assert!(output.status.success());
+    let dir = tempfile::tempdir().unwrap();
+    let bundle_path = dir.path().join("bundle.json");
+    std::fs::write(&bundle_path, &output.stdout).unwrap();
+
+    let output = run(&[bundle_path.to_str().unwrap(), "--from-bundle"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "スキーマエラーはありませんでした。\n"
+    );
+}
+
+#[test]
+fn min_coverage_compares_with_threshold() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("partial.conf");
+    std::fs::write(&config_path, "a.key = 1\nb.key = 0\n").unwrap();
+    std::fs::write(
+        dir.path().join("partial.conf.schema"),
+        "a.key: bool\nb.key: bool\nc.key: number\nd.key: string\n",
+    )
+    .unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    // 4件中2件で50%
+    assert!(run(&[config_path, "--min-coverage", "40"]).status.success());
+    assert!(run(&[config_path, "--min-coverage", "50"]).status.success());
+    let output = run(&[config_path, "--min-coverage", "60"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("スキーマのキー4件のうち2件(50.0%)が設定されています。"));
+}
+
+#[test]
+fn explain_renders_schema_as_markdown() {
+    let output = run(&[
+        "examples/success1.conf.schema",
+        "--explain",
+        "--format",
+        "markdown",
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        rows[0],
+        "| key | type | constraints | default | description |"
+    );
+    assert_eq!(rows.len(), 2 + 5);
+}
+
+#[test]
+fn target_selects_schema_by_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, content: &str| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_owned()
+    };
+    let config = write("target.conf", "a.key = true\nnew.key = 1\n");
+    let v1 = format!("v1={}", write("v1.schema", "a.key: bool\n"));
+    let v2 = format!(
+        "v2={}",
+        write("v2.schema", "a.key: bool\nnew.key: number\n")
+    );
+    let targets = ["--target-schema", &v1, "--target-schema", &v2];
+
+    let output = run(&[&[config.as_str()], &targets[..], &["--target", "v2"]].concat());
+    assert!(output.status.success());
+
+    // --target の場合は、隣のスキーマファイルを読まない
+    write("target.conf.schema", "not a schema\n");
+    let output = run(&[&[config.as_str()], &targets[..], &["--target", "v2"]].concat());
+    assert!(output.status.success(), "{:?}", output);
+
+    let output = run(&[&[config.as_str()], &targets[..], &["--target", "v1"]].concat());
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("定義されていない'new.key'が存在しており"));
+
+    // --target が無ければすべてのターゲットで検証する
+    let output = run(&[&[config.as_str()], &targets[..]].concat());
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("[v1] スキーマエラーがありました。"));
+    assert!(stdout.contains("[v2] スキーマエラーはありませんでした。"));
+    assert!(stdout.contains("'new.key'はv2でのみ有効です。"));
+}
+
+#[test]
+fn repl_reports_each_line() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = command()
+        .args(["examples/success1.conf.schema", "--repl"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"debug = true\nport = http\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "debug = true");
+    assert_eq!(lines[1], "  OK");
+    assert_eq!(lines[2], "port = http");
+    assert!(lines[3].contains("'port'の型が間違っています。"));
+}
+
+#[test]
+fn merge_carries_comment_of_winning_value() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("base.conf");
+    let overlay = dir.path().join("overlay.conf");
+    std::fs::write(&base, "# default\nvm.swappiness = 60\n").unwrap();
+    std::fs::write(&overlay, "# for databases\nvm.swappiness = 10\n").unwrap();
+    let (base, overlay) = (base.to_str().unwrap(), overlay.to_str().unwrap());
+
+    let output = run(&[base, "--merge", overlay]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "# for databases\nvm.swappiness = 10\n"
+    );
+
+    let output = run(&[base, "--merge", overlay, "--merge-comments", "concat"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "# default\n# for databases\nvm.swappiness = 10\n"
+    );
+}
+
+#[test]
+fn allowlist_rejects_unlisted_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let allowlist = dir.path().join("allowlist");
+    std::fs::write(&allowlist, "endpoint\ngreet*\n").unwrap();
+    let allowlist = allowlist.to_str().unwrap();
+
+    let output = run(&["examples/success2.conf", "--allowlist", allowlist]);
+    assert!(output.status.success());
+
+    std::fs::write(dir.path().join("allowlist"), "endpoint\n").unwrap();
+    let output = run(&["examples/success2.conf", "--allowlist", allowlist]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("定義されていない'greeting'が存在しており"));
+}
+
+#[test]
+fn merge_warns_when_trusted_key_is_overridden() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("base.conf");
+    let overlay = dir.path().join("overlay.conf");
+    std::fs::write(&base, "kernel.kptr_restrict = 2\nvm.swappiness = 60\n").unwrap();
+    std::fs::write(
+        dir.path().join("base.conf.schema"),
+        "%trusted kernel.kptr_restrict base.conf\n",
+    )
+    .unwrap();
+    let (base, overlay_path) = (base.to_str().unwrap(), overlay.to_str().unwrap());
+
+    std::fs::write(&overlay, "vm.swappiness = 10\n").unwrap();
+    let output = run(&[base, "--merge", overlay_path]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().is_empty());
+
+    std::fs::write(&overlay, "kernel.kptr_restrict = 0\n").unwrap();
+    let output = run(&[base, "--merge", overlay_path]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("警告: 'kernel.kptr_restrict'の値は'base.conf'から設定される必要があります"));
+}
+
+#[test]
+fn failed_only_prints_only_failing_keys() {
+    let output = run(&["examples/fail.conf", "--validate", "--failed-only"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let keys: Vec<&str> = stdout
+        .lines()
+        .filter(|line| !line.starts_with("  "))
+        .collect();
+    assert_eq!(keys, vec!["debug:", "log.file:", "port:"]);
+    assert!(!stdout.contains("スキーマエラーがありました。"));
+    assert!(!stdout.contains("SysctlValue"));
+
+    // エラーが無ければ何も出力しない
+    let output = run(&["examples/success1.conf", "--validate", "--failed-only"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    // --validate が無くても検証する
+    let output = run(&["examples/fail.conf", "--failed-only"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn annotations_validate_file_against_itself() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("annotated.conf");
+    let config_path = config.to_str().unwrap();
+
+    std::fs::write(
+        &config,
+        "# @type number(0..100)\nvm.swappiness = 60\nkernel.hostname = host\n",
+    )
+    .unwrap();
+    let output = run(&[config_path, "--annotations"]);
+    assert!(output.status.success());
+
+    std::fs::write(&config, "# @type number(0..100)\nvm.swappiness = 600\n").unwrap();
+    let output = run(&[config_path, "--annotations"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("'vm.swappiness'の値600は範囲0..100の外にあります。"));
+
+    std::fs::write(&config, "# @type number\nvm.swappiness = high\n").unwrap();
+    let output = run(&[config_path, "--annotations"]);
+    assert_eq!(output.status.code(), Some(3));
+
+    // 注釈の型の誤りは、注釈を書いた行の位置で報告する
+    std::fs::write(&config, "a = 1\n\n# @type numbr\nz = 2\n").unwrap();
+    let output = run(&[config_path, "--annotations", "--pretty"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("annotated.conf:3:9"), "{}", stdout);
+    assert!(stdout.contains("# @type numbr"), "{}", stdout);
+}
+
+#[test]
+fn pretty_renders_parse_errors_with_help() {
+    let output = run(&["examples/parse_error1.conf", "--pretty"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "エラー[SL0101]: 文法に誤りがあります。key = value の形式になっていません\n \
+         --> examples/parse_error1.conf:1:1\n  \
+         |\n\
+         1 | a b c = hoge\n  \
+         | ^\n  \
+         = ヘルプ: key = value の形式で書くか、先頭に#を付けてコメントにしてください\n"
+    );
+}
+
+#[test]
+fn lang_flag_selects_english_messages() {
+    let output = run(&["examples/fail.conf", "--validate", "--lang", "en"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("schema errors were found."));
+    assert!(stdout.contains("[SL0003] 'port' has the wrong type."));
+    assert!(!stdout.contains("スキーマエラー"));
+}
+
+#[test]
+fn lang_follows_locale_environment() {
+    let output = command()
+        .args(["examples/fail.conf", "--validate"])
+        .env("LANG", "en_US.UTF-8")
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("schema errors were found."));
+
+    // --lang は環境変数より優先する
+    let output = command()
+        .args(["examples/fail.conf", "--validate", "--lang", "ja"])
+        .env("LANG", "en_US.UTF-8")
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("スキーマエラーがありました。"));
+}
+
+#[test]
+fn lang_rejects_unknown_language() {
+    let output = run(&["examples/fail.conf", "--lang", "fr"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn missing_input_file_is_reported_without_panic() {
+    let output = run(&["examples/no_such_file.conf", "--validate"]);
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("設定ファイル'examples/no_such_file.conf'を読み込めませんでした"));
+    assert!(!stderr.contains("panicked"));
+}
+
+#[test]
+fn missing_policy_file_is_reported_without_panic() {
+    let output = run(&[
+        "examples/success1.conf",
+        "--policy",
+        "examples/no_such_policy.json",
+    ]);
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("ポリシーファイル'examples/no_such_policy.json'を読み込めませんでした"));
+}
+
+#[test]
+fn verbose_logs_files_and_counts_to_stderr() {
+    let output = run(&["examples/success1.conf", "--validate", "--verbose"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("ファイルを開きました"));
+    assert!(stderr.contains("examples/success1.conf.schema"));
+    assert!(stderr.contains("entries=5"));
+    assert!(stderr.contains("検証しました"));
+    // キーごとのログは --trace の場合だけ
+    assert!(!stderr.contains("エントリを読み込みました"));
+
+    let output = run(&["examples/success1.conf", "-v", "--trace"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("エントリを読み込みました"));
+}
+
+#[test]
+fn no_logs_without_verbose() {
+    let output = run(&["examples/success1.conf", "--validate"]);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn validate_subcommand_matches_legacy_flag() {
+    let legacy = run(&["examples/fail.conf", "--validate"]);
+    let subcommand = run(&["validate", "examples/fail.conf"]);
+    assert_eq!(subcommand.status.code(), Some(3));
+    // エラーの順序は実行ごとに変わるため、行を並べ替えて比べる
+    let sorted_lines = |stdout: Vec<u8>| {
+        let mut lines: Vec<String> = String::from_utf8(stdout)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        lines.sort();
+        lines
+    };
+    assert_eq!(sorted_lines(subcommand.stdout), sorted_lines(legacy.stdout));
+}
+
+#[test]
+fn diff_subcommand_compares_schemas() {
+    let output = run(&[
+        "diff",
+        "examples/success1.conf.schema",
+        "examples/success1.conf.schema",
+    ]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("スキーマに差分はありません。"));
+}
+
+#[test]
+fn help_and_version() {
+    let output = run(&["--help"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("validate"));
+    assert!(stdout.contains("--validate"));
+
+    let output = run(&["--version"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains(env!("CARGO_PKG_VERSION")));
+}
+
+#[test]
+fn unknown_flag_is_a_usage_error() {
+    let output = run(&["examples/success1.conf", "--no-such-flag"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    // ファイルもサブコマンドも無い場合
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Usage"));
+}
+
+#[test]
+fn schema_flag_validates_with_shared_schemas() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("base.schema");
+    std::fs::write(&base, "port: number\ndebug: bool\n").unwrap();
+    let host = dir.path().join("host.schema");
+    std::fs::write(&host, "port: string\n").unwrap();
+    let conf = dir.path().join("app.conf");
+    std::fs::write(&conf, "port = :3910\ndebug = true\n").unwrap();
+
+    // <入力ファイル>.schema は無いが、指定したスキーマで検証する
+    let output = run(&[
+        conf.to_str().unwrap(),
+        "--validate",
+        "--schema",
+        base.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("'port'の型が間違っています"));
+
+    // 後に指定したスキーマの定義が優先される
+    let output = run(&[
+        conf.to_str().unwrap(),
+        "--validate",
+        "--schema",
+        base.to_str().unwrap(),
+        "--schema",
+        host.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn validates_files_and_directories_per_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let sysctl_d = dir.path().join("sysctl.d");
+    std::fs::create_dir(&sysctl_d).unwrap();
+    let conf = dir.path().join("sysctl.conf");
+    std::fs::write(&conf, "port = 3910\n").unwrap();
+    std::fs::write(dir.path().join("sysctl.conf.schema"), "port: number\n").unwrap();
+    std::fs::write(sysctl_d.join("99-local.conf"), "port = :3910\n").unwrap();
+    std::fs::write(sysctl_d.join("99-local.conf.schema"), "port: number\n").unwrap();
+    std::fs::write(sysctl_d.join("10-base.conf"), "debug = true\n").unwrap();
+    std::fs::write(sysctl_d.join("README"), "not a config\n").unwrap();
+
+    let output = run(&[
+        "validate",
+        conf.to_str().unwrap(),
+        sysctl_d.to_str().unwrap(),
+    ]);
+    // 1つでもエラーのあるファイルがあれば失敗する
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let headers: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.starts_with("==> "))
+        .collect();
+    assert_eq!(
+        headers,
+        vec![
+            format!("==> {} <==", conf.display()),
+            format!("==> {} <==", sysctl_d.join("10-base.conf").display()),
+            format!("==> {} <==", sysctl_d.join("99-local.conf").display()),
+        ]
+    );
+    // エラーは該当するファイルの見出しの後に出力される
+    let local = stdout.find("99-local.conf <==").unwrap();
+    assert!(stdout[local..].contains("'port'の型が間違っています"));
+    assert!(!stdout[..local].contains("'port'の型が間違っています"));
+
+    // --ci ではファイルごとのレポートをJSONの配列として出力する
+    let output = run(&["--ci", conf.to_str().unwrap(), sysctl_d.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(3));
+    let reports: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let oks: Vec<bool> = reports
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|report| report["ok"].as_bool().unwrap())
+        .collect();
+    assert_eq!(oks, vec![true, true, false]);
+}
+
+#[test]
+fn missing_file_among_inputs_does_not_stop_the_others() {
+    let output = run(&["no_such.conf", "examples/success1.conf", "--validate"]);
+    // 終了コードは各ファイルのうち最も大きいもの
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("==> examples/success1.conf <=="));
+}
+
+#[test]
+fn single_file_modes_reject_multiple_files() {
+    let output = run(&["explain", "examples/success1.conf", "examples/fail.conf"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn dash_reads_config_from_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = command()
+        .args([
+            "validate",
+            "-",
+            "--schema",
+            "examples/success1.conf.schema",
+            "--ci",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"port = :3910\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["file"], "-");
+    // 標準入力から読み込んだ内容にも行番号が付く
+    let wrong_type = report["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|d| d["kind"] == "wrong_type")
+        .unwrap();
+    assert_eq!(wrong_type["key"], "port");
+    assert_eq!(wrong_type["line"], 1);
+
+    // 標準入力は一度しか読めない
+    let output = run(&["-", "-"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn format_json_outputs_parsed_data() {
+    let output = run(&["validate", "examples/success1.conf", "--format", "json"]);
+    assert!(output.status.success());
+    let data: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        data["port"],
+        serde_json::json!({ "value": "3910", "ignore_error": false, "type": "integer" })
+    );
+
+    // スキーマの説明はJSONに対応していない
+    let output = run(&[
+        "explain",
+        "examples/success1.conf.schema",
+        "--format",
+        "json",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn format_toml_outputs_nested_tables() {
+    let output = run(&["examples/success1.conf", "--format", "toml"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("[log]\nfile = \"/var/log/console.log\"\n"));
+    assert!(stdout.contains("port = 3910\n"));
+}
+
+#[test]
+fn format_toml_handles_key_that_is_also_a_table() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("sysctl.conf");
+    std::fs::write(&config, "kernel = 1\nkernel.hostname = x\n").unwrap();
+    let output = run(&[config.to_str().unwrap(), "--format", "toml"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("[kernel]\n\"\" = 1\nhostname = \"x\"\n"));
+}
+
+#[test]
+fn output_writes_data_to_file_and_no_print_suppresses_it() {
+    let dir = tempfile::tempdir().unwrap();
+    let output_path = dir.path().join("out.json");
+    let output = run(&[
+        "validate",
+        "examples/success1.conf",
+        "--format",
+        "json",
+        "--output",
+        output_path.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let data: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&output_path).unwrap()).unwrap();
+    assert_eq!(data["port"]["value"], "3910");
+
+    let output = run(&["validate", "examples/success1.conf", "--no-print"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    // 出力しなくても、終了コードは検証の結果に従う
+    let output = run(&["validate", "examples/fail.conf", "--no-print"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn quiet_prints_nothing_on_success() {
+    let output = run(&["validate", "examples/success1.conf", "--quiet"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    // 失敗した場合はエラーを表示し、種類ごとの終了コードを返す
+    let output = run(&["validate", "examples/fail.conf", "-q"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("スキーマエラーがありました。"));
+    let output = run(&["no_such.conf", "--quiet"]);
+    assert_eq!(output.status.code(), Some(4));
+}
+
+#[test]
+fn color_flag_controls_escape_sequences() {
+    // 端末ではないため、auto では色を付けない
+    let output = run(&["validate", "examples/fail.conf"]);
+    assert!(!String::from_utf8(output.stdout).unwrap().contains('\x1b'));
+
+    // always は NO_COLOR より優先する
+    let output = command()
+        .args(["validate", "examples/fail.conf", "--color", "always"])
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("'\x1b[1mport\x1b[0m'"));
+    assert!(stdout.contains("\x1b[32mnumber\x1b[0m"));
+
+    // JSONのレポートには色を付けない
+    let output = run(&["examples/fail.conf", "--ci", "--color", "always"]);
+    assert!(!String::from_utf8(output.stdout).unwrap().contains('\x1b'));
+}
+
+#[test]
+fn format_sarif_reports_results_with_regions() {
+    let output = run(&["examples/fail.conf", "--format", "sarif"]);
+    assert_eq!(output.status.code(), Some(3));
+    let sarif: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(sarif["version"], "2.1.0");
+    let results = sarif["runs"][0]["results"].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    let location = &results[0]["locations"][0]["physicalLocation"];
+    assert_eq!(location["artifactLocation"]["uri"], "examples/fail.conf");
+    assert!(location["region"]["startLine"].is_u64());
+    assert_eq!(results[0]["ruleId"], "SL0003");
+}
+
+#[test]
+fn format_github_prints_workflow_annotations() {
+    let output = run(&["examples/fail.conf", "--format", "github"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines
+        .iter()
+        .all(|line| line.starts_with("::error file=examples/fail.conf,line=")));
+    assert!(stdout.contains(",title=SL0003::'port'の型が間違っています。"));
+}
+
+#[test]
+fn format_json_report_lists_all_diagnostics() {
+    let output = run(&["examples/fail.conf", "--format", "json-report"]);
+    assert_eq!(output.status.code(), Some(3));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["ok"], false);
+    let port = report["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|d| d["key"] == "port")
+        .unwrap();
+    assert_eq!(port["file"], "examples/fail.conf");
+    assert_eq!(port["code"], "SL0003");
+    assert_eq!(port["severity"], "error");
+    assert_eq!(port["line"], 1);
+    assert_eq!(port["expected"], "number");
+    assert_eq!(port["actual"], "string");
+}
+
+#[test]
+fn format_checkstyle_outputs_xml() {
+    let output = run(&["examples/fail.conf", "--format", "checkstyle"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<checkstyle"));
+    assert!(stdout.contains("<file name=\"examples/fail.conf\">"));
+    assert_eq!(stdout.matches("<error line=").count(), 3);
+    assert!(stdout.contains("source=\"SL0003\"/>"));
+}
+
+#[test]
+fn format_tap_reports_each_schema_key() {
+    let output = run(&["examples/fail.conf", "--format", "tap"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("TAP version 13\n"));
+    assert!(stdout.contains(" - examples/fail.conf: port\n"));
+    assert!(stdout.lines().any(|line| line.starts_with("not ok ")));
+    let tests = stdout
+        .lines()
+        .filter(|line| line.starts_with("ok ") || line.starts_with("not ok "))
+        .count();
+    assert!(stdout.ends_with(&format!("1..{}\n", tests)));
+}
+
+#[test]
+fn format_tap_reports_keys_matching_glob_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("rp.conf");
+    std::fs::write(
+        &config,
+        "net.ipv4.conf.eth0.rp_filter = 1\nnet.ipv4.conf.eth1.rp_filter = 3\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("rp.conf.schema"),
+        "net.ipv4.conf.*.rp_filter: integer(0..2)\n",
+    )
+    .unwrap();
+    let config_path = config.to_str().unwrap();
+
+    let output = run(&[config_path, "--format", "tap"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("*"));
+    assert!(stdout.contains(&format!(
+        "ok 1 - {}: net.ipv4.conf.eth0.rp_filter\n",
+        config_path
+    )));
+    assert!(stdout.contains(&format!(
+        "not ok 2 - {}: net.ipv4.conf.eth1.rp_filter\n",
+        config_path
+    )));
+    assert!(stdout.ends_with("1..2\n"));
+}
+
+#[test]
+fn baseline_suppresses_recorded_violations() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("app.conf");
+    std::fs::write(&config_path, "port = abc\ndebug = true\n").unwrap();
+    std::fs::write(
+        dir.path().join("app.conf.schema"),
+        "port: number\ndebug: bool\n",
+    )
+    .unwrap();
+    let config_path = config_path.to_str().unwrap();
+    let baseline_path = dir.path().join("baseline.json");
+    let baseline_path = baseline_path.to_str().unwrap();
+
+    // 初回はファイルが無いため、今回の違反を記録して成功する
+    let output = run(&[config_path, "--baseline", baseline_path]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("1件の違反をベースライン"));
+    let baseline: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(baseline_path).unwrap()).unwrap();
+    assert_eq!(baseline[0]["code"], "SL0003");
+    assert_eq!(baseline[0]["key"], "port");
+
+    // 記録済みの違反だけなら成功する
+    let output = run(&[config_path, "--baseline", baseline_path]);
+    assert!(output.status.success());
+
+    // 新しい違反だけを報告する
+    std::fs::write(config_path, "port = abc\ndebug = maybe\n").unwrap();
+    let output = run(&[config_path, "--baseline", baseline_path]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("debug"));
+    assert!(!stdout.contains("port"));
+}
+
+#[test]
+fn fail_on_selects_failing_severity() {
+    let warning_args = [
+        "examples/success1.conf",
+        "--defaults",
+        "examples/kernel_defaults.conf",
+    ];
+    let output = run(&[&warning_args[..], &["--fail-on", "warning"]].concat());
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("警告: [SL0202]"));
+
+    // エラーがあっても失敗にしないが、エラーは表示する
+    let output = run(&["examples/fail.conf", "--validate", "--fail-on", "never"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("スキーマエラーがありました。"));
+
+    let output = run(&["examples/fail.conf", "--fail-on", "info"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn max_errors_truncates_with_notice() {
+    let output = run(&["examples/fail.conf", "--validate", "--max-errors", "1"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches("[SL0003]").count(), 1);
+    assert!(stdout.ends_with("…ほかに2件のエラーがあります。\n"));
+
+    let output = run(&["examples/fail.conf", "--ci", "--max-errors", "2"]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["diagnostics"].as_array().unwrap().len(), 2);
+    assert_eq!(report["omitted"], 1);
+
+    let output = run(&["examples/fail.conf", "--max-errors", "0"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn max_errors_truncates_syntax_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("broken.conf");
+    std::fs::write(&config, "broken line\n".repeat(50)).unwrap();
+    let config_path = config.to_str().unwrap();
+
+    let output = run(&[config_path, "--max-errors", "2"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches("[SL0101]").count(), 2);
+    assert!(stdout.contains("1行1列目"));
+    assert!(stdout.ends_with("…ほかに48件のエラーがあります。\n"));
+
+    let output = run(&[config_path, "--ci", "--max-errors", "2"]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["diagnostics"].as_array().unwrap().len(), 2);
+    assert_eq!(report["omitted"], 48);
+}
+
+#[test]
+fn staged_validates_index_contents() {
+    let dir = tempfile::tempdir().unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    std::fs::write(dir.path().join("app.conf.schema"), "port: number\n").unwrap();
+    std::fs::write(dir.path().join("app.conf"), "port = 80\n").unwrap();
+    git(&["add", "app.conf", "app.conf.schema"]);
+    // ステージされていない変更は検証しない
+    std::fs::write(dir.path().join("app.conf"), "port = abc\n").unwrap();
+    let staged = |args: &[&str]| {
+        command()
+            .current_dir(dir.path())
+            .arg("--staged")
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let output = staged(&[]);
+    assert!(output.status.success());
+
+    std::fs::write(dir.path().join("bad.conf"), "port = :80\n").unwrap();
+    std::fs::write(dir.path().join("bad.conf.schema"), "port: number\n").unwrap();
+    git(&["add", "bad.conf"]);
+    let output = staged(&[]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("==> bad.conf <=="));
+
+    // パスを指定すれば、その中のステージされたファイルだけを検証する
+    let output = staged(&["app.conf"]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn duplicate_keys_warn_or_fail() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("dup.conf");
+    std::fs::write(&config_path, "port = 80\nport = 81\n").unwrap();
+    std::fs::write(dir.path().join("dup.conf.schema"), "port: number\n").unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    // 既定では警告だけで成功する
+    let output = run(&[config_path, "--validate"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("警告: [SL0009] 'port'が1行目と2行目で重複して設定されています。"));
+
+    let output = run(&[config_path, "--validate", "--duplicate-keys", "error"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("[SL0009]"));
+}
+
+#[test]
+fn inline_comments_are_stripped_and_shown() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("inline.conf");
+    std::fs::write(&config_path, "port = 80  # http\n").unwrap();
+    std::fs::write(dir.path().join("inline.conf.schema"), "port: number\n").unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    // 既定ではコメントも値に含まれる
+    let output = run(&[config_path, "--validate"]);
+    assert_eq!(output.status.code(), Some(3));
+    let output = run(&[config_path, "--validate", "--inline-comments"]);
+    assert!(output.status.success());
+
+    // エラーの行にはコメントを添える
+    std::fs::write(dir.path().join("inline.conf"), "port = http  # legacy\n").unwrap();
+    let output = run(&[config_path, "--validate", "--inline-comments"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(":1: [SL0003]"), "{}", stdout);
+    assert!(stdout.contains("  # legacy\n"), "{}", stdout);
+}
+
+#[test]
+fn reads_files_with_bom_and_utf16() {
+    let dir = tempfile::tempdir().unwrap();
+    let utf8_path = dir.path().join("bom.conf");
+    std::fs::write(&utf8_path, "\u{feff}port = 80\n").unwrap();
+    let utf16_path = dir.path().join("utf16.conf");
+    let mut utf16 = vec![0xFF, 0xFE];
+    utf16.extend("port = 80\r\n".encode_utf16().flat_map(u16::to_le_bytes));
+    std::fs::write(&utf16_path, utf16).unwrap();
+
+    for path in [utf8_path, utf16_path] {
+        let output = run(&[path.to_str().unwrap(), "--format", "json"]);
+        assert!(output.status.success(), "{:?}", output);
+        assert!(String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("\"port\""));
+    }
+}
+
+#[test]
+fn long_lines_are_syntax_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("long.conf");
+    std::fs::write(
+        &config_path,
+        format!("a.key = 1\nb.key = {}\n", "x".repeat(100)),
+    )
+    .unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    let output = run(&[config_path, "--max-line-length", "50"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stdout).unwrap().contains(
+        "[SL0104] 文法に誤りがあります。2行1列目: 行が長すぎます。50バイト未満にしてください"
+    ));
+
+    // 既定の上限は4096バイト。0 なら制限しない
+    assert!(run(&[config_path]).status.success());
+    let output = run(&[config_path, "--max-line-length", "0"]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn slash_keys_match_dotted_schema() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("slash.conf");
+    std::fs::write(&config_path, "net/ipv4/conf/eth0.100/forwarding = true\n").unwrap();
+    std::fs::write(
+        dir.path().join("slash.conf.schema"),
+        "net.ipv4.conf.eth0/100.forwarding: bool\n",
+    )
+    .unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    let output = run(&[config_path, "--validate", "--fmt"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "net.ipv4.conf.eth0/100.forwarding = true\n"
+    );
+
+    let output = run(&[config_path, "--fmt", "--normalize", "slashes"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "net/ipv4/conf/eth0.100/forwarding = true\n"
+    );
+}
+
+#[test]
+fn invalid_key_names_are_reported_with_position() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("keys.conf");
+    std::fs::write(&config_path, "a.key = 1\nnet..ipv4 = 1\n").unwrap();
+
+    let output = run(&[config_path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stdout).unwrap().contains(
+        "[SL0105] 文法に誤りがあります。2行5列目: キーに使えない文字か、空の名前があります: net..ipv4 = 1"
+    ));
+}
+
+#[test]
+fn sections_prefix_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("sections.conf");
+    std::fs::write(
+        &config_path,
+        "[net.ipv4]\nip_forward = 1\ntcp_syncookies = 1\n",
+    )
+    .unwrap();
+
+    let output = run(&[config_path.to_str().unwrap(), "--sections", "--fmt"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "net.ipv4.ip_forward = 1\nnet.ipv4.tcp_syncookies = 1\n"
+    );
+}
+
+#[test]
+fn follow_includes_reads_fragments() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sysctl.d")).unwrap();
+    let config_path = dir.path().join("main.conf");
+    std::fs::write(&config_path, "a.key = 1\ninclude sysctl.d/*.conf\n").unwrap();
+    std::fs::write(dir.path().join("sysctl.d/10-b.conf"), "b.key = 2\n").unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    let output = run(&[config_path, "--follow-includes", "--fmt"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a.key = 1\nb.key = 2\n"
+    );
+
+    // 指定しなければ、include の行は文法の誤り
+    assert_eq!(run(&[config_path]).status.code(), Some(2));
+
+    std::fs::write(dir.path().join("sysctl.d/20-c.conf"), "broken\n").unwrap();
+    let output = run(&[config_path, "--follow-includes"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("20-c.conf"));
+
+    // 取り込んだファイルの誤りも、すべての行をレポートに出す
+    std::fs::write(
+        dir.path().join("sysctl.d/20-c.conf"),
+        "broken
+c.key = 3
+also broken
+",
+    )
+    .unwrap();
+    let output = run(&[config_path, "--follow-includes", "--format", "json-report"]);
+    assert_eq!(output.status.code(), Some(2));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["ok"], false);
+    let diagnostics = report["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 2);
+    assert!(diagnostics[0]["file"]
+        .as_str()
+        .unwrap()
+        .ends_with("20-c.conf"));
+    assert_eq!(diagnostics[0]["line"], 1);
+    assert_eq!(diagnostics[1]["line"], 3);
+}
+
+#[test]
+fn expand_env_substitutes_variables() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("template.conf");
+    std::fs::write(
+        &config_path,
+        "kernel.shmmax = ${SHMMAX}\nb.key = $${SHMMAX}\n",
+    )
+    .unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    let output = command()
+        .args([config_path, "--expand-env", "--fmt"])
+        .env("SHMMAX", "68719476736")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "b.key = ${SHMMAX}\nkernel.shmmax = 68719476736\n"
+    );
+
+    // 定義されていない変数はエラー
+    let output = command()
+        .args([config_path, "--expand-env"])
+        .env_remove("SHMMAX")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("[SL0106] 文法に誤りがあります。1行17列目: 定義されていない変数です"));
+}
+
+#[test]
+fn vars_render_templates_before_validation() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("template.conf");
+    std::fs::write(&config_path, "net.core.somaxconn = {{ somaxconn }}\n").unwrap();
+    std::fs::write(
+        dir.path().join("template.conf.schema"),
+        "net.core.somaxconn: number\n",
+    )
+    .unwrap();
+    let prod_path = dir.path().join("prod.yaml");
+    std::fs::write(&prod_path, "somaxconn: 4096\n").unwrap();
+    let broken_path = dir.path().join("broken.yaml");
+    std::fs::write(&broken_path, "somaxconn: many\n").unwrap();
+    let empty_path = dir.path().join("empty.yaml");
+    std::fs::write(&empty_path, "other: 1\n").unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    let output = run(&[config_path, "--vars", prod_path.to_str().unwrap(), "--fmt"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "net.core.somaxconn = 4096\n"
+    );
+
+    // 置き換えた値をスキーマで検証する
+    let output = run(&[
+        config_path,
+        "--vars",
+        broken_path.to_str().unwrap(),
+        "--validate",
+    ]);
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = run(&[config_path, "--vars", empty_path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("[SL0106] 文法に誤りがあります。1行22列目: 定義されていない変数です"));
+}
+
+#[test]
+fn kernel_conditionals_select_blocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("fleet.conf");
+    std::fs::write(
+        &config_path,
+        "a.key = 1\n#@if kernel >= 5.10\nb.key = 2\n#@else\nb.key = 3\n#@endif\n",
+    )
+    .unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    let output = run(&[config_path, "--kernel-version", "6.1.0-18-amd64", "--fmt"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a.key = 1\nb.key = 2\n"
+    );
+
+    let output = run(&[config_path, "--kernel-version", "5.4", "--fmt"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a.key = 1\nb.key = 3\n"
+    );
+
+    // 閉じていないブロックは、その #@if の行の誤り
+    std::fs::write(config_path, "a.key = 1\n#@if kernel >= 5.10\n").unwrap();
+    let output = run(&[config_path, "--kernel-version", "5.10"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("[SL0108] 文法に誤りがあります。2行1列目"));
+
+    assert_eq!(
+        run(&[config_path, "--kernel-version", "latest"])
+            .status
+            .code(),
+        Some(1)
+    );
+}
+
+#[test]
+fn kernel_conditionals_apply_with_follow_includes() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("fleet.conf");
+    std::fs::write(
+        &config_path,
+        "#@if kernel >= 5.10\nb.key = 2\n#@else\nb.key = 3\n#@endif\ninclude extra.conf\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("extra.conf"),
+        "#@if kernel < 6\nc.key = 1\n#@endif\n",
+    )
+    .unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    let output = run(&[
+        config_path,
+        "--kernel-version",
+        "6.1",
+        "--follow-includes",
+        "--fmt",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "b.key = 2\n");
+}
+
+#[test]
+fn freebsd_dialect_reads_bsd_syntax() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("sysctl.conf");
+    std::fs::write(
+        &config_path,
+        "kern.ipc.somaxconn=1024 # listen queue\nkern.hostname=\"bsd host\"\n",
+    )
+    .unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    let output = run(&[config_path, "--dialect", "freebsd", "--fmt"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "kern.hostname = bsd host\nkern.ipc.somaxconn = 1024\n"
+    );
+    assert!(run(&[config_path, "--dialect", "freebsd", "--validate"])
+        .status
+        .success());
+
+    // Linuxの名前空間のキーは、FreeBSDでは検証エラー
+    std::fs::write(config_path, "kernel.shmmax=1\n").unwrap();
+    let output = run(&[config_path, "--dialect", "freebsd", "--validate"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("[SL0015]"));
+    assert!(run(&[config_path, "--dialect", "linux", "--validate"])
+        .status
+        .success());
+}
+
+#[test]
+fn compat_busybox_matches_busybox_parsing() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("busybox.conf");
+    std::fs::write(
+        &config_path,
+        "net.ipv4.ip_forward 1 # comment\n-vm.swappiness = 10\nbroken\n",
+    )
+    .unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    let output = run(&[config_path, "--compat", "busybox", "--fmt"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "-vm.swappiness = 10\nnet.ipv4.ip_forward = 1\n"
+    );
+    assert!(String::from_utf8(output.stderr).unwrap().contains("3"));
+
+    // procpsでは、= の無い行は読み飛ばし、- は失敗を無視する印
+    let output = run(&[config_path, "--compat", "procps", "--fmt"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "-vm.swappiness = 10\n"
+    );
+
+    assert_eq!(
+        run(&[config_path, "--compat", "procps", "--sysctl-compat"])
+            .status
+            .code(),
+        Some(1)
+    );
+}
+
+#[test]
+fn lenient_skips_malformed_lines_with_warnings() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("lenient.conf");
+    std::fs::write(&config_path, "a.key = 1\nbroken line\nb.key = 2\n").unwrap();
+    let config_path = config_path.to_str().unwrap();
+
+    assert_eq!(run(&[config_path]).status.code(), Some(2));
+
+    let output = run(&[config_path, "--lenient", "--fmt"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a.key = 1\nb.key = 2\n"
+    );
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("lenient.conf(2): [SL0201] key = value の形式になっていません: broken line"));
+}
+
+#[test]
+fn provenance_points_at_defining_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("base.conf");
+    let overlay = dir.path().join("overlay.conf");
+    std::fs::write(&base, "kernel.pid_max = 4096\nvm.swappiness = 60\n").unwrap();
+    std::fs::write(&overlay, "\nvm.swappiness = 10\n").unwrap();
+    let (base, overlay) = (base.to_str().unwrap(), overlay.to_str().unwrap());
+
+    let output = run(&[base, "--merge", overlay, "--provenance"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "# 設定元: {base}:1\nkernel.pid_max = 4096\n# 設定元: {overlay}:2 ({base}:2を上書き)\nvm.swappiness = 10\n"
+        )
+    );
+
+    // 取り込んだファイルの値の検証エラーは、そのファイルと行を示す
+    let main = dir.path().join("main.conf");
+    std::fs::write(&main, "include overlay.conf\n").unwrap();
+    std::fs::write(dir.path().join("main.conf.schema"), "vm.swappiness: bool\n").unwrap();
+    let output = run(&[main.to_str().unwrap(), "--follow-includes", "--validate"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains(&format!("{overlay}:2: [SL0003]")));
+
+    // 機械向けの出力も、取り込んだファイルと行を指す
+    let main = main.to_str().unwrap();
+    let output = run(&[main, "--follow-includes", "--format", "json-report"]);
+    assert_eq!(output.status.code(), Some(3));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let diagnostic = &report["diagnostics"][0];
+    assert_eq!(diagnostic["file"], overlay);
+    assert_eq!(diagnostic["line"], 2);
+    let output = run(&[main, "--follow-includes", "--format", "checkstyle"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!("<file name=\"{main}\">\n  </file>")));
+    assert!(stdout.contains(&format!("<file name=\"{overlay}\">\n    <error line=\"2\"")));
+}
+
+#[test]
+fn fix_appends_schema_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("fix.conf");
+    std::fs::write(&config, "net.core.somaxconn = 1024").unwrap();
+    std::fs::write(
+        dir.path().join("fix.conf.schema"),
+        "net.core.somaxconn: number\nvm.swappiness: number(0..100) = 60\nkernel.pid_max?: number = 4096\n",
+    )
+    .unwrap();
+    let config_path = config.to_str().unwrap();
+
+    // 既定値のあるキーが無いことを、既定値と合わせて報告する
+    let output = run(&[config_path, "--validate"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("必要なキーである'vm.swappiness'が存在しません。既定値は'60'です"));
+
+    let output = run(&[config_path, "--fix", "--no-print"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        "net.core.somaxconn = 1024\nvm.swappiness = 60\n"
+    );
+    assert!(run(&[config_path, "--validate", "--no-print"])
+        .status
+        .success());
+
+    // 追記するものが無ければ、ファイルは変わらない
+    assert!(run(&[config_path, "--fix", "--no-print"]).status.success());
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        "net.core.somaxconn = 1024\nvm.swappiness = 60\n"
+    );
+}
+
+#[test]
+fn fix_with_sections_appends_outside_the_last_section() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("fix.conf");
+    std::fs::write(&config, "[net.ipv4]\nip_forward = 1\n").unwrap();
+    std::fs::write(
+        dir.path().join("fix.conf.schema"),
+        "net.ipv4.ip_forward: number\nvm.swappiness: number(0..100) = 60\n",
+    )
+    .unwrap();
+    let config_path = config.to_str().unwrap();
+
+    let output = run(&[config_path, "--fix", "--sections", "--no-print"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        "[net.ipv4]\nip_forward = 1\n[]\nvm.swappiness = 60\n"
+    );
+    let output = run(&[config_path, "--sections", "--validate", "--no-print"]);
+    assert!(output.status.success(), "{:?}", output);
+}
+
+#[test]
+fn enum_rejects_values_outside_the_choices() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("tcp.conf");
+    std::fs::write(&config, "net.ipv4.tcp_congestion_control = vegas\n").unwrap();
+    std::fs::write(
+        dir.path().join("tcp.conf.schema"),
+        "net.ipv4.tcp_congestion_control: enum(cubic, bbr, reno)\n",
+    )
+    .unwrap();
+
+    let output = run(&[config.to_str().unwrap(), "--validate"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stdout).unwrap().contains(
+        "[SL0013] 'net.ipv4.tcp_congestion_control'の値'vegas'は選択肢(cubic, bbr, reno)のいずれでもありません。"
+    ));
+
+    std::fs::write(&config, "net.ipv4.tcp_congestion_control = bbr\n").unwrap();
+    let output = run(&[config.to_str().unwrap(), "--validate", "--no-print"]);
+    assert!(output.status.success(), "{:?}", output);
+}